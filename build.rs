@@ -1,12 +1,100 @@
 use {
     cargo_lock::Lockfile,
-    std::collections::HashSet,
+    prost::Message,
+    prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorSet,
+    },
+    std::{collections::HashSet, env, fmt::Write, fs, path::PathBuf},
     vergen::{vergen, Config},
 };
 
+const PROTO_PACKAGE: &str = ".blockdaemon.solana.accountsdb_plugin_kafka.types";
+
+/// Bytes fields encoded with the topic's data encoding in JSON.
+const DATA_FIELDS: &[&str] = &[
+    "UpdateAccountEvent.data",
+    "CompiledInstruction.data",
+    "InstructionEvent.data",
+];
+
+/// Bytes fields holding keys, hashes or signatures, encoded as base58 in JSON.
+const KEY_FIELDS: &[&str] = &[
+    "UpdateAccountEvent.pubkey",
+    "UpdateAccountEvent.owner",
+    "UpdateAccountEvent.txn_signature",
+    "UpdateAccountEvent.previous_owner",
+    "TokenAccountEvent.pubkey",
+    "TokenAccountEvent.program",
+    "TokenAccountEvent.mint",
+    "TokenAccountEvent.owner",
+    "TokenAccountEvent.delegate",
+    "TokenAccountEvent.close_authority",
+    "StakeAccountEvent.pubkey",
+    "StakeAccountEvent.staker",
+    "StakeAccountEvent.withdrawer",
+    "StakeAccountEvent.lockup_custodian",
+    "StakeAccountEvent.voter",
+    "VoteAccountEvent.pubkey",
+    "VoteAccountEvent.node_pubkey",
+    "VoteAccountEvent.authorized_voter",
+    "VoteAccountEvent.authorized_withdrawer",
+    "MessageAddressTableLookup.account_key",
+    "V0Message.recent_block_hash",
+    "LegacyMessage.recent_block_hash",
+    "SanitizedTransaction.message_hash",
+    "TransactionEvent.signature",
+    "SignatureEvent.signature",
+    "InstructionEvent.signature",
+    "InstructionEvent.program_id",
+    "ProgramUpgradedEvent.signature",
+    "ProgramUpgradedEvent.program_id",
+    "ProgramUpgradedEvent.program_data",
+    "ProgramUpgradedEvent.buffer",
+    "ProgramUpgradedEvent.authority",
+];
+
+/// Repeated bytes fields holding keys or signatures, encoded as base58 in JSON.
+const KEY_LIST_FIELDS: &[&str] = &[
+    "LoadedAddresses.writable",
+    "LoadedAddresses.readonly",
+    "V0Message.account_keys",
+    "LegacyMessage.account_keys",
+    "SanitizedTransaction.signatures",
+    "InstructionEvent.accounts",
+];
+
 fn main() -> anyhow::Result<()> {
     // Proto
-    prost_build::compile_protos(&["proto/event.proto"], &["proto/"])?;
+    let mut config = prost_build::Config::new();
+    config.type_attribute(".", "#[derive(serde::Serialize, borsh::BorshSerialize)]");
+    // Slot statuses are named in the plugin config.
+    config.type_attribute(
+        format!("{}.SlotStatus", PROTO_PACKAGE),
+        "#[derive(serde::Deserialize)] #[serde(rename_all = \"snake_case\")]",
+    );
+    for (fields, serializer) in [
+        (DATA_FIELDS, "crate::json::data"),
+        (KEY_FIELDS, "crate::json::pubkey"),
+        (KEY_LIST_FIELDS, "crate::json::pubkeys"),
+    ] {
+        for field in fields {
+            config.field_attribute(
+                format!("{}.{}", PROTO_PACKAGE, field),
+                format!("#[serde(serialize_with = \"{}\")]", serializer),
+            );
+        }
+    }
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let descriptors = out_dir.join("event.bin");
+    config.file_descriptor_set_path(&descriptors);
+    config.compile_protos(&["proto/event.proto"], &["proto/"])?;
+
+    // FlatBuffers schema and encoders, generated from the same messages.
+    let set = FileDescriptorSet::decode(&*fs::read(&descriptors)?)?;
+    let (schema, encoders) = flatbuffers(&set)?;
+    fs::write(out_dir.join("event.fbs"), schema)?;
+    fs::write(out_dir.join("flatbuffers.rs"), encoders)?;
 
     // Version metrics
     vergen(Config::default())?;
@@ -31,6 +119,290 @@ fn main() -> anyhow::Result<()> {
             .collect::<Vec<_>>()
             .join(",")
     );
+    // The lockfile can hold both interface crates, only the one the build links counts
+    let interface = if env::var_os("CARGO_FEATURE_AGAVE").is_some() {
+        "agave-geyser-plugin-interface"
+    } else {
+        "solana-geyser-plugin-interface"
+    };
+    println!(
+        "cargo:rustc-env=GEYSER_INTERFACE_VERSION={}",
+        lockfile
+            .packages
+            .iter()
+            .filter(|pkg| pkg.name.as_str() == interface)
+            .map(|pkg| pkg.version.to_string())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>()
+            .join(",")
+    );
 
     Ok(())
 }
+
+/// How a proto field is laid out in FlatBuffers.
+enum FlatField {
+    /// Scalar with its FlatBuffers type, Rust type and default.
+    Scalar(String, &'static str, &'static str),
+    /// `google.protobuf` wrapper, an optional scalar.
+    Wrapper(&'static str, &'static str),
+    String,
+    Bytes,
+    Message(String),
+    /// Member of a oneof, with the Rust path of its variant.
+    OneofMessage(String, String, String),
+    RepeatedScalar(&'static str),
+    RepeatedString,
+    /// Vectors of vectors are not supported, each element is wrapped in a `Bytes` table.
+    RepeatedBytes,
+    RepeatedMessage(String),
+}
+
+/// FlatBuffers and Rust types of a scalar proto type.
+fn flat_scalar(r#type: Type) -> Option<(&'static str, &'static str, &'static str)> {
+    Some(match r#type {
+        Type::Double => ("double", "f64", "0.0"),
+        Type::Float => ("float", "f32", "0.0"),
+        Type::Int64 | Type::Sint64 | Type::Sfixed64 => ("long", "i64", "0"),
+        Type::Uint64 | Type::Fixed64 => ("ulong", "u64", "0"),
+        Type::Int32 | Type::Sint32 | Type::Sfixed32 => ("int", "i32", "0"),
+        Type::Uint32 | Type::Fixed32 => ("uint", "u32", "0"),
+        Type::Bool => ("bool", "bool", "false"),
+        _ => return None,
+    })
+}
+
+fn flat_wrapper(type_name: &str) -> Option<(&'static str, &'static str)> {
+    Some(match type_name {
+        ".google.protobuf.DoubleValue" => ("double", "f64"),
+        ".google.protobuf.Int64Value" => ("long", "i64"),
+        ".google.protobuf.UInt64Value" => ("ulong", "u64"),
+        ".google.protobuf.Int32Value" => ("int", "i32"),
+        ".google.protobuf.UInt32Value" => ("uint", "u32"),
+        _ => return None,
+    })
+}
+
+fn short_name(type_name: &str) -> String {
+    type_name.rsplit('.').next().unwrap_or_default().to_owned()
+}
+
+/// Field name as generated by prost.
+fn rust_field(name: &str) -> &str {
+    name.trim_end_matches('_')
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn flat_field(
+    message: &DescriptorProto,
+    field: &FieldDescriptorProto,
+) -> anyhow::Result<FlatField> {
+    let repeated = field.label() == Label::Repeated;
+    let r#type = field.r#type();
+    Ok(match (repeated, r#type) {
+        (false, Type::Enum) => FlatField::Scalar(short_name(field.type_name()), "i32", "0"),
+        (false, Type::String) => FlatField::String,
+        (false, Type::Bytes) => FlatField::Bytes,
+        (false, Type::Message) => match (flat_wrapper(field.type_name()), field.oneof_index) {
+            (Some((flat, rust)), _) => FlatField::Wrapper(flat, rust),
+            (None, Some(index)) => {
+                let oneof = message.oneof_decl[index as usize].name();
+                FlatField::OneofMessage(
+                    short_name(field.type_name()),
+                    rust_field(oneof).to_owned(),
+                    format!(
+                        "{}::{}::{}",
+                        snake_case(message.name()),
+                        upper_camel_case(oneof),
+                        upper_camel_case(field.name())
+                    ),
+                )
+            }
+            (None, None) => FlatField::Message(short_name(field.type_name())),
+        },
+        (true, Type::String) => FlatField::RepeatedString,
+        (true, Type::Bytes) => FlatField::RepeatedBytes,
+        (true, Type::Message) => FlatField::RepeatedMessage(short_name(field.type_name())),
+        (false, r#type) => match flat_scalar(r#type) {
+            Some((flat, rust, default)) => FlatField::Scalar(flat.to_owned(), rust, default),
+            None => anyhow::bail!("unsupported field {}.{}", message.name(), field.name()),
+        },
+        (true, r#type) => match flat_scalar(r#type) {
+            Some((flat, _, _)) => FlatField::RepeatedScalar(flat),
+            None => anyhow::bail!("unsupported field {}.{}", message.name(), field.name()),
+        },
+    })
+}
+
+/// FlatBuffers schema of the proto messages and `FlatEncode` implementations writing them.
+/// Field ids are the proto field numbers minus one, so that removing a proto field leaves the
+/// ids of the others as they were. Unused numbers become deprecated placeholders, since
+/// FlatBuffers ids must be contiguous. Oneof members become optional fields.
+fn flatbuffers(set: &FileDescriptorSet) -> anyhow::Result<(String, String)> {
+    let file = set
+        .file
+        .iter()
+        .find(|file| file.name() == "event.proto")
+        .ok_or_else(|| anyhow::anyhow!("event.proto descriptor missing"))?;
+    let mut schema = String::new();
+    let mut code = String::new();
+    writeln!(schema, "// Generated from event.proto, do not edit.\n")?;
+    writeln!(schema, "namespace {};\n", file.package())?;
+    for r#enum in &file.enum_type {
+        writeln!(schema, "enum {} : int {{", r#enum.name())?;
+        for value in &r#enum.value {
+            writeln!(schema, "  {} = {},", value.name(), value.number())?;
+        }
+        writeln!(schema, "}}\n")?;
+    }
+    writeln!(schema, "table Bytes {{\n  value: [ubyte];\n}}\n")?;
+
+    for message in &file.message_type {
+        let mut fields = message.field.iter().collect::<Vec<_>>();
+        fields.sort_by_key(|field| field.number());
+        writeln!(schema, "table {} {{", message.name())?;
+        let mut offsets = String::new();
+        let mut slots = String::new();
+        let mut next_id = 0;
+        for field in fields {
+            let name = field.name();
+            let rust = rust_field(name);
+            let id = field.number() - 1;
+            for unused in next_id..id {
+                writeln!(
+                    schema,
+                    "  unused_{}: ubyte (deprecated, id: {});",
+                    unused + 1,
+                    unused
+                )?;
+            }
+            next_id = id + 1;
+            let slot = 4 + 2 * id;
+            let flat = flat_field(message, field)?;
+            let has_offset = !matches!(flat, FlatField::Scalar(..) | FlatField::Wrapper(..));
+            match flat {
+                FlatField::Scalar(flat, rust_type, default) => {
+                    writeln!(schema, "  {}: {} (id: {});", name, flat, id)?;
+                    writeln!(
+                        slots,
+                        "fbb.push_slot::<{}>({}, self.{}, {});",
+                        rust_type, slot, rust, default
+                    )?;
+                }
+                FlatField::Wrapper(flat, rust_type) => {
+                    writeln!(schema, "  {}: {} = null (id: {});", name, flat, id)?;
+                    writeln!(
+                        slots,
+                        "if let Some(value) = self.{} {{ fbb.push_slot_always::<{}>({}, value); }}",
+                        rust, rust_type, slot
+                    )?;
+                }
+                FlatField::String => {
+                    writeln!(schema, "  {}: string (id: {});", name, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = (!self.{}.is_empty()).then(|| fbb.create_string(&self.{}));",
+                        rust, rust, rust
+                    )?;
+                }
+                FlatField::Bytes => {
+                    writeln!(schema, "  {}: [ubyte] (id: {});", name, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = (!self.{}.is_empty()).then(|| fbb.create_vector(&self.{}[..]));",
+                        rust, rust, rust
+                    )?;
+                }
+                FlatField::Message(r#type) => {
+                    writeln!(schema, "  {}: {} (id: {});", name, r#type, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = self.{}.as_ref().map(|message| message.encode_flat(fbb));",
+                        rust, rust
+                    )?;
+                }
+                FlatField::OneofMessage(r#type, oneof, variant) => {
+                    writeln!(schema, "  {}: {} (id: {});", name, r#type, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = match &self.{} {{ Some({}(message)) => Some(message.encode_flat(fbb)), _ => None }};",
+                        rust, oneof, variant
+                    )?;
+                }
+                FlatField::RepeatedScalar(flat) => {
+                    writeln!(schema, "  {}: [{}] (id: {});", name, flat, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = (!self.{}.is_empty()).then(|| fbb.create_vector(&self.{}[..]));",
+                        rust, rust, rust
+                    )?;
+                }
+                FlatField::RepeatedString => {
+                    writeln!(schema, "  {}: [string] (id: {});", name, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = (!self.{}.is_empty()).then(|| {{ let items = self.{}.iter().map(|item| fbb.create_string(item)).collect::<Vec<_>>(); fbb.create_vector(&items) }});",
+                        rust, rust, rust
+                    )?;
+                }
+                FlatField::RepeatedBytes => {
+                    writeln!(schema, "  {}: [Bytes] (id: {});", name, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = (!self.{}.is_empty()).then(|| {{ let items = self.{}.iter().map(|item| encode_bytes(fbb, item)).collect::<Vec<_>>(); fbb.create_vector(&items) }});",
+                        rust, rust, rust
+                    )?;
+                }
+                FlatField::RepeatedMessage(r#type) => {
+                    writeln!(schema, "  {}: [{}] (id: {});", name, r#type, id)?;
+                    writeln!(
+                        offsets,
+                        "let {} = (!self.{}.is_empty()).then(|| {{ let items = self.{}.iter().map(|item| item.encode_flat(fbb)).collect::<Vec<_>>(); fbb.create_vector(&items) }});",
+                        rust, rust, rust
+                    )?;
+                }
+            }
+            if has_offset {
+                writeln!(
+                    slots,
+                    "if let Some(offset) = {} {{ fbb.push_slot_always({}, offset); }}",
+                    rust, slot
+                )?;
+            }
+        }
+        writeln!(schema, "}}\n")?;
+        writeln!(
+            code,
+            "impl FlatEncode for {} {{\n\
+             fn encode_flat(&self, fbb: &mut FlatBufferBuilder<'_>) -> WIPOffset<TableFinishedWIPOffset> {{\n\
+             {}let table = fbb.start_table();\n{}fbb.end_table(table)\n}}\n}}\n",
+            message.name(),
+            offsets,
+            slots
+        )?;
+    }
+    Ok((schema, code))
+}
@@ -16,5 +16,6 @@ set -x
 # Build/test all host crates
 cargo +"$rust_stable" build
 cargo +"$rust_stable" test -- --nocapture
+cargo +"$rust_stable" build --manifest-path compat/solana/Cargo.toml
 
 exit 0
@@ -0,0 +1 @@
+../../build.rs
\ No newline at end of file
@@ -0,0 +1,310 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prints the events published by the plugin.
+//!
+//! Reads the plugin config to find the brokers and topics:
+//!
+//! ```shell
+//! cargo run --example kafka-consumer -- config.json
+//! ```
+//!
+//! `tail` follows a single topic, keeping events matching all given filters, and prints them
+//! as JSON with base58 keys:
+//!
+//! ```shell
+//! cargo run --example kafka-consumer -- tail config.json <topic> [--pubkey <key>] [--owner <key>] [--slot <slot>]
+//! ```
+
+use {
+    prost::Message,
+    rdkafka::{
+        config::ClientConfig,
+        consumer::{BaseConsumer, Consumer},
+        message::Message as _,
+    },
+    serde::Serialize,
+    solana_accountsdb_plugin_kafka::{
+        sanitized_message::MessagePayload, Config, Format, SlotStatusEvent, StakeAccountEvent,
+        TokenAccountEvent, TransactionEvent, UpdateAccountEvent, VoteAccountEvent,
+    },
+    std::{collections::HashMap, env, error::Error, fmt::Debug, time::Duration},
+};
+
+const USAGE: &str = "usage: kafka-consumer <plugin config file>
+       kafka-consumer tail <plugin config file> <topic> [--pubkey <key>] [--owner <key>] [--slot <slot>]";
+
+#[derive(Clone, Copy)]
+enum Event {
+    Account,
+    Slot,
+    Transaction,
+    TokenAccount,
+    StakeAccount,
+    VoteAccount,
+}
+
+impl Event {
+    fn print(self, payload: &[u8]) {
+        match self {
+            Self::Account => print_decoded::<UpdateAccountEvent>(payload),
+            Self::Slot => print_decoded::<SlotStatusEvent>(payload),
+            Self::Transaction => print_decoded::<TransactionEvent>(payload),
+            Self::TokenAccount => print_decoded::<TokenAccountEvent>(payload),
+            Self::StakeAccount => print_decoded::<StakeAccountEvent>(payload),
+            Self::VoteAccount => print_decoded::<VoteAccountEvent>(payload),
+        }
+    }
+
+    fn tail(self, payload: &[u8], filter: &TailFilter) {
+        match self {
+            Self::Account => tail_decoded::<UpdateAccountEvent>(payload, filter),
+            Self::Slot => tail_decoded::<SlotStatusEvent>(payload, filter),
+            Self::Transaction => tail_decoded::<TransactionEvent>(payload, filter),
+            Self::TokenAccount => tail_decoded::<TokenAccountEvent>(payload, filter),
+            Self::StakeAccount => tail_decoded::<StakeAccountEvent>(payload, filter),
+            Self::VoteAccount => tail_decoded::<VoteAccountEvent>(payload, filter),
+        }
+    }
+}
+
+fn print_decoded<M: Message + Default + Debug>(payload: &[u8]) {
+    match M::decode(payload) {
+        Ok(event) => println!("{:#?}", event),
+        Err(error) => eprintln!("failed to decode event: {}", error),
+    }
+}
+
+fn tail_decoded<M: Message + Default + Serialize + Tail>(payload: &[u8], filter: &TailFilter) {
+    let event = match M::decode(payload) {
+        Ok(event) => event,
+        Err(error) => return eprintln!("failed to decode event: {}", error),
+    };
+    if filter.matches(&event) {
+        match serde_json::to_string(&event) {
+            Ok(json) => println!("{}", json),
+            Err(error) => eprintln!("failed to print event: {}", error),
+        }
+    }
+}
+
+/// Fields of an event that `tail` filters on.
+trait Tail {
+    fn slot(&self) -> u64;
+
+    /// Accounts the event refers to.
+    fn pubkeys(&self) -> Vec<&[u8]>;
+
+    /// Program owning the account.
+    fn owner(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl Tail for UpdateAccountEvent {
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn pubkeys(&self) -> Vec<&[u8]> {
+        vec![&self.pubkey]
+    }
+
+    fn owner(&self) -> Option<&[u8]> {
+        Some(&self.owner)
+    }
+}
+
+impl Tail for SlotStatusEvent {
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn pubkeys(&self) -> Vec<&[u8]> {
+        Vec::new()
+    }
+}
+
+impl Tail for TransactionEvent {
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn pubkeys(&self) -> Vec<&[u8]> {
+        let payload = self
+            .transaction
+            .as_ref()
+            .and_then(|transaction| transaction.message.as_ref())
+            .and_then(|message| message.message_payload.as_ref());
+        let account_keys = match payload {
+            Some(MessagePayload::Legacy(message)) => &message.account_keys,
+            Some(MessagePayload::V0(message)) => match &message.message {
+                Some(message) => &message.account_keys,
+                None => return Vec::new(),
+            },
+            None => return Vec::new(),
+        };
+        account_keys.iter().map(Vec::as_slice).collect()
+    }
+}
+
+impl Tail for TokenAccountEvent {
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    /// The token account and the wallet owning it.
+    fn pubkeys(&self) -> Vec<&[u8]> {
+        vec![&self.pubkey, &self.owner]
+    }
+
+    fn owner(&self) -> Option<&[u8]> {
+        Some(&self.program)
+    }
+}
+
+impl Tail for StakeAccountEvent {
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn pubkeys(&self) -> Vec<&[u8]> {
+        vec![&self.pubkey]
+    }
+}
+
+impl Tail for VoteAccountEvent {
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn pubkeys(&self) -> Vec<&[u8]> {
+        vec![&self.pubkey]
+    }
+}
+
+#[derive(Default)]
+struct TailFilter {
+    pubkey: Option<Vec<u8>>,
+    owner: Option<Vec<u8>>,
+    slot: Option<u64>,
+}
+
+impl TailFilter {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut filter = Self::default();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or(USAGE)?;
+            match flag.as_str() {
+                "--pubkey" => filter.pubkey = Some(bs58::decode(value).into_vec()?),
+                "--owner" => filter.owner = Some(bs58::decode(value).into_vec()?),
+                "--slot" => filter.slot = Some(value.parse()?),
+                _ => return Err(USAGE.into()),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn matches(&self, event: &impl Tail) -> bool {
+        self.slot.is_none_or(|slot| slot == event.slot())
+            && self
+                .pubkey
+                .as_ref()
+                .is_none_or(|pubkey| event.pubkeys().iter().any(|key| key == pubkey))
+            && self
+                .owner
+                .as_ref()
+                .is_none_or(|owner| event.owner() == Some(owner.as_slice()))
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let (config_file, tail) = match args.next().ok_or(USAGE)? {
+        command if command == "tail" => {
+            let config_file = args.next().ok_or(USAGE)?;
+            let topic = args.next().ok_or(USAGE)?;
+            (config_file, Some((topic, TailFilter::parse(args)?)))
+        }
+        config_file => (config_file, None),
+    };
+    let config = Config::read_from(config_file)?;
+
+    let mut topics: HashMap<&str, Event> = [
+        (&config.update_account_topic, Event::Account),
+        (&config.slot_status_topic, Event::Slot),
+        (&config.transaction_topic, Event::Transaction),
+        (&config.token_account_topic, Event::TokenAccount),
+        (&config.stake_account_topic, Event::StakeAccount),
+        (&config.vote_account_topic, Event::VoteAccount),
+    ]
+    .into_iter()
+    .filter(|(topic, _)| !topic.is_empty())
+    .map(|(topic, event)| (topic.as_str(), event))
+    .collect();
+    if let Some((topic, _)) = &tail {
+        if config.format != Format::Protobuf {
+            return Err("tail decodes protobuf topics only".into());
+        }
+        topics.retain(|name, _| name == topic);
+    }
+    if topics.is_empty() {
+        return Err("no matching topics configured".into());
+    }
+
+    let mut client_config = ClientConfig::new();
+    for key in ["bootstrap.servers", "security.protocol"] {
+        if let Some(value) = config.kafka.get(key) {
+            client_config.set(key, value);
+        }
+    }
+    for (key, value) in &config.kafka {
+        if key.starts_with("sasl.") || key.starts_with("ssl.") {
+            client_config.set(key, value);
+        }
+    }
+    let consumer: BaseConsumer = client_config
+        .set("group.id", "solana-accountsdb-plugin-kafka-example")
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "latest")
+        .create()?;
+    consumer.subscribe(&topics.keys().copied().collect::<Vec<_>>())?;
+    eprintln!("consuming {:?}", topics.keys());
+
+    loop {
+        let message = match consumer.poll(Duration::from_secs(1)) {
+            Some(message) => message?,
+            None => continue,
+        };
+        let payload = message.payload().unwrap_or_default();
+        let event = topics.get(message.topic());
+        if let (Some((_, filter)), Some(event)) = (&tail, event) {
+            event.tail(payload, filter);
+            continue;
+        }
+        println!(
+            "{} partition {} offset {}",
+            message.topic(),
+            message.partition(),
+            message.offset()
+        );
+        match (config.format, event) {
+            (Format::Json, _) => println!("{}", String::from_utf8_lossy(payload)),
+            (Format::Protobuf, Some(event)) => event.print(payload),
+            (Format::Protobuf, None) => {}
+            (Format::Flatbuffers, _) => println!("{} bytes of FlatBuffers", payload.len()),
+            (Format::Borsh, _) => println!("{} bytes of Borsh", payload.len()),
+        }
+    }
+}
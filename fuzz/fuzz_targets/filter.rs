@@ -0,0 +1,90 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Evaluates arbitrary filter groups against arbitrary accounts, which must never panic.
+
+#![no_main]
+
+use {
+    arbitrary::Arbitrary,
+    libfuzzer_sys::fuzz_target,
+    solana_accountsdb_plugin_kafka::{
+        AccountFilterConfig, Config, DataSlice, Filter, MemcmpConfig, RedactAction, Redaction,
+    },
+};
+
+#[derive(Debug, Arbitrary)]
+struct Group {
+    owner: Option<[u8; 32]>,
+    memcmp: Vec<(usize, Vec<u8>)>,
+    data_size: Option<usize>,
+    data_slice: Option<(usize, usize)>,
+    redact: Vec<(usize, usize, bool)>,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    groups: Vec<Group>,
+    owner: Vec<u8>,
+    pubkey: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn group_config(group: Group) -> AccountFilterConfig {
+    AccountFilterConfig {
+        owners: group
+            .owner
+            .map(|owner| vec![bs58::encode(owner).into_string()])
+            .unwrap_or_default(),
+        accounts: Vec::new(),
+        memcmp: group
+            .memcmp
+            .into_iter()
+            .map(|(offset, bytes)| MemcmpConfig {
+                offset,
+                bytes: bs58::encode(bytes).into_string(),
+            })
+            .collect(),
+        data_size: group.data_size,
+        data_slice: group
+            .data_slice
+            .map(|(offset, length)| DataSlice { offset, length }),
+        redact: group
+            .redact
+            .into_iter()
+            .map(|(offset, length, remove)| Redaction {
+                range: DataSlice { offset, length },
+                action: if remove {
+                    RedactAction::Remove
+                } else {
+                    RedactAction::Zero
+                },
+            })
+            .collect(),
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let config = Config {
+        filters: input.groups.into_iter().map(group_config).collect(),
+        ..Config::default()
+    };
+    let filter = Filter::new(&config);
+    if let Some(group) = filter.wants_filter(&input.owner, &input.pubkey, &input.data) {
+        let data = group.account_data(&input.data);
+        assert!(data.len() <= input.data.len());
+    }
+    filter.wants_program(&input.owner);
+    filter.wants_account(&input.pubkey);
+});
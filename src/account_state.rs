@@ -0,0 +1,281 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Latest state of every filtered account, for snapshots of the full state rather than the
+//! change stream, and to report owner changes.
+
+use {
+    crate::{
+        config::{AccountStatesConfig, Eviction},
+        prom::{ACCOUNT_STATES, ACCOUNT_STATE_EVICTIONS_TOTAL, ACCOUNT_STATE_LOOKUPS_TOTAL},
+        replica::AccountInfo,
+    },
+    std::{
+        collections::{BTreeSet, HashMap},
+        sync::{Arc, RwLock},
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountState {
+    pub pubkey: [u8; 32],
+    pub slot: u64,
+    pub lamports: u64,
+    pub owner: [u8; 32],
+    pub executable: bool,
+    pub rent_epoch: u64,
+    /// As published, after redaction and `data_slice`. Empty unless the states keep the data,
+    /// for snapshots and bootstrap.
+    pub data: Vec<u8>,
+    pub write_version: u64,
+    /// Of the account's full data length.
+    pub rent_exempt: bool,
+}
+
+#[derive(Debug)]
+pub struct AccountStates {
+    /// Unbounded if `None`.
+    max_accounts: Option<usize>,
+    eviction: Eviction,
+    /// Whether the states hold the account data or only its metadata.
+    keep_data: bool,
+    /// Whether the states are indexed by update, only to evict the oldest.
+    index_updates: bool,
+    accounts: RwLock<Accounts>,
+}
+
+#[derive(Debug, Default)]
+struct Accounts {
+    /// Shared with snapshots in progress, which then cost no copies of the data.
+    states: HashMap<[u8; 32], Arc<AccountState>>,
+    /// Pubkeys by slot and write version of their state, oldest first, if `index_updates`.
+    updated: BTreeSet<(u64, u64, [u8; 32])>,
+}
+
+impl AccountStates {
+    pub fn new(config: Option<&AccountStatesConfig>, keep_data: bool) -> Self {
+        let max_accounts = config.map(|config| config.max_accounts);
+        let eviction = config.map(|config| config.eviction).unwrap_or_default();
+        Self {
+            max_accounts,
+            eviction,
+            keep_data,
+            index_updates: max_accounts.is_some() && eviction == Eviction::Oldest,
+            accounts: RwLock::default(),
+        }
+    }
+
+    /// Keeps the update if it is newer than the state held, by slot and write version.
+    /// Returns the previous owner if the update changes it.
+    pub fn record(
+        &self,
+        info: &AccountInfo,
+        data: &[u8],
+        slot: u64,
+        rent_exempt: bool,
+    ) -> Option<[u8; 32]> {
+        let (pubkey, owner) = match (info.pubkey.try_into(), info.owner.try_into()) {
+            (Ok(pubkey), Ok(owner)) => (pubkey, owner),
+            _ => return None,
+        };
+        let mut guard = self.accounts.write().unwrap();
+        let accounts = &mut *guard;
+        let previous = accounts.states.get(&pubkey);
+        ACCOUNT_STATE_LOOKUPS_TOTAL
+            .with_label_values(&[if previous.is_some() { "hit" } else { "miss" }])
+            .inc();
+        let previous_owner = match previous {
+            Some(state) if (state.slot, state.write_version) > (slot, info.write_version) => {
+                return None
+            }
+            Some(state) => {
+                if self.index_updates {
+                    accounts
+                        .updated
+                        .remove(&(state.slot, state.write_version, pubkey));
+                }
+                Some(state.owner).filter(|previous| *previous != owner)
+            }
+            None => None,
+        };
+        // Closed accounts no longer belong to the state.
+        if info.lamports == 0 {
+            accounts.states.remove(&pubkey);
+            ACCOUNT_STATES.set(accounts.states.len() as i64);
+            return previous_owner;
+        }
+        let full = self
+            .max_accounts
+            .is_some_and(|max_accounts| accounts.states.len() >= max_accounts);
+        if full && !accounts.states.contains_key(&pubkey) {
+            ACCOUNT_STATE_EVICTIONS_TOTAL.inc();
+            match self.eviction {
+                Eviction::Oldest => match accounts.updated.iter().next().copied() {
+                    Some(oldest) => {
+                        accounts.updated.remove(&oldest);
+                        accounts.states.remove(&oldest.2);
+                    }
+                    None => return previous_owner,
+                },
+                Eviction::Reject => return previous_owner,
+            }
+        }
+        accounts.states.insert(
+            pubkey,
+            Arc::new(AccountState {
+                pubkey,
+                slot,
+                lamports: info.lamports,
+                owner,
+                executable: info.executable,
+                rent_epoch: info.rent_epoch,
+                data: if self.keep_data {
+                    data.to_vec()
+                } else {
+                    Vec::new()
+                },
+                write_version: info.write_version,
+                rent_exempt,
+            }),
+        );
+        if self.index_updates {
+            accounts.updated.insert((slot, info.write_version, pubkey));
+        }
+        ACCOUNT_STATES.set(accounts.states.len() as i64);
+        previous_owner
+    }
+
+    /// The states held, in no particular order.
+    pub fn snapshot(&self) -> Vec<Arc<AccountState>> {
+        self.accounts
+            .read()
+            .unwrap()
+            .states
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(pubkey: &[u8; 32], lamports: u64, write_version: u64) -> AccountInfo<'_> {
+        owned_by(pubkey, &[0; 32], lamports, write_version)
+    }
+
+    fn owned_by<'a>(
+        pubkey: &'a [u8; 32],
+        owner: &'a [u8; 32],
+        lamports: u64,
+        write_version: u64,
+    ) -> AccountInfo<'a> {
+        AccountInfo {
+            pubkey,
+            lamports,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+            data: &[],
+            write_version,
+            txn_signature: None,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let states = AccountStates::new(None, true);
+        let pubkey = [1; 32];
+        states.record(&info(&pubkey, 10, 2), &[1], 5, false);
+        states.record(&info(&pubkey, 20, 1), &[2], 5, false);
+        assert_eq!(states.snapshot()[0].lamports, 10);
+        states.record(&info(&pubkey, 30, 1), &[3], 6, false);
+        assert_eq!(states.snapshot()[0].data, [3]);
+        states.record(&info(&pubkey, 0, 2), &[], 6, false);
+        assert!(states.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_metadata_only() {
+        let states = AccountStates::new(None, false);
+        states.record(&info(&[1; 32], 10, 1), &[1, 2], 5, true);
+        let snapshot = states.snapshot();
+        assert_eq!(snapshot[0].lamports, 10);
+        assert!(snapshot[0].data.is_empty());
+        // Unbounded states are never evicted, so not indexed by update.
+        assert!(states.accounts.read().unwrap().updated.is_empty());
+    }
+
+    #[test]
+    fn test_previous_owner() {
+        let states = AccountStates::new(None, true);
+        let pubkey = [1; 32];
+        let owner = [2; 32];
+        assert_eq!(states.record(&info(&pubkey, 10, 1), &[], 5, false), None);
+        assert_eq!(states.record(&info(&pubkey, 10, 2), &[], 5, false), None);
+        assert_eq!(
+            states.record(&owned_by(&pubkey, &owner, 10, 3), &[], 5, false),
+            Some([0; 32])
+        );
+        // Older updates are ignored.
+        assert_eq!(states.record(&info(&pubkey, 10, 1), &[], 5, false), None);
+        assert_eq!(
+            states.record(&info(&pubkey, 0, 4), &[], 5, false),
+            Some(owner)
+        );
+    }
+
+    #[test]
+    fn test_eviction() {
+        let pubkeys = [[1; 32], [2; 32], [3; 32]];
+        let held = |states: &AccountStates| {
+            let mut held = states
+                .snapshot()
+                .iter()
+                .map(|state| state.pubkey[0])
+                .collect::<Vec<_>>();
+            held.sort_unstable();
+            held
+        };
+
+        let oldest = AccountStates::new(
+            Some(&AccountStatesConfig {
+                max_accounts: 2,
+                eviction: Eviction::Oldest,
+            }),
+            true,
+        );
+        oldest.record(&info(&pubkeys[0], 10, 1), &[], 5, false);
+        oldest.record(&info(&pubkeys[1], 10, 2), &[], 5, false);
+        oldest.record(&info(&pubkeys[0], 10, 3), &[], 5, false);
+        oldest.record(&info(&pubkeys[2], 10, 4), &[], 5, false);
+        assert_eq!(held(&oldest), [1, 3]);
+
+        let reject = AccountStates::new(
+            Some(&AccountStatesConfig {
+                max_accounts: 2,
+                eviction: Eviction::Reject,
+            }),
+            true,
+        );
+        reject.record(&info(&pubkeys[0], 10, 1), &[], 5, false);
+        reject.record(&info(&pubkeys[1], 10, 2), &[], 5, false);
+        reject.record(&info(&pubkeys[2], 10, 3), &[], 5, false);
+        assert_eq!(held(&reject), [1, 2]);
+        reject.record(&info(&pubkeys[0], 0, 4), &[], 5, false);
+        reject.record(&info(&pubkeys[2], 10, 5), &[], 5, false);
+        assert_eq!(held(&reject), [2, 3]);
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Startup announcement, an auditable record of every plugin load and config change.
+
+use {
+    crate::{config::Config, version::VERSION, FilterCount, PluginStartedEvent},
+    sha2::{Digest, Sha256},
+    std::{
+        fs, io,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Announcement of the plugin loaded from `config_file`.
+pub fn plugin_started(config: &Config, config_file: &str) -> io::Result<PluginStartedEvent> {
+    let config_hash = hex::encode(Sha256::digest(fs::read(config_file)?));
+    let filters = [
+        ("program_ignores", config.program_ignores.len()),
+        ("program_filters", config.program_filters.len()),
+        ("account_filters", config.account_filters.len()),
+        ("filters", config.filters.len()),
+        (
+            "token_delegate_filter",
+            config
+                .token_delegate_filter
+                .as_ref()
+                .map_or(0, |filter| filter.delegates.len()),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(name, count)| FilterCount {
+        name: name.to_owned(),
+        count: count as u64,
+    })
+    .collect();
+    Ok(PluginStartedEvent {
+        plugin_version: VERSION.version.to_owned(),
+        git_version: VERSION.git.to_owned(),
+        interface_version: VERSION.interface.to_owned(),
+        config_hash,
+        filters,
+        validator_identity: config.identity.clone().unwrap_or_default(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::env};
+
+    #[test]
+    fn test_plugin_started() {
+        let path =
+            env::temp_dir().join(format!("kafka-plugin-announce-{}.json", std::process::id()));
+        fs::write(&path, b"{}").unwrap();
+        let config = Config {
+            program_filters: vec!["a".to_owned(), "b".to_owned()],
+            identity: Some("validator".to_owned()),
+            ..Config::default()
+        };
+        let event = plugin_started(&config, path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let event = event.unwrap();
+
+        assert_eq!(
+            event.config_hash,
+            "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+        assert_eq!(
+            event.filters,
+            vec![FilterCount {
+                name: "program_filters".to_owned(),
+                count: 2,
+            }]
+        );
+        assert_eq!(event.validator_identity, "validator");
+        assert_eq!(event.plugin_version, VERSION.version);
+        // Only the interface crate the build links, never a list from the lockfile.
+        assert!(!event.interface_version.is_empty());
+        assert!(!event.interface_version.contains(','));
+        assert!(event.timestamp > 0);
+
+        assert!(plugin_started(&config, path.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishes synthetic account updates and transactions at fixed rates through the plugin's
+//! `Publisher`, to size a Kafka cluster before connecting a validator.
+//!
+//! ```shell
+//! load-generator config.json --accounts-per-sec 50000 --transactions-per-sec 3000 --seconds 300
+//! ```
+
+use {
+    solana_accountsdb_plugin_kafka::{
+        sanitized_message::MessagePayload, CompiledInstruction, Config, LegacyMessage,
+        MessageHeader, MessageVersion, PublishError, Publisher, SanitizedMessage,
+        SanitizedTransaction, TransactionEvent, TransactionStatusMeta, UpdateAccountEvent,
+    },
+    std::{
+        env,
+        error::Error,
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+const USAGE: &str = "usage: load-generator <plugin config file> [--accounts-per-sec <n>] \
+[--transactions-per-sec <n>] [--data-size <bytes>] [--accounts <n>] [--seconds <n>]";
+
+/// Account data sizes drawn from when `--data-size` is not set: empty system accounts,
+/// token accounts, mints, vote accounts and larger program state.
+const DATA_SIZES: &[usize] = &[0, 165, 165, 165, 165, 82, 3762, 200, 1_024, 10_240];
+
+const TICK: Duration = Duration::from_millis(10);
+
+struct Options {
+    accounts_per_sec: u64,
+    transactions_per_sec: u64,
+    data_size: Option<usize>,
+    /// Number of distinct account pubkeys.
+    accounts: u64,
+    seconds: Option<u64>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut options = Self {
+            accounts_per_sec: 10_000,
+            transactions_per_sec: 1_000,
+            data_size: None,
+            accounts: 1_000_000,
+            seconds: None,
+        };
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or(USAGE)?;
+            match flag.as_str() {
+                "--accounts-per-sec" => options.accounts_per_sec = value.parse()?,
+                "--transactions-per-sec" => options.transactions_per_sec = value.parse()?,
+                "--data-size" => options.data_size = Some(value.parse()?),
+                "--accounts" => options.accounts = value.parse::<u64>()?.max(1),
+                "--seconds" => options.seconds = Some(value.parse()?),
+                _ => return Err(USAGE.into()),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// xorshift64, good enough to spread keys and sizes.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    /// A pubkey out of `count` distinct ones.
+    fn pubkey(&mut self, count: u64) -> Vec<u8> {
+        let index = self.next() % count;
+        let mut key = vec![0; 32];
+        key[..8].copy_from_slice(&index.to_le_bytes());
+        key[8..16].copy_from_slice(&index.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_le_bytes());
+        key
+    }
+}
+
+struct Generator {
+    options: Options,
+    rng: Rng,
+    slot: u64,
+    write_version: u64,
+    owners: Vec<Vec<u8>>,
+}
+
+impl Generator {
+    fn account(&mut self) -> UpdateAccountEvent {
+        let size = self
+            .options
+            .data_size
+            .unwrap_or_else(|| DATA_SIZES[self.rng.next() as usize % DATA_SIZES.len()]);
+        self.write_version += 1;
+        UpdateAccountEvent {
+            slot: self.slot,
+            pubkey: self.rng.pubkey(self.options.accounts),
+            lamports: self.rng.next() % 1_000_000_000_000,
+            owner: self.owners[self.rng.next() as usize % self.owners.len()].clone(),
+            executable: false,
+            rent_epoch: 0,
+            data: self.rng.bytes(size),
+            write_version: self.write_version,
+            txn_signature: self.rng.bytes(64),
+            block_time: None,
+            rent_exempt: true,
+            previous_owner: Vec::new(),
+        }
+    }
+
+    fn transaction(&mut self) -> TransactionEvent {
+        let account_count = 3 + self.rng.next() as usize % 10;
+        let account_keys = (0..account_count)
+            .map(|_| self.rng.pubkey(self.options.accounts))
+            .collect::<Vec<_>>();
+        let instructions = (0..1 + self.rng.next() % 3)
+            .map(|_| {
+                let data_len = 8 + self.rng.next() as usize % 120;
+                CompiledInstruction {
+                    program_id_index: (account_count - 1) as u32,
+                    accounts: (0..account_count as u32 - 1).collect(),
+                    data: self.rng.bytes(data_len),
+                }
+            })
+            .collect();
+        let balances = (0..account_count)
+            .map(|_| self.rng.next() % 1_000_000_000_000)
+            .collect::<Vec<_>>();
+        let signature = self.rng.bytes(64);
+        TransactionEvent {
+            signature: signature.clone(),
+            is_vote: false,
+            slot: self.slot,
+            index: Some(self.rng.next() % 2_000),
+            block_time: None,
+            compute_unit_limit: None,
+            compute_unit_price: None,
+            transaction: Some(SanitizedTransaction {
+                message: Some(SanitizedMessage {
+                    version: MessageVersion::Legacy.into(),
+                    message_payload: Some(MessagePayload::Legacy(LegacyMessage {
+                        header: Some(MessageHeader {
+                            num_required_signatures: 1,
+                            num_readonly_signed_accounts: 0,
+                            num_readonly_unsigned_accounts: 1,
+                        }),
+                        account_keys,
+                        recent_block_hash: self.rng.bytes(32),
+                        instructions,
+                    })),
+                }),
+                message_hash: self.rng.bytes(32),
+                is_simple_vote_transaction: false,
+                signatures: vec![signature],
+            }),
+            transaction_status_meta: Some(TransactionStatusMeta {
+                fee: 5_000,
+                pre_balances: balances.clone(),
+                post_balances: balances,
+                log_messages: (0..2 + self.rng.next() % 10)
+                    .map(|i| format!("Program log: synthetic log message {}", i))
+                    .collect(),
+                ..TransactionStatusMeta::default()
+            }),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    sent: u64,
+    queue_full: u64,
+    failed: u64,
+}
+
+impl Counts {
+    fn record(&mut self, result: Result<(), PublishError>) {
+        match result {
+            Ok(()) => self.sent += 1,
+            Err(PublishError::QueueFull) => self.queue_full += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let config = Config::read_from(args.next().ok_or(USAGE)?)?;
+    let options = Options::parse(args)?;
+    if config.update_account_topic.is_empty() && config.transaction_topic.is_empty() {
+        return Err("neither update_account_topic nor transaction_topic is configured".into());
+    }
+
+    let publisher = Publisher::new(Some(config.create_producer()?), None, &config);
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let owners = (0..16).map(|_| rng.bytes(32)).collect();
+    let mut generator = Generator {
+        options,
+        rng,
+        slot: 0,
+        write_version: 0,
+        owners,
+    };
+
+    let started = Instant::now();
+    let mut last_report = started;
+    let mut counts = Counts::default();
+    let (mut accounts_due, mut transactions_due) = (0.0, 0.0);
+    let tick_secs = TICK.as_secs_f64();
+    loop {
+        let tick_started = Instant::now();
+        if let Some(seconds) = generator.options.seconds {
+            if started.elapsed() >= Duration::from_secs(seconds) {
+                break;
+            }
+        }
+        // Slots advance every 400ms like on mainnet.
+        generator.slot = started.elapsed().as_millis() as u64 / 400;
+
+        if publisher.wants_update_account() {
+            accounts_due += generator.options.accounts_per_sec as f64 * tick_secs;
+            while accounts_due >= 1.0 {
+                accounts_due -= 1.0;
+                let event = generator.account();
+                counts.record(publisher.update_account(event));
+            }
+        }
+        if publisher.wants_transaction() {
+            transactions_due += generator.options.transactions_per_sec as f64 * tick_secs;
+            while transactions_due >= 1.0 {
+                transactions_due -= 1.0;
+                let event = generator.transaction();
+                counts.record(publisher.update_transaction(event));
+            }
+        }
+
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            eprintln!(
+                "{:>6.0}s sent {} queue full {} failed {}",
+                started.elapsed().as_secs_f64(),
+                counts.sent,
+                counts.queue_full,
+                counts.failed
+            );
+            counts = Counts::default();
+            last_report = Instant::now();
+        }
+        if let Some(remaining) = TICK.checked_sub(tick_started.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+    eprintln!("done, flushing");
+    // Dropping the publisher flushes pending messages until `shutdown_timeout_ms`.
+    drop(publisher);
+    Ok(())
+}
@@ -0,0 +1,354 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishes the accounts of a snapshot that pass the plugin's filters, to backfill consumers
+//! without replaying the snapshot through a validator.
+//!
+//! Takes the plugin config and the `accounts` directory of an unpacked snapshot archive:
+//!
+//! ```shell
+//! tar -I zstd -xf snapshot-<slot>-<hash>.tar.zst accounts
+//! snapshot-backfill config.json accounts
+//! ```
+//!
+//! With `handoff` configured, the completion is announced on the control topic once the
+//! accounts are delivered, so that the plugin, started from the same snapshot, publishes the
+//! live events of later slots.
+
+use {
+    solana_accountsdb_plugin_kafka::{
+        BackfillCompletedEvent, Config, Filter, PublishError, Publisher, RentTracker,
+        UpdateAccountEvent,
+    },
+    std::{
+        collections::HashMap,
+        env,
+        error::Error,
+        fs,
+        path::Path,
+        thread,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+const USAGE: &str = "usage: snapshot-backfill <plugin config file> <snapshot accounts dir>";
+
+/// `StoredMeta` (write version, data length, pubkey), `AccountMeta` (lamports, rent epoch,
+/// owner, executable padded to 8 bytes) and the account hash preceding each account's data.
+const STORED_ACCOUNT_HEADER_LEN: usize = 48 + 56 + 32;
+
+/// How long to wait for the producer queue to drain when it is full.
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// An account stored in an append vec.
+struct StoredAccount<'a> {
+    write_version: u64,
+    pubkey: &'a [u8],
+    lamports: u64,
+    rent_epoch: u64,
+    owner: &'a [u8],
+    executable: bool,
+    data: &'a [u8],
+}
+
+/// Iterates the accounts of an append vec file, stopping at the unused tail.
+fn stored_accounts(mut buf: &[u8]) -> impl Iterator<Item = StoredAccount> {
+    std::iter::from_fn(move || {
+        if buf.len() < STORED_ACCOUNT_HEADER_LEN {
+            return None;
+        }
+        let write_version = read_u64(&buf[0..8]);
+        let data_len = read_u64(&buf[8..16]) as usize;
+        let pubkey = &buf[16..48];
+        let data_end = STORED_ACCOUNT_HEADER_LEN.checked_add(data_len)?;
+        if data_end > buf.len() || (write_version == 0 && pubkey.iter().all(|b| *b == 0)) {
+            return None;
+        }
+        let account = StoredAccount {
+            write_version,
+            pubkey,
+            lamports: read_u64(&buf[48..56]),
+            rent_epoch: read_u64(&buf[56..64]),
+            owner: &buf[64..96],
+            executable: buf[96] != 0,
+            data: &buf[STORED_ACCOUNT_HEADER_LEN..data_end],
+        };
+        // Stored accounts are 8 byte aligned.
+        buf = buf.get((data_end + 7) & !7..).unwrap_or_default();
+        Some(account)
+    })
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Slot and write version of a stored account, newer versions compare greater.
+type Version = (u64, u64);
+
+/// Newest version of every account, fed in slot order.
+#[derive(Default)]
+struct Latest {
+    /// Matching accounts, and those whose newest version no longer matches.
+    accounts: HashMap<[u8; 32], (Version, Option<UpdateAccountEvent>)>,
+    /// Non matching accounts of the current slot, which may hold several versions of an account
+    /// in any order. Older slots need none, their versions are superseded anyway.
+    unmatched: HashMap<[u8; 32], u64>,
+    slot: u64,
+}
+
+impl Latest {
+    /// Keeps `event` if `version` is the newest of the account. `None` if the version does not
+    /// match the filters or deletes the account.
+    fn offer(&mut self, pubkey: [u8; 32], version: Version, event: Option<UpdateAccountEvent>) {
+        if version.0 != self.slot {
+            self.unmatched.clear();
+            self.slot = version.0;
+        }
+        if let Some((newest, _)) = self.accounts.get(&pubkey) {
+            if *newest > version {
+                return;
+            }
+        }
+        if let Some(write_version) = self.unmatched.get(&pubkey) {
+            if *write_version > version.1 {
+                return;
+            }
+        }
+        match event {
+            Some(event) => {
+                self.unmatched.remove(&pubkey);
+                self.accounts.insert(pubkey, (version, Some(event)));
+            }
+            None => {
+                self.unmatched.insert(pubkey, version.1);
+                // A newer version no longer matching supersedes the older one.
+                if let Some(entry) = self.accounts.get_mut(&pubkey) {
+                    *entry = (version, None);
+                }
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.accounts
+            .values()
+            .filter(|(_, event)| event.is_some())
+            .count()
+    }
+
+    fn into_events(self) -> impl Iterator<Item = UpdateAccountEvent> {
+        self.accounts.into_values().filter_map(|(_, event)| event)
+    }
+}
+
+/// Append vec files are named `<slot>.<id>`.
+fn append_vec_slot(path: &Path) -> Option<u64> {
+    path.file_name()?.to_str()?.split('.').next()?.parse().ok()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let config_file = args.next().ok_or(USAGE)?;
+    let accounts_dir = args.next().ok_or(USAGE)?;
+    let config = Config::read_from(config_file)?;
+    if config.update_account_topic.is_empty() {
+        return Err("update_account_topic is not configured".into());
+    }
+
+    let mut append_vecs = fs::read_dir(&accounts_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter_map(|path| append_vec_slot(&path).map(|slot| (slot, path)))
+        .collect::<Vec<_>>();
+    // Visiting slots in order lets newer versions of an account replace older ones.
+    append_vecs.sort();
+    eprintln!("reading {} append vecs", append_vecs.len());
+
+    let filter = Filter::new(&config);
+    let mut latest = Latest::default();
+    let mut scanned = 0u64;
+    let rent = RentTracker::default();
+    for (slot, path) in &append_vecs {
+        let buf = fs::read(path)?;
+        for account in stored_accounts(&buf) {
+            scanned += 1;
+            let pubkey = <[u8; 32]>::try_from(account.pubkey)?;
+            rent.record(account.pubkey, account.data);
+            let rent_exempt = rent.is_exempt(account.lamports, account.data.len());
+            let data = match filter.wants_filter(account.owner, account.pubkey, account.data) {
+                // Zero lamport accounts are deleted.
+                _ if account.lamports == 0 => None,
+                _ if config.rent_exempt_only && !rent_exempt => None,
+                Some(group) => Some(group.account_data(account.data)),
+                None if filter.wants_program(account.owner)
+                    || filter.wants_account(account.pubkey) =>
+                {
+                    Some(account.data.into())
+                }
+                None => None,
+            };
+            let event = data.map(|data| UpdateAccountEvent {
+                slot: *slot,
+                pubkey: account.pubkey.to_vec(),
+                lamports: account.lamports,
+                owner: account.owner.to_vec(),
+                executable: account.executable,
+                rent_epoch: account.rent_epoch,
+                data: match config.account_data_limit {
+                    Some(limit) => data[..data.len().min(limit)].to_vec(),
+                    None => data.into_owned(),
+                },
+                write_version: account.write_version,
+                txn_signature: Vec::new(),
+                block_time: None,
+                rent_exempt,
+                previous_owner: Vec::new(),
+            });
+            latest.offer(pubkey, (*slot, account.write_version), event);
+        }
+    }
+    eprintln!(
+        "scanned {} stored accounts, {} matching accounts",
+        scanned,
+        latest.len()
+    );
+
+    let publisher = Publisher::new(Some(config.create_producer()?), None, &config);
+    let mut published = 0u64;
+    for event in latest.into_events() {
+        loop {
+            match publisher.update_account(event.clone()) {
+                Ok(()) => break,
+                Err(PublishError::QueueFull) => thread::sleep(QUEUE_FULL_BACKOFF),
+                Err(error) => return Err(error.into()),
+            }
+        }
+        published += 1;
+    }
+    eprintln!("published {} accounts, flushing", published);
+    if let Some(handoff) = &config.handoff {
+        let undelivered = publisher.flush();
+        if undelivered > 0 {
+            return Err(format!(
+                "{} accounts undelivered, not completing the handoff",
+                undelivered
+            )
+            .into());
+        }
+        // The newest append vec holds the accounts written in the snapshot's slot.
+        let slot = append_vecs.last().map_or(0, |(slot, _)| *slot);
+        publisher.backfill_completed(
+            &handoff.control_topic,
+            BackfillCompletedEvent {
+                topic: config.update_account_topic.clone(),
+                slot,
+                accounts: published,
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64,
+            },
+        )?;
+        eprintln!(
+            "announced backfill up to slot {} on {}",
+            slot, handoff.control_topic
+        );
+    }
+    // Dropping the publisher flushes pending messages until `shutdown_timeout_ms`.
+    drop(publisher);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(slot: u64, write_version: u64) -> Option<UpdateAccountEvent> {
+        Some(UpdateAccountEvent {
+            slot,
+            write_version,
+            lamports: 1,
+            ..UpdateAccountEvent::default()
+        })
+    }
+
+    fn versions(latest: Latest) -> Vec<Version> {
+        let mut versions = latest
+            .into_events()
+            .map(|event| (event.slot, event.write_version))
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions
+    }
+
+    #[test]
+    fn test_newest_version_wins() {
+        let mut latest = Latest::default();
+        latest.offer([1; 32], (1, 5), event(1, 5));
+        latest.offer([1; 32], (2, 3), event(2, 3));
+        // An older version of the same slot, e.g. in another append vec.
+        latest.offer([1; 32], (2, 2), event(2, 2));
+        latest.offer([2; 32], (2, 9), event(2, 9));
+        latest.offer([2; 32], (2, 8), event(2, 8));
+        assert_eq!(versions(latest), [(2, 3), (2, 9)]);
+    }
+
+    #[test]
+    fn test_unmatched_versions_supersede() {
+        let mut latest = Latest::default();
+        latest.offer([1; 32], (1, 1), event(1, 1));
+        // Deleted, or no longer matching the filters.
+        latest.offer([1; 32], (2, 2), None);
+        latest.offer([1; 32], (2, 1), event(2, 1));
+        // Not matching before an older matching version of the same slot.
+        latest.offer([2; 32], (3, 7), None);
+        latest.offer([2; 32], (3, 6), event(3, 6));
+        assert_eq!(latest.len(), 0);
+
+        latest.offer([2; 32], (4, 1), event(4, 1));
+        assert_eq!(versions(latest), [(4, 1)]);
+    }
+
+    #[test]
+    fn test_stored_accounts() {
+        let mut buf = Vec::new();
+        for (write_version, data) in [(1u64, &b"abc"[..]), (2, &b""[..])] {
+            buf.extend_from_slice(&write_version.to_le_bytes());
+            buf.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&[write_version as u8; 32]);
+            buf.extend_from_slice(&7u64.to_le_bytes());
+            buf.extend_from_slice(&8u64.to_le_bytes());
+            buf.extend_from_slice(&[9; 32]);
+            buf.extend_from_slice(&[1; 8]);
+            buf.extend_from_slice(&[0; 32]);
+            buf.extend_from_slice(data);
+            buf.resize((buf.len() + 7) & !7, 0);
+        }
+        // The unused tail.
+        buf.resize(buf.len() + STORED_ACCOUNT_HEADER_LEN, 0);
+
+        let accounts = stored_accounts(&buf).collect::<Vec<_>>();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].write_version, 1);
+        assert_eq!(accounts[0].pubkey, [1; 32]);
+        assert_eq!(accounts[0].lamports, 7);
+        assert_eq!(accounts[0].rent_epoch, 8);
+        assert_eq!(accounts[0].owner, [9; 32]);
+        assert!(accounts[0].executable);
+        assert_eq!(accounts[0].data, b"abc");
+        assert_eq!(accounts[1].write_version, 2);
+        assert!(accounts[1].data.is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block times of recent slots, stamped onto account and transaction events.
+//!
+//! Block metadata is only notified once a bank is frozen, after the events of its slot. The
+//! clock sysvar holds the same timestamp and is written when the bank is created, so its
+//! updates are used too. Events of slots with neither seen yet have no block time.
+//!
+//! The timestamp of the newest slot recorded is also kept as the current chain time, sent
+//! as a message header.
+
+use {
+    solana_program::sysvar,
+    std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicI64, Ordering},
+            RwLock,
+        },
+    },
+};
+
+/// Slots kept behind the newest one recorded.
+const RETAINED_SLOTS: u64 = 512;
+
+/// Bincode `Clock`: slot, epoch_start_timestamp, epoch, leader_schedule_epoch and
+/// unix_timestamp, eight bytes each.
+const SLOT_OFFSET: usize = 0;
+const EPOCH_OFFSET: usize = 16;
+const UNIX_TIMESTAMP_OFFSET: usize = 32;
+
+/// Unix timestamp of the newest slot recorded, `i64::MIN` until one is.
+static CHAIN_TIME: AtomicI64 = AtomicI64::new(i64::MIN);
+
+/// Current chain unix timestamp, `None` until a block time or clock update is seen.
+pub fn chain_time() -> Option<i64> {
+    match CHAIN_TIME.load(Ordering::Relaxed) {
+        i64::MIN => None,
+        time => Some(time),
+    }
+}
+
+/// Epoch of a clock sysvar update, `None` for any other account.
+pub fn clock_epoch(pubkey: &[u8], data: &[u8]) -> Option<u64> {
+    clock_field(pubkey, data, EPOCH_OFFSET).map(u64::from_le_bytes)
+}
+
+fn clock_field(pubkey: &[u8], data: &[u8], offset: usize) -> Option<[u8; 8]> {
+    if pubkey != sysvar::clock::id().as_ref() {
+        return None;
+    }
+    data.get(offset..offset + 8)
+        .map(|bytes| bytes.try_into().expect("eight bytes"))
+}
+
+#[derive(Debug, Default)]
+pub struct BlockTimes {
+    times: RwLock<BTreeMap<u64, i64>>,
+}
+
+impl BlockTimes {
+    pub fn record(&self, slot: u64, block_time: i64) {
+        let mut times = self.times.write().unwrap();
+        times.insert(slot, block_time);
+        let newest = *times.keys().next_back().expect("just inserted");
+        if newest == slot {
+            CHAIN_TIME.store(block_time, Ordering::Relaxed);
+        }
+        *times = times.split_off(&newest.saturating_sub(RETAINED_SLOTS));
+    }
+
+    /// Records the timestamp of a clock sysvar update, ignoring any other account.
+    pub fn record_clock(&self, pubkey: &[u8], data: &[u8]) {
+        if let (Some(slot), Some(unix_timestamp)) = (
+            clock_field(pubkey, data, SLOT_OFFSET),
+            clock_field(pubkey, data, UNIX_TIMESTAMP_OFFSET),
+        ) {
+            self.record(u64::from_le_bytes(slot), i64::from_le_bytes(unix_timestamp));
+        }
+    }
+
+    pub fn get(&self, slot: u64) -> Option<i64> {
+        self.times.read().unwrap().get(&slot).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_and_retention() {
+        let block_times = BlockTimes::default();
+        let mut clock = Vec::new();
+        for value in [7u64, 0, 1, 2] {
+            clock.extend_from_slice(&value.to_le_bytes());
+        }
+        clock.extend_from_slice(&1_700_000_000i64.to_le_bytes());
+        block_times.record_clock(&[0; 32], &clock);
+        assert_eq!(block_times.get(7), None);
+        block_times.record_clock(sysvar::clock::id().as_ref(), &clock);
+        assert_eq!(block_times.get(7), Some(1_700_000_000));
+        assert_eq!(clock_epoch(sysvar::clock::id().as_ref(), &clock), Some(1));
+        assert_eq!(chain_time(), Some(1_700_000_000));
+
+        block_times.record(7 + RETAINED_SLOTS + 1, 1_700_000_300);
+        assert_eq!(block_times.get(7), None);
+        assert_eq!(block_times.get(7 + RETAINED_SLOTS + 1), Some(1_700_000_300));
+        // Older slots do not move the chain time back.
+        block_times.record(8, 1_700_000_001);
+        assert_eq!(chain_time(), Some(1_700_000_300));
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Blockhashes of recent slots, stamped onto slot status events.
+//!
+//! Slot status notifications carry no hash, so the blockhash reported with the block metadata
+//! of a slot is kept until its later statuses, such as `confirmed` and `rooted`, are published.
+//! The bank hash is not passed to plugins.
+
+use std::{collections::BTreeMap, sync::RwLock};
+
+/// Slots kept behind the newest one recorded.
+const RETAINED_SLOTS: u64 = 512;
+
+#[derive(Debug, Default)]
+pub struct Blockhashes {
+    hashes: RwLock<BTreeMap<u64, String>>,
+}
+
+impl Blockhashes {
+    pub fn record(&self, slot: u64, blockhash: &str) {
+        let mut hashes = self.hashes.write().unwrap();
+        hashes.insert(slot, blockhash.to_owned());
+        let newest = *hashes.keys().next_back().expect("just inserted");
+        *hashes = hashes.split_off(&newest.saturating_sub(RETAINED_SLOTS));
+    }
+
+    /// Base58 blockhash of a slot, `None` until its block metadata is seen.
+    pub fn get(&self, slot: u64) -> Option<String> {
+        self.hashes.read().unwrap().get(&slot).cloned()
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Republishing the latest state of every filtered account to a bootstrap topic on demand, so
+//! that new consumers can start from current state without waiting for updates.
+
+use {
+    crate::{
+        account_state::AccountStates, prom::BOOTSTRAP_ACCOUNTS_TOTAL, BootstrapMarkerEvent,
+        PublishError, Publisher, UpdateAccountEvent,
+    },
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    tracing::{error, info, warn},
+};
+
+/// Set by the admin API, consumed by the bootstrap thread.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Whether a bootstrap thread is running to take requests.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Pause before publishing again into a full queue.
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Asks for the state to be republished, `false` if no bootstrap topic is configured.
+pub fn request() -> bool {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return false;
+    }
+    REQUESTED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Background thread republishing the state when requested.
+#[derive(Debug)]
+pub struct Bootstrapper {
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Bootstrapper {
+    pub fn new(
+        states: Arc<AccountStates>,
+        publisher: Arc<Publisher>,
+        account_data_limit: Option<usize>,
+    ) -> Self {
+        REQUESTED.store(false, Ordering::Relaxed);
+        ENABLED.store(true, Ordering::Relaxed);
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaBootstrap".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(CHECK_INTERVAL) {
+                    if REQUESTED.swap(false, Ordering::Relaxed) {
+                        publish(&states, &publisher, account_data_limit, &stopped);
+                    }
+                }
+            })
+            .expect("failed to spawn bootstrap thread");
+        Self {
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Publishes every account held, in pubkey order and between a begin and an end marker, until
+/// done or stopped.
+fn publish(
+    states: &AccountStates,
+    publisher: &Publisher,
+    account_data_limit: Option<usize>,
+    stopped: &mpsc::Receiver<()>,
+) {
+    let mut accounts = states.snapshot();
+    accounts.sort_unstable_by_key(|account| account.pubkey);
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let slot = accounts
+        .iter()
+        .map(|account| account.slot)
+        .max()
+        .unwrap_or_default();
+    info!(accounts = accounts.len(), slot, "bootstrap publish started");
+    let begin = BootstrapMarkerEvent {
+        started_at,
+        end: false,
+        slot,
+        accounts: accounts.len() as u64,
+    };
+    match retry(stopped, || publisher.bootstrap_marker(begin.clone())) {
+        Some(Ok(())) => {}
+        Some(Err(error)) => warn!(%error, "failed to publish bootstrap begin marker"),
+        None => {
+            warn!("bootstrap publish interrupted by unload");
+            return;
+        }
+    }
+    let (mut published, mut failed) = (0u64, 0u64);
+    for account in accounts {
+        let data = match account_data_limit {
+            Some(limit) => &account.data[..account.data.len().min(limit)],
+            None => &account.data[..],
+        };
+        let event = UpdateAccountEvent {
+            slot: account.slot,
+            pubkey: account.pubkey.to_vec(),
+            lamports: account.lamports,
+            owner: account.owner.to_vec(),
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            data: data.to_vec(),
+            write_version: account.write_version,
+            txn_signature: Vec::new(),
+            block_time: None,
+            rent_exempt: account.rent_exempt,
+            previous_owner: Vec::new(),
+        };
+        match retry(stopped, || publisher.bootstrap_account(event.clone())) {
+            Some(Ok(())) => {
+                published += 1;
+                BOOTSTRAP_ACCOUNTS_TOTAL
+                    .with_label_values(&["success"])
+                    .inc();
+            }
+            Some(Err(error)) => {
+                failed += 1;
+                warn!(%error, "failed to publish bootstrap account");
+                BOOTSTRAP_ACCOUNTS_TOTAL
+                    .with_label_values(&["failed"])
+                    .inc();
+            }
+            None => {
+                warn!(published, "bootstrap publish interrupted by unload");
+                return;
+            }
+        }
+    }
+    let end = BootstrapMarkerEvent {
+        started_at,
+        end: true,
+        slot,
+        accounts: published,
+    };
+    match retry(stopped, || publisher.bootstrap_marker(end.clone())) {
+        Some(Ok(())) => {}
+        Some(Err(error)) => warn!(%error, "failed to publish bootstrap end marker"),
+        None => warn!("bootstrap end marker interrupted by unload"),
+    }
+    info!(published, failed, "bootstrap publish finished");
+}
+
+/// Publishes again while the queue is full, `None` if stopped meanwhile.
+fn retry(
+    stopped: &mpsc::Receiver<()>,
+    mut publish: impl FnMut() -> Result<(), PublishError>,
+) -> Option<Result<(), PublishError>> {
+    loop {
+        match publish() {
+            Err(error) if error.is_retriable() => {
+                if !matches!(
+                    stopped.recv_timeout(RETRY_INTERVAL),
+                    Err(RecvTimeoutError::Timeout)
+                ) {
+                    return None;
+                }
+            }
+            result => return Some(result),
+        }
+    }
+}
+
+impl Drop for Bootstrapper {
+    fn drop(&mut self) {
+        ENABLED.store(false, Ordering::Relaxed);
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("bootstrap thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            config::Config,
+            producer::{MemoryProducer, ProducerError},
+            replica::AccountInfo,
+        },
+        prost::Message,
+    };
+
+    fn states() -> AccountStates {
+        let states = AccountStates::new(None, true);
+        for (pubkey, slot) in [([2; 32], 6), ([1; 32], 5)] {
+            let info = AccountInfo {
+                pubkey: &pubkey,
+                lamports: 10,
+                owner: &[0; 32],
+                executable: false,
+                rent_epoch: 0,
+                data: &[1, 2, 3],
+                write_version: 1,
+                txn_signature: None,
+            };
+            states.record(&info, info.data, slot, true);
+        }
+        states
+    }
+
+    fn publisher() -> (Publisher, MemoryProducer) {
+        let producer = MemoryProducer::default();
+        let config = Config {
+            bootstrap_topic: "bootstrap".to_owned(),
+            ..Config::default()
+        };
+        let publisher = Publisher::new(Some(Box::new(producer.clone())), None, &config);
+        (publisher, producer)
+    }
+
+    #[test]
+    fn test_publish() {
+        let (publisher, producer) = publisher();
+        producer.set_partition_count(Some(2));
+        let (_stop, stopped) = mpsc::channel();
+        publish(&states(), &publisher, Some(2), &stopped);
+
+        let records = producer.records();
+        assert_eq!(records.len(), 6);
+        assert!(records.iter().all(|record| record.topic == "bootstrap"));
+        let marker = |index: usize, key: &[u8], partition| {
+            let record = &records[index];
+            assert_eq!(record.key.as_deref(), Some(key));
+            assert_eq!(record.partition, Some(partition));
+            BootstrapMarkerEvent::decode(&record.payload[..]).unwrap()
+        };
+        let begin = marker(0, b"bootstrap-begin", 0);
+        assert_eq!(marker(1, b"bootstrap-begin", 1), begin);
+        assert!(!begin.end);
+        assert_eq!(begin.slot, 6);
+        assert_eq!(begin.accounts, 2);
+
+        let accounts = records[2..4]
+            .iter()
+            .map(|record| UpdateAccountEvent::decode(&record.payload[..]).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(accounts[0].pubkey, [1; 32]);
+        assert_eq!(accounts[0].slot, 5);
+        assert_eq!(accounts[0].data, [1, 2]);
+        assert_eq!(accounts[1].pubkey, [2; 32]);
+        assert_eq!(accounts[1].slot, 6);
+
+        let end = marker(4, b"bootstrap-end", 0);
+        assert_eq!(marker(5, b"bootstrap-end", 1), end);
+        assert!(end.end);
+        assert_eq!(end.started_at, begin.started_at);
+        assert_eq!(end.accounts, 2);
+    }
+
+    #[test]
+    fn test_unknown_partition_count() {
+        let (publisher, producer) = publisher();
+        let (_stop, stopped) = mpsc::channel();
+        publish(&AccountStates::new(None, true), &publisher, None, &stopped);
+
+        let records = producer.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].key.as_deref(), Some(&b"bootstrap-begin"[..]));
+        assert_eq!(records[0].partition, None);
+        assert_eq!(records[1].key.as_deref(), Some(&b"bootstrap-end"[..]));
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let (publisher, producer) = publisher();
+        producer.fail_sends(Some(ProducerError::QueueFull));
+        let (stop, stopped) = mpsc::channel();
+        stop.send(()).unwrap();
+        publish(&states(), &publisher, None, &stopped);
+        producer.fail_sends(None);
+        assert!(producer.records().is_empty());
+    }
+}
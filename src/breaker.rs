@@ -0,0 +1,247 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Circuit breaker skipping publishes while most of them fail, so that the validator does not
+//! spend CPU encoding events that cannot be delivered. After `open_ms` a few probe events are
+//! let through, and publishing resumes once they are delivered.
+
+use {
+    crate::{
+        config::CircuitBreakerConfig,
+        prom::{CIRCUIT_BREAKER_STATE, CIRCUIT_BREAKER_TRANSITIONS_TOTAL},
+    },
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    },
+    tracing::{info, warn},
+};
+
+lazy_static::lazy_static! {
+    pub static ref BREAKER: CircuitBreaker = CircuitBreaker::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    HalfOpen,
+}
+
+impl State {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open { .. } => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+
+    /// Value of the `circuit_breaker_state` gauge.
+    fn gauge(&self) -> i64 {
+        match self {
+            Self::Closed => 0,
+            Self::Open { .. } => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    /// Whether a breaker is configured, checked first on every event.
+    enabled: AtomicBool,
+    inner: Mutex<Option<Breaker>>,
+}
+
+impl CircuitBreaker {
+    pub fn configure(&self, config: &CircuitBreakerConfig) {
+        *self.inner.lock().unwrap() = Some(Breaker::new(config.clone(), Instant::now()));
+        CIRCUIT_BREAKER_STATE.set(State::Closed.gauge());
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        *self.inner.lock().unwrap() = None;
+    }
+
+    /// Whether an event may be published.
+    pub fn allow(&self) -> bool {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return true;
+        }
+        match self.inner.lock().unwrap().as_mut() {
+            Some(breaker) => breaker.allow(Instant::now()),
+            None => true,
+        }
+    }
+
+    /// Records the outcome of a publish, on enqueue failure or delivery.
+    pub fn record(&self, success: bool) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(breaker) = self.inner.lock().unwrap().as_mut() {
+            breaker.record(success, Instant::now());
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Breaker {
+    config: CircuitBreakerConfig,
+    state: State,
+    window_start: Instant,
+    successes: u64,
+    failures: u64,
+    /// Probes let through while half-open.
+    probes_sent: u32,
+    probes_delivered: u32,
+}
+
+impl Breaker {
+    fn new(config: CircuitBreakerConfig, now: Instant) -> Self {
+        Self {
+            config,
+            state: State::Closed,
+            window_start: now,
+            successes: 0,
+            failures: 0,
+            probes_sent: 0,
+            probes_delivered: 0,
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            State::Closed => true,
+            State::Open { until } if now < until => false,
+            State::Open { .. } => {
+                self.transition(State::HalfOpen, now);
+                self.allow(now)
+            }
+            State::HalfOpen if self.probes_sent < self.config.probes => {
+                self.probes_sent += 1;
+                true
+            }
+            State::HalfOpen => false,
+        }
+    }
+
+    fn record(&mut self, success: bool, now: Instant) {
+        match self.state {
+            State::Closed => {
+                if now.duration_since(self.window_start)
+                    > Duration::from_millis(self.config.window_ms)
+                {
+                    self.window_start = now;
+                    self.successes = 0;
+                    self.failures = 0;
+                }
+                match success {
+                    true => self.successes += 1,
+                    false => self.failures += 1,
+                }
+                let total = self.successes + self.failures;
+                if total >= self.config.min_requests
+                    && self.failures as f64 >= self.config.error_rate * total as f64
+                {
+                    warn!(
+                        failures = self.failures,
+                        total, "circuit breaker open, skipping publishes"
+                    );
+                    self.open(now);
+                }
+            }
+            // Outcomes of events sent before the breaker opened.
+            State::Open { .. } => {}
+            State::HalfOpen if !success => {
+                warn!("circuit breaker probe failed, skipping publishes again");
+                self.open(now);
+            }
+            State::HalfOpen => {
+                self.probes_delivered += 1;
+                if self.probes_delivered >= self.config.probes {
+                    info!("circuit breaker closed, publishing resumed");
+                    self.transition(State::Closed, now);
+                }
+            }
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        let until = now + Duration::from_millis(self.config.open_ms);
+        self.transition(State::Open { until }, now);
+    }
+
+    fn transition(&mut self, state: State, now: Instant) {
+        if state == State::HalfOpen {
+            info!("circuit breaker half-open, probing");
+        }
+        self.state = state;
+        self.window_start = now;
+        self.successes = 0;
+        self.failures = 0;
+        self.probes_sent = 0;
+        self.probes_delivered = 0;
+        CIRCUIT_BREAKER_STATE.set(state.gauge());
+        CIRCUIT_BREAKER_TRANSITIONS_TOTAL
+            .with_label_values(&[state.as_str()])
+            .inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker() {
+        let config = CircuitBreakerConfig {
+            window_ms: 10_000,
+            min_requests: 4,
+            error_rate: 0.5,
+            open_ms: 1_000,
+            probes: 2,
+        };
+        let start = Instant::now();
+        let mut breaker = Breaker::new(config, start);
+        breaker.record(true, start);
+        breaker.record(false, start);
+        breaker.record(true, start);
+        assert!(breaker.allow(start));
+        breaker.record(false, start);
+        assert!(!breaker.allow(start));
+
+        let later = start + Duration::from_millis(1_001);
+        assert!(breaker.allow(later));
+        assert!(breaker.allow(later));
+        assert!(!breaker.allow(later));
+        breaker.record(true, later);
+        breaker.record(false, later);
+        assert!(!breaker.allow(later));
+
+        let later = later + Duration::from_millis(1_001);
+        assert!(breaker.allow(later));
+        assert!(breaker.allow(later));
+        breaker.record(true, later);
+        breaker.record(true, later);
+        assert_eq!(breaker.state, State::Closed);
+        assert!(breaker.allow(later));
+    }
+}
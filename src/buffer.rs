@@ -0,0 +1,55 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-thread encode buffers, reused across events instead of allocating one per payload.
+//!
+//! The producer copies payloads into its own queue, so a buffer is free again as soon as
+//! the event is enqueued. Buffers grown by an occasional large account are shrunk back
+//! towards the size of recent payloads.
+
+use std::cell::RefCell;
+
+/// Capacity kept regardless of recent payload sizes.
+const MIN_RETAINED: usize = 64 * 1024;
+
+#[derive(Default)]
+struct Pool {
+    buffers: Vec<Vec<u8>>,
+    /// Moving average of recent payload sizes.
+    recent_len: usize,
+}
+
+thread_local! {
+    static POOL: RefCell<Pool> = RefCell::new(Pool::default());
+}
+
+/// Runs `f` with an empty buffer from the calling thread's pool.
+pub fn with_buffer<R>(f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+    let mut buf = POOL.with(|pool| pool.borrow_mut().buffers.pop().unwrap_or_default());
+    let result = f(&mut buf);
+    POOL.with(|pool| pool.borrow_mut().put(buf));
+    result
+}
+
+impl Pool {
+    fn put(&mut self, mut buf: Vec<u8>) {
+        self.recent_len = (self.recent_len * 7 + buf.len()) / 8;
+        buf.clear();
+        let retained = MIN_RETAINED.max(self.recent_len * 4);
+        if buf.capacity() > retained {
+            buf.shrink_to(retained);
+        }
+        self.buffers.push(buf);
+    }
+}
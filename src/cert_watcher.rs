@@ -0,0 +1,179 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::watchdog,
+    std::{
+        collections::HashMap,
+        fs,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, SystemTime},
+    },
+    tracing::{error, info, warn},
+};
+
+/// librdkafka settings naming files read when the producer is created.
+const CERT_SETTINGS: &[&str] = &[
+    "ssl.certificate.location",
+    "ssl.key.location",
+    "ssl.ca.location",
+    "ssl.keystore.location",
+];
+
+/// Modification time and length of a file, `None` while it is missing.
+type Version = Option<(SystemTime, u64)>;
+
+fn version(path: &str) -> Version {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Versions of the watched files, deciding when the producer has to be recreated.
+#[derive(Debug)]
+struct Versions {
+    /// Versions the current producer was created with.
+    loaded: Vec<Version>,
+    /// Versions seen on the previous check.
+    previous: Vec<Version>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Change {
+    None,
+    Missing,
+    Recreate,
+}
+
+impl Versions {
+    fn new(current: Vec<Version>) -> Self {
+        Self {
+            loaded: current.clone(),
+            previous: current,
+        }
+    }
+
+    fn check(&mut self, current: Vec<Version>) -> Change {
+        // Certificate and key are often written one after the other, wait until neither
+        // changed for a whole interval.
+        let mut change = Change::None;
+        if current != self.loaded && current == self.previous {
+            if current.iter().any(Option::is_none) {
+                change = Change::Missing;
+            } else {
+                self.loaded = current.clone();
+                change = Change::Recreate;
+            }
+        }
+        self.previous = current;
+        change
+    }
+}
+
+/// Background thread recreating the producer when its TLS certificate or key files change,
+/// so that rotated short-lived certificates are picked up without a restart.
+#[derive(Debug)]
+pub struct CertWatcher {
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CertWatcher {
+    /// Returns `None` if the Kafka config names no certificate files.
+    pub fn new(kafka: &HashMap<String, String>, interval: Duration) -> Option<Self> {
+        let paths = CERT_SETTINGS
+            .iter()
+            .filter_map(|setting| kafka.get(*setting).cloned())
+            .collect::<Vec<_>>();
+        if paths.is_empty() || interval.is_zero() {
+            return None;
+        }
+
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaCertWatcher".to_owned())
+            .spawn(move || {
+                let current = || paths.iter().map(|path| version(path)).collect::<Vec<_>>();
+                let mut versions = Versions::new(current());
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match versions.check(current()) {
+                        Change::None => {}
+                        Change::Missing => {
+                            warn!(?paths, "certificate file missing, keeping current producer")
+                        }
+                        Change::Recreate => {
+                            info!(?paths, "certificate files changed, recreating producer");
+                            watchdog::request_recreate();
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn certificate watcher thread");
+        Some(Self {
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for CertWatcher {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("certificate watcher thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::time::UNIX_EPOCH};
+
+    fn at(secs: u64) -> Version {
+        Some((UNIX_EPOCH + Duration::from_secs(secs), 100))
+    }
+
+    #[test]
+    fn test_recreate_once_settled() {
+        let mut versions = Versions::new(vec![at(1), at(1)]);
+        assert_eq!(versions.check(vec![at(1), at(1)]), Change::None);
+        // The certificate is written, then the key.
+        assert_eq!(versions.check(vec![at(2), at(1)]), Change::None);
+        assert_eq!(versions.check(vec![at(2), at(3)]), Change::None);
+        assert_eq!(versions.check(vec![at(2), at(3)]), Change::Recreate);
+        assert_eq!(versions.check(vec![at(2), at(3)]), Change::None);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let mut versions = Versions::new(vec![at(1)]);
+        assert_eq!(versions.check(vec![None]), Change::None);
+        assert_eq!(versions.check(vec![None]), Change::Missing);
+        assert_eq!(versions.check(vec![at(2)]), Change::None);
+        assert_eq!(versions.check(vec![at(2)]), Change::Recreate);
+    }
+
+    #[test]
+    fn test_no_certificates() {
+        let kafka = HashMap::from([("bootstrap.servers".to_owned(), "kafka:9092".to_owned())]);
+        assert!(CertWatcher::new(&kafka, Duration::from_secs(1)).is_none());
+        let kafka = HashMap::from([("ssl.ca.location".to_owned(), "ca.pem".to_owned())]);
+        assert!(CertWatcher::new(&kafka, Duration::ZERO).is_none());
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        config::CheckpointConfig,
+        watermark::{Position, WATERMARKS},
+        *,
+    },
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    tracing::{error, info, warn},
+};
+
+/// Background thread publishing the highest fully delivered slot of each topic to a
+/// compacted topic, so that consumers can tell up to where the stream is complete.
+#[derive(Debug)]
+pub struct Checkpointer {
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    pub fn new(publisher: Arc<Publisher>, config: &CheckpointConfig) -> Self {
+        let interval = Duration::from_millis(config.interval_ms);
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaCheckpoints".to_owned())
+            .spawn(move || {
+                let mut published = HashMap::new();
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    checkpoint(&publisher, &mut published, WATERMARKS.snapshot());
+                }
+            })
+            .expect("failed to spawn checkpoint thread");
+        info!(topic = %config.topic, interval_ms = config.interval_ms, "publishing checkpoints");
+        Self {
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Publishes the checkpoints that advanced since the last call. A mark moving back, e.g. after
+/// the watermarks were restored from an older store, is not published, consumers would take it
+/// for a gap.
+fn checkpoint(
+    publisher: &Publisher,
+    published: &mut HashMap<String, u64>,
+    marks: BTreeMap<String, Position>,
+) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    for (topic, mark) in marks {
+        let slot = match mark.complete_slot() {
+            Some(slot) => slot,
+            None => continue,
+        };
+        if matches!(published.get(&topic), Some(last) if slot <= *last) {
+            continue;
+        }
+        let event = CheckpointEvent {
+            topic: topic.clone(),
+            slot,
+            timestamp,
+        };
+        match publisher.update_checkpoint(event) {
+            Ok(()) => {
+                published.insert(topic, slot);
+            }
+            Err(error) => warn!(%topic, slot, %error, "failed to publish checkpoint"),
+        }
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("checkpoint thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::producer::MemoryProducer, prost::Message};
+
+    fn marks(slots: &[(&str, u64)]) -> BTreeMap<String, Position> {
+        slots
+            .iter()
+            .map(|(topic, slot)| {
+                let position = Position {
+                    slot: *slot,
+                    write_version: u64::MAX,
+                };
+                (topic.to_string(), position)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_only_advancing_checkpoints() {
+        let producer = MemoryProducer::default();
+        let config = Config {
+            checkpoints: Some(CheckpointConfig {
+                topic: "checkpoints".to_owned(),
+                interval_ms: 1000,
+            }),
+            ..Config::default()
+        };
+        let publisher = Publisher::new(Some(Box::new(producer.clone())), None, &config);
+        let mut published = HashMap::new();
+
+        checkpoint(&publisher, &mut published, marks(&[("a", 5), ("b", 3)]));
+        checkpoint(&publisher, &mut published, marks(&[("a", 5), ("b", 4)]));
+        checkpoint(&publisher, &mut published, marks(&[("a", 4), ("b", 4)]));
+        checkpoint(&publisher, &mut published, marks(&[("a", 6), ("b", 4)]));
+
+        let checkpoints = producer
+            .records()
+            .iter()
+            .map(|record| {
+                assert_eq!(record.topic, "checkpoints");
+                let event = CheckpointEvent::decode(&record.payload[..]).unwrap();
+                assert_eq!(record.key.as_deref(), Some(event.topic.as_bytes()));
+                (event.topic, event.slot)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            checkpoints,
+            [
+                ("a".to_owned(), 5),
+                ("b".to_owned(), 3),
+                ("b".to_owned(), 4),
+                ("a".to_owned(), 6)
+            ]
+        );
+    }
+}
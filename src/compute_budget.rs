@@ -0,0 +1,90 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compute unit limit and price requested by a transaction's ComputeBudget instructions.
+
+use solana_program::pubkey::Pubkey;
+
+const PROGRAM_ID: &str = "ComputeBudget111111111111111111111111111111";
+
+/// Borsh variants of `ComputeBudgetInstruction`.
+const SET_COMPUTE_UNIT_LIMIT: u8 = 2;
+const SET_COMPUTE_UNIT_PRICE: u8 = 3;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ComputeBudget {
+    pub compute_unit_limit: Option<u32>,
+    /// In micro-lamports per compute unit.
+    pub compute_unit_price: Option<u64>,
+}
+
+impl ComputeBudget {
+    /// Reads the instructions of the transaction, as (program, data) pairs. The last instruction
+    /// of each kind wins, transactions with duplicates fail anyway.
+    pub fn parse<'a>(instructions: impl Iterator<Item = (&'a Pubkey, &'a [u8])>) -> Self {
+        let program_id = PROGRAM_ID.parse::<Pubkey>().expect("valid program id");
+        let mut budget = Self::default();
+        for (program, data) in instructions {
+            if *program != program_id {
+                continue;
+            }
+            match data.split_first() {
+                Some((&SET_COMPUTE_UNIT_LIMIT, value)) => {
+                    if let Some(value) = value.get(..4) {
+                        budget.compute_unit_limit =
+                            Some(u32::from_le_bytes(value.try_into().expect("four bytes")));
+                    }
+                }
+                Some((&SET_COMPUTE_UNIT_PRICE, value)) => {
+                    if let Some(value) = value.get(..8) {
+                        budget.compute_unit_price =
+                            Some(u64::from_le_bytes(value.try_into().expect("eight bytes")));
+                    }
+                }
+                _ => {}
+            }
+        }
+        budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let compute_budget = PROGRAM_ID.parse::<Pubkey>().unwrap();
+        let other = Pubkey::new_unique();
+        let limit = [&[2][..], &200_000u32.to_le_bytes()].concat();
+        let price = [&[3][..], &1_000u64.to_le_bytes()].concat();
+        let instructions = [
+            (&other, &price[..]),
+            (&compute_budget, &limit[..]),
+            (&compute_budget, &price[..]),
+            (&compute_budget, &[1, 0, 0, 4, 0][..]),
+        ];
+        assert_eq!(
+            ComputeBudget::parse(instructions.iter().copied()),
+            ComputeBudget {
+                compute_unit_limit: Some(200_000),
+                compute_unit_price: Some(1_000),
+            }
+        );
+        assert_eq!(
+            ComputeBudget::parse(instructions[..1].iter().copied()),
+            ComputeBudget::default()
+        );
+    }
+}
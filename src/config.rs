@@ -13,37 +13,178 @@
 // limitations under the License.
 
 use {
-    crate::{prom::StatsThreadedProducerContext, PrometheusService},
-    rdkafka::{
-        config::FromClientConfigAndContext,
-        error::KafkaResult,
-        producer::{DefaultProducerContext, ThreadedProducer},
-        ClientConfig,
+    crate::{
+        event_hubs,
+        headers::HeaderTemplates,
+        health::HEALTH,
+        network,
+        producer::{ProducerError, ProducerLike},
+        prom::PrometheusAddress,
+        remote, PrometheusService, SlotStatus,
     },
     serde::Deserialize,
     solana_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPluginError, Result as PluginResult,
     },
-    std::{collections::HashMap, fs::File, io::Result as IoResult, net::SocketAddr, path::Path},
+    solana_sdk::{
+        pubkey::Pubkey,
+        signature::{read_keypair_file, Signer},
+    },
+    std::{
+        collections::{HashMap, HashSet},
+        fs::File,
+        io::Result as IoResult,
+        mem,
+        net::SocketAddr,
+        ops::Range,
+        path::{Path, PathBuf},
+        str::FromStr,
+        time::Duration,
+    },
+};
+#[cfg(feature = "rdkafka-backend")]
+use {
+    crate::{failover, prom::StatsThreadedProducerContext},
+    rdkafka::{
+        config::FromClientConfigAndContext,
+        producer::{DefaultProducerContext, ThreadedProducer},
+        ClientConfig,
+    },
 };
 
+/// Fields of the config file read by the validator rather than the plugin.
+const VALIDATOR_FIELDS: &[&str] = &["libpath", "name"];
+
 /// Plugin config.
 #[derive(Deserialize)]
 pub struct Config {
+    /// Fail to load on unknown fields instead of ignoring them.
+    #[serde(default)]
+    pub strict: bool,
+    /// Unknown fields found in the config file.
+    #[serde(skip)]
+    pub unknown_fields: Vec<String>,
     /// Kafka config.
     pub kafka: HashMap<String, String>,
+    /// Kafka client library to publish with.
+    #[serde(default)]
+    pub backend: Backend,
+    /// How keyed records are assigned to partitions, unless `kafka` sets `partitioner`.
+    #[serde(default)]
+    pub partitioner: Partitioner,
+    /// Record key of account updates.
+    #[serde(default)]
+    pub account_key: AccountKey,
+    /// Broker groups in order of preference, replacing `bootstrap.servers`.
+    #[serde(default)]
+    pub broker_failover: Option<BrokerFailoverConfig>,
     /// Graceful shutdown timeout.
     #[serde(default)]
     pub shutdown_timeout_ms: u64,
+    /// Network name replacing `{network}` in topic names, detected from `ledger_path` if unset.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Validator ledger, whose genesis hash identifies the network.
+    #[serde(default)]
+    pub ledger_path: Option<String>,
+    /// Validator identity keypair file, whose pubkey is sent as the `validator-identity` header.
+    #[serde(default)]
+    pub identity_keypair: Option<String>,
+    /// Send the current chain unix timestamp as the `chain-timestamp` header.
+    #[serde(default)]
+    pub chain_time_header: bool,
+    /// Headers sent with every event as routing hints for Kafka Connect sinks, by name. Values
+    /// may contain `{event}`, `{topic}`, `{slot}`, `{pubkey}`, `{owner}` and `{network}`.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Base58 pubkey read from `identity_keypair`.
+    #[serde(skip)]
+    pub identity: Option<String>,
     /// Kafka topic to send account updates to.
     #[serde(default)]
     pub update_account_topic: String,
+    /// Topics for account updates with small data, `update_account_topic` receives the rest.
+    #[serde(default)]
+    pub account_size_topics: Vec<SizeTopic>,
+    /// Skip updates of accounts that are not rent-exempt, closed accounts excepted.
+    #[serde(default)]
+    pub rent_exempt_only: bool,
+    /// Track account owners to report the previous owner on owner changes.
+    #[serde(default)]
+    pub previous_owner: bool,
     /// Kafka topic to send slot status updates to.
     #[serde(default)]
     pub slot_status_topic: String,
+    /// How slot status updates are assigned to partitions.
+    #[serde(default)]
+    pub slot_partitioning: SlotPartitioning,
+    /// Slot statuses to publish.
+    #[serde(default = "Config::default_slot_statuses")]
+    pub slot_statuses: Vec<SlotStatus>,
     /// Kafka topic to send transaction to.
     #[serde(default)]
     pub transaction_topic: String,
+    /// Kafka topic to send failed transactions to instead, if set.
+    #[serde(default)]
+    pub transaction_failed_topic: String,
+    /// Publish transactions of each program in `program_filters` to
+    /// `{transaction_topic}-{program}` instead.
+    #[serde(default)]
+    pub transaction_topic_per_program: bool,
+    /// Transaction topics by program, taking precedence over `transaction_topic_per_program`.
+    #[serde(default)]
+    pub transaction_program_topics: HashMap<String, String>,
+    /// Kafka topic to send transaction signatures and outcomes to.
+    #[serde(default)]
+    pub signature_topic: String,
+    /// Kafka topic to send the instructions of transactions to, one message per instruction.
+    #[serde(default)]
+    pub instruction_topic: String,
+    /// Kafka topic to send program deployments and upgrades to.
+    #[serde(default)]
+    pub program_upgrade_topic: String,
+    /// Kafka topic to announce plugin startups to.
+    #[serde(default)]
+    pub ops_topic: String,
+    /// Kafka topic to send block rewards to.
+    #[serde(default)]
+    pub rewards_topic: String,
+    /// Kafka topic to republish the latest state of all filtered accounts to on request.
+    #[serde(default)]
+    pub bootstrap_topic: String,
+    /// Message encoding.
+    #[serde(default)]
+    pub format: Format,
+    /// Encoding of account and instruction data in JSON messages.
+    #[serde(default)]
+    pub data_encoding: DataEncoding,
+    /// Compression of whole JSON messages.
+    #[serde(default)]
+    pub json_compression: JsonCompression,
+    /// Per-topic settings.
+    #[serde(default)]
+    pub topics: HashMap<String, TopicConfig>,
+    /// Transform applied to events before publishing.
+    #[serde(default)]
+    pub transform: Option<TransformConfig>,
+    /// Kafka topic to send decoded token accounts to.
+    #[serde(default)]
+    pub token_account_topic: String,
+    /// Publish only token accounts with an active delegation to `token_account_topic`.
+    #[serde(default)]
+    pub token_delegate_filter: Option<TokenDelegateFilterConfig>,
+    /// Kafka topic to send decoded stake accounts to.
+    #[serde(default)]
+    pub stake_account_topic: String,
+    /// Kafka topic to send decoded vote accounts to.
+    #[serde(default)]
+    pub vote_account_topic: String,
+    /// Also publish decoded token, stake and vote accounts to `update_account_topic`.
+    #[serde(
+        default = "Config::default_decoded_accounts_raw",
+        alias = "token_account_raw"
+    )]
+    pub decoded_accounts_raw: bool,
     /// List of programs to ignore.
     #[serde(default)]
     pub program_ignores: Vec<String>,
@@ -53,27 +194,234 @@ pub struct Config {
     // List of accounts to include
     #[serde(default)]
     pub account_filters: Vec<String>,
+    /// JSON files holding arrays of programs merged into `program_filters`, relative to the
+    /// config file.
+    #[serde(default)]
+    pub program_filters_files: Vec<PathBuf>,
+    /// JSON files holding arrays of accounts merged into `account_filters`, relative to the
+    /// config file.
+    #[serde(default)]
+    pub account_filters_files: Vec<PathBuf>,
+    /// Account filter groups, each matching on owner, pubkey and data.
+    #[serde(default)]
+    pub filters: Vec<AccountFilterConfig>,
+    /// Filter section fetched from a URL at startup and polled for changes.
+    #[serde(default)]
+    pub remote_filters: Option<RemoteFiltersConfig>,
+    /// Local filter lists and ETag of the remote filter section applied at load.
+    #[serde(skip)]
+    pub remote_filters_loaded: Option<remote::Loaded>,
+    /// Write Parquet snapshots of the filtered accounts to S3 at every epoch boundary.
+    #[serde(default)]
+    pub snapshots: Option<SnapshotConfig>,
+    /// Bound the latest-state cache of snapshots, bootstrapping and owner changes.
+    #[serde(default)]
+    pub account_states: Option<AccountStatesConfig>,
     /// Publish all accounts on startup.
     #[serde(default)]
     pub publish_all_accounts: bool,
     /// Prometheus endpoint.
     #[serde(default)]
     pub prometheus: Option<SocketAddr>,
+    /// Unix socket path to serve the Prometheus endpoint on, instead of `prometheus`.
+    #[serde(default)]
+    pub prometheus_socket: Option<PathBuf>,
+    /// Filter and encode events but do not publish them, to measure plugin overhead.
+    #[serde(default)]
+    pub null_sink: bool,
+    /// Run the full publishing pipeline but count the messages instead of sending them.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Check events against the `max.message.bytes` of their topics before sending.
+    #[serde(default)]
+    pub oversize: Option<OversizeConfig>,
+    /// Publish through a Confluent REST Proxy instead of connecting to the brokers.
+    #[serde(default)]
+    pub rest_proxy: Option<RestProxyConfig>,
+    /// Publish on a ZeroMQ PUB socket instead of to Kafka.
+    #[serde(default)]
+    pub zeromq: Option<ZeroMqConfig>,
+    /// Publish to an MQTT 5 broker instead of to Kafka.
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    /// Publish at most this many bytes of account data, 0 to omit it.
+    #[serde(default)]
+    pub account_data_limit: Option<usize>,
+    /// Log startup replay progress every this many accounts, 0 to disable.
+    #[serde(default = "Config::default_startup_log_interval")]
+    pub startup_log_interval: u64,
+    /// Expected number of accounts in the snapshot, used to estimate startup completion.
+    #[serde(default)]
+    pub startup_expected_accounts: Option<u64>,
+    /// Report unhealthy on `/health` once publishing has been failing this long.
+    #[serde(default = "Config::default_health_failure_window_ms")]
+    pub health_failure_window_ms: u64,
+    /// Act on sustained publish failure or queue growth.
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    /// Skip publishing while most publishes fail.
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Rebuild the producer after fatal librdkafka errors.
+    #[serde(default)]
+    pub producer_recovery: ProducerRecoveryConfig,
+    /// Check TLS certificate and key files for changes this often, 0 to disable.
+    #[serde(default = "Config::default_cert_reload_interval_ms")]
+    pub cert_reload_interval_ms: u64,
+    /// Export the busiest owner programs as metrics.
+    #[serde(default)]
+    pub top_programs: Option<TopProgramsConfig>,
+    /// Encode and enqueue events on worker threads instead of the validator's.
+    #[serde(default)]
+    pub workers: Option<WorkersConfig>,
+    /// Cap on the bytes of events held in the worker queues and the pause buffer, 0 for no cap.
+    #[serde(default)]
+    pub max_buffered_bytes: usize,
+    /// Publish only this instance's share of the events.
+    #[serde(default)]
+    pub shard: Option<ShardConfig>,
+    /// Limit the updates published per account and second.
+    #[serde(default)]
+    pub account_rate_limit: Option<AccountRateLimitConfig>,
+    /// Persist delivered account update positions and skip them after a restart.
+    #[serde(default)]
+    pub watermark: Option<WatermarkConfig>,
+    /// Publish the highest delivered slot of each topic to a checkpoint topic.
+    #[serde(default)]
+    pub checkpoints: Option<CheckpointConfig>,
+    /// Publish the compiled event schemas to a meta topic on startup.
+    #[serde(default)]
+    pub schemas: Option<SchemasConfig>,
+    /// Hold live events until `snapshot-backfill` announces the slot its accounts cover, then
+    /// publish only those of later slots.
+    #[serde(default)]
+    pub handoff: Option<HandoffConfig>,
+    /// Serve the Prometheus endpoint over TLS.
+    #[serde(default)]
+    pub prometheus_tls: Option<PrometheusTls>,
+    /// Require authentication on the Prometheus endpoint.
+    #[serde(default)]
+    pub prometheus_auth: Option<PrometheusAuth>,
+    /// Prefix of all metric names, e.g. `geyser_kafka`.
+    #[serde(default)]
+    pub prometheus_namespace: Option<String>,
+    /// Labels added to all metrics, e.g. the cluster and validator.
+    #[serde(default)]
+    pub prometheus_labels: HashMap<String, String>,
+    /// Serve the admin API on the Prometheus endpoint.
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// Signal on which to log a dump of the plugin's state.
+    #[serde(default)]
+    pub dump_signal: Option<DumpSignal>,
+    /// Log a summary of one in every N published events per topic, 0 to disable.
+    #[serde(default)]
+    pub log_sample_every: u64,
+    /// Azure Event Hubs namespace to publish to, setting the required client settings.
+    #[serde(default)]
+    pub event_hubs: Option<EventHubsConfig>,
+    /// Source of OAUTHBEARER tokens when `sasl.mechanism` is `OAUTHBEARER`.
+    #[serde(default)]
+    pub oauthbearer: Option<OAuthBearerConfig>,
+    /// Log output.
+    #[serde(default)]
+    pub log: LogConfig,
+    /// Export publish path traces to an OTLP collector.
+    #[serde(default)]
+    pub otlp: Option<OtlpConfig>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            strict: false,
+            unknown_fields: Vec::new(),
             kafka: HashMap::new(),
+            backend: Backend::default(),
+            partitioner: Partitioner::default(),
+            account_key: AccountKey::default(),
+            broker_failover: None,
             shutdown_timeout_ms: 30_000,
+            network: None,
+            ledger_path: None,
+            identity_keypair: None,
+            chain_time_header: false,
+            headers: HashMap::new(),
+            identity: None,
             update_account_topic: "".to_owned(),
+            account_size_topics: Vec::new(),
+            rent_exempt_only: false,
+            previous_owner: false,
             slot_status_topic: "".to_owned(),
+            slot_partitioning: SlotPartitioning::default(),
+            slot_statuses: Self::default_slot_statuses(),
             transaction_topic: "".to_owned(),
+            transaction_failed_topic: "".to_owned(),
+            transaction_topic_per_program: false,
+            transaction_program_topics: HashMap::new(),
+            signature_topic: "".to_owned(),
+            instruction_topic: "".to_owned(),
+            program_upgrade_topic: "".to_owned(),
+            ops_topic: "".to_owned(),
+            rewards_topic: "".to_owned(),
+            bootstrap_topic: "".to_owned(),
+            format: Format::default(),
+            data_encoding: DataEncoding::default(),
+            json_compression: JsonCompression::default(),
+            topics: HashMap::new(),
+            transform: None,
+            token_account_topic: "".to_owned(),
+            token_delegate_filter: None,
+            stake_account_topic: "".to_owned(),
+            vote_account_topic: "".to_owned(),
+            decoded_accounts_raw: Self::default_decoded_accounts_raw(),
             program_ignores: Vec::new(),
             program_filters: Vec::new(),
             account_filters: Vec::new(),
+            program_filters_files: Vec::new(),
+            account_filters_files: Vec::new(),
+            filters: Vec::new(),
+            remote_filters: None,
+            remote_filters_loaded: None,
+            snapshots: None,
+            account_states: None,
             publish_all_accounts: false,
             prometheus: None,
+            prometheus_socket: None,
+            null_sink: false,
+            dry_run: false,
+            oversize: None,
+            rest_proxy: None,
+            zeromq: None,
+            mqtt: None,
+            account_data_limit: None,
+            startup_log_interval: Self::default_startup_log_interval(),
+            startup_expected_accounts: None,
+            health_failure_window_ms: Self::default_health_failure_window_ms(),
+            watchdog: None,
+            circuit_breaker: None,
+            producer_recovery: ProducerRecoveryConfig::default(),
+            cert_reload_interval_ms: Self::default_cert_reload_interval_ms(),
+            top_programs: None,
+            workers: None,
+            max_buffered_bytes: 0,
+            shard: None,
+            account_rate_limit: None,
+            watermark: None,
+            checkpoints: None,
+            schemas: None,
+            handoff: None,
+            prometheus_tls: None,
+            prometheus_auth: None,
+            prometheus_namespace: None,
+            prometheus_labels: HashMap::new(),
+            admin: None,
+            dump_signal: None,
+            log_sample_every: 0,
+            event_hubs: None,
+            oauthbearer: None,
+            log: LogConfig::default(),
+            otlp: None,
         }
     }
 }
@@ -81,20 +429,375 @@ impl Default for Config {
 impl Config {
     /// Read plugin from JSON file.
     pub fn read_from<P: AsRef<Path>>(config_path: P) -> PluginResult<Self> {
+        let config_path = config_path.as_ref();
         let file = File::open(config_path)?;
-        let mut this: Self = serde_json::from_reader(file)
+        let mut unknown_fields = Vec::new();
+        let mut this: Self =
+            serde_ignored::deserialize(&mut serde_json::Deserializer::from_reader(file), |path| {
+                // Optional sections show up as `?` segments.
+                unknown_fields.push(path.to_string().replace(".?", ""))
+            })
             .map_err(|e| GeyserPluginError::ConfigFileReadError { msg: e.to_string() })?;
+        unknown_fields.retain(|field| !VALIDATOR_FIELDS.contains(&field.as_str()));
+        if this.strict && !unknown_fields.is_empty() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("unknown config fields: {}", unknown_fields.join(", ")),
+            });
+        }
+        this.unknown_fields = unknown_fields;
+        if matches!(&this.broker_failover, Some(failover) if failover.groups.is_empty()) {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "broker_failover has no groups".to_owned(),
+            });
+        }
+        if let Some(shard) = &this.shard {
+            if shard.index >= shard.total {
+                return Err(GeyserPluginError::ConfigFileReadError {
+                    msg: format!(
+                        "shard index {} is not below shard total {}",
+                        shard.index, shard.total
+                    ),
+                });
+            }
+        }
+        if matches!(&this.top_programs, Some(top_programs) if top_programs.window_secs == 0) {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "top_programs window_secs must be positive".to_owned(),
+            });
+        }
+        if !this.account_size_topics.is_empty() && this.update_account_topic.is_empty() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "account_size_topics requires update_account_topic".to_owned(),
+            });
+        }
+        if matches!(&this.account_states, Some(states) if states.max_accounts == 0) {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "account_states max_accounts must be positive".to_owned(),
+            });
+        }
+        let json_compressed = this.json_compression != JsonCompression::None
+            || this
+                .topics
+                .values()
+                .filter_map(|topic| topic.json_compression)
+                .any(|compression| compression != JsonCompression::None);
+        if (json_compressed || !this.headers.is_empty()) && this.rest_proxy.is_some() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "rest_proxy sends no headers, so json_compression and headers cannot be used"
+                    .to_owned(),
+            });
+        }
+        if (json_compressed || !this.headers.is_empty()) && this.zeromq.is_some() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "zeromq sends no headers, so json_compression and headers cannot be used"
+                    .to_owned(),
+            });
+        }
+        let sinks = [
+            ("null_sink", this.null_sink),
+            ("dry_run", this.dry_run),
+            ("rest_proxy", this.rest_proxy.is_some()),
+            ("zeromq", this.zeromq.is_some()),
+            ("mqtt", this.mqtt.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect::<Vec<_>>();
+        if sinks.len() > 1 {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("only one of {} can be set", sinks.join(", ")),
+            });
+        }
+        if this.prometheus.is_some() && this.prometheus_socket.is_some() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "prometheus and prometheus_socket cannot be combined".to_owned(),
+            });
+        }
+        if !this.bootstrap_topic.is_empty() && this.admin.is_none() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "bootstrap_topic requires admin".to_owned(),
+            });
+        }
+        if this.slot_partitioning == SlotPartitioning::Slot && !this.uses_rdkafka() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "slot_partitioning slot requires the rdkafka backend".to_owned(),
+            });
+        }
+        if this.oversize.is_some() && !this.uses_rdkafka() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "oversize requires the rdkafka backend".to_owned(),
+            });
+        }
+        if this.handoff.is_some() && !this.uses_rdkafka() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "handoff requires the rdkafka backend".to_owned(),
+            });
+        }
+        if this.oauthbearer.is_some() && !this.uses_rdkafka() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "oauthbearer requires the rdkafka backend".to_owned(),
+            });
+        }
+        if this.handoff.is_some() && this.update_account_topic.is_empty() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "handoff requires update_account_topic".to_owned(),
+            });
+        }
+        let config_dir = config_path.parent().unwrap_or_else(|| Path::new(""));
+        merge_filter_files(
+            &mut this.program_filters,
+            &this.program_filters_files,
+            config_dir,
+        )?;
+        merge_filter_files(
+            &mut this.account_filters,
+            &this.account_filters_files,
+            config_dir,
+        )?;
+        remote::load(&mut this).map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        if let Some(delegate) = this
+            .token_delegate_filter
+            .iter()
+            .flat_map(|filter| &filter.delegates)
+            .find(|delegate| Pubkey::from_str(delegate).is_err())
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("invalid delegate in token_delegate_filter: {}", delegate),
+            });
+        }
+        check_memcmp(&this.filters)
+            .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        if let Some(program) = this
+            .transaction_program_topics
+            .keys()
+            .find(|program| Pubkey::from_str(program).is_err())
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("invalid program in transaction_program_topics: {}", program),
+            });
+        }
+        this.resolve_network()
+            .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        event_hubs::apply(&mut this)
+            .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        HeaderTemplates::parse(&this.headers)
+            .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        if let Some(path) = &this.identity_keypair {
+            // Only the pubkey is kept.
+            let keypair = read_keypair_file(path).map_err(|error| {
+                GeyserPluginError::ConfigFileReadError {
+                    msg: format!("failed to read identity keypair {}: {}", path, error),
+                }
+            })?;
+            this.identity = Some(keypair.pubkey().to_string());
+        }
         this.fill_defaults();
         Ok(this)
     }
 
-    /// Create rdkafka::FutureProducer from config.
-    pub fn producer(&self) -> KafkaResult<ThreadedProducer<StatsThreadedProducerContext>> {
+    /// Replaces `{network}` in topic names with the configured or detected network.
+    fn resolve_network(&mut self) -> Result<(), String> {
+        let uses_placeholder = self
+            .topic_names()
+            .any(|topic| topic.contains(network::PLACEHOLDER))
+            || self
+                .topics
+                .keys()
+                .any(|topic| topic.contains(network::PLACEHOLDER))
+            || self
+                .headers
+                .values()
+                .any(|value| value.contains(network::PLACEHOLDER));
+        if !uses_placeholder {
+            return Ok(());
+        }
+        let network = match (&self.network, &self.ledger_path) {
+            (Some(network), _) => network.clone(),
+            (None, Some(ledger_path)) => network::detect(Path::new(ledger_path))
+                .map_err(|error| format!("failed to read genesis from {}: {}", ledger_path, error))?
+                .to_owned(),
+            (None, None) => {
+                return Err(format!(
+                    "topic names or headers contain {} but neither network nor ledger_path is set",
+                    network::PLACEHOLDER
+                ))
+            }
+        };
+        for topic in self.topic_names_mut() {
+            *topic = topic.replace(network::PLACEHOLDER, &network);
+        }
+        self.topics = mem::take(&mut self.topics)
+            .into_iter()
+            .map(|(topic, config)| (topic.replace(network::PLACEHOLDER, &network), config))
+            .collect();
+        for value in self.headers.values_mut() {
+            *value = value.replace(network::PLACEHOLDER, &network);
+        }
+        self.network = Some(network);
+        Ok(())
+    }
+
+    pub fn topic_names(&self) -> impl Iterator<Item = &String> {
+        [
+            &self.update_account_topic,
+            &self.slot_status_topic,
+            &self.transaction_topic,
+            &self.transaction_failed_topic,
+            &self.token_account_topic,
+            &self.stake_account_topic,
+            &self.vote_account_topic,
+            &self.signature_topic,
+            &self.instruction_topic,
+            &self.program_upgrade_topic,
+            &self.ops_topic,
+            &self.rewards_topic,
+            &self.bootstrap_topic,
+        ]
+        .into_iter()
+        .chain(
+            self.account_size_topics
+                .iter()
+                .map(|size_topic| &size_topic.topic),
+        )
+        .chain(self.transaction_program_topics.values())
+        .chain(
+            self.checkpoints
+                .iter()
+                .map(|checkpoints| &checkpoints.topic),
+        )
+        .chain(self.schemas.iter().map(|schemas| &schemas.topic))
+        .chain(self.handoff.iter().map(|handoff| &handoff.control_topic))
+        .chain(
+            self.oversize
+                .iter()
+                .filter(|oversize| oversize.policy == OversizePolicy::DeadLetter)
+                .map(|oversize| &oversize.dead_letter_topic),
+        )
+    }
+
+    fn topic_names_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        [
+            &mut self.update_account_topic,
+            &mut self.slot_status_topic,
+            &mut self.transaction_topic,
+            &mut self.transaction_failed_topic,
+            &mut self.token_account_topic,
+            &mut self.stake_account_topic,
+            &mut self.vote_account_topic,
+            &mut self.signature_topic,
+            &mut self.instruction_topic,
+            &mut self.program_upgrade_topic,
+            &mut self.ops_topic,
+            &mut self.rewards_topic,
+            &mut self.bootstrap_topic,
+        ]
+        .into_iter()
+        .chain(
+            self.account_size_topics
+                .iter_mut()
+                .map(|size_topic| &mut size_topic.topic),
+        )
+        .chain(self.transaction_program_topics.values_mut())
+        .chain(
+            self.checkpoints
+                .iter_mut()
+                .map(|checkpoints| &mut checkpoints.topic),
+        )
+        .chain(self.schemas.iter_mut().map(|schemas| &mut schemas.topic))
+        .chain(
+            self.handoff
+                .iter_mut()
+                .map(|handoff| &mut handoff.control_topic),
+        )
+        .chain(
+            self.oversize
+                .iter_mut()
+                .filter(|oversize| oversize.policy == OversizePolicy::DeadLetter)
+                .map(|oversize| &mut oversize.dead_letter_topic),
+        )
+    }
+
+    /// librdkafka client config, connected to the active broker group.
+    #[cfg(feature = "rdkafka-backend")]
+    pub fn client_config(&self) -> ClientConfig {
         let mut config = ClientConfig::new();
         for (k, v) in self.kafka.iter() {
             config.set(k, v);
         }
-        ThreadedProducer::from_config_and_context(&config, StatsThreadedProducerContext::default())
+        if let Some(failover) = &self.broker_failover {
+            let group = &failover.groups[failover::active_group()];
+            config.set("bootstrap.servers", &group.bootstrap_servers);
+        }
+        config
+    }
+
+    /// Create rdkafka::FutureProducer from config.
+    #[cfg(feature = "rdkafka-backend")]
+    pub fn producer(
+        &self,
+    ) -> rdkafka::error::KafkaResult<ThreadedProducer<StatsThreadedProducerContext>> {
+        ThreadedProducer::from_config_and_context(
+            &self.client_config(),
+            StatsThreadedProducerContext::new(self.oauthbearer.clone()),
+        )
+    }
+
+    /// Whether events go to Kafka brokers, rather than to no or another sink.
+    pub fn uses_brokers(&self) -> bool {
+        !self.null_sink
+            && !self.dry_run
+            && self.rest_proxy.is_none()
+            && self.zeromq.is_none()
+            && self.mqtt.is_none()
+    }
+
+    /// Whether events go to Kafka brokers through librdkafka, which is built in.
+    fn uses_rdkafka(&self) -> bool {
+        cfg!(feature = "rdkafka-backend") && self.uses_brokers() && self.backend == Backend::Rdkafka
+    }
+
+    /// Producer of the configured backend.
+    pub fn create_producer(&self) -> Result<Box<dyn ProducerLike>, ProducerError> {
+        match self.backend {
+            #[cfg(feature = "rdkafka-backend")]
+            Backend::Rdkafka => Ok(Box::new(self.producer()?)),
+            #[cfg(not(feature = "rdkafka-backend"))]
+            Backend::Rdkafka => Err(ProducerError::Creation(
+                "built without the rdkafka-backend feature".to_owned(),
+            )),
+            #[cfg(feature = "rskafka-backend")]
+            Backend::Rskafka => Ok(Box::new(crate::rskafka_producer::RskafkaProducer::new(
+                self,
+            )?)),
+            #[cfg(not(feature = "rskafka-backend"))]
+            Backend::Rskafka => Err(ProducerError::Creation(
+                "built without the rskafka-backend feature".to_owned(),
+            )),
+        }
+    }
+
+    fn default_slot_statuses() -> Vec<SlotStatus> {
+        vec![
+            SlotStatus::Processed,
+            SlotStatus::Rooted,
+            SlotStatus::Confirmed,
+        ]
+    }
+
+    fn default_decoded_accounts_raw() -> bool {
+        true
+    }
+
+    fn default_startup_log_interval() -> u64 {
+        1_000_000
+    }
+
+    fn default_health_failure_window_ms() -> u64 {
+        60_000
+    }
+
+    fn default_cert_reload_interval_ms() -> u64 {
+        10_000
     }
 
     fn set_default(&mut self, k: &'static str, v: &'static str) {
@@ -107,12 +810,1238 @@ impl Config {
         self.set_default("request.required.acks", "1");
         self.set_default("message.timeout.ms", "30000");
         self.set_default("compression.type", "lz4");
-        self.set_default("partitioner", "murmur2_random");
+        self.set_default("partitioner", self.partitioner.librdkafka_name());
     }
 
     pub fn create_prometheus(&self) -> IoResult<Option<PrometheusService>> {
-        self.prometheus.map(PrometheusService::new).transpose()
+        HEALTH.set_failure_window(Duration::from_millis(self.health_failure_window_ms));
+        let address = match (self.prometheus, &self.prometheus_socket) {
+            (Some(address), _) => PrometheusAddress::Tcp(address),
+            (None, Some(path)) => PrometheusAddress::Unix(path.clone()),
+            (None, None) => return Ok(None),
+        };
+        PrometheusService::new(
+            address,
+            self.prometheus_tls.as_ref(),
+            self.prometheus_auth.as_ref(),
+            self.admin.is_some(),
+            self.prometheus_namespace.as_deref(),
+            &self.prometheus_labels,
+        )
+        .map(Some)
+    }
+}
+
+/// Fails on the first memcmp pattern of the filter groups that does not decode.
+pub fn check_memcmp(filters: &[AccountFilterConfig]) -> Result<(), String> {
+    for memcmp in filters.iter().flat_map(|filter| &filter.memcmp) {
+        memcmp.decode().map_err(|error| {
+            format!(
+                "invalid memcmp bytes in filters: {}: {}",
+                memcmp.bytes, error
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Appends the pubkeys listed in each file that are not in `filters` yet, resolving relative
+/// paths against the directory of the config file.
+fn merge_filter_files(
+    filters: &mut Vec<String>,
+    files: &[PathBuf],
+    config_dir: &Path,
+) -> PluginResult<()> {
+    let mut seen = filters.iter().cloned().collect::<HashSet<_>>();
+    for path in files {
+        let path = config_dir.join(path);
+        let read_error = |error: &dyn std::fmt::Display| GeyserPluginError::ConfigFileReadError {
+            msg: format!("failed to read filter file {}: {}", path.display(), error),
+        };
+        let file = File::open(&path).map_err(|error| read_error(&error))?;
+        let pubkeys: Vec<String> =
+            serde_json::from_reader(file).map_err(|error| read_error(&error))?;
+        for pubkey in pubkeys {
+            if seen.insert(pubkey.clone()) {
+                filters.push(pubkey);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Filter section served over HTTP(S) or from S3, for managing the filters of a fleet of
+/// validators in one place.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteFiltersConfig {
+    /// `http://`, `https://` or `s3://bucket/key` URL of a JSON object with any of
+    /// `program_ignores`, `program_filters`, `account_filters` and `filters`.
+    pub url: String,
+    /// How often to check for a new version, 0 to only fetch at load.
+    #[serde(default = "RemoteFiltersConfig::default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Region of the S3 bucket, falling back to `AWS_REGION`.
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+impl RemoteFiltersConfig {
+    fn default_poll_interval_ms() -> u64 {
+        60_000
+    }
+}
+
+/// Latest-state cache config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountStatesConfig {
+    /// Accounts held at most.
+    pub max_accounts: usize,
+    /// Which accounts go when the cache is full.
+    #[serde(default)]
+    pub eviction: Eviction,
+}
+
+/// Eviction from the latest-state cache.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Eviction {
+    /// Evict the least recently updated account to hold a new one.
+    Oldest,
+    /// Hold no new accounts until others are closed.
+    Reject,
+}
+
+impl Default for Eviction {
+    fn default() -> Self {
+        Self::Oldest
+    }
+}
+
+/// Account snapshots config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SnapshotConfig {
+    /// `s3://bucket/prefix` under which `epoch=<epoch>/accounts.parquet` is written.
+    pub url: String,
+    /// Region of the bucket, falling back to `AWS_REGION`.
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+/// ZeroMQ sink config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ZeroMqConfig {
+    /// Endpoint to bind the PUB socket to, e.g. `tcp://127.0.0.1:5556` or `ipc:///run/solana.sock`.
+    pub endpoint: String,
+    /// Messages queued per subscriber before further ones are dropped for it.
+    #[serde(default = "ZeroMqConfig::default_send_hwm")]
+    pub send_hwm: i32,
+}
+
+impl ZeroMqConfig {
+    fn default_send_hwm() -> i32 {
+        100_000
+    }
+}
+
+/// MQTT sink config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "MqttConfig::default_port")]
+    pub port: u16,
+    #[serde(default = "MqttConfig::default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Connect over TLS, verifying the broker against the system roots.
+    #[serde(default)]
+    pub tls: bool,
+    /// 0 (at most once), 1 (at least once) or 2 (exactly once).
+    #[serde(default = "MqttConfig::default_qos")]
+    pub qos: u8,
+    /// MQTT topic of each event, with `{topic}` replaced by the configured topic and `{key}` by
+    /// the base58 event key, or `_` for unkeyed events.
+    #[serde(default = "MqttConfig::default_topic_template")]
+    pub topic_template: String,
+    /// Most messages waiting to be written to the connection.
+    #[serde(default = "MqttConfig::default_queue_capacity")]
+    pub queue_capacity: usize,
+}
+
+impl MqttConfig {
+    fn default_port() -> u16 {
+        1883
+    }
+
+    fn default_client_id() -> String {
+        "solana-accountsdb-plugin-kafka".to_owned()
+    }
+
+    fn default_qos() -> u8 {
+        1
+    }
+
+    fn default_topic_template() -> String {
+        "{topic}".to_owned()
+    }
+
+    fn default_queue_capacity() -> usize {
+        10_000
+    }
+}
+
+/// Azure Event Hubs config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventHubsConfig {
+    /// Namespace connection string with send rights, from a shared access policy.
+    pub connection_string: String,
+}
+
+/// Confluent REST Proxy config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestProxyConfig {
+    /// Base URL of the proxy, e.g. `https://rest-proxy:8082`.
+    pub url: String,
+    /// Basic authentication credentials.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Most records sent in one request.
+    #[serde(default = "RestProxyConfig::default_batch_size")]
+    pub batch_size: usize,
+    /// How long to wait for more records before sending a batch.
+    #[serde(default = "RestProxyConfig::default_linger_ms")]
+    pub linger_ms: u64,
+    /// Most records waiting to be sent, beyond which publishing fails with a full queue.
+    #[serde(default = "RestProxyConfig::default_queue_capacity")]
+    pub queue_capacity: usize,
+    #[serde(default = "RestProxyConfig::default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    /// Times a failed request or record is sent again before it is reported as failed.
+    #[serde(default = "RestProxyConfig::default_retries")]
+    pub retries: u32,
+    #[serde(default = "RestProxyConfig::default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+}
+
+impl RestProxyConfig {
+    fn default_batch_size() -> usize {
+        500
+    }
+
+    fn default_linger_ms() -> u64 {
+        5
+    }
+
+    fn default_queue_capacity() -> usize {
+        100_000
+    }
+
+    fn default_request_timeout_ms() -> u64 {
+        30_000
+    }
+
+    fn default_retries() -> u32 {
+        3
+    }
+
+    fn default_retry_backoff_ms() -> u64 {
+        100
     }
 }
 
+/// Broker failover config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BrokerFailoverConfig {
+    /// Broker groups, most preferred first.
+    pub groups: Vec<BrokerGroup>,
+    /// Move to the next group once publishing has been failing this long.
+    #[serde(default = "BrokerFailoverConfig::default_failover_after_ms")]
+    pub failover_after_ms: u64,
+    /// Move back to a preferred group once its brokers have been reachable this long.
+    #[serde(default = "BrokerFailoverConfig::default_failback_after_ms")]
+    pub failback_after_ms: u64,
+}
+
+impl BrokerFailoverConfig {
+    fn default_failover_after_ms() -> u64 {
+        60_000
+    }
+
+    fn default_failback_after_ms() -> u64 {
+        300_000
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BrokerGroup {
+    /// Label of the `broker_group_active` metric.
+    pub name: String,
+    /// `bootstrap.servers` of the group.
+    pub bootstrap_servers: String,
+}
+
+/// Kafka client library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// librdkafka, through the `rdkafka` crate.
+    Rdkafka,
+    /// The pure-Rust `rskafka` client, with the `rskafka-backend` feature.
+    Rskafka,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::Rdkafka
+    }
+}
+
+/// Partitioning of slot status updates.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotPartitioning {
+    /// Spread across partitions by the partitioner, as the updates have no key.
+    Random,
+    /// Slot modulo the number of partitions, keeping all statuses of a slot together.
+    Slot,
+}
+
+impl Default for SlotPartitioning {
+    fn default() -> Self {
+        Self::Random
+    }
+}
+
+/// Record key of account updates.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountKey {
+    /// The account pubkey, so that compaction keeps the latest update of each account.
+    Pubkey,
+    /// The account pubkey followed by the big-endian write version, so that compaction keeps
+    /// every version of an account. Updates of an account no longer share a partition.
+    PubkeyWriteVersion,
+}
+
+impl Default for AccountKey {
+    fn default() -> Self {
+        Self::Pubkey
+    }
+}
+
+/// Partitioner of keyed records.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Partitioner {
+    /// murmur2 hash of the key, like the Java client's default partitioner, so that records
+    /// keyed identically by Java producers land in the same partition.
+    Murmur2,
+    /// CRC32 hash of the key, librdkafka's own default.
+    Crc32,
+}
+
+impl Partitioner {
+    /// Value of the librdkafka `partitioner` property. Records without a key are spread
+    /// randomly with either.
+    fn librdkafka_name(self) -> &'static str {
+        match self {
+            Self::Murmur2 => "murmur2_random",
+            Self::Crc32 => "consistent_random",
+        }
+    }
+}
+
+impl Default for Partitioner {
+    fn default() -> Self {
+        Self::Murmur2
+    }
+}
+
+/// Message encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Protobuf,
+    Json,
+    Flatbuffers,
+    Borsh,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::Protobuf
+    }
+}
+
+impl Format {
+    /// Value of the `content-type` record header.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Protobuf => "application/x-protobuf",
+            Self::Json => "application/json",
+            Self::Flatbuffers => "application/x-flatbuffers",
+            Self::Borsh => "application/x-borsh",
+        }
+    }
+}
+
+/// Encoding of binary data in JSON messages.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataEncoding {
+    Base64,
+    /// Zstandard compressed, then base64.
+    Base64Zstd,
+    Hex,
+}
+
+impl Default for DataEncoding {
+    fn default() -> Self {
+        Self::Base64
+    }
+}
+
+/// Compression of whole JSON messages, sent with a `content-encoding` header.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Default for JsonCompression {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl JsonCompression {
+    /// Value of the `content-encoding` record header, `None` if uncompressed.
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gzip"),
+            Self::Zstd => Some("zstd"),
+        }
+    }
+}
+
+/// Settings of a single topic, overriding the global ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TopicConfig {
+    /// Overrides the global message encoding.
+    #[serde(default)]
+    pub format: Option<Format>,
+    #[serde(default)]
+    pub data_encoding: Option<DataEncoding>,
+    #[serde(default)]
+    pub json_compression: Option<JsonCompression>,
+    /// Event fields to publish, all if empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Event fields to omit.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// Topic receiving the account updates with at most `max_data_len` bytes of data.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SizeTopic {
+    pub max_data_len: usize,
+    pub topic: String,
+}
+
+/// Source of the event transform.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformConfig {
+    /// Path to a WebAssembly module.
+    Wasm(String),
+    /// Path to a shared library exporting the C transform interface.
+    Library(String),
+}
+
+/// Account filter group. All configured conditions must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AccountFilterConfig {
+    /// Owner programs to match, any if empty.
+    #[serde(default)]
+    pub owners: Vec<String>,
+    /// Accounts to match, any if empty.
+    #[serde(default)]
+    pub accounts: Vec<String>,
+    /// Byte patterns the account data must contain.
+    #[serde(default)]
+    pub memcmp: Vec<MemcmpConfig>,
+    /// Exact account data length to match.
+    #[serde(default)]
+    pub data_size: Option<usize>,
+    /// Publish only this range of the data of matching accounts.
+    #[serde(default)]
+    pub data_slice: Option<DataSlice>,
+    /// Byte ranges of the data of matching accounts to redact, applied before `data_slice`.
+    #[serde(default)]
+    pub redact: Vec<Redaction>,
+}
+
+/// Byte pattern at an offset of the account data.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemcmpConfig {
+    pub offset: usize,
+    /// Base58 encoded bytes, or hex with a `0x` prefix.
+    pub bytes: String,
+}
+
+impl MemcmpConfig {
+    pub fn decode(&self) -> Result<Vec<u8>, String> {
+        match self.bytes.strip_prefix("0x") {
+            Some(hex) => hex::decode(hex).map_err(|error| error.to_string()),
+            None => bs58::decode(&self.bytes)
+                .into_vec()
+                .map_err(|error| error.to_string()),
+        }
+    }
+}
+
+/// Range of account data, like `dataSlice` in the RPC API.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct DataSlice {
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl DataSlice {
+    /// The slice of `data` in range, empty if `offset` is past its end.
+    pub fn apply<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.range(data.len())]
+    }
+
+    /// The range clamped to data of length `len`.
+    pub fn range(&self, len: usize) -> Range<usize> {
+        let start = self.offset.min(len);
+        let end = start.saturating_add(self.length).min(len);
+        start..end
+    }
+}
+
+/// Range of account data that must not be published.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Redaction {
+    #[serde(flatten)]
+    pub range: DataSlice,
+    #[serde(default)]
+    pub action: RedactAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactAction {
+    /// Overwrite the range with zeros, keeping the data length.
+    Zero,
+    /// Cut the range out of the data.
+    Remove,
+}
+
+impl Default for RedactAction {
+    fn default() -> Self {
+        Self::Zero
+    }
+}
+
+/// Watchdog config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchdogConfig {
+    /// How often to check publishing health.
+    #[serde(default = "WatchdogConfig::default_check_interval_ms")]
+    pub check_interval_ms: u64,
+    /// Act once publishing has been failing this long.
+    #[serde(default = "WatchdogConfig::default_failure_timeout_ms")]
+    pub failure_timeout_ms: u64,
+    /// Act once this many messages are waiting for delivery. 0 disables the check.
+    #[serde(default)]
+    pub max_queue_depth: i64,
+    /// What to do.
+    pub action: WatchdogAction,
+    /// How long to pause publishing for with the `pause` action.
+    #[serde(default = "WatchdogConfig::default_pause_ms")]
+    pub pause_ms: u64,
+}
+
+impl WatchdogConfig {
+    fn default_check_interval_ms() -> u64 {
+        5_000
+    }
+
+    fn default_failure_timeout_ms() -> u64 {
+        120_000
+    }
+
+    fn default_pause_ms() -> u64 {
+        60_000
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogAction {
+    /// Tear down and recreate the Kafka producer.
+    RecreateProducer,
+    /// Drop events for `pause_ms`.
+    Pause,
+    /// Send SIGTERM to the validator so that orchestration restarts it.
+    Exit,
+}
+
+impl WatchdogAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RecreateProducer => "recreate_producer",
+            Self::Pause => "pause",
+            Self::Exit => "exit",
+        }
+    }
+}
+
+/// Top-N owner program metrics config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopProgramsConfig {
+    /// Number of programs to export.
+    #[serde(default = "TopProgramsConfig::default_count")]
+    pub count: usize,
+    /// Length of the sliding window.
+    #[serde(default = "TopProgramsConfig::default_window_secs")]
+    pub window_secs: u64,
+}
+
+impl TopProgramsConfig {
+    fn default_count() -> usize {
+        10
+    }
+
+    fn default_window_secs() -> u64 {
+        60
+    }
+}
+
+/// Publishing worker pool config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkersConfig {
+    /// Number of worker threads.
+    #[serde(default = "WorkersConfig::default_threads")]
+    pub threads: usize,
+    /// Number of events waiting for a worker before new ones are dropped.
+    #[serde(default = "WorkersConfig::default_queue_capacity")]
+    pub queue_capacity: usize,
+}
+
+impl WorkersConfig {
+    fn default_threads() -> usize {
+        4
+    }
+
+    fn default_queue_capacity() -> usize {
+        100_000
+    }
+}
+
+/// Admin API config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminConfig {
+    /// What happens to events of a paused type.
+    #[serde(default)]
+    pub pause_policy: PausePolicy,
+    /// Events held while paused before further ones are dropped.
+    #[serde(default = "AdminConfig::default_pause_buffer_capacity")]
+    pub pause_buffer_capacity: usize,
+}
+
+impl AdminConfig {
+    fn default_pause_buffer_capacity() -> usize {
+        100_000
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PausePolicy {
+    /// Drop events while paused.
+    Drop,
+    /// Hold events while paused and publish them once resumed.
+    Buffer,
+}
+
+impl Default for PausePolicy {
+    fn default() -> Self {
+        Self::Drop
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DumpSignal {
+    Sigusr1,
+    Sigusr2,
+}
+
+/// Token account delegation filter.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenDelegateFilterConfig {
+    /// Base58 delegates to match, any delegate if empty.
+    #[serde(default)]
+    pub delegates: Vec<String>,
+}
+
+/// Share of the events published by one of several plugin instances.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShardConfig {
+    /// Index of this instance, below `total`.
+    pub index: u64,
+    /// Number of instances.
+    pub total: u64,
+    /// How accounts and transactions are assigned to instances.
+    #[serde(default)]
+    pub by: ShardBy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShardBy {
+    /// By account pubkey or transaction signature, keeping all updates of an account together.
+    Key,
+    /// By slot, keeping all events of a slot together.
+    Slot,
+}
+
+impl Default for ShardBy {
+    fn default() -> Self {
+        Self::Key
+    }
+}
+
+/// Per-account rate limit config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountRateLimitConfig {
+    /// Updates of an account published per second, the latest of the rest follows later.
+    pub max_per_second: u32,
+}
+
+/// Delivered watermark state file config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatermarkConfig {
+    /// State file, created if missing.
+    pub path: String,
+    /// How often to save the state file, it is also saved on unload.
+    #[serde(default = "WatermarkConfig::default_save_interval_ms")]
+    pub save_interval_ms: u64,
+}
+
+impl WatermarkConfig {
+    fn default_save_interval_ms() -> u64 {
+        5_000
+    }
+}
+
+/// Checkpoint publishing config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckpointConfig {
+    /// Compacted topic receiving the checkpoints.
+    #[serde(default = "CheckpointConfig::default_topic")]
+    pub topic: String,
+    /// How often to publish checkpoints that changed.
+    #[serde(default = "CheckpointConfig::default_interval_ms")]
+    pub interval_ms: u64,
+}
+
+impl CheckpointConfig {
+    fn default_topic() -> String {
+        "__kafka_plugin_checkpoints".to_owned()
+    }
+
+    fn default_interval_ms() -> u64 {
+        10_000
+    }
+}
+
+/// Schema publishing config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SchemasConfig {
+    /// Compacted topic receiving the schemas, keyed by plugin version.
+    #[serde(default = "SchemasConfig::default_topic")]
+    pub topic: String,
+}
+
+impl SchemasConfig {
+    fn default_topic() -> String {
+        "__schemas".to_owned()
+    }
+}
+
+/// Backfill handoff config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HandoffConfig {
+    /// Topic `snapshot-backfill` announces its completion on.
+    #[serde(default = "HandoffConfig::default_control_topic")]
+    pub control_topic: String,
+    /// Live events held until the backfill completes before further ones are dropped.
+    #[serde(default = "HandoffConfig::default_buffer_capacity")]
+    pub buffer_capacity: usize,
+}
+
+impl HandoffConfig {
+    fn default_control_topic() -> String {
+        "__backfill_handoff".to_owned()
+    }
+
+    fn default_buffer_capacity() -> usize {
+        1_000_000
+    }
+}
+
+/// Oversize event config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OversizeConfig {
+    /// What to do with events larger than the `max.message.bytes` of their topic.
+    #[serde(default)]
+    pub policy: OversizePolicy,
+    /// Topic receiving oversized events with the `dead_letter` policy. Its own
+    /// `max.message.bytes` must be large enough to hold them.
+    #[serde(default = "OversizeConfig::default_dead_letter_topic")]
+    pub dead_letter_topic: String,
+}
+
+impl OversizeConfig {
+    fn default_dead_letter_topic() -> String {
+        "__oversize".to_owned()
+    }
+}
+
+/// Handling of events larger than their topic accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    /// Fail the publish with a message size error, without sending.
+    Reject,
+    /// Drop the event.
+    Skip,
+    /// Send the event to the dead letter topic instead.
+    DeadLetter,
+    /// Split the payload into messages within the limit, to be reassembled by consumers.
+    Chunk,
+}
+
+impl Default for OversizePolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// Circuit breaker config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Window over which the error rate is measured.
+    #[serde(default = "CircuitBreakerConfig::default_window_ms")]
+    pub window_ms: u64,
+    /// Publishes in a window before the error rate is acted on.
+    #[serde(default = "CircuitBreakerConfig::default_min_requests")]
+    pub min_requests: u64,
+    /// Share of failed publishes in a window that opens the breaker.
+    #[serde(default = "CircuitBreakerConfig::default_error_rate")]
+    pub error_rate: f64,
+    /// How long to skip publishes for before probing.
+    #[serde(default = "CircuitBreakerConfig::default_open_ms")]
+    pub open_ms: u64,
+    /// Events let through while probing, all of which have to be delivered to close the breaker.
+    #[serde(default = "CircuitBreakerConfig::default_probes")]
+    pub probes: u32,
+}
+
+impl CircuitBreakerConfig {
+    fn default_window_ms() -> u64 {
+        10_000
+    }
+
+    fn default_min_requests() -> u64 {
+        100
+    }
+
+    fn default_error_rate() -> f64 {
+        0.5
+    }
+
+    fn default_open_ms() -> u64 {
+        30_000
+    }
+
+    fn default_probes() -> u32 {
+        10
+    }
+}
+
+/// Producer recovery config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProducerRecoveryConfig {
+    /// Restarts without a delivery in between before giving up, 0 to disable.
+    #[serde(default = "ProducerRecoveryConfig::default_max_restarts")]
+    pub max_restarts: u32,
+    /// Delay after the first restart before another, doubling with every restart.
+    #[serde(default = "ProducerRecoveryConfig::default_backoff_ms")]
+    pub backoff_ms: u64,
+    /// Longest delay between restarts.
+    #[serde(default = "ProducerRecoveryConfig::default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for ProducerRecoveryConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: Self::default_max_restarts(),
+            backoff_ms: Self::default_backoff_ms(),
+            max_backoff_ms: Self::default_max_backoff_ms(),
+        }
+    }
+}
+
+impl ProducerRecoveryConfig {
+    fn default_max_restarts() -> u32 {
+        5
+    }
+
+    fn default_backoff_ms() -> u64 {
+        1_000
+    }
+
+    fn default_max_backoff_ms() -> u64 {
+        60_000
+    }
+}
+
+/// Log output config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogConfig {
+    /// Default log level.
+    #[serde(default = "LogConfig::default_level")]
+    pub level: String,
+    /// Write logs as JSON lines.
+    #[serde(default)]
+    pub json: bool,
+    /// Per-module log levels, keyed by module (`filter`, `publisher`, `kafka`, ...).
+    #[serde(default)]
+    pub modules: HashMap<String, String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: Self::default_level(),
+            json: false,
+            modules: HashMap::new(),
+        }
+    }
+}
+
+impl LogConfig {
+    fn default_level() -> String {
+        "info".to_owned()
+    }
+
+    /// Filter directives in `tracing_subscriber::EnvFilter` syntax.
+    pub fn directives(&self) -> String {
+        let mut directives = vec![self.level.clone()];
+        for (module, level) in self.modules.iter() {
+            let targets = match module.as_str() {
+                "kafka" => vec!["rdkafka".to_owned(), "librdkafka".to_owned()],
+                "filter" | "plugin" | "prom" | "publisher" | "telemetry" => {
+                    vec![format!("{}::{}", env!("CARGO_CRATE_NAME"), module)]
+                }
+                target => vec![target.to_owned()],
+            };
+            for target in targets {
+                directives.push(format!("{}={}", target, level));
+            }
+        }
+        directives.join(",")
+    }
+}
+
+/// OTLP trace exporter config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OtlpConfig {
+    /// gRPC endpoint of the collector, e.g. `http://localhost:4317`.
+    pub endpoint: String,
+    /// Fraction of events to trace.
+    #[serde(default = "OtlpConfig::default_sample_ratio")]
+    pub sample_ratio: f64,
+    /// Reported `service.name`.
+    #[serde(default = "OtlpConfig::default_service_name")]
+    pub service_name: String,
+}
+
+impl OtlpConfig {
+    fn default_sample_ratio() -> f64 {
+        0.001
+    }
+
+    fn default_service_name() -> String {
+        "solana-accountsdb-plugin-kafka".to_owned()
+    }
+}
+
+/// TLS certificate and private key, both PEM encoded.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrometheusTls {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Credentials expected in the `Authorization` header.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrometheusAuth {
+    Bearer { token: String },
+    Basic { username: String, password: String },
+}
+
+impl PrometheusAuth {
+    pub fn header_value(&self) -> String {
+        match self {
+            Self::Bearer { token } => format!("Bearer {}", token),
+            Self::Basic { username, password } => {
+                format!(
+                    "Basic {}",
+                    base64::encode(format!("{}:{}", username, password))
+                )
+            }
+        }
+    }
+}
+
+/// Source of OAUTHBEARER tokens.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OAuthBearerConfig {
+    /// Program printing a token on stdout.
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// File holding a token, read again on every refresh.
+    File { path: PathBuf },
+    /// OpenID Connect client credentials flow.
+    Oidc {
+        token_endpoint: String,
+        client_id: String,
+        client_secret: String,
+        #[serde(default)]
+        scope: Option<String>,
+    },
+    /// AWS MSK IAM authentication, region defaulting to `AWS_REGION`.
+    MskIam {
+        #[serde(default)]
+        region: Option<String>,
+    },
+}
+
+#[cfg(feature = "rdkafka-backend")]
 pub type Producer = ThreadedProducer<DefaultProducerContext>;
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            env, fs,
+            sync::atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    /// Reads `json` as a config file.
+    fn read(json: &str) -> PluginResult<Config> {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "kafka-plugin-config-{}-{}.json",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, json).unwrap();
+        let config = Config::read_from(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn read_error(json: &str) -> String {
+        match read(json) {
+            Err(GeyserPluginError::ConfigFileReadError { msg }) => msg,
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("config accepted: {}", json),
+        }
+    }
+
+    #[test]
+    fn test_top_programs_window() {
+        read(r#"{"kafka": {}, "top_programs": {"window_secs": 1}}"#).unwrap();
+        assert_eq!(
+            read_error(r#"{"kafka": {}, "top_programs": {"window_secs": 0}}"#),
+            "top_programs window_secs must be positive"
+        );
+    }
+
+    #[test]
+    fn test_token_account_raw_alias() {
+        let config = read(r#"{"kafka": {}, "token_account_raw": false}"#).unwrap();
+        assert!(!config.decoded_accounts_raw);
+        assert!(read(r#"{"kafka": {}}"#).unwrap().decoded_accounts_raw);
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let config = read(
+            r#"{"libpath": "plugin.so", "name": "kafka", "kafka": {}, "topc": "a", "top_programs": {"windw_secs": 1}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.unknown_fields, ["topc", "top_programs.windw_secs"]);
+
+        assert_eq!(
+            read_error(r#"{"strict": true, "libpath": "plugin.so", "kafka": {}, "topc": "a"}"#),
+            "unknown config fields: topc"
+        );
+        read(r#"{"strict": true, "libpath": "plugin.so", "name": "kafka", "kafka": {}}"#).unwrap();
+    }
+
+    #[test]
+    fn test_account_size_topics() {
+        let sizes = r#""account_size_topics": [{"max_data_len": 1024, "topic": "accounts.small"}]"#;
+        read(&format!(
+            r#"{{"kafka": {{}}, "update_account_topic": "accounts", {}}}"#,
+            sizes
+        ))
+        .unwrap();
+        assert_eq!(
+            read_error(&format!(r#"{{"kafka": {{}}, {}}}"#, sizes)),
+            "account_size_topics requires update_account_topic"
+        );
+    }
+
+    #[test]
+    fn test_filter_files() {
+        let dir = env::temp_dir().join(format!("kafka-plugin-filters-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = Pubkey::new_unique().to_string();
+        let account = Pubkey::new_unique().to_string();
+        fs::write(
+            dir.join("programs.json"),
+            serde_json::to_string(&[&program, &program]).unwrap(),
+        )
+        .unwrap();
+        let accounts = dir.join("accounts.json");
+        fs::write(&accounts, serde_json::to_string(&[&account]).unwrap()).unwrap();
+        let path = dir.join("config.json");
+        fs::write(
+            &path,
+            serde_json::json!({
+                "kafka": {},
+                "program_filters": [program],
+                "program_filters_files": ["programs.json"],
+                "account_filters_files": [accounts],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let config = Config::read_from(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.program_filters, vec![program]);
+        assert_eq!(config.account_filters, vec![account]);
+    }
+
+    #[test]
+    fn test_rdkafka_only_settings() {
+        let token = r#""oauthbearer": {"file": {"path": "/run/token"}}"#;
+        assert_eq!(
+            read_error(&format!(
+                r#"{{"kafka": {{}}, "backend": "rskafka", {}}}"#,
+                token
+            )),
+            "oauthbearer requires the rdkafka backend"
+        );
+        assert_eq!(
+            read_error(r#"{"kafka": {}, "backend": "rskafka", "oversize": {}}"#),
+            "oversize requires the rdkafka backend"
+        );
+        let rdkafka = read(&format!(r#"{{"kafka": {{}}, {}}}"#, token));
+        if cfg!(feature = "rdkafka-backend") {
+            rdkafka.unwrap();
+        } else {
+            assert!(rdkafka.is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_memcmp() {
+        read(r#"{"kafka": {}, "filters": [{"memcmp": [{"offset": 0, "bytes": "0xf19a"}]}]}"#)
+            .unwrap();
+        assert!(read_error(
+            r#"{"kafka": {}, "filters": [{"memcmp": [{"offset": 0, "bytes": "0xf19"}]}]}"#
+        )
+        .starts_with("invalid memcmp bytes in filters: 0xf19: "));
+        assert!(read_error(
+            r#"{"kafka": {}, "filters": [{"memcmp": [{"offset": 0, "bytes": "0OIl"}]}]}"#
+        )
+        .starts_with("invalid memcmp bytes in filters: 0OIl: "));
+    }
+
+    #[test]
+    fn test_headerless_sinks() {
+        let zeromq = r#""zeromq": {"endpoint": "tcp://127.0.0.1:5556"}"#;
+        read(&format!(r#"{{"kafka": {{}}, {}}}"#, zeromq)).unwrap();
+        assert_eq!(
+            read_error(&format!(
+                r#"{{"kafka": {{}}, {}, "json_compression": "gzip"}}"#,
+                zeromq
+            )),
+            "zeromq sends no headers, so json_compression and headers cannot be used"
+        );
+        assert_eq!(
+            read_error(&format!(
+                r#"{{"kafka": {{}}, {}, "topics": {{"slots": {{"json_compression": "zstd"}}}}}}"#,
+                zeromq
+            )),
+            "zeromq sends no headers, so json_compression and headers cannot be used"
+        );
+        assert_eq!(
+            read_error(&format!(
+                r#"{{"kafka": {{}}, {}, "headers": {{"table": "{{event}}"}}}}"#,
+                zeromq
+            )),
+            "zeromq sends no headers, so json_compression and headers cannot be used"
+        );
+        assert_eq!(
+            read_error(
+                r#"{"kafka": {}, "rest_proxy": {"url": "http://localhost"}, "json_compression": "gzip"}"#
+            ),
+            "rest_proxy sends no headers, so json_compression and headers cannot be used"
+        );
+    }
+
+    #[test]
+    fn test_one_sink() {
+        read(r#"{"kafka": {}, "dry_run": true}"#).unwrap();
+        assert_eq!(
+            read_error(r#"{"kafka": {}, "dry_run": true, "null_sink": true}"#),
+            "only one of null_sink, dry_run can be set"
+        );
+        assert_eq!(
+            read_error(
+                r#"{"kafka": {}, "zeromq": {"endpoint": "tcp://127.0.0.1:5556"},
+                    "mqtt": {"host": "localhost"}, "rest_proxy": {"url": "http://localhost"}}"#
+            ),
+            "only one of rest_proxy, zeromq, mqtt can be set"
+        );
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshot of the plugin's state written to the log on a signal or admin call, for debugging
+//! live incidents without a metrics stack.
+
+use {
+    crate::{
+        config::{Config, DumpSignal},
+        health::HEALTH,
+        pause::PAUSE,
+        prom,
+    },
+    prometheus::TextEncoder,
+    std::{
+        collections::HashMap,
+        fmt::Write,
+        mem, ptr,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc::{self, RecvTimeoutError, Sender},
+            Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+    tracing::{error, info},
+};
+
+/// Set by the signal handler or the admin API, consumed by the dump thread.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Asks for a dump to be logged.
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Handler replaced by ours, chained to so other users of the signal keep working.
+static PREVIOUS_HANDLER: AtomicUsize = AtomicUsize::new(libc::SIG_DFL);
+/// Whether the replaced handler takes `siginfo_t`.
+static PREVIOUS_SIGINFO: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    request();
+    let previous = PREVIOUS_HANDLER.load(Ordering::Relaxed);
+    if previous == libc::SIG_DFL || previous == libc::SIG_IGN {
+        return;
+    }
+    // SAFETY: the value was read from `sa_sigaction` of the replaced disposition, and its flags
+    // tell which of the two handler signatures it has.
+    unsafe {
+        if PREVIOUS_SIGINFO.load(Ordering::Relaxed) {
+            let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                mem::transmute(previous);
+            handler(signal, info, context);
+        } else {
+            let handler: extern "C" fn(libc::c_int) = mem::transmute(previous);
+            handler(signal);
+        }
+    }
+}
+
+/// Installs `on_signal` for the signal, returning the disposition it replaced.
+fn install(signal: libc::c_int) -> libc::sigaction {
+    // SAFETY: both structs are fully initialized before being passed to `sigaction`, and the
+    // handler only touches atomics before chaining, which is async-signal-safe.
+    unsafe {
+        let mut previous: libc::sigaction = mem::zeroed();
+        libc::sigaction(signal, ptr::null(), &mut previous);
+        PREVIOUS_HANDLER.store(previous.sa_sigaction, Ordering::Relaxed);
+        PREVIOUS_SIGINFO.store(previous.sa_flags & libc::SA_SIGINFO != 0, Ordering::Relaxed);
+
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = on_signal as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, ptr::null_mut());
+        previous
+    }
+}
+
+/// Background thread logging dumps when requested.
+pub struct Dumper {
+    /// Signal and disposition it replaced, restored on drop.
+    signal: Option<(libc::c_int, libc::sigaction)>,
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Dumper {
+    pub fn new(config: &Config) -> Self {
+        let summary = summary(config);
+        let signal = config.dump_signal.map(|signal| {
+            let signal = match signal {
+                DumpSignal::Sigusr1 => libc::SIGUSR1,
+                DumpSignal::Sigusr2 => libc::SIGUSR2,
+            };
+            (signal, install(signal))
+        });
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaDump".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(CHECK_INTERVAL) {
+                    if REQUESTED.swap(false, Ordering::Relaxed) {
+                        info!("plugin state dump\n{}", render(&summary));
+                    }
+                }
+            })
+            .expect("failed to spawn dump thread");
+        Self {
+            signal,
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Topics and filter sizes, fixed for the lifetime of the plugin.
+fn summary(config: &Config) -> String {
+    let mut summary = String::new();
+    let topics = config
+        .topic_names()
+        .filter(|topic| !topic.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let _ = writeln!(summary, "topics: {}", topics.join(", "));
+    let _ = writeln!(
+        summary,
+        "filters: {} programs, {} ignored programs, {} accounts, {} filter groups",
+        config.program_filters.len(),
+        config.program_ignores.len(),
+        config.account_filters.len(),
+        config.filters.len()
+    );
+    summary
+}
+
+fn render(summary: &str) -> String {
+    let mut dump = String::new();
+    let _ = writeln!(dump, "== config\n{}", summary);
+    let _ = writeln!(
+        dump,
+        "== health\n{}",
+        serde_json::to_string_pretty(&HEALTH.report()).unwrap_or_default()
+    );
+    let _ = writeln!(
+        dump,
+        "== pause\n{}",
+        serde_json::to_string(&PAUSE.report()).unwrap_or_default()
+    );
+    // Queue depths, per-topic counters and librdkafka statistics.
+    let metrics = prom::registry(None, &HashMap::new())
+        .map_err(|error| error.to_string())
+        .and_then(|registry| {
+            TextEncoder::new()
+                .encode_to_string(&registry.gather())
+                .map_err(|error| error.to_string())
+        })
+        .unwrap_or_else(|error| format!("failed to gather metrics: {}", error));
+    let _ = write!(dump, "== metrics\n{}", metrics);
+    dump
+}
+
+impl Drop for Dumper {
+    fn drop(&mut self) {
+        if let Some((signal, previous)) = self.signal.take() {
+            // SAFETY: `previous` was filled in by `sigaction` when installing our handler.
+            unsafe { libc::sigaction(signal, &previous, ptr::null_mut()) };
+        }
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("dump thread panicked");
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Dumper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dumper")
+            .field("signal", &self.signal.map(|(signal, _)| signal))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::sync::atomic::AtomicU32};
+
+    static CHAINED: AtomicU32 = AtomicU32::new(0);
+
+    extern "C" fn on_test_signal(_: libc::c_int) {
+        CHAINED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn current(signal: libc::c_int) -> usize {
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            libc::sigaction(signal, ptr::null(), &mut action);
+            action.sa_sigaction
+        }
+    }
+
+    #[test]
+    fn test_signal_chaining() {
+        let signal = libc::SIGUSR2;
+        let original = unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_test_signal as usize;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut original: libc::sigaction = mem::zeroed();
+            libc::sigaction(signal, &action, &mut original);
+            original
+        };
+
+        let dumper = Dumper::new(&Config {
+            dump_signal: Some(DumpSignal::Sigusr2),
+            ..Config::default()
+        });
+        assert_eq!(current(signal), on_signal as usize);
+        unsafe { libc::raise(signal) };
+        assert_eq!(CHAINED.load(Ordering::Relaxed), 1);
+
+        drop(dumper);
+        assert_eq!(current(signal), on_test_signal as usize);
+        unsafe { libc::raise(signal) };
+        assert_eq!(CHAINED.load(Ordering::Relaxed), 2);
+
+        unsafe { libc::sigaction(signal, &original, ptr::null_mut()) };
+    }
+}
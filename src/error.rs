@@ -0,0 +1,172 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::producer::ProducerError,
+    std::{
+        error::Error,
+        fmt::{self, Display, Formatter},
+    },
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PublishError {
+    /// The producer queue is full, retrying later may succeed.
+    QueueFull,
+    /// The encoded event exceeds `message.max.bytes`.
+    MessageTooLarge,
+    /// A request to the brokers timed out.
+    Timeout,
+    /// The producer is unusable and has to be recreated.
+    Fatal(String),
+    /// Any other producer error.
+    Producer(ProducerError),
+}
+
+impl PublishError {
+    /// Whether publishing the same event again may succeed.
+    pub fn is_retriable(&self) -> bool {
+        matches!(self, Self::QueueFull | Self::Timeout)
+    }
+
+    /// Reason label used in metrics, `None` for unclassified errors.
+    pub fn reason(&self) -> Option<&'static str> {
+        match self {
+            Self::QueueFull => Some("queue_full"),
+            Self::MessageTooLarge => Some("oversize"),
+            Self::Timeout => Some("timeout"),
+            Self::Fatal(_) => Some("fatal"),
+            Self::Producer(_) => None,
+        }
+    }
+}
+
+impl From<ProducerError> for PublishError {
+    fn from(error: ProducerError) -> Self {
+        match error {
+            ProducerError::QueueFull => Self::QueueFull,
+            ProducerError::MessageTooLarge => Self::MessageTooLarge,
+            ProducerError::Timeout => Self::Timeout,
+            ProducerError::Fatal(error) => Self::Fatal(error),
+            error => Self::Producer(error),
+        }
+    }
+}
+
+impl Display for PublishError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull => write!(f, "producer queue is full"),
+            Self::MessageTooLarge => write!(f, "message is larger than message.max.bytes"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Fatal(error) => write!(f, "fatal producer error: {}", error),
+            Self::Producer(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for PublishError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Producer(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        assert_eq!(
+            PublishError::from(ProducerError::QueueFull),
+            PublishError::QueueFull
+        );
+        assert_eq!(
+            PublishError::from(ProducerError::MessageTooLarge),
+            PublishError::MessageTooLarge
+        );
+        assert_eq!(
+            PublishError::from(ProducerError::Timeout),
+            PublishError::Timeout
+        );
+        assert_eq!(
+            PublishError::from(ProducerError::Fatal("fenced".to_owned())),
+            PublishError::Fatal("fenced".to_owned())
+        );
+        assert_eq!(
+            PublishError::from(ProducerError::ShutDown),
+            PublishError::Producer(ProducerError::ShutDown)
+        );
+    }
+
+    #[cfg(feature = "rdkafka-backend")]
+    #[test]
+    fn test_rdkafka_classification() {
+        use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+
+        let classify =
+            |code| PublishError::from(ProducerError::from(KafkaError::MessageProduction(code)));
+        assert_eq!(
+            classify(RDKafkaErrorCode::QueueFull),
+            PublishError::QueueFull
+        );
+        assert_eq!(
+            classify(RDKafkaErrorCode::MessageSizeTooLarge),
+            PublishError::MessageTooLarge
+        );
+        for code in [
+            RDKafkaErrorCode::MessageTimedOut,
+            RDKafkaErrorCode::RequestTimedOut,
+            RDKafkaErrorCode::OperationTimedOut,
+        ] {
+            assert_eq!(classify(code), PublishError::Timeout);
+        }
+        assert!(matches!(
+            classify(RDKafkaErrorCode::Fatal),
+            PublishError::Fatal(_)
+        ));
+        assert_eq!(
+            classify(RDKafkaErrorCode::BrokerDestroy),
+            PublishError::Producer(ProducerError::ShutDown)
+        );
+        assert!(matches!(
+            classify(RDKafkaErrorCode::UnknownTopic),
+            PublishError::Producer(ProducerError::Other(_))
+        ));
+        assert_eq!(
+            ProducerError::from(KafkaError::ClientCreation("bad config".to_owned())),
+            ProducerError::Creation("bad config".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_retriable_and_reason() {
+        let fatal = PublishError::Fatal("fenced".to_owned());
+        let other = PublishError::Producer(ProducerError::Other("unknown topic".to_owned()));
+        assert!(PublishError::QueueFull.is_retriable());
+        assert!(PublishError::Timeout.is_retriable());
+        assert!(!PublishError::MessageTooLarge.is_retriable());
+        assert!(!fatal.is_retriable());
+
+        assert_eq!(PublishError::QueueFull.reason(), Some("queue_full"));
+        assert_eq!(PublishError::MessageTooLarge.reason(), Some("oversize"));
+        assert_eq!(PublishError::Timeout.reason(), Some("timeout"));
+        assert_eq!(fatal.reason(), Some("fatal"));
+        assert_eq!(other.reason(), None);
+        assert!(other.source().is_some());
+    }
+}
@@ -14,17 +14,52 @@
 
 use solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus as PluginSlotStatus;
 
+/// Version of the event schemas, sent as the `schema-version` record header.
+/// Follows the crate's major and minor version, bumped with incompatible schema changes.
+pub const SCHEMA_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION_MAJOR"),
+    ".",
+    env!("CARGO_PKG_VERSION_MINOR")
+);
+
 include!(concat!(
     env!("OUT_DIR"),
     "/blockdaemon.solana.accountsdb_plugin_kafka.types.rs"
 ));
 
-impl From<PluginSlotStatus> for SlotStatus {
-    fn from(other: PluginSlotStatus) -> Self {
-        match other {
-            PluginSlotStatus::Processed => SlotStatus::Processed,
-            PluginSlotStatus::Rooted => SlotStatus::Rooted,
-            PluginSlotStatus::Confirmed => SlotStatus::Confirmed,
+/// Compiled `FileDescriptorSet` of `event.proto` and its imports, for consumers decoding events
+/// without the `.proto` files.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/event.bin"));
+
+impl SlotStatus {
+    /// Converts the validator's slot status, `None` if it has no counterpart.
+    pub fn from_plugin(status: &PluginSlotStatus) -> Option<Self> {
+        match status {
+            PluginSlotStatus::Processed => Some(SlotStatus::Processed),
+            PluginSlotStatus::Rooted => Some(SlotStatus::Rooted),
+            PluginSlotStatus::Confirmed => Some(SlotStatus::Confirmed),
+            #[cfg(feature = "agave")]
+            PluginSlotStatus::FirstShredReceived => Some(SlotStatus::FirstShredReceived),
+            #[cfg(feature = "agave")]
+            PluginSlotStatus::Completed => Some(SlotStatus::Completed),
+            #[cfg(feature = "agave")]
+            PluginSlotStatus::CreatedBank => Some(SlotStatus::CreatedBank),
+            #[cfg(feature = "agave")]
+            PluginSlotStatus::Dead(_) => Some(SlotStatus::Dead),
         }
     }
+
+    /// Why the slot was marked dead, empty for other statuses.
+    #[cfg(feature = "agave")]
+    pub fn dead_error(status: &PluginSlotStatus) -> String {
+        match status {
+            PluginSlotStatus::Dead(error) => error.clone(),
+            _ => String::new(),
+        }
+    }
+
+    #[cfg(not(feature = "agave"))]
+    pub fn dead_error(_status: &PluginSlotStatus) -> String {
+        String::new()
+    }
 }
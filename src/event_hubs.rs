@@ -0,0 +1,238 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Azure Event Hubs compatibility: the Kafka endpoint of a namespace only accepts a narrow set
+//! of client settings, and rejects others with errors that do not point at the cause. The
+//! settings are derived from the namespace connection string and the rest of the config is
+//! checked against the Event Hubs limits.
+
+use {crate::config::Config, std::collections::HashMap};
+
+/// Largest message Event Hubs accepts.
+const MAX_MESSAGE_BYTES: u64 = 1_046_528;
+/// Event Hubs closes connections idle for 240 seconds.
+const MAX_IDLE_MS: &str = "180000";
+const KAFKA_PORT: u16 = 9093;
+
+/// Parts of a `Endpoint=sb://...;SharedAccessKeyName=...;SharedAccessKey=...` connection string.
+#[derive(Debug, PartialEq, Eq)]
+struct ConnectionString {
+    host: String,
+    entity_path: Option<String>,
+}
+
+impl ConnectionString {
+    fn parse(connection_string: &str) -> Result<Self, String> {
+        let parts = connection_string
+            .split(';')
+            .filter(|part| !part.trim().is_empty())
+            .map(|part| {
+                part.split_once('=')
+                    .map(|(name, value)| (name.trim(), value.trim()))
+                    .ok_or_else(|| format!("invalid connection string part {}", name_of(part)))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        for required in ["Endpoint", "SharedAccessKeyName", "SharedAccessKey"] {
+            if !parts.contains_key(required) {
+                return Err(format!("connection string has no {}", required));
+            }
+        }
+        let host = parts["Endpoint"]
+            .strip_prefix("sb://")
+            .map(|host| host.trim_end_matches('/'))
+            .filter(|host| !host.is_empty())
+            .ok_or("connection string Endpoint is not sb://<namespace>.servicebus.windows.net/")?;
+        Ok(Self {
+            host: host.to_owned(),
+            entity_path: parts.get("EntityPath").map(|path| path.to_string()),
+        })
+    }
+}
+
+/// Name of a connection string part, never its value, which may be the key.
+fn name_of(part: &str) -> &str {
+    part.split('=').next().unwrap_or_default().trim()
+}
+
+/// Applies the Event Hubs client settings to the config and checks it against the Event Hubs
+/// limits, if Event Hubs is configured.
+pub fn apply(config: &mut Config) -> Result<(), String> {
+    let connection_string = match &config.event_hubs {
+        Some(event_hubs) => event_hubs.connection_string.clone(),
+        None => return Ok(()),
+    };
+    let parsed = ConnectionString::parse(&connection_string)?;
+    if config.rest_proxy.is_some() {
+        return Err("event_hubs and rest_proxy cannot be combined".to_owned());
+    }
+    if config.broker_failover.is_some() {
+        return Err("event_hubs and broker_failover cannot be combined".to_owned());
+    }
+
+    // An event hub is a topic. A connection string scoped to one only grants access to it.
+    for topic in config.topic_names().filter(|topic| !topic.is_empty()) {
+        if let Some(entity_path) = &parsed.entity_path {
+            if topic != entity_path {
+                return Err(format!(
+                    "topic {} is not the event hub {} of the connection string, \
+                     use a namespace connection string",
+                    topic, entity_path
+                ));
+            }
+        }
+        if !is_valid_name(topic) {
+            return Err(format!(
+                "topic {} is not a valid event hub name: up to 256 letters, digits, periods, \
+                 hyphens and underscores, starting and ending with a letter or digit",
+                topic
+            ));
+        }
+    }
+
+    let required = [
+        (
+            "bootstrap.servers",
+            format!("{}:{}", parsed.host, KAFKA_PORT),
+        ),
+        ("security.protocol", "SASL_SSL".to_owned()),
+        ("sasl.mechanism", "PLAIN".to_owned()),
+        ("sasl.username", "$ConnectionString".to_owned()),
+        ("sasl.password", connection_string),
+        // Event Hubs does not support idempotent producers.
+        ("enable.idempotence", "false".to_owned()),
+    ];
+    for (key, value) in required {
+        match config.kafka.get(key) {
+            Some(configured) if *configured != value => {
+                return Err(format!(
+                    "kafka.{} is set by event_hubs and must not be configured",
+                    key
+                ));
+            }
+            _ => {
+                config.kafka.insert(key.to_owned(), value);
+            }
+        }
+    }
+
+    if let Some(compression) = ["compression.type", "compression.codec"]
+        .iter()
+        .filter_map(|key| config.kafka.get(*key))
+        .find(|compression| !matches!(compression.as_str(), "none" | "gzip"))
+    {
+        return Err(format!(
+            "Event Hubs does not support {} compression, use gzip or none",
+            compression
+        ));
+    }
+    if !config.kafka.contains_key("compression.codec") {
+        config
+            .kafka
+            .entry("compression.type".to_owned())
+            .or_insert_with(|| "none".to_owned());
+    }
+    match config.kafka.get("message.max.bytes") {
+        Some(bytes) => match bytes.parse::<u64>() {
+            Ok(bytes) if bytes <= MAX_MESSAGE_BYTES => {}
+            _ => {
+                return Err(format!(
+                    "kafka.message.max.bytes {} is over the Event Hubs limit of {}",
+                    bytes, MAX_MESSAGE_BYTES
+                ))
+            }
+        },
+        None => {
+            config.kafka.insert(
+                "message.max.bytes".to_owned(),
+                MAX_MESSAGE_BYTES.to_string(),
+            );
+        }
+    }
+    for key in ["connections.max.idle.ms", "metadata.max.age.ms"] {
+        config
+            .kafka
+            .entry(key.to_owned())
+            .or_insert_with(|| MAX_IDLE_MS.to_owned());
+    }
+    Ok(())
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let alphanumeric = |c: Option<char>| matches!(c, Some(c) if c.is_ascii_alphanumeric());
+    name.len() <= 256
+        && alphanumeric(name.chars().next())
+        && alphanumeric(name.chars().last())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::config::EventHubsConfig};
+
+    const CONNECTION_STRING: &str = "Endpoint=sb://validators.servicebus.windows.net/;\
+        SharedAccessKeyName=send;SharedAccessKey=c2VjcmV0";
+
+    fn config() -> Config {
+        Config {
+            event_hubs: Some(EventHubsConfig {
+                connection_string: CONNECTION_STRING.to_owned(),
+            }),
+            update_account_topic: "solana.accounts".to_owned(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut config = config();
+        apply(&mut config).unwrap();
+        assert_eq!(
+            config.kafka["bootstrap.servers"],
+            "validators.servicebus.windows.net:9093"
+        );
+        assert_eq!(config.kafka["sasl.password"], CONNECTION_STRING);
+        assert_eq!(config.kafka["enable.idempotence"], "false");
+        assert_eq!(config.kafka["compression.type"], "none");
+
+        let mut config = self::config();
+        config
+            .kafka
+            .insert("compression.type".to_owned(), "lz4".to_owned());
+        assert!(apply(&mut config).is_err());
+
+        let mut config = self::config();
+        config
+            .kafka
+            .insert("enable.idempotence".to_owned(), "true".to_owned());
+        assert!(apply(&mut config).is_err());
+
+        let mut config = self::config();
+        config.update_account_topic = "solana/accounts".to_owned();
+        assert!(apply(&mut config).is_err());
+    }
+
+    #[test]
+    fn test_entity_path() {
+        let mut config = config();
+        config.event_hubs = Some(EventHubsConfig {
+            connection_string: format!("{};EntityPath=solana.slots", CONNECTION_STRING),
+        });
+        let error = apply(&mut config).unwrap_err();
+        assert!(error.contains("namespace connection string"));
+        assert!(!error.contains("c2VjcmV0"));
+        assert!(ConnectionString::parse("Endpoint=sb://x/;SharedAccessKeyName=send").is_err());
+    }
+}
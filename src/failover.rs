@@ -0,0 +1,295 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Switching between broker groups, e.g. the local region first and a remote one as fallback.
+//! The producer moves to the next group once publishing has been failing for a while, and back
+//! to a preferred group once its brokers have been reachable for a while.
+
+use {
+    crate::{
+        config::{BrokerFailoverConfig, BrokerGroup},
+        health::HEALTH,
+        prom::BROKER_GROUP_ACTIVE,
+        watchdog,
+    },
+    std::{
+        net::{TcpStream, ToSocketAddrs},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc::{self, RecvTimeoutError, Sender},
+            Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tracing::{error, info, warn},
+};
+
+/// Index of the broker group the producer is created for.
+static ACTIVE_GROUP: AtomicUsize = AtomicUsize::new(0);
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn active_group() -> usize {
+    ACTIVE_GROUP.load(Ordering::Relaxed)
+}
+
+/// Background thread choosing the broker group.
+#[derive(Debug)]
+pub struct Failover {
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Failover {
+    pub fn new(config: BrokerFailoverConfig) -> Self {
+        set_active(&config, active_group());
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaFailover".to_owned())
+            .spawn(move || {
+                let mut choice = Choice::new(&config, Instant::now());
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(CHECK_INTERVAL) {
+                    let active = active_group();
+                    let failing_for = HEALTH
+                        .report()
+                        .failing_for_ms
+                        .map(Duration::from_millis)
+                        .unwrap_or_default();
+                    let next = choice.next(active, failing_for, Instant::now(), |index| {
+                        is_reachable(&config.groups[index])
+                    });
+                    let next = match next {
+                        Some(Switch::Failover(next)) => {
+                            warn!(
+                                from = %config.groups[active].name,
+                                to = %config.groups[next].name,
+                                failing_for_ms = failing_for.as_millis() as u64,
+                                "failing over to the next broker group"
+                            );
+                            next
+                        }
+                        Some(Switch::Failback(next)) => {
+                            info!(
+                                from = %config.groups[active].name,
+                                to = %config.groups[next].name,
+                                "failing back to a preferred broker group"
+                            );
+                            next
+                        }
+                        None => continue,
+                    };
+                    switch(&config, next);
+                }
+            })
+            .expect("failed to spawn failover thread");
+        Self {
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Switch {
+    Failover(usize),
+    Failback(usize),
+}
+
+/// Decides when to switch broker groups.
+#[derive(Debug)]
+struct Choice {
+    groups: usize,
+    failover_after: Duration,
+    failback_after: Duration,
+    switched_at: Instant,
+    /// Since when each group has been reachable.
+    reachable_since: Vec<Option<Instant>>,
+}
+
+impl Choice {
+    fn new(config: &BrokerFailoverConfig, now: Instant) -> Self {
+        Self {
+            groups: config.groups.len(),
+            failover_after: Duration::from_millis(config.failover_after_ms),
+            failback_after: Duration::from_millis(config.failback_after_ms),
+            switched_at: now,
+            reachable_since: vec![None; config.groups.len()],
+        }
+    }
+
+    /// Group to switch to, if any. Moves to the next group once publishing has been failing
+    /// for `failover_after`, wrapping around, and back to the most preferred group that stayed
+    /// reachable for `failback_after`.
+    fn next(
+        &mut self,
+        active: usize,
+        failing_for: Duration,
+        now: Instant,
+        is_reachable: impl Fn(usize) -> bool,
+    ) -> Option<Switch> {
+        if self.groups > 1
+            && failing_for >= self.failover_after
+            && now.duration_since(self.switched_at) >= self.failover_after
+        {
+            self.switched_at = now;
+            return Some(Switch::Failover((active + 1) % self.groups));
+        }
+
+        for index in 0..active {
+            if !is_reachable(index) {
+                self.reachable_since[index] = None;
+                continue;
+            }
+            let since = *self.reachable_since[index].get_or_insert(now);
+            if now.duration_since(since) >= self.failback_after {
+                self.switched_at = now;
+                self.reachable_since
+                    .iter_mut()
+                    .for_each(|since| *since = None);
+                return Some(Switch::Failback(index));
+            }
+            break;
+        }
+        None
+    }
+}
+
+fn switch(config: &BrokerFailoverConfig, index: usize) {
+    set_active(config, index);
+    HEALTH.reset_failure();
+    watchdog::request_recreate();
+}
+
+fn set_active(config: &BrokerFailoverConfig, index: usize) {
+    ACTIVE_GROUP.store(index, Ordering::Relaxed);
+    for (i, group) in config.groups.iter().enumerate() {
+        BROKER_GROUP_ACTIVE
+            .with_label_values(&[&group.name])
+            .set(i64::from(i == index));
+    }
+}
+
+/// Whether any bootstrap server of the group accepts TCP connections.
+fn is_reachable(group: &BrokerGroup) -> bool {
+    group.bootstrap_servers.split(',').any(|server| {
+        // Strip a `PROTOCOL://` prefix.
+        let address = server.trim().rsplit("://").next().unwrap_or_default();
+        address
+            .to_socket_addrs()
+            .map(|mut addresses| {
+                addresses
+                    .any(|address| TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).is_ok())
+            })
+            .unwrap_or(false)
+    })
+}
+
+impl Drop for Failover {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("failover thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::net::TcpListener};
+
+    fn group(name: &str, bootstrap_servers: &str) -> BrokerGroup {
+        BrokerGroup {
+            name: name.to_owned(),
+            bootstrap_servers: bootstrap_servers.to_owned(),
+        }
+    }
+
+    fn config(groups: usize) -> BrokerFailoverConfig {
+        BrokerFailoverConfig {
+            groups: (0..groups)
+                .map(|i| group(&format!("group{}", i), "localhost:9092"))
+                .collect(),
+            failover_after_ms: 10_000,
+            failback_after_ms: 60_000,
+        }
+    }
+
+    #[test]
+    fn test_failover_and_failback() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut choice = Choice::new(&config(3), start);
+        let healthy = Duration::ZERO;
+        let failing = Duration::from_secs(10);
+
+        assert_eq!(choice.next(0, healthy, secs(10), |_| true), None);
+        assert_eq!(
+            choice.next(0, failing, secs(10), |_| true),
+            Some(Switch::Failover(1))
+        );
+        // Not again before `failover_after` since the last switch.
+        assert_eq!(choice.next(1, failing, secs(15), |_| false), None);
+        assert_eq!(
+            choice.next(1, failing, secs(20), |_| false),
+            Some(Switch::Failover(2))
+        );
+        assert_eq!(
+            choice.next(2, failing, secs(30), |_| false),
+            Some(Switch::Failover(0))
+        );
+
+        let mut choice = Choice::new(&config(3), start);
+        assert_eq!(choice.next(2, healthy, secs(0), |index| index == 1), None);
+        // Group 0 becomes reachable later but is preferred.
+        assert_eq!(choice.next(2, healthy, secs(30), |_| true), None);
+        assert_eq!(choice.next(2, healthy, secs(60), |_| true), None);
+        assert_eq!(
+            choice.next(2, healthy, secs(90), |_| true),
+            Some(Switch::Failback(0))
+        );
+        assert_eq!(choice.next(0, healthy, secs(200), |_| true), None);
+    }
+
+    #[test]
+    fn test_single_group() {
+        let start = Instant::now();
+        let mut choice = Choice::new(&config(1), start);
+        let later = start + Duration::from_secs(60);
+        assert_eq!(
+            choice.next(0, Duration::from_secs(60), later, |_| true),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        assert!(is_reachable(&group(
+            "a",
+            &format!("{}, SSL://{}", closed, address)
+        )));
+        assert!(!is_reachable(&group("b", &closed.to_string())));
+        assert!(!is_reachable(&group("c", "not an address")));
+    }
+}
@@ -13,39 +13,207 @@
 // limitations under the License.
 
 use {
-    crate::*,
+    crate::{
+        config::{AccountFilterConfig, DataSlice, RedactAction, Redaction},
+        *,
+    },
     solana_program::pubkey::Pubkey,
-    std::{collections::HashSet, str::FromStr},
+    std::{
+        borrow::Cow,
+        collections::{HashMap, HashSet},
+        str::FromStr,
+    },
+    tracing::error,
 };
 
 pub struct Filter {
     program_ignores: HashSet<[u8; 32]>,
     program_filters: HashSet<[u8; 32]>,
     account_filters: HashSet<[u8; 32]>,
+    filters: Vec<FiltersAccounts>,
+    /// Indexes into `filters` of the groups restricted to each owner, in config order.
+    filters_by_owner: HashMap<[u8; 32], Vec<usize>>,
+    /// Indexes into `filters` of the groups matching any owner, in config order.
+    filters_any_owner: Vec<usize>,
+}
+
+/// Compiled account filter group.
+#[derive(Debug)]
+pub struct FiltersAccounts {
+    owners: HashSet<[u8; 32]>,
+    accounts: HashSet<[u8; 32]>,
+    memcmp: Vec<(usize, Vec<u8>)>,
+    data_size: Option<usize>,
+    /// Set when a memcmp pattern failed to decode; the group then matches nothing. Configs and
+    /// polled remote filters are checked first, so only filters built otherwise can hit this.
+    invalid: bool,
+    pub data_slice: Option<DataSlice>,
+    redact: Vec<Redaction>,
+}
+
+impl FiltersAccounts {
+    fn new(config: &AccountFilterConfig) -> Self {
+        let mut invalid = false;
+        let mut memcmp = config
+            .memcmp
+            .iter()
+            .flat_map(|memcmp| match memcmp.decode() {
+                Ok(bytes) => Some((memcmp.offset, bytes)),
+                Err(err) => {
+                    error!(bytes = %memcmp.bytes, %err, "invalid memcmp bytes, disabling filter");
+                    invalid = true;
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        // Short patterns first, they reject most accounts for the least work.
+        memcmp.sort_by_key(|(_, bytes)| bytes.len());
+        Self {
+            owners: parse_pubkeys(&config.owners),
+            accounts: parse_pubkeys(&config.accounts),
+            memcmp,
+            data_size: config.data_size,
+            invalid,
+            data_slice: config.data_slice,
+            redact: config.redact.clone(),
+        }
+    }
+
+    /// Data to publish for a matching account, redacted and then sliced.
+    pub fn account_data<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.redact.is_empty() {
+            return Cow::Borrowed(self.data_slice.map_or(data, |slice| slice.apply(data)));
+        }
+
+        let mut redacted = data.to_vec();
+        let mut removed = vec![false; data.len()];
+        for redaction in &self.redact {
+            let range = redaction.range.range(data.len());
+            match redaction.action {
+                RedactAction::Zero => redacted[range].fill(0),
+                RedactAction::Remove => removed[range].fill(true),
+            }
+        }
+        if removed.contains(&true) {
+            redacted = redacted
+                .into_iter()
+                .zip(removed)
+                .filter(|(_, removed)| !removed)
+                .map(|(byte, _)| byte)
+                .collect();
+        }
+        match self.data_slice {
+            Some(slice) => Cow::Owned(slice.apply(&redacted).to_vec()),
+            None => Cow::Owned(redacted),
+        }
+    }
+
+    fn matches(&self, owner: &[u8; 32], pubkey: &[u8; 32], data: &[u8]) -> bool {
+        !self.invalid
+            && (self.owners.is_empty() || self.owners.contains(owner))
+            && (self.accounts.is_empty() || self.accounts.contains(pubkey))
+            && self.data_size.is_none_or(|size| size == data.len())
+            && self
+                .memcmp
+                .iter()
+                .all(|(offset, bytes)| memcmp(data, *offset, bytes))
+    }
+}
+
+/// Whether `data` contains `bytes` at `offset`. Compares the first byte and then eight bytes
+/// at a time, which the compiler turns into wide loads, stopping at the first mismatch.
+fn memcmp(data: &[u8], offset: usize, bytes: &[u8]) -> bool {
+    let data = match data.get(offset..offset.saturating_add(bytes.len())) {
+        Some(data) => data,
+        None => return false,
+    };
+    if data.first() != bytes.first() {
+        return false;
+    }
+    let mut data_chunks = data.chunks_exact(8);
+    let mut bytes_chunks = bytes.chunks_exact(8);
+    for (a, b) in (&mut data_chunks).zip(&mut bytes_chunks) {
+        if u64::from_ne_bytes(a.try_into().unwrap()) != u64::from_ne_bytes(b.try_into().unwrap()) {
+            return false;
+        }
+    }
+    data_chunks.remainder() == bytes_chunks.remainder()
+}
+
+fn parse_pubkeys(pubkeys: &[String]) -> HashSet<[u8; 32]> {
+    pubkeys
+        .iter()
+        .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
+        .collect()
 }
 
 impl Filter {
     pub fn new(config: &Config) -> Self {
+        let filters = config
+            .filters
+            .iter()
+            .map(FiltersAccounts::new)
+            .collect::<Vec<_>>();
+        let mut filters_by_owner: HashMap<[u8; 32], Vec<usize>> = HashMap::new();
+        let mut filters_any_owner = Vec::new();
+        for (index, filter) in filters.iter().enumerate() {
+            if filter.owners.is_empty() {
+                filters_any_owner.push(index);
+            }
+            for owner in &filter.owners {
+                filters_by_owner.entry(*owner).or_default().push(index);
+            }
+        }
         Self {
-            program_ignores: config
-                .program_ignores
-                .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
-                .collect(),
-            program_filters: config
-                .program_filters
-                .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
-                .collect(),
-            account_filters: config
-                .account_filters
-                .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
-                .collect(),
+            program_ignores: parse_pubkeys(&config.program_ignores),
+            program_filters: parse_pubkeys(&config.program_filters),
+            account_filters: parse_pubkeys(&config.account_filters),
+            filters,
+            filters_by_owner,
+            filters_any_owner,
         }
     }
 
+    /// Returns the first filter group matching the account, if any.
+    pub fn wants_filter(
+        &self,
+        owner: &[u8],
+        pubkey: &[u8],
+        data: &[u8],
+    ) -> Option<&FiltersAccounts> {
+        let owner = <&[u8; 32]>::try_from(owner).ok()?;
+        let pubkey = <&[u8; 32]>::try_from(pubkey).ok()?;
+        if self.program_ignores.contains(owner) {
+            return None;
+        }
+        let matches = |index: &usize| self.filters[*index].matches(owner, pubkey, data);
+        let owned = self
+            .filters_by_owner
+            .get(owner)
+            .and_then(|indexes| indexes.iter().find(|index| matches(index)));
+        // A group for any owner wins if it comes first in the config.
+        let any_owner = self
+            .filters_any_owner
+            .iter()
+            .take_while(|index| owned.is_none_or(|owned| *index < owned))
+            .find(|index| matches(index));
+        any_owner.or(owned).map(|index| &self.filters[*index])
+    }
+
     pub fn wants_program(&self, program: &[u8]) -> bool {
+        let key = match <&[u8; 32]>::try_from(program) {
+            Ok(key) => key,
+            _ => return true,
+        };
+        // With filter groups configured, an empty program list no longer means all programs.
+        !self.program_ignores.contains(key)
+            && ((self.program_filters.is_empty() && self.filters.is_empty())
+                || self.program_filters.contains(key))
+    }
+
+    /// Whether transactions and instructions of `program` are published. Filter groups only
+    /// select accounts, so an empty program list still means all programs here.
+    pub fn wants_transaction_program(&self, program: &[u8]) -> bool {
         let key = match <&[u8; 32]>::try_from(program) {
             Ok(key) => key,
             _ => return true,
@@ -65,7 +233,7 @@ impl Filter {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use {super::*, crate::config::MemcmpConfig};
 
     #[test]
     fn test_filter() {
@@ -157,4 +325,150 @@ mod tests {
                 .to_bytes()
         ));
     }
+
+    #[test]
+    fn test_filter_groups() {
+        let owner = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
+        let config = Config {
+            filters: vec![AccountFilterConfig {
+                owners: vec![owner.to_string()],
+                memcmp: vec![MemcmpConfig {
+                    offset: 2,
+                    bytes: bs58::encode([7, 8]).into_string(),
+                }],
+                data_size: Some(6),
+                data_slice: Some(DataSlice {
+                    offset: 4,
+                    length: 8,
+                }),
+                ..AccountFilterConfig::default()
+            }],
+            ..Config::default()
+        };
+
+        let filter = Filter::new(&config);
+        let pubkey = Pubkey::new_unique().to_bytes();
+        let data = [0, 1, 7, 8, 9, 10];
+
+        let group = filter
+            .wants_filter(&owner.to_bytes(), &pubkey, &data)
+            .unwrap();
+        assert_eq!(group.data_slice.unwrap().apply(&data), &[9, 10]);
+
+        assert!(filter
+            .wants_filter(&owner.to_bytes(), &pubkey, &data[..5])
+            .is_none());
+        assert!(filter
+            .wants_filter(&owner.to_bytes(), &pubkey, &[0, 1, 7, 9, 9, 10])
+            .is_none());
+        assert!(filter
+            .wants_filter(&Pubkey::new_unique().to_bytes(), &pubkey, &data)
+            .is_none());
+        // Groups replace the catch-all of an empty program list.
+        assert!(!filter.wants_program(&Pubkey::new_unique().to_bytes()));
+        // But not for transactions, which groups do not select.
+        assert!(filter.wants_transaction_program(&Pubkey::new_unique().to_bytes()));
+    }
+
+    #[test]
+    fn test_memcmp_encodings() {
+        let memcmp = |bytes: &str| MemcmpConfig {
+            offset: 0,
+            bytes: bytes.to_owned(),
+        };
+        assert_eq!(memcmp("0x0a0B").decode().unwrap(), [10, 11]);
+        assert_eq!(memcmp("mL").decode().unwrap(), [10, 11]);
+        assert!(memcmp("0xabc").decode().is_err());
+        assert!(memcmp("0x0g").decode().is_err());
+        assert!(memcmp("0OIl").decode().is_err());
+    }
+
+    #[test]
+    fn test_memcmp() {
+        let data = (0..40).collect::<Vec<u8>>();
+        assert!(memcmp(&data, 0, &[]));
+        assert!(memcmp(&data, 3, &data[3..30]));
+        assert!(memcmp(&data, 30, &data[30..]));
+        assert!(!memcmp(&data, 30, &data[29..]));
+        assert!(!memcmp(&data, 41, &[]));
+
+        let mut pattern = data[2..23].to_vec();
+        for index in [0, 7, 8, 20] {
+            pattern[index] ^= 1;
+            assert!(!memcmp(&data, 2, &pattern), "{}", index);
+            pattern[index] ^= 1;
+        }
+        assert!(memcmp(&data, 2, &pattern));
+    }
+
+    #[test]
+    fn test_filter_group_order() {
+        let owner = Pubkey::new_unique();
+        // Groups are told apart by their data slice offset.
+        let group = |id, owners: Vec<String>, data_size| AccountFilterConfig {
+            owners,
+            data_size: Some(data_size),
+            data_slice: Some(DataSlice {
+                offset: id,
+                length: 0,
+            }),
+            ..AccountFilterConfig::default()
+        };
+        let config = Config {
+            filters: vec![
+                group(0, vec![Pubkey::new_unique().to_string()], 1),
+                group(1, vec![], 2),
+                group(2, vec![owner.to_string()], 2),
+                group(3, vec![owner.to_string()], 3),
+                group(4, vec![], 3),
+            ],
+            ..Config::default()
+        };
+
+        let filter = Filter::new(&config);
+        let pubkey = Pubkey::new_unique().to_bytes();
+        let first = |data: &[u8]| {
+            let group = filter.wants_filter(&owner.to_bytes(), &pubkey, data)?;
+            Some(group.data_slice.unwrap().offset)
+        };
+        assert_eq!(first(&[0; 2]), Some(1));
+        assert_eq!(first(&[0; 3]), Some(3));
+        assert_eq!(first(&[0; 1]), None);
+    }
+
+    #[test]
+    fn test_redaction() {
+        let config = Config {
+            filters: vec![AccountFilterConfig {
+                redact: vec![
+                    Redaction {
+                        range: DataSlice {
+                            offset: 1,
+                            length: 2,
+                        },
+                        action: RedactAction::Zero,
+                    },
+                    Redaction {
+                        range: DataSlice {
+                            offset: 4,
+                            length: 100,
+                        },
+                        action: RedactAction::Remove,
+                    },
+                ],
+                data_slice: Some(DataSlice {
+                    offset: 1,
+                    length: 8,
+                }),
+                ..AccountFilterConfig::default()
+            }],
+            ..Config::default()
+        };
+
+        let filter = Filter::new(&config);
+        let pubkey = Pubkey::new_unique().to_bytes();
+        let data = [1, 2, 3, 4, 5, 6];
+        let group = filter.wants_filter(&pubkey, &pubkey, &data).unwrap();
+        assert_eq!(&*group.account_data(&data), &[0, 0, 4]);
+    }
 }
@@ -0,0 +1,103 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! FlatBuffers encoding of events, for consumers reading at rates where decoding protobuf
+//! becomes the bottleneck.
+//!
+//! The schema and the encoders are generated from the proto messages at build time. Field ids
+//! follow the proto field numbers, `google.protobuf` wrappers become optional scalars, oneof
+//! members optional fields and repeated bytes vectors of `Bytes` tables.
+
+use {
+    crate::event::*,
+    flatbuffers::{FlatBufferBuilder, TableFinishedWIPOffset, WIPOffset},
+    std::cell::RefCell,
+};
+
+/// The schema, `event.fbs`.
+pub const SCHEMA: &str = include_str!(concat!(env!("OUT_DIR"), "/event.fbs"));
+
+thread_local! {
+    static BUILDER: RefCell<FlatBufferBuilder<'static>> = RefCell::new(FlatBufferBuilder::new());
+}
+
+pub trait FlatEncode {
+    fn encode_flat(&self, fbb: &mut FlatBufferBuilder<'_>) -> WIPOffset<TableFinishedWIPOffset>;
+}
+
+/// An element of a repeated bytes field.
+fn encode_bytes(
+    fbb: &mut FlatBufferBuilder<'_>,
+    bytes: &[u8],
+) -> WIPOffset<TableFinishedWIPOffset> {
+    let value = fbb.create_vector(bytes);
+    let table = fbb.start_table();
+    fbb.push_slot_always(4, value);
+    fbb.end_table(table)
+}
+
+include!(concat!(env!("OUT_DIR"), "/flatbuffers.rs"));
+
+/// Appends the event as a FlatBuffer with the event's table at the root.
+pub fn to_writer<M: FlatEncode>(buf: &mut Vec<u8>, message: &M) {
+    BUILDER.with(|builder| {
+        let mut fbb = builder.borrow_mut();
+        fbb.reset();
+        let root = message.encode_flat(&mut fbb);
+        fbb.finish_minimal(root);
+        buf.extend_from_slice(fbb.finished_data());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, flatbuffers::Table};
+
+    #[test]
+    fn test_update_account() {
+        let event = UpdateAccountEvent {
+            slot: 7,
+            pubkey: vec![1; 32],
+            data: vec![2, 3],
+            block_time: Some(-1),
+            ..UpdateAccountEvent::default()
+        };
+        let mut buf = Vec::new();
+        to_writer(&mut buf, &event);
+        // SAFETY: the buffer was just written with the root table at its start.
+        let table = unsafe { flatbuffers::root_unchecked::<Table>(&buf) };
+        let slot = unsafe { table.get::<u64>(4, Some(0)) };
+        assert_eq!(slot, Some(7));
+        let data =
+            unsafe { table.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<u8>>>(16, None) };
+        assert_eq!(data.map(|data| data.bytes()), Some(&[2, 3][..]));
+        let lamports = unsafe { table.get::<u64>(8, None) };
+        assert_eq!(lamports, None);
+        let block_time = unsafe { table.get::<i64>(22, None) };
+        assert_eq!(block_time, Some(-1));
+    }
+
+    #[test]
+    fn test_schema_ids() {
+        assert!(SCHEMA.contains("  block_time: long = null (id: 9);\n"));
+        for table in SCHEMA.split("table ").skip(1) {
+            let ids = table
+                .lines()
+                .filter_map(|line| line.split("(id: ").nth(1))
+                .map(|id| id.trim_end_matches(");").parse::<usize>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, (0..ids.len()).collect::<Vec<_>>(), "{}", table);
+        }
+    }
+}
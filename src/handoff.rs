@@ -0,0 +1,405 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Gapless handoff from `snapshot-backfill` to the live stream.
+//!
+//! Live events are held from startup until the backfill announces on the control topic the
+//! slot up to which it published the accounts of a snapshot. Events of that slot and older are
+//! then dropped and the others published, held ones first, so that consumers reading the
+//! backfill and then the live topics see every change once. Events dropped while held, when the
+//! buffer is full or over the memory cap, break that guarantee and are logged as errors.
+
+use {
+    crate::{
+        config::{Config, HandoffConfig},
+        memory::{self, MEMORY},
+        prom::{StatsThreadedProducerContext, EVENTS_SKIPPED_TOTAL},
+        workers::Job,
+        BackfillCompletedEvent,
+    },
+    prost::Message as _,
+    rdkafka::{
+        config::FromClientConfigAndContext,
+        consumer::{BaseConsumer, Consumer},
+        error::{KafkaError, RDKafkaErrorCode},
+        Message, Offset, TopicPartitionList,
+    },
+    std::{
+        collections::{HashSet, VecDeque},
+        mem,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            mpsc::{self, Sender, TryRecvError},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+    tracing::{error, info, warn},
+};
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Holds live events until the backfill completes, consuming the control topic on a
+/// background thread.
+#[derive(Debug)]
+pub struct Handoff {
+    state: Arc<HandoffState>,
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug, Default)]
+struct HandoffState {
+    /// Set once the backfill slot is known, until the held events are dispatched.
+    releasable: AtomicBool,
+    /// Set once the held events were taken, after which events are only checked against
+    /// `backfill_slot`.
+    released: AtomicBool,
+    /// Held while releasing, so that events admitted meanwhile wait and follow the released ones.
+    releasing: Mutex<()>,
+    backfill_slot: AtomicU64,
+    held: Mutex<Held>,
+}
+
+#[derive(Debug, Default)]
+struct Held {
+    jobs: VecDeque<Job>,
+    capacity: usize,
+    /// Oldest slot of the events held, to detect events missed before startup.
+    first_slot: Option<u64>,
+    /// Events dropped while held, after which the handoff is no longer gapless.
+    dropped: u64,
+}
+
+impl Handoff {
+    /// Starts consuming the control topic from its beginning. The latest announcement for the
+    /// account topic found up to its end releases the events, so that a backfill completing
+    /// before startup does too, else the first announcement made after startup.
+    pub fn start(config: &Config, handoff: &HandoffConfig) -> Result<Self, KafkaError> {
+        let mut client_config = config.client_config();
+        // Statistics of the consumer would be mixed into the producer's.
+        client_config.remove("statistics.interval.ms");
+        client_config
+            .set(
+                "group.id",
+                format!("{}-handoff-{}", env!("CARGO_PKG_NAME"), std::process::id()),
+            )
+            .set("enable.auto.commit", "false")
+            .set("enable.partition.eof", "true");
+        let consumer = BaseConsumer::from_config_and_context(
+            &client_config,
+            StatsThreadedProducerContext::new(config.oauthbearer.clone()),
+        )?;
+        let metadata =
+            consumer.fetch_metadata(Some(handoff.control_topic.as_str()), METADATA_TIMEOUT)?;
+        let mut partitions = TopicPartitionList::new();
+        // Partitions not yet read up to their end, before which announcements may be outdated.
+        let mut unread = HashSet::new();
+        for topic in metadata.topics() {
+            for partition in topic.partitions() {
+                unread.insert(partition.id());
+                partitions.add_partition_offset(topic.name(), partition.id(), Offset::Beginning)?;
+            }
+        }
+        if partitions.count() == 0 {
+            return Err(KafkaError::MetadataFetch(
+                RDKafkaErrorCode::UnknownTopicOrPartition,
+            ));
+        }
+        consumer.assign(&partitions)?;
+
+        let state = Arc::new(HandoffState {
+            held: Mutex::new(Held {
+                capacity: handoff.buffer_capacity,
+                ..Held::default()
+            }),
+            ..HandoffState::default()
+        });
+        let account_topic = config.update_account_topic.clone();
+        let (stop, stopped) = mpsc::channel();
+        let thread = {
+            let state = Arc::clone(&state);
+            thread::Builder::new()
+                .name("kafkaHandoff".to_owned())
+                .spawn(move || {
+                    let mut latest = None;
+                    while let Err(TryRecvError::Empty) = stopped.try_recv() {
+                        match consumer.poll(POLL_INTERVAL) {
+                            Some(Ok(message)) => match BackfillCompletedEvent::decode(
+                                message.payload().unwrap_or_default(),
+                            ) {
+                                Ok(event) if event.topic == account_topic => latest = Some(event),
+                                // Another plugin's backfill.
+                                Ok(_) => {}
+                                Err(error) => warn!(%error, "invalid handoff control message"),
+                            },
+                            Some(Err(KafkaError::PartitionEOF(partition))) => {
+                                unread.remove(&partition);
+                            }
+                            Some(Err(error)) => {
+                                warn!(%error, "failed to consume handoff control topic");
+                            }
+                            None => {}
+                        }
+                        if !unread.is_empty() {
+                            continue;
+                        }
+                        if let Some(event) = latest.take() {
+                            info!(
+                                slot = event.slot,
+                                accounts = event.accounts,
+                                completed_at = event.timestamp,
+                                "backfill completed, releasing live events"
+                            );
+                            state.complete(event.slot);
+                            return;
+                        }
+                    }
+                })
+                .expect("failed to spawn handoff thread")
+        };
+        info!(topic = %handoff.control_topic, "holding live events until the backfill completes");
+        Ok(Self {
+            state,
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the job if it is to be published now, holding it until the backfill completes
+    /// or dropping it if the backfill covers its slot. Once the backfill completed, the held
+    /// jobs it does not cover are first passed to `dispatch`, so that they precede it.
+    pub fn admit(&self, job: Job, mut dispatch: impl FnMut(Job)) -> Option<Job> {
+        if !self.state.released.load(Ordering::SeqCst) {
+            if self.state.releasable.load(Ordering::SeqCst) {
+                self.release(&mut dispatch);
+            } else {
+                let mut held = self.state.held.lock().unwrap();
+                // Released while waiting for the lock.
+                if !self.state.released.load(Ordering::SeqCst) {
+                    held.hold(job);
+                    return None;
+                }
+            }
+        }
+        // Released by another event, whose dispatch of the held jobs this one waits for.
+        if self.state.releasable.load(Ordering::SeqCst) {
+            self.release(&mut dispatch);
+        }
+        self.state.check(job)
+    }
+
+    /// Passes the held jobs not covered by the backfill to `dispatch` once it completed, in the
+    /// order they were held. Jobs admitted meanwhile wait until they are dispatched.
+    fn release(&self, mut dispatch: impl FnMut(Job)) {
+        let _releasing = self.state.releasing.lock().unwrap();
+        if !self.state.releasable.load(Ordering::SeqCst) {
+            return;
+        }
+        let jobs = {
+            let mut held = self.state.held.lock().unwrap();
+            let backfill_slot = self.state.backfill_slot.load(Ordering::SeqCst);
+            if let Some(first_slot) = held.first_slot.filter(|slot| *slot > backfill_slot + 1) {
+                error!(
+                    backfill_slot,
+                    first_slot,
+                    "live events started after the backfill, slots in between are missing"
+                );
+            }
+            if held.dropped > 0 {
+                error!(
+                    dropped = held.dropped,
+                    "events held for the backfill were dropped, the handoff is not gapless"
+                );
+            }
+            self.state.released.store(true, Ordering::SeqCst);
+            mem::take(&mut held.jobs)
+        };
+        for job in jobs {
+            MEMORY.release(job.size());
+            if let Some(job) = self.state.check(job) {
+                dispatch(job);
+            }
+        }
+        self.state.releasable.store(false, Ordering::SeqCst);
+    }
+}
+
+impl HandoffState {
+    fn complete(&self, slot: u64) {
+        self.backfill_slot.store(slot, Ordering::SeqCst);
+        self.releasable.store(true, Ordering::SeqCst);
+    }
+
+    /// Drops jobs of slots the backfill covers.
+    fn check(&self, job: Job) -> Option<Job> {
+        if job.slot() > self.backfill_slot.load(Ordering::SeqCst) {
+            return Some(job);
+        }
+        EVENTS_SKIPPED_TOTAL
+            .with_label_values(&[job.event(), "backfilled"])
+            .inc();
+        None
+    }
+}
+
+impl Held {
+    fn hold(&mut self, job: Job) {
+        let slot = job.slot();
+        self.first_slot = Some(self.first_slot.map_or(slot, |first| first.min(slot)));
+        if self.jobs.len() >= self.capacity {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[job.event(), "handoff_buffer_full"])
+                .inc();
+            self.drop_events(1);
+            return;
+        }
+        let jobs = &mut self.jobs;
+        let mut evicted = 0;
+        let reserved = MEMORY.reserve(&job, job.size(), |needed| {
+            let len = jobs.len();
+            let freed = memory::evict_oldest_accounts(jobs, needed);
+            evicted += len - jobs.len();
+            freed
+        });
+        if reserved {
+            jobs.push_back(job);
+        } else {
+            evicted += 1;
+        }
+        self.drop_events(evicted as u64);
+    }
+
+    /// Counts events dropped while held, logging the first drop as it breaks the handoff.
+    fn drop_events(&mut self, events: u64) {
+        if events > 0 && self.dropped == 0 {
+            error!("dropping events held for the backfill, the handoff is no longer gapless");
+        }
+        self.dropped += events;
+    }
+}
+
+impl Drop for Handoff {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("handoff thread panicked");
+            }
+        }
+        // Events still held were never released by a backfill.
+        for job in self.state.held.lock().unwrap().jobs.drain(..) {
+            MEMORY.release(job.size());
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[job.event(), "handoff_pending"])
+                .inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::*};
+
+    fn slot(slot: u64) -> Job {
+        Job::Slot(SlotStatusEvent {
+            slot,
+            ..SlotStatusEvent::default()
+        })
+    }
+
+    fn handoff(capacity: usize) -> Handoff {
+        Handoff {
+            state: Arc::new(HandoffState {
+                held: Mutex::new(Held {
+                    capacity,
+                    ..Held::default()
+                }),
+                ..HandoffState::default()
+            }),
+            stop: Mutex::new(mpsc::channel().0),
+            thread: None,
+        }
+    }
+
+    #[test]
+    fn test_release() {
+        let handoff = handoff(10);
+        let mut dispatched = Vec::new();
+        for held in [5, 7, 6, 8] {
+            assert!(handoff
+                .admit(slot(held), |job| dispatched.push(job.slot()))
+                .is_none());
+        }
+        assert!(dispatched.is_empty());
+
+        handoff.state.complete(6);
+        let admitted = handoff.admit(slot(9), |job| dispatched.push(job.slot()));
+        assert_eq!(admitted.map(|job| job.slot()), Some(9));
+        assert_eq!(dispatched, [7, 8]);
+        assert!(handoff.admit(slot(6), |_| unreachable!()).is_none());
+        assert_eq!(
+            handoff
+                .admit(slot(10), |_| unreachable!())
+                .map(|job| job.slot()),
+            Some(10)
+        );
+    }
+
+    #[test]
+    fn test_admitted_while_releasing() {
+        let handoff = Arc::new(handoff(10));
+        for held in [5, 6] {
+            assert!(handoff.admit(slot(held), |_| unreachable!()).is_none());
+        }
+        handoff.state.complete(4);
+
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let (started, start) = mpsc::channel();
+        let releasing = {
+            let handoff = Arc::clone(&handoff);
+            let published = Arc::clone(&published);
+            thread::spawn(move || {
+                let admitted = handoff.admit(slot(7), |job| {
+                    let _ = started.send(());
+                    thread::sleep(Duration::from_millis(50));
+                    published.lock().unwrap().push(job.slot());
+                });
+                published.lock().unwrap().push(admitted.unwrap().slot());
+            })
+        };
+        start.recv().unwrap();
+        // Admitted while the held events are dispatched, so published after them.
+        let admitted = handoff.admit(slot(8), |_| unreachable!());
+        published.lock().unwrap().push(admitted.unwrap().slot());
+        releasing.join().unwrap();
+
+        let published = published.lock().unwrap();
+        assert_eq!(published[..2], [5, 6]);
+        assert_eq!(published.len(), 4);
+    }
+
+    #[test]
+    fn test_buffer_full() {
+        let handoff = handoff(1);
+        for held in [5, 6, 7] {
+            assert!(handoff.admit(slot(held), |_| unreachable!()).is_none());
+        }
+        let held = handoff.state.held.lock().unwrap();
+        assert_eq!(held.jobs.len(), 1);
+        assert_eq!(held.dropped, 2);
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Static and templated headers added to every message, as routing hints for Kafka Connect
+//! sinks, e.g. `table={owner}`.
+
+use {
+    crate::sample::Summary,
+    std::{collections::HashMap, fmt::Write},
+};
+
+/// Placeholders taking their value from the event, as named in templates.
+const PLACEHOLDERS: &[(&str, Field)] = &[
+    ("event", Field::Event),
+    ("topic", Field::Topic),
+    ("slot", Field::Slot),
+    ("pubkey", Field::Pubkey),
+    ("owner", Field::Owner),
+];
+
+/// Headers the publisher sets itself, which configured headers would shadow.
+const RESERVED: &[&str] = &[
+    "content-type",
+    "schema-version",
+    "content-encoding",
+    "validator-identity",
+    "chain-timestamp",
+    "original-topic",
+    "chunk-id",
+    "chunk-index",
+    "chunk-count",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Event,
+    Topic,
+    Slot,
+    Pubkey,
+    Owner,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// Header templates, rendered for every message.
+#[derive(Debug, Default)]
+pub struct HeaderTemplates {
+    templates: Vec<(String, Vec<Part>)>,
+}
+
+impl HeaderTemplates {
+    /// Parses `{name}` placeholders in the values, `{network}` having been resolved already.
+    pub fn parse(headers: &HashMap<String, String>) -> Result<Self, String> {
+        if let Some(name) = headers.keys().find(|name| {
+            RESERVED
+                .iter()
+                .any(|reserved| name.eq_ignore_ascii_case(reserved))
+        }) {
+            return Err(format!("header {} is set by the plugin", name));
+        }
+        let mut templates = headers
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), parse_template(value)?)))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|error| format!("invalid header template: {}", error))?;
+        templates.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(Self { templates })
+    }
+
+    /// Whether rendering needs the event summary.
+    pub fn uses_summary(&self) -> bool {
+        self.templates
+            .iter()
+            .flat_map(|(_, parts)| parts)
+            .any(|part| {
+                matches!(
+                    part,
+                    Part::Field(Field::Slot)
+                        | Part::Field(Field::Pubkey)
+                        | Part::Field(Field::Owner)
+                )
+            })
+    }
+
+    /// Renders the headers of an event. Headers using a field the event does not have, such as
+    /// the owner of a slot status, are left out.
+    pub fn render(
+        &self,
+        event: &str,
+        topic: &str,
+        summary: Option<&Summary>,
+    ) -> Vec<(&str, String)> {
+        self.templates
+            .iter()
+            .filter_map(|(name, parts)| {
+                let mut value = String::new();
+                for part in parts {
+                    match part {
+                        Part::Literal(literal) => value.push_str(literal),
+                        Part::Field(Field::Event) => value.push_str(event),
+                        Part::Field(Field::Topic) => value.push_str(topic),
+                        Part::Field(Field::Slot) => {
+                            let _ = write!(value, "{}", summary?.slot);
+                        }
+                        Part::Field(Field::Pubkey) => value.push_str(summary?.pubkey.as_ref()?),
+                        Part::Field(Field::Owner) => value.push_str(summary?.owner.as_ref()?),
+                    }
+                }
+                Some((name.as_str(), value))
+            })
+            .collect()
+    }
+}
+
+fn parse_template(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| format!("unclosed placeholder in {:?}", template))?;
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_owned()));
+        }
+        let name = &rest[start + 1..end];
+        let field = PLACEHOLDERS
+            .iter()
+            .find(|(placeholder, _)| *placeholder == name)
+            .map(|(_, field)| *field)
+            .ok_or_else(|| format!("unknown placeholder {{{}}} in {:?}", name, template))?;
+        parts.push(Part::Field(field));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_owned()));
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let templates = HeaderTemplates::parse(&HashMap::from([
+            ("dataset".to_owned(), "solana_mainnet".to_owned()),
+            ("table".to_owned(), "{event}_{owner}".to_owned()),
+        ]))
+        .unwrap();
+        assert!(templates.uses_summary());
+
+        let summary = Summary {
+            slot: 1,
+            pubkey: Some("pubkey".to_owned()),
+            owner: Some("owner".to_owned()),
+        };
+        assert_eq!(
+            templates.render("account", "accounts", Some(&summary)),
+            [
+                ("dataset", "solana_mainnet".to_owned()),
+                ("table", "account_owner".to_owned())
+            ]
+        );
+        // Slot statuses have no owner.
+        assert_eq!(
+            templates.render("slot", "slots", Some(&Summary::default())),
+            [("dataset", "solana_mainnet".to_owned())]
+        );
+
+        for invalid in ["{owner", "{lamports}"] {
+            assert!(HeaderTemplates::parse(&HashMap::from([(
+                "table".to_owned(),
+                invalid.to_owned()
+            )]))
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_reserved() {
+        for name in ["content-type", "Original-Topic"] {
+            assert_eq!(
+                HeaderTemplates::parse(&HashMap::from([(name.to_owned(), "x".to_owned())]))
+                    .unwrap_err(),
+                format!("header {} is set by the plugin", name)
+            );
+        }
+    }
+}
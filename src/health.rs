@@ -0,0 +1,180 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::breaker::BREAKER,
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, HashMap},
+        fmt::Display,
+        sync::{
+            atomic::{AtomicI64, AtomicU64, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+lazy_static::lazy_static! {
+    pub static ref HEALTH: Health = Health::default();
+}
+
+/// Publishing health, fed by the producer context and served on `/health`.
+#[derive(Debug, Default)]
+pub struct Health {
+    /// Messages handed to librdkafka and not yet acknowledged.
+    queue_depth: AtomicI64,
+    /// Publishing is reported unhealthy once it has been failing for this long.
+    failure_window_ms: AtomicU64,
+    state: Mutex<HealthState>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    brokers: BTreeMap<String, String>,
+    last_delivery: HashMap<String, SystemTime>,
+    failing_since: Option<Instant>,
+    last_error: Option<(SystemTime, String)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub brokers: BTreeMap<String, String>,
+    /// Unix timestamp in milliseconds of the last successful delivery per topic.
+    pub last_delivery_ms: BTreeMap<String, u64>,
+    pub queue_depth: i64,
+    pub failing_for_ms: Option<u64>,
+    /// Unix timestamp in milliseconds and message of the last publishing error.
+    pub last_error: Option<(u64, String)>,
+}
+
+impl Health {
+    pub fn set_failure_window(&self, window: Duration) {
+        self.failure_window_ms
+            .store(window.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_enqueued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_delivery(&self, topic: &str) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+        BREAKER.record(true);
+        let mut state = self.state.lock().unwrap();
+        state.failing_since = None;
+        match state.last_delivery.get_mut(topic) {
+            Some(timestamp) => *timestamp = SystemTime::now(),
+            None => {
+                state
+                    .last_delivery
+                    .insert(topic.to_owned(), SystemTime::now());
+            }
+        }
+    }
+
+    /// Delivery of an enqueued message failed.
+    pub fn record_delivery_failure(&self, error: &dyn Display) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+        self.record_failure(error);
+    }
+
+    /// Publishing failed, either on enqueue or on delivery.
+    pub fn record_failure(&self, error: &dyn Display) {
+        BREAKER.record(false);
+        let mut state = self.state.lock().unwrap();
+        state.failing_since.get_or_insert_with(Instant::now);
+        state.last_error = Some((SystemTime::now(), error.to_string()));
+    }
+
+    /// Restart the failure window, e.g. after the watchdog intervened.
+    pub fn reset_failure(&self) {
+        self.state.lock().unwrap().failing_since = None;
+    }
+
+    /// Broker state from librdkafka statistics.
+    #[cfg_attr(not(feature = "rdkafka-backend"), allow(dead_code))]
+    pub fn set_broker_state(&self, broker: &str, broker_state: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.brokers.get(broker).map(String::as_str) != Some(broker_state) {
+            state
+                .brokers
+                .insert(broker.to_owned(), broker_state.to_owned());
+        }
+    }
+
+    pub fn queue_depth(&self) -> i64 {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let failure_window = Duration::from_millis(self.failure_window_ms.load(Ordering::Relaxed));
+        let state = self.state.lock().unwrap();
+        let failing_for = state.failing_since.map(|since| since.elapsed());
+        let unix_ms = |timestamp: &SystemTime| {
+            timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64
+        };
+        HealthReport {
+            healthy: !matches!(failing_for, Some(failing_for) if failing_for > failure_window),
+            brokers: state.brokers.clone(),
+            last_delivery_ms: state
+                .last_delivery
+                .iter()
+                .map(|(topic, timestamp)| (topic.clone(), unix_ms(timestamp)))
+                .collect(),
+            queue_depth: self.queue_depth(),
+            failing_for_ms: failing_for.map(|failing_for| failing_for.as_millis() as u64),
+            last_error: state
+                .last_error
+                .as_ref()
+                .map(|(timestamp, error)| (unix_ms(timestamp), error.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::thread};
+
+    #[test]
+    fn test_report() {
+        let health = Health::default();
+        health.set_failure_window(Duration::from_millis(1));
+        health.set_broker_state("localhost:9092/1", "UP");
+        assert!(health.report().healthy);
+
+        health.record_enqueued();
+        health.record_delivery_failure(&"broker down");
+        thread::sleep(Duration::from_millis(5));
+        let report = health.report();
+        assert!(!report.healthy);
+        assert_eq!(report.queue_depth, 0);
+        assert!(report.failing_for_ms.is_some());
+        assert_eq!(report.last_error.unwrap().1, "broker down");
+        assert_eq!(report.brokers["localhost:9092/1"], "UP");
+
+        health.record_enqueued();
+        health.record_delivery("accounts");
+        let report = health.report();
+        assert!(report.healthy);
+        assert_eq!(report.failing_for_ms, None);
+        assert!(report.last_delivery_ms.contains_key("accounts"));
+        // The last error is kept for diagnosis.
+        assert!(report.last_error.is_some());
+    }
+}
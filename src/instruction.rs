@@ -0,0 +1,187 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transactions exploded into one event per instruction, for consumers indexing instructions of
+//! particular programs rather than whole transactions.
+
+use {
+    crate::{replica::TransactionInfo, *},
+    solana_program::instruction::CompiledInstruction as SdkCompiledInstruction,
+};
+
+/// Events of the outer and inner instructions of a transaction whose program the filter wants,
+/// in execution order.
+pub fn build_instruction_events(
+    slot: u64,
+    transaction: &TransactionInfo,
+    filter: &Filter,
+    block_time: Option<i64>,
+) -> Vec<InstructionEvent> {
+    let message = transaction.transaction.message();
+    let account_keys = message.account_keys();
+    let is_status_err = transaction.transaction_status_meta.status.is_err();
+    let build = |ix: &SdkCompiledInstruction,
+                 instruction_index: usize,
+                 inner_index: Option<usize>,
+                 stack_height: Option<u32>| {
+        let program_id = account_keys.get(ix.program_id_index as usize)?;
+        if !filter.wants_transaction_program(program_id.as_ref()) {
+            return None;
+        }
+        Some(InstructionEvent {
+            signature: transaction.signature.as_ref().to_vec(),
+            slot,
+            transaction_index: transaction.index.map(|index| index as u64),
+            instruction_index: instruction_index as u32,
+            inner_index: inner_index.map(|index| index as u32),
+            stack_height,
+            program_id: program_id.to_bytes().to_vec(),
+            accounts: ix
+                .accounts
+                .iter()
+                .filter_map(|index| account_keys.get(*index as usize))
+                .map(|account| account.to_bytes().to_vec())
+                .collect(),
+            data: ix.data.clone(),
+            is_status_err,
+            block_time,
+        })
+    };
+
+    let inner_instructions = transaction
+        .transaction_status_meta
+        .inner_instructions
+        .as_deref()
+        .unwrap_or_default();
+    let mut events = Vec::new();
+    for (index, ix) in message.instructions().iter().enumerate() {
+        events.extend(build(ix, index, None, Some(1)));
+        for inner in inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == index)
+        {
+            for (inner_index, ix) in inner.instructions.iter().enumerate() {
+                let (ix, stack_height) = (&ix.instruction, ix.stack_height);
+                events.extend(build(ix, index, Some(inner_index), stack_height));
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::{
+            hash::Hash,
+            message::Message,
+            pubkey::Pubkey,
+            transaction::{SanitizedTransaction, Transaction, TransactionError},
+        },
+        solana_transaction_status::{
+            InnerInstruction, InnerInstructions, TransactionStatusMeta as SdkTransactionStatusMeta,
+        },
+    };
+
+    fn instruction(program_id_index: u8, data: u8) -> SdkCompiledInstruction {
+        SdkCompiledInstruction {
+            program_id_index,
+            accounts: vec![0, 1],
+            data: vec![data],
+        }
+    }
+
+    fn inner(program_id_index: u8, data: u8) -> InnerInstruction {
+        InnerInstruction {
+            instruction: instruction(program_id_index, data),
+            stack_height: Some(2),
+        }
+    }
+
+    #[test]
+    fn test_matched_instructions() {
+        let (payer, account) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (wanted, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let message = Message::new_with_compiled_instructions(
+            1,
+            0,
+            2,
+            vec![payer, account, wanted, other],
+            Hash::new_unique(),
+            vec![instruction(2, 0), instruction(3, 1), instruction(2, 2)],
+        );
+        let transaction =
+            SanitizedTransaction::from_transaction_for_tests(Transaction::new_unsigned(message));
+        let meta = SdkTransactionStatusMeta {
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 1,
+                instructions: vec![inner(2, 10), inner(3, 11), inner(2, 12)],
+            }]),
+            ..SdkTransactionStatusMeta::default()
+        };
+        let failed = SdkTransactionStatusMeta {
+            status: Err(TransactionError::AccountNotFound),
+            ..meta.clone()
+        };
+        let filter = Filter::new(&Config {
+            program_filters: vec![wanted.to_string()],
+            ..Config::default()
+        });
+        let info = |meta| TransactionInfo {
+            signature: transaction.signature(),
+            is_vote: false,
+            transaction: &transaction,
+            transaction_status_meta: meta,
+            index: Some(4),
+        };
+
+        let events = build_instruction_events(9, &info(&meta), &filter, Some(100));
+        let positions = events
+            .iter()
+            .map(|event| {
+                (
+                    event.instruction_index,
+                    event.inner_index,
+                    event.stack_height,
+                    event.data[0],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                (0, None, Some(1), 0),
+                (1, Some(0), Some(2), 10),
+                (1, Some(2), Some(2), 12),
+                (2, None, Some(1), 2)
+            ]
+        );
+        let event = &events[0];
+        assert_eq!(event.signature, transaction.signature().as_ref());
+        assert_eq!(event.slot, 9);
+        assert_eq!(event.transaction_index, Some(4));
+        assert_eq!(event.program_id, wanted.to_bytes());
+        assert_eq!(
+            event.accounts,
+            [payer.to_bytes().to_vec(), account.to_bytes().to_vec()]
+        );
+        assert_eq!(event.block_time, Some(100));
+        assert!(!event.is_status_err);
+
+        let events = build_instruction_events(9, &info(&failed), &filter, None);
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|event| event.is_status_err));
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON encoding of events.
+//!
+//! Keys, hashes and signatures are encoded as base58. Account and instruction data use the
+//! encoding configured for the topic, passed to the field serializers through a thread local
+//! since serde has no way to hand state to `serialize_with` functions.
+
+use {
+    crate::{
+        config::{DataEncoding, JsonCompression},
+        prom::DATA_COMPRESSION_FAILURES_TOTAL,
+    },
+    flate2::{write::GzEncoder, Compression},
+    serde::{Serialize, Serializer},
+    std::{
+        cell::Cell,
+        io::{Result as IoResult, Write},
+    },
+};
+
+thread_local! {
+    static DATA_ENCODING: Cell<DataEncoding> = const { Cell::new(DataEncoding::Base64) };
+}
+
+pub fn to_writer<T: Serialize>(buf: &mut Vec<u8>, value: &T, data_encoding: DataEncoding) {
+    DATA_ENCODING.with(|encoding| encoding.set(data_encoding));
+    serde_json::to_writer(buf, value).expect("events always serialize to JSON")
+}
+
+/// Compresses an encoded message, `None` if uncompressed or compression failed.
+pub fn compress(payload: &[u8], compression: JsonCompression) -> Option<Vec<u8>> {
+    match compression {
+        JsonCompression::None => None,
+        JsonCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(payload).ok()?;
+            encoder.finish().ok()
+        }
+        JsonCompression::Zstd => zstd::bulk::compress(payload, 0).ok(),
+    }
+}
+
+pub fn data<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    match DATA_ENCODING.with(Cell::get) {
+        DataEncoding::Base64 => serializer.serialize_str(&base64::encode(bytes)),
+        DataEncoding::Base64Zstd => {
+            serializer.serialize_str(&base64_zstd(bytes, zstd::bulk::compress(bytes, 0)))
+        }
+        DataEncoding::Hex => serializer.serialize_str(&hex::encode(bytes)),
+    }
+}
+
+/// Falls back to plain base64 if compression failed, which consumers cannot tell apart from
+/// compressed data, hence the counter.
+fn base64_zstd(bytes: &[u8], compressed: IoResult<Vec<u8>>) -> String {
+    match compressed {
+        Ok(compressed) => base64::encode(compressed),
+        Err(_) => {
+            DATA_COMPRESSION_FAILURES_TOTAL.inc();
+            base64::encode(bytes)
+        }
+    }
+}
+
+pub fn pubkey<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&bs58::encode(bytes).into_string())
+}
+
+pub fn pubkeys<S: Serializer>(keys: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(keys.iter().map(|key| bs58::encode(key).into_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Error};
+
+    #[test]
+    fn test_base64_zstd_fallback() {
+        let compressed = zstd::bulk::compress(b"data", 0).unwrap();
+        let failures = DATA_COMPRESSION_FAILURES_TOTAL.get();
+        assert_eq!(
+            base64_zstd(b"data", Ok(compressed.clone())),
+            base64::encode(compressed)
+        );
+        assert_eq!(DATA_COMPRESSION_FAILURES_TOTAL.get(), failures);
+
+        let error = Error::other("compression failed");
+        assert_eq!(base64_zstd(b"data", Err(error)), base64::encode(b"data"));
+        assert_eq!(DATA_COMPRESSION_FAILURES_TOTAL.get(), failures + 1);
+    }
+}
@@ -12,25 +12,115 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(all(feature = "solana", feature = "agave"))]
+compile_error!("features `solana` and `agave` are mutually exclusive");
+#[cfg(not(any(feature = "solana", feature = "agave")))]
+compile_error!("build with the `agave` feature, or through compat/solana/Cargo.toml");
+
+// Agave crates are renamed so that the rest of the plugin is written against one set of names.
+#[cfg(feature = "agave")]
+extern crate agave_geyser_plugin_interface as solana_geyser_plugin_interface;
+#[cfg(feature = "agave")]
+extern crate solana_program_agave as solana_program;
+#[cfg(feature = "agave")]
+extern crate solana_sdk_agave as solana_sdk;
+#[cfg(feature = "agave")]
+extern crate solana_transaction_status_agave as solana_transaction_status;
+
 use solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPlugin;
 
+mod account_state;
+mod announce;
+mod block_time;
+mod blockhash;
+mod bootstrap;
+mod breaker;
+mod buffer;
+mod cert_watcher;
+mod checkpoint;
+mod compute_budget;
 mod config;
+mod dump;
+mod error;
 mod event;
+mod event_hubs;
+mod failover;
 mod filter;
+mod flat;
+#[cfg(feature = "rdkafka-backend")]
+mod handoff;
+mod headers;
+mod health;
+mod instruction;
+mod json;
+mod memory;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "rdkafka-backend")]
+mod msk;
+mod network;
+#[cfg(feature = "rdkafka-backend")]
+mod oauth;
+mod pause;
 mod plugin;
+mod producer;
+mod projection;
 mod prom;
 mod publisher;
+mod recovery;
+mod remote;
+mod rent;
+mod replica;
+mod rest_proxy;
+#[cfg(feature = "rskafka-backend")]
+mod rskafka_producer;
+mod s3;
+mod sample;
+mod shard;
+mod sigv4;
+#[cfg(feature = "parquet-snapshots")]
+mod snapshot;
+mod staking;
+mod startup;
+mod switches;
+mod telemetry;
+mod throttle;
+mod token;
+mod top_programs;
+mod topic_limits;
+mod transform;
+mod upgrade;
 mod version;
+mod watchdog;
+mod watermark;
+mod workers;
+#[cfg(feature = "zeromq")]
+mod zeromq;
 
 pub use {
-    config::{Config, Producer},
+    config::{
+        AccountFilterConfig, Backend, Config, DataSlice, Format, MemcmpConfig, RedactAction,
+        Redaction,
+    },
+    error::PublishError,
     event::*,
     filter::Filter,
+    flat::SCHEMA as FLATBUFFERS_SCHEMA,
     plugin::KafkaPlugin,
-    prom::PrometheusService,
+    producer::{MemoryProducer, MemoryRecord, ProducerError, ProducerLike},
+    prom::{PrometheusAddress, PrometheusService},
     publisher::Publisher,
+    rent::RentTracker,
+    startup::StartupProgress,
+    telemetry::Telemetry,
+    top_programs::TopPrograms,
+    transform::Transform,
+    watchdog::Watchdog,
 };
 
+#[cfg(feature = "rdkafka-backend")]
+pub use config::Producer;
+
 #[no_mangle]
 #[allow(improper_ctypes_definitions)]
 /// # Safety
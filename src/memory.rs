@@ -0,0 +1,138 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cap on the bytes of events held in the plugin's own buffers, the worker queues and the
+//! pause buffer, so that a long outage cannot exhaust the validator's memory. Over the cap the
+//! oldest account updates are evicted first, then new events other than slot statuses are
+//! dropped. Slot statuses are always kept.
+
+use {
+    crate::{
+        prom::{BUFFERED_BYTES, EVENTS_SKIPPED_TOTAL},
+        workers::Job,
+    },
+    std::{
+        collections::VecDeque,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+};
+
+lazy_static::lazy_static! {
+    pub static ref MEMORY: MemoryBudget = MemoryBudget::default();
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    /// 0 for no cap.
+    limit: AtomicUsize,
+    held: AtomicUsize,
+}
+
+impl MemoryBudget {
+    pub fn configure(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Accounts for a job about to be buffered, evicting held jobs through `evict` when over
+    /// the cap. `evict` is given the bytes to free and returns the bytes it freed. Returns
+    /// `false` if the job has to be dropped.
+    pub fn reserve(&self, job: &Job, size: usize, mut evict: impl FnMut(usize) -> usize) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit > 0 {
+            let held = self.held.load(Ordering::Relaxed);
+            if held + size > limit {
+                let freed = evict(held + size - limit);
+                self.release(freed);
+                if self.held.load(Ordering::Relaxed) + size > limit && !matches!(job, Job::Slot(_))
+                {
+                    EVENTS_SKIPPED_TOTAL
+                        .with_label_values(&[job.event(), "memory_cap"])
+                        .inc();
+                    return false;
+                }
+            }
+        }
+        let held = self.held.fetch_add(size, Ordering::Relaxed) + size;
+        BUFFERED_BYTES.set(held as i64);
+        true
+    }
+
+    /// Accounts for buffered jobs leaving the buffer.
+    pub fn release(&self, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let held = self.held.fetch_sub(size, Ordering::Relaxed) - size;
+        BUFFERED_BYTES.set(held as i64);
+    }
+}
+
+/// Drops the oldest account updates of a buffer until `needed` bytes are freed, returning the
+/// bytes freed. The caller releases them.
+pub fn evict_oldest_accounts(jobs: &mut VecDeque<Job>, needed: usize) -> usize {
+    let mut freed = 0;
+    jobs.retain(|job| {
+        if freed >= needed || job.pubkey().is_none() {
+            return true;
+        }
+        freed += job.size();
+        EVENTS_SKIPPED_TOTAL
+            .with_label_values(&[job.event(), "memory_cap"])
+            .inc();
+        false
+    });
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::*};
+
+    fn account(data_len: usize) -> Job {
+        Job::Account(UpdateAccountEvent {
+            data: vec![0; data_len],
+            ..UpdateAccountEvent::default()
+        })
+    }
+
+    fn slot(slot: u64) -> Job {
+        Job::Slot(SlotStatusEvent {
+            slot,
+            ..SlotStatusEvent::default()
+        })
+    }
+
+    #[test]
+    fn test_evict_oldest_accounts() {
+        let mut jobs = VecDeque::from([slot(1), account(100), account(200), slot(2)]);
+        let freed = evict_oldest_accounts(&mut jobs, 50);
+        assert_eq!(freed, account(100).size());
+        assert_eq!(jobs.len(), 3);
+        assert!(matches!(&jobs[1], Job::Account(ev) if ev.data.len() == 200));
+    }
+
+    #[test]
+    fn test_reserve() {
+        let budget = MemoryBudget::default();
+        let size = account(100).size();
+        budget.configure(size * 2);
+        assert!(budget.reserve(&account(100), size, |_| 0));
+        assert!(budget.reserve(&account(100), size, |_| 0));
+        assert!(!budget.reserve(&account(100), size, |_| 0));
+        assert!(budget.reserve(&account(100), size, |_| size));
+        assert!(budget.reserve(&slot(1), slot(1).size(), |_| 0));
+        budget.release(size * 2 + slot(1).size());
+        assert_eq!(budget.held.load(Ordering::Relaxed), 0);
+    }
+}
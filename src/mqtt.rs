@@ -0,0 +1,463 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MQTT 5 sink, for edge consumers and platforms with MQTT ingestion. Topics are rendered from
+//! a template, so that subscribers can pick e.g. the updates of one account with a topic filter,
+//! and the event headers become user properties.
+
+use {
+    crate::{
+        config::MqttConfig,
+        health::HEALTH,
+        producer::{ProducerError, ProducerLike},
+        watermark::{Position, WATERMARKS},
+    },
+    rumqttc::{
+        v5::{
+            mqttbytes::{v5::PublishProperties, QoS},
+            Client, Connection, Event, Incoming, MqttOptions,
+        },
+        Outgoing, Transport,
+    },
+    std::{
+        collections::{HashMap, VecDeque},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tracing::{error, info, warn},
+};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A message handed to the client, resolved once the broker acknowledged it.
+struct Pending {
+    topic: String,
+    position: Option<Position>,
+}
+
+#[derive(Default)]
+struct InFlight {
+    /// Messages not written to the connection yet, in the order they were published.
+    queued: VecDeque<Pending>,
+    /// Messages written and waiting for an acknowledgement, by packet id.
+    sent: HashMap<u16, Pending>,
+}
+
+impl InFlight {
+    fn len(&self) -> usize {
+        self.queued.len() + self.sent.len()
+    }
+}
+
+pub struct MqttProducer {
+    client: Client,
+    qos: QoS,
+    topic_template: String,
+    in_flight: Arc<Mutex<InFlight>>,
+    /// Set on drop, so that the thread stops reconnecting.
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MqttProducer {
+    pub fn new(config: &MqttConfig) -> Result<Self, ProducerError> {
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            qos => {
+                return Err(ProducerError::Creation(format!(
+                    "mqtt qos {} is not 0, 1 or 2",
+                    qos
+                )))
+            }
+        };
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+        }
+        if config.tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+        let (client, connection) = Client::new(options, config.queue_capacity.max(1));
+        let in_flight = Arc::new(Mutex::new(InFlight::default()));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let in_flight = Arc::clone(&in_flight);
+            let stopping = Arc::clone(&stopping);
+            thread::Builder::new()
+                .name("kafkaMqtt".to_owned())
+                .spawn(move || run(connection, qos, &in_flight, &stopping))
+                .expect("failed to spawn mqtt thread")
+        };
+        info!(host = %config.host, port = config.port, "created mqtt client");
+        Ok(Self {
+            client,
+            qos,
+            topic_template: config.topic_template.clone(),
+            in_flight,
+            stopping,
+            thread: Some(thread),
+        })
+    }
+
+    fn topic(&self, topic: &str, key: Option<&[u8]>) -> String {
+        let key = key.map_or_else(|| "_".to_owned(), |key| bs58::encode(key).into_string());
+        self.topic_template
+            .replace("{topic}", topic)
+            .replace("{key}", &key)
+    }
+}
+
+/// Drives the connection, resolving messages as they are written and acknowledged.
+fn run(mut connection: Connection, qos: QoS, in_flight: &Mutex<InFlight>, stopping: &AtomicBool) {
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Outgoing(Outgoing::Publish(pkid))) => {
+                let mut in_flight = in_flight.lock().unwrap();
+                // Retransmissions after a reconnect reuse the packet id of a sent message.
+                if qos != QoS::AtMostOnce && in_flight.sent.contains_key(&pkid) {
+                    continue;
+                }
+                if let Some(pending) = in_flight.queued.pop_front() {
+                    if qos == QoS::AtMostOnce {
+                        resolve(&pending, true);
+                    } else {
+                        in_flight.sent.insert(pkid, pending);
+                    }
+                }
+            }
+            Ok(Event::Incoming(Incoming::PubAck(ack))) if qos == QoS::AtLeastOnce => {
+                if let Some(pending) = in_flight.lock().unwrap().sent.remove(&ack.pkid) {
+                    resolve(&pending, true);
+                }
+            }
+            Ok(Event::Incoming(Incoming::PubComp(comp))) if qos == QoS::ExactlyOnce => {
+                if let Some(pending) = in_flight.lock().unwrap().sent.remove(&comp.pkid) {
+                    resolve(&pending, true);
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            // The disconnect request is stuck behind a full in-flight window.
+            Ok(Event::Outgoing(Outgoing::PingReq)) if stopping.load(Ordering::Relaxed) => break,
+            Ok(_) => {}
+            Err(_) if stopping.load(Ordering::Relaxed) => break,
+            Err(error) => {
+                // Unacknowledged messages are sent again once reconnected.
+                warn!(%error, "mqtt connection failed");
+                HEALTH.record_failure(&error);
+                thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+    // Messages the broker never acknowledged.
+    let mut in_flight = in_flight.lock().unwrap();
+    let InFlight { queued, sent } = &mut *in_flight;
+    for pending in queued
+        .drain(..)
+        .chain(sent.drain().map(|(_, pending)| pending))
+    {
+        resolve(&pending, false);
+    }
+}
+
+fn resolve(pending: &Pending, delivered: bool) {
+    if delivered {
+        HEALTH.record_delivery(&pending.topic);
+    } else {
+        HEALTH.record_delivery_failure(&"not acknowledged by the mqtt broker");
+    }
+    if let Some(position) = pending.position {
+        WATERMARKS.record_resolved(&pending.topic, position, delivered);
+    }
+}
+
+impl ProducerLike for MqttProducer {
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        let mut properties = PublishProperties::default();
+        for (name, value) in headers {
+            match *name {
+                "content-type" => properties.content_type = Some(value.to_string()),
+                _ => properties
+                    .user_properties
+                    .push((name.to_string(), value.to_string())),
+            }
+        }
+        if let Some(position) = position {
+            WATERMARKS.record_enqueued(topic, position);
+        }
+        // Held while handing the message over, so that the queue is in the client's order.
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.queued.push_back(Pending {
+            topic: topic.to_owned(),
+            position,
+        });
+        let result = self.client.try_publish_with_properties(
+            self.topic(topic, key),
+            self.qos,
+            false,
+            payload.to_vec(),
+            properties,
+        );
+        if result.is_err() {
+            in_flight.queued.pop_back();
+            if let Some(position) = position {
+                WATERMARKS.record_resolved(topic, position, false);
+            }
+            return Err(ProducerError::QueueFull);
+        }
+        Ok(())
+    }
+
+    fn in_flight_count(&self) -> i32 {
+        self.in_flight.lock().unwrap().len() as i32
+    }
+
+    fn flush(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.in_flight_count() > 0 && Instant::now() < deadline {
+            thread::sleep(FLUSH_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for MqttProducer {
+    /// Callers flush first, messages still unacknowledged are failed.
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        // Waits for room in the queue, and fails once the thread stopped and dropped the connection.
+        let _ = self.client.disconnect();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("mqtt thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            io::{Read, Write},
+            net::{TcpListener, TcpStream},
+            sync::mpsc,
+        },
+    };
+
+    const CONNACK: [u8; 5] = [0x20, 0x03, 0x00, 0x00, 0x00];
+
+    /// A received PUBLISH packet.
+    struct Received {
+        topic: String,
+        pkid: u16,
+        payload: Vec<u8>,
+    }
+
+    fn read_length(stream: &mut TcpStream) -> usize {
+        let mut length = 0;
+        for shift in (0..28).step_by(7) {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            length |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        length
+    }
+
+    /// Reads packets until the next PUBLISH, `None` once the client disconnected.
+    fn read_publish(stream: &mut TcpStream) -> Option<Received> {
+        loop {
+            let mut header = [0];
+            stream.read_exact(&mut header).ok()?;
+            let mut packet = vec![0; read_length(stream)];
+            stream.read_exact(&mut packet).ok()?;
+            match header[0] >> 4 {
+                // PUBLISH at QoS 1: topic, packet id, properties (skipped) and payload.
+                3 => {
+                    let topic_len = usize::from(u16::from_be_bytes([packet[0], packet[1]]));
+                    let topic = String::from_utf8(packet[2..2 + topic_len].to_vec()).unwrap();
+                    let pkid = u16::from_be_bytes([packet[2 + topic_len], packet[3 + topic_len]]);
+                    let properties = 4 + topic_len;
+                    let payload =
+                        packet[properties + 1 + usize::from(packet[properties])..].to_vec();
+                    return Some(Received {
+                        topic,
+                        pkid,
+                        payload,
+                    });
+                }
+                14 => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the CONNECT packet and accepts it.
+    fn accept(listener: &TcpListener) -> TcpStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut header = [0];
+        stream.read_exact(&mut header).unwrap();
+        let mut connect = vec![0; read_length(&mut stream)];
+        stream.read_exact(&mut connect).unwrap();
+        stream.write_all(&CONNACK).unwrap();
+        stream
+    }
+
+    /// Acknowledges every message, sending the received ones on `received`.
+    fn acknowledge(mut stream: TcpStream, received: &mpsc::Sender<Received>) {
+        while let Some(publish) = read_publish(&mut stream) {
+            let [high, low] = publish.pkid.to_be_bytes();
+            stream.write_all(&[0x40, 0x02, high, low]).unwrap();
+            let _ = received.send(publish);
+        }
+    }
+
+    fn config(port: u16, queue_capacity: usize) -> MqttConfig {
+        MqttConfig {
+            host: "127.0.0.1".to_owned(),
+            port,
+            client_id: "test".to_owned(),
+            username: None,
+            password: None,
+            tls: false,
+            qos: 1,
+            topic_template: "{topic}/{key}".to_owned(),
+            queue_capacity,
+        }
+    }
+
+    fn position(slot: u64) -> Option<Position> {
+        Some(Position {
+            slot,
+            write_version: 0,
+        })
+    }
+
+    #[test]
+    fn test_publish() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || acknowledge(accept(&listener), &sender));
+
+        let producer = MqttProducer::new(&config(port, 10)).unwrap();
+        producer
+            .send("mqtt-publish", None, &[], b"first", position(1))
+            .unwrap();
+        producer
+            .send("mqtt-publish", Some(&[1, 2]), &[], b"second", position(2))
+            .unwrap();
+        producer.flush(Duration::from_secs(10));
+        assert_eq!(producer.in_flight_count(), 0);
+        assert_eq!(WATERMARKS.snapshot()["mqtt-publish"], position(2).unwrap());
+
+        let received: Vec<_> = received.try_iter().collect();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].topic, "mqtt-publish/_");
+        assert_eq!(received[0].payload, b"first");
+        assert_eq!(received[1].topic, "mqtt-publish/5T");
+        assert_eq!(received[1].payload, b"second");
+    }
+
+    #[test]
+    fn test_retransmission() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, received) = mpsc::channel();
+        let (dropped, connection_dropped) = mpsc::channel();
+        thread::spawn(move || {
+            // The first message is never acknowledged, and sent again on the next connection.
+            let mut stream = accept(&listener);
+            read_publish(&mut stream).unwrap();
+            drop(stream);
+            dropped.send(()).unwrap();
+            acknowledge(accept(&listener), &sender);
+        });
+
+        let producer = MqttProducer::new(&config(port, 10)).unwrap();
+        producer
+            .send("mqtt-retransmission", None, &[], b"first", position(1))
+            .unwrap();
+        connection_dropped
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap();
+        // Queued while reconnecting, so that it is written after the retransmission.
+        thread::sleep(Duration::from_millis(200));
+        producer
+            .send("mqtt-retransmission", None, &[], b"second", position(2))
+            .unwrap();
+        producer.flush(Duration::from_secs(10));
+        assert_eq!(producer.in_flight_count(), 0);
+        assert_eq!(
+            WATERMARKS.snapshot()["mqtt-retransmission"],
+            position(2).unwrap()
+        );
+
+        let payloads: Vec<_> = received.try_iter().map(|publish| publish.payload).collect();
+        assert_eq!(payloads, [&b"first"[..], b"second"]);
+    }
+
+    #[test]
+    fn test_drop_with_full_queue() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (connect, connected) = mpsc::channel();
+        thread::spawn(move || {
+            // Accepts the connection only once the queue is full and the producer dropped.
+            let (mut stream, _) = listener.accept().unwrap();
+            connected.recv().unwrap();
+            thread::sleep(Duration::from_millis(200));
+            let mut header = [0];
+            stream.read_exact(&mut header).unwrap();
+            let mut packet = vec![0; read_length(&mut stream)];
+            stream.read_exact(&mut packet).unwrap();
+            stream.write_all(&CONNACK).unwrap();
+            while read_publish(&mut stream).is_some() {}
+        });
+
+        let producer = MqttProducer::new(&config(port, 1)).unwrap();
+        let mut sent = 0;
+        while producer.send("mqtt-drop", None, &[], b"data", None).is_ok() {
+            sent += 1;
+            assert!(sent < 10, "the queue never filled up");
+        }
+        let (dropped, producer_dropped) = mpsc::channel();
+        thread::spawn(move || {
+            drop(producer);
+            dropped.send(()).unwrap();
+        });
+        connect.send(()).unwrap();
+        producer_dropped
+            .recv_timeout(Duration::from_secs(20))
+            .expect("dropping the producer hung");
+    }
+}
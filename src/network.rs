@@ -0,0 +1,62 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cluster detection from the genesis hash, for the `{network}` placeholder in topic names.
+
+use {
+    solana_sdk::{genesis_config::GenesisConfig, hash::Hash},
+    std::{io, path::Path},
+};
+
+/// Replaced with the network name in topic names.
+pub const PLACEHOLDER: &str = "{network}";
+
+const NETWORKS: &[(&str, &str)] = &[
+    ("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d", "mainnet"),
+    ("4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY", "testnet"),
+    ("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG", "devnet"),
+];
+
+/// Network of the ledger at `ledger_path`, from the hash of its `genesis.bin`.
+pub fn detect(ledger_path: &Path) -> io::Result<&'static str> {
+    Ok(name(&GenesisConfig::load(ledger_path)?.hash()))
+}
+
+/// `mainnet`, `testnet`, `devnet`, or `custom` for any other cluster.
+pub fn name(genesis_hash: &Hash) -> &'static str {
+    let genesis_hash = genesis_hash.to_string();
+    NETWORKS
+        .iter()
+        .find(|(hash, _)| *hash == genesis_hash)
+        .map_or("custom", |(_, name)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::str::FromStr};
+
+    #[test]
+    fn test_name() {
+        let hash = |s| Hash::from_str(s).unwrap();
+        assert_eq!(
+            name(&hash("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d")),
+            "mainnet"
+        );
+        assert_eq!(
+            name(&hash("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG")),
+            "devnet"
+        );
+        assert_eq!(name(&Hash::default()), "custom");
+    }
+}
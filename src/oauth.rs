@@ -0,0 +1,169 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OAUTHBEARER tokens for SASL authentication, fetched whenever librdkafka asks for a refresh.
+
+use {
+    crate::{config::OAuthBearerConfig, msk, sigv4},
+    rdkafka::client::OAuthToken,
+    serde::Deserialize,
+    std::{
+        error::Error,
+        fs,
+        process::Command,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    tracing::info,
+};
+
+/// Lifetime assumed for tokens without an expiry, so that they are still refreshed regularly.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Principal reported for tokens without a subject.
+const DEFAULT_PRINCIPAL: &str = "solana-accountsdb-plugin-kafka";
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// Claims read from a JWT to report its lifetime and principal to librdkafka.
+#[derive(Default, Deserialize)]
+struct Claims {
+    exp: Option<u64>,
+    sub: Option<String>,
+}
+
+/// Fetches a new token from the configured source.
+pub fn token(config: &OAuthBearerConfig) -> Result<OAuthToken, Box<dyn Error>> {
+    let (token, expires_in) = match config {
+        OAuthBearerConfig::Command { program, args } => {
+            let output = Command::new(program).args(args).output()?;
+            if !output.status.success() {
+                return Err(format!(
+                    "token command failed with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into());
+            }
+            (String::from_utf8(output.stdout)?.trim().to_owned(), None)
+        }
+        OAuthBearerConfig::File { path } => (fs::read_to_string(path)?.trim().to_owned(), None),
+        OAuthBearerConfig::Oidc {
+            token_endpoint,
+            client_id,
+            client_secret,
+            scope,
+        } => {
+            let credentials = base64::encode(format!("{}:{}", client_id, client_secret));
+            let mut form = vec![("grant_type", "client_credentials")];
+            if let Some(scope) = scope {
+                form.push(("scope", scope.as_str()));
+            }
+            let response = ureq::post(token_endpoint)
+                .set("Authorization", &format!("Basic {}", credentials))
+                .send_form(&form)?;
+            let response: TokenResponse = serde_json::from_str(&response.into_string()?)?;
+            (response.access_token, response.expires_in)
+        }
+        OAuthBearerConfig::MskIam { region } => {
+            let region = sigv4::region(region.as_deref())?;
+            let credentials = sigv4::Credentials::load()?;
+            let token = msk::token(&region, &credentials, SystemTime::now());
+            (token, Some(msk::TOKEN_LIFETIME.as_secs()))
+        }
+    };
+    if token.is_empty() {
+        return Err("empty OAUTHBEARER token".into());
+    }
+
+    let claims = jwt_claims(&token).unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let expires_at = match (expires_in, claims.exp) {
+        (Some(expires_in), _) => now + Duration::from_secs(expires_in),
+        (None, Some(exp)) => Duration::from_secs(exp),
+        (None, None) => now + DEFAULT_LIFETIME,
+    };
+    let principal_name = claims.sub.unwrap_or_else(|| DEFAULT_PRINCIPAL.to_owned());
+    info!(
+        principal = %principal_name,
+        expires_in_secs = expires_at.saturating_sub(now).as_secs(),
+        "refreshed OAUTHBEARER token"
+    );
+    Ok(OAuthToken {
+        token,
+        principal_name,
+        lifetime_ms: expires_at.as_millis() as i64,
+    })
+}
+
+/// Claims of a JWT, `None` for opaque tokens. The signature is the broker's business.
+fn jwt_claims(token: &str) -> Option<Claims> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&payload).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(claims: &str) -> String {
+        let encode = |part: &str| base64::encode_config(part, base64::URL_SAFE_NO_PAD);
+        format!(
+            "{}.{}.signature",
+            encode(r#"{"alg":"RS256"}"#),
+            encode(claims)
+        )
+    }
+
+    fn command(script: &str) -> OAuthBearerConfig {
+        OAuthBearerConfig::Command {
+            program: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script.to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_command_token() {
+        let jwt = jwt(r#"{"exp":4102444800,"sub":"validator"}"#);
+        let fetched = token(&command(&format!("echo {}", jwt))).unwrap();
+        assert_eq!(fetched.token, jwt);
+        assert_eq!(fetched.principal_name, "validator");
+        assert_eq!(fetched.lifetime_ms, 4_102_444_800_000);
+
+        let error = token(&command("echo denied >&2; exit 3")).err().unwrap();
+        assert!(error.to_string().contains("denied"));
+        assert_eq!(
+            token(&command("true")).err().unwrap().to_string(),
+            "empty OAUTHBEARER token"
+        );
+    }
+
+    #[test]
+    fn test_file_token() {
+        let path = std::env::temp_dir().join(format!("kafka-plugin-token-{}", std::process::id()));
+        fs::write(&path, "opaque\n").unwrap();
+        let config = OAuthBearerConfig::File { path: path.clone() };
+
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let fetched = token(&config).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(fetched.token, "opaque");
+        assert_eq!(fetched.principal_name, DEFAULT_PRINCIPAL);
+        assert!(fetched.lifetime_ms >= (before + DEFAULT_LIFETIME).as_millis() as i64);
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishing paused by operators through the admin API, per event type. Events of a paused
+//! type are dropped or held until resumed, depending on the configured policy.
+
+use {
+    crate::{
+        config::{AdminConfig, PausePolicy},
+        memory::{self, MEMORY},
+        prom::EVENTS_SKIPPED_TOTAL,
+        workers::Job,
+    },
+    serde::Serialize,
+    std::{
+        collections::{BTreeSet, VecDeque},
+        mem,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+    },
+    tracing::info,
+};
+
+lazy_static::lazy_static! {
+    pub static ref PAUSE: Pause = Pause::default();
+}
+
+/// Event types that can be paused, as labelled in metrics.
+pub const EVENTS: &[&str] = &[
+    "account",
+    "slot",
+    "transaction",
+    "token_account",
+    "stake_account",
+    "vote_account",
+    "signature",
+    "instruction",
+    "program_upgrade",
+    "block_rewards",
+];
+
+#[derive(Debug, Default)]
+pub struct Pause {
+    /// Whether any event type is paused, checked first on every event.
+    any_paused: AtomicBool,
+    /// Set on resume while events are held, checked on every event to release them.
+    releasable: AtomicBool,
+    /// Held while releasing, so that events admitted meanwhile wait and follow the released ones.
+    releasing: Mutex<()>,
+    state: Mutex<PauseState>,
+}
+
+#[derive(Debug, Default)]
+struct PauseState {
+    paused: BTreeSet<&'static str>,
+    policy: PausePolicy,
+    buffer_capacity: usize,
+    held: VecDeque<Job>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PauseReport {
+    pub paused: Vec<&'static str>,
+    pub held: usize,
+}
+
+impl Pause {
+    pub fn configure(&self, config: &AdminConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.policy = config.pause_policy;
+        state.buffer_capacity = config.pause_buffer_capacity;
+    }
+
+    /// Pauses the given event types, all if empty. Fails on unknown event types.
+    pub fn pause(&self, events: &[&str]) -> Result<PauseReport, String> {
+        let events = resolve(events)?;
+        let mut state = self.state.lock().unwrap();
+        state.paused.extend(events);
+        self.any_paused.store(true, Ordering::SeqCst);
+        info!(paused = ?state.paused, "paused publishing");
+        Ok(self.report_locked(&state))
+    }
+
+    /// Resumes the given event types, all if empty. Held events are released with the next
+    /// event published.
+    pub fn resume(&self, events: &[&str]) -> Result<PauseReport, String> {
+        let events = resolve(events)?;
+        let mut state = self.state.lock().unwrap();
+        for event in events {
+            state.paused.remove(event);
+        }
+        // Set before clearing `any_paused`, which `admit` reads first.
+        self.releasable
+            .store(Self::has_released(&state), Ordering::SeqCst);
+        self.any_paused
+            .store(!state.paused.is_empty(), Ordering::SeqCst);
+        info!(paused = ?state.paused, "resumed publishing");
+        Ok(self.report_locked(&state))
+    }
+
+    pub fn report(&self) -> PauseReport {
+        self.report_locked(&self.state.lock().unwrap())
+    }
+
+    fn report_locked(&self, state: &PauseState) -> PauseReport {
+        PauseReport {
+            paused: state.paused.iter().copied().collect(),
+            held: state.held.len(),
+        }
+    }
+
+    /// Returns the job unless its event type is paused, in which case it is held or dropped.
+    /// Held jobs of resumed types are first passed to `dispatch`, so that they precede it.
+    pub fn admit(&self, job: Job, mut dispatch: impl FnMut(Job)) -> Option<Job> {
+        let any_paused = self.any_paused.load(Ordering::SeqCst);
+        if self.releasable.load(Ordering::SeqCst) {
+            self.release(&mut dispatch);
+        }
+        if !any_paused {
+            return Some(job);
+        }
+        let mut state = self.state.lock().unwrap();
+        if !state.paused.contains(job.event()) {
+            // Resumed since `any_paused` was read, the held jobs may not be released yet.
+            drop(state);
+            self.release(&mut dispatch);
+            return Some(job);
+        }
+        let reason = match state.policy {
+            PausePolicy::Buffer if state.held.len() < state.buffer_capacity => {
+                let held = &mut state.held;
+                if MEMORY.reserve(&job, job.size(), |needed| {
+                    memory::evict_oldest_accounts(held, needed)
+                }) {
+                    held.push_back(job);
+                }
+                return None;
+            }
+            PausePolicy::Buffer => "pause_buffer_full",
+            PausePolicy::Drop => "admin_paused",
+        };
+        EVENTS_SKIPPED_TOTAL
+            .with_label_values(&[job.event(), reason])
+            .inc();
+        None
+    }
+
+    /// Passes the held jobs whose event type is no longer paused to `dispatch`, in the order
+    /// they were held. Jobs admitted meanwhile wait until they are dispatched.
+    pub fn release(&self, mut dispatch: impl FnMut(Job)) {
+        let _releasing = self.releasing.lock().unwrap();
+        if !self.releasable.load(Ordering::SeqCst) {
+            return;
+        }
+        let released = {
+            let mut state = self.state.lock().unwrap();
+            let held = mem::take(&mut state.held);
+            let (released, held): (Vec<_>, Vec<_>) = held
+                .into_iter()
+                .partition(|job| !state.paused.contains(job.event()));
+            state.held = held.into();
+            released
+        };
+        for job in released {
+            MEMORY.release(job.size());
+            dispatch(job);
+        }
+        // Types resumed while dispatching have their held jobs released by the next event.
+        let state = self.state.lock().unwrap();
+        self.releasable
+            .store(Self::has_released(&state), Ordering::SeqCst);
+    }
+
+    /// Whether jobs of resumed types are held.
+    fn has_released(state: &PauseState) -> bool {
+        state
+            .held
+            .iter()
+            .any(|job| !state.paused.contains(job.event()))
+    }
+
+    /// Drops the oldest held account updates until `needed` bytes are freed, returning the
+    /// bytes freed. The caller releases them.
+    pub fn evict_oldest_accounts(&self, needed: usize) -> usize {
+        memory::evict_oldest_accounts(&mut self.state.lock().unwrap().held, needed)
+    }
+
+    /// Drops the jobs still held on unload, those of resumed types are released before, and
+    /// resumes all event types for the next load.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        for job in state.held.drain(..) {
+            MEMORY.release(job.size());
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[job.event(), "admin_paused"])
+                .inc();
+        }
+        state.paused.clear();
+        self.any_paused.store(false, Ordering::SeqCst);
+        self.releasable.store(false, Ordering::SeqCst);
+    }
+}
+
+fn resolve(events: &[&str]) -> Result<Vec<&'static str>, String> {
+    if events.is_empty() {
+        return Ok(EVENTS.to_vec());
+    }
+    events
+        .iter()
+        .map(|event| {
+            EVENTS
+                .iter()
+                .find(|known| *known == event)
+                .copied()
+                .ok_or_else(|| format!("unknown event type {:?}", event))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::*};
+
+    fn slot(slot: u64) -> Job {
+        Job::Slot(SlotStatusEvent {
+            slot,
+            ..SlotStatusEvent::default()
+        })
+    }
+
+    fn account() -> Job {
+        Job::Account(UpdateAccountEvent::default())
+    }
+
+    fn slots(jobs: impl IntoIterator<Item = Job>) -> Vec<u64> {
+        jobs.into_iter()
+            .map(|job| match job {
+                Job::Slot(ev) => ev.slot,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn buffering(capacity: usize) -> Pause {
+        let pause = Pause::default();
+        pause.configure(&AdminConfig {
+            pause_policy: PausePolicy::Buffer,
+            pause_buffer_capacity: capacity,
+        });
+        pause
+    }
+
+    #[test]
+    fn test_buffer_until_resumed() {
+        let pause = buffering(2);
+        assert_eq!(pause.pause(&["slot"]).unwrap().paused, ["slot"]);
+        assert!(pause.pause(&["slots"]).is_err());
+
+        let mut dispatched = Vec::new();
+        assert!(pause.admit(account(), |job| dispatched.push(job)).is_some());
+        for i in 0..3 {
+            assert!(pause.admit(slot(i), |job| dispatched.push(job)).is_none());
+        }
+        assert_eq!(pause.report().held, 2);
+        assert!(dispatched.is_empty());
+
+        pause.resume(&[]).unwrap();
+        let admitted = pause.admit(slot(3), |job| dispatched.push(job));
+        assert_eq!(slots(dispatched), [0, 1]);
+        assert_eq!(slots(admitted), [3]);
+        assert_eq!(pause.report().held, 0);
+    }
+
+    #[test]
+    fn test_released_before_concurrent_events() {
+        let pause = buffering(1000);
+        pause.pause(&["slot"]).unwrap();
+        for i in 0..100 {
+            assert!(pause.admit(slot(i), |_| unreachable!()).is_none());
+        }
+        pause.resume(&[]).unwrap();
+
+        // Every thread publishes its event after the held ones.
+        let published = Mutex::new(Vec::new());
+        std::thread::scope(|scope| {
+            for i in 100..108 {
+                let (pause, published) = (&pause, &published);
+                scope.spawn(move || {
+                    let job = pause.admit(slot(i), |job| published.lock().unwrap().push(job));
+                    published.lock().unwrap().extend(job);
+                });
+            }
+        });
+        let published = slots(published.into_inner().unwrap());
+        assert_eq!(published[..100], (0..100).collect::<Vec<_>>()[..]);
+        assert_eq!(published.len(), 108);
+    }
+
+    #[test]
+    fn test_clear() {
+        let pause = buffering(10);
+        pause.pause(&["slot", "account"]).unwrap();
+        pause.admit(slot(1), |_| unreachable!());
+        pause.admit(account(), |_| unreachable!());
+        pause.resume(&["account"]).unwrap();
+
+        let mut released = Vec::new();
+        pause.release(|job| released.push(job));
+        assert_eq!(released.len(), 1);
+        assert_eq!(pause.report().held, 1);
+
+        let dropped = EVENTS_SKIPPED_TOTAL
+            .with_label_values(&["slot", "admin_paused"])
+            .get();
+        pause.clear();
+        assert_eq!(
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["slot", "admin_paused"])
+                .get(),
+            dropped + 1
+        );
+        assert!(pause.report().paused.is_empty());
+        assert!(pause.admit(slot(2), |_| unreachable!()).is_some());
+    }
+}
@@ -13,24 +13,108 @@
 // limitations under the License.
 
 use {
-    crate::*,
-    log::*,
-    rdkafka::util::get_rdkafka_version,
+    crate::{
+        account_state::AccountStates,
+        announce,
+        block_time::{self, BlockTimes},
+        blockhash::Blockhashes,
+        bootstrap::Bootstrapper,
+        breaker::BREAKER,
+        cert_watcher::CertWatcher,
+        checkpoint::Checkpointer,
+        compute_budget::ComputeBudget,
+        config::{MqttConfig, ZeroMqConfig},
+        dump::Dumper,
+        failover::Failover,
+        instruction,
+        memory::MEMORY,
+        pause::PAUSE,
+        producer::DryRunProducer,
+        prom::{CALLBACK_OVERHEAD_SECONDS, EVENTS_SKIPPED_TOTAL, PLUGIN_PANICS_TOTAL},
+        recovery::Recovery,
+        remote::{FilterPoller, SharedFilter},
+        rent::RentTracker,
+        replica::{AccountInfo, BlockInfo, TransactionInfo},
+        rest_proxy::RestProxyProducer,
+        shard::Shard,
+        staking,
+        switches::{Notification, SWITCHES},
+        throttle::Throttle,
+        token::{self, DelegateFilter},
+        transform, upgrade, watchdog,
+        watermark::WatermarkStore,
+        workers::{Job, Workers},
+        *,
+    },
     simple_error::simple_error,
     solana_geyser_plugin_interface::geyser_plugin_interface::{
-        GeyserPlugin, GeyserPluginError as PluginError, ReplicaAccountInfo,
-        ReplicaAccountInfoVersions, ReplicaTransactionInfo, ReplicaTransactionInfoVersions,
-        Result as PluginResult, SlotStatus as PluginSlotStatus,
+        GeyserPlugin, GeyserPluginError as PluginError, ReplicaAccountInfoVersions,
+        ReplicaBlockInfoVersions, ReplicaTransactionInfoVersions, Result as PluginResult,
+        SlotStatus as PluginSlotStatus,
+    },
+    std::{
+        any::Any,
+        borrow::Cow,
+        fmt::{Debug, Formatter},
+        panic::{self, AssertUnwindSafe},
+        sync::{Arc, Mutex, RwLock},
+        time::Duration,
     },
-    std::fmt::{Debug, Formatter},
+    tracing::{error, info, trace_span, warn},
 };
 
 #[derive(Default)]
 pub struct KafkaPlugin {
-    publisher: Option<Publisher>,
-    filter: Option<Filter>,
+    publisher: Option<Arc<Publisher>>,
+    /// Publishes events off the validator's threads when configured.
+    workers: Option<Arc<Workers>>,
+    /// Holds back updates of accounts over the rate limit.
+    throttle: Option<Throttle>,
+    /// Replaced by the poller when remote filters change.
+    filter: Option<Arc<SharedFilter>>,
+    filter_poller: Option<FilterPoller>,
+    /// Share of the events this instance publishes, all without.
+    shard: Option<Shard>,
+    /// Published token accounts, all without.
+    token_delegate_filter: Option<DelegateFilter>,
     publish_all_accounts: bool,
+    account_data_limit: Option<usize>,
+    decoded_accounts_raw: bool,
+    rent_exempt_only: bool,
+    previous_owner: bool,
+    slot_statuses: Vec<SlotStatus>,
+    account_data_notifications: bool,
+    transaction_notifications: bool,
     prometheus: Option<PrometheusService>,
+    telemetry: Option<Telemetry>,
+    watchdog: Option<Watchdog>,
+    failover: Option<Failover>,
+    /// Logs state dumps on request.
+    dumper: Option<Dumper>,
+    /// Rebuilds the producer after fatal errors.
+    recovery: Option<Recovery>,
+    cert_watcher: Option<CertWatcher>,
+    watermark_store: Option<WatermarkStore>,
+    checkpointer: Option<Checkpointer>,
+    /// Holds live events until the snapshot backfill completes.
+    #[cfg(feature = "rdkafka-backend")]
+    handoff: Option<crate::handoff::Handoff>,
+    /// Retries fetching the topic limits for the oversize policy after a failed fetch.
+    #[cfg(feature = "rdkafka-backend")]
+    limits_fetcher: Option<crate::topic_limits::LimitsFetcher>,
+    top_programs: Option<Mutex<TopPrograms>>,
+    /// Latest state of the filtered accounts, when snapshots or the bootstrap topic are configured.
+    account_states: Option<Arc<AccountStates>>,
+    bootstrapper: Option<Bootstrapper>,
+    #[cfg(feature = "parquet-snapshots")]
+    snapshotter: Option<crate::snapshot::Snapshotter>,
+    /// Block times of recent slots, for stamping events.
+    block_times: BlockTimes,
+    /// Blockhashes of recent slots, for stamping slot statuses.
+    blockhashes: Blockhashes,
+    rent: RentTracker,
+    startup: StartupProgress,
+    config: Option<Config>,
 }
 
 impl Debug for KafkaPlugin {
@@ -39,69 +123,514 @@ impl Debug for KafkaPlugin {
     }
 }
 
+#[cfg(feature = "zeromq")]
+fn create_zeromq_producer(config: &ZeroMqConfig) -> PluginResult<Box<dyn ProducerLike>> {
+    match crate::zeromq::ZeroMqProducer::new(config) {
+        Ok(producer) => Ok(Box::new(producer)),
+        Err(error) => {
+            error!(%error, "failed to create zeromq sink");
+            Err(PluginError::Custom(Box::new(error)))
+        }
+    }
+}
+
+#[cfg(not(feature = "zeromq"))]
+fn create_zeromq_producer(_config: &ZeroMqConfig) -> PluginResult<Box<dyn ProducerLike>> {
+    Err(PluginError::Custom(Box::new(simple_error!(
+        "zeromq is configured but the plugin was built without the zeromq feature"
+    ))))
+}
+
+#[cfg(feature = "mqtt")]
+fn create_mqtt_producer(config: &MqttConfig) -> PluginResult<Box<dyn ProducerLike>> {
+    match crate::mqtt::MqttProducer::new(config) {
+        Ok(producer) => Ok(Box::new(producer)),
+        Err(error) => {
+            error!(%error, "failed to create mqtt sink");
+            Err(PluginError::Custom(Box::new(error)))
+        }
+    }
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn create_mqtt_producer(_config: &MqttConfig) -> PluginResult<Box<dyn ProducerLike>> {
+    Err(PluginError::Custom(Box::new(simple_error!(
+        "mqtt is configured but the plugin was built without the mqtt feature"
+    ))))
+}
+
+/// Runs a validator callback, turning a panic into an error so that it never unwinds into the validator.
+fn catch_panic<T>(callback: &'static str, f: impl FnOnce() -> PluginResult<T>) -> PluginResult<T> {
+    contain_panic(callback, f).unwrap_or_else(|message| {
+        Err(PluginError::Custom(Box::new(simple_error!(
+            "{} panicked: {}",
+            callback,
+            message
+        ))))
+    })
+}
+
+/// Runs a validator callback that cannot return an error, logging and counting a panic and
+/// returning its message instead of unwinding into the validator.
+fn contain_panic<T>(callback: &'static str, f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = panic_message(payload.as_ref());
+        PLUGIN_PANICS_TOTAL.with_label_values(&[callback]).inc();
+        error!(callback, panic = %message, "plugin panicked");
+        message
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
 impl GeyserPlugin for KafkaPlugin {
     fn name(&self) -> &'static str {
         "KafkaPlugin"
     }
 
-    fn on_load(&mut self, config_file: &str) -> PluginResult<()> {
+    fn on_load(&mut self, config_file: &str, _is_reload: bool) -> PluginResult<()> {
+        catch_panic("on_load", || self.load(config_file))
+    }
+
+    fn on_unload(&mut self) {
+        let _ = contain_panic("on_unload", || self.unload());
+    }
+
+    fn update_account(
+        &self,
+        account: ReplicaAccountInfoVersions,
+        slot: u64,
+        is_startup: bool,
+    ) -> PluginResult<()> {
+        catch_panic("update_account", || {
+            self.try_update_account(account, slot, is_startup)
+        })
+    }
+
+    fn notify_end_of_startup(&self) -> PluginResult<()> {
+        catch_panic("notify_end_of_startup", || {
+            self.startup.finish();
+            Ok(())
+        })
+    }
+
+    #[cfg(not(feature = "agave"))]
+    fn update_slot_status(
+        &self,
+        slot: u64,
+        parent: Option<u64>,
+        status: PluginSlotStatus,
+    ) -> PluginResult<()> {
+        catch_panic("update_slot_status", || {
+            self.try_update_slot_status(slot, parent, &status)
+        })
+    }
+
+    #[cfg(feature = "agave")]
+    fn update_slot_status(
+        &self,
+        slot: u64,
+        parent: Option<u64>,
+        status: &PluginSlotStatus,
+    ) -> PluginResult<()> {
+        catch_panic("update_slot_status", || {
+            self.try_update_slot_status(slot, parent, status)
+        })
+    }
+
+    fn notify_transaction(
+        &self,
+        transaction: ReplicaTransactionInfoVersions,
+        slot: u64,
+    ) -> PluginResult<()> {
+        catch_panic("notify_transaction", || {
+            self.try_notify_transaction(transaction, slot)
+        })
+    }
+
+    fn notify_block_metadata(&self, block: ReplicaBlockInfoVersions) -> PluginResult<()> {
+        catch_panic("notify_block_metadata", || {
+            self.try_notify_block_metadata(block)
+        })
+    }
+
+    /// Derived from config so the validator skips building notifications we would drop.
+    /// Also reflects the admin switches, for validators that check the flags on every
+    /// notification.
+    /// Falls back to the config if the switches cannot be read.
+    fn account_data_notifications_enabled(&self) -> bool {
+        contain_panic("account_data_notifications_enabled", || {
+            // The account states need accounts even while they are disabled.
+            self.account_data_notifications
+                && (self.account_states.is_some() || SWITCHES.is_enabled(Notification::Account))
+        })
+        .unwrap_or(self.account_data_notifications)
+    }
+
+    fn transaction_notifications_enabled(&self) -> bool {
+        contain_panic("transaction_notifications_enabled", || {
+            self.transaction_notifications && SWITCHES.is_enabled(Notification::Transaction)
+        })
+        .unwrap_or(self.transaction_notifications)
+    }
+}
+
+impl KafkaPlugin {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn unload(&mut self) {
+        info!("unloading plugin, draining kafka producer");
+        self.watchdog = None;
+        self.failover = None;
+        self.dumper = None;
+        self.recovery = None;
+        self.filter_poller = None;
+        self.bootstrapper = None;
+        #[cfg(feature = "parquet-snapshots")]
+        {
+            self.snapshotter = None;
+        }
+        self.cert_watcher = None;
+        self.checkpointer = None;
+        #[cfg(feature = "rdkafka-backend")]
+        {
+            self.handoff = None;
+            self.limits_fetcher = None;
+        }
+        // Events held while paused and since resumed precede those still queued.
+        if self.publisher.is_some() {
+            PAUSE.release(|held| self.dispatch_held(held));
+        }
+        // Dropping the throttle hands the held updates to the workers, behind the older ones.
+        self.throttle = None;
+        // Dropping the workers publishes the events still queued.
+        self.workers = None;
+        PAUSE.clear();
+        SWITCHES.clear();
+        BREAKER.clear();
+        // Dropping the publisher flushes pending messages until `shutdown_timeout_ms`.
+        self.publisher = None;
+        // Saved once the delivery reports of the flushed messages are in.
+        self.watermark_store = None;
+        self.filter = None;
+        if let Some(prometheus) = self.prometheus.take() {
+            prometheus.shutdown();
+        }
+        if let Some(telemetry) = self.telemetry.take() {
+            telemetry.shutdown();
+        }
+    }
+
+    fn load(&mut self, config_file: &str) -> PluginResult<()> {
         if self.publisher.is_some() {
             let err = simple_error!("plugin already loaded");
             return Err(PluginError::Custom(Box::new(err)));
         }
 
-        solana_logger::setup_with_default("info");
-        info!(
-            "Loading plugin {:?} from config_file {:?}",
-            self.name(),
-            config_file
-        );
         let config = Config::read_from(config_file)?;
         self.publish_all_accounts = config.publish_all_accounts;
+        self.account_data_limit = config.account_data_limit;
+        self.account_data_notifications = !config.update_account_topic.is_empty()
+            || !config.token_account_topic.is_empty()
+            || !config.stake_account_topic.is_empty()
+            || !config.vote_account_topic.is_empty();
+        self.decoded_accounts_raw = config.decoded_accounts_raw;
+        self.rent_exempt_only = config.rent_exempt_only;
+        self.previous_owner = config.previous_owner;
+        self.slot_statuses = config.slot_statuses.clone();
+        self.shard = config.shard.as_ref().map(Shard::new);
+        self.token_delegate_filter = config
+            .token_delegate_filter
+            .as_ref()
+            .map(DelegateFilter::new);
+        self.transaction_notifications = !config.transaction_topic.is_empty()
+            || !config.signature_topic.is_empty()
+            || !config.instruction_topic.is_empty()
+            || !config.program_upgrade_topic.is_empty();
+        self.telemetry = Some(Telemetry::new(&config).map_err(PluginError::Custom)?);
+        info!(plugin = self.name(), config_file, "loading plugin");
+        if let Some(network) = &config.network {
+            info!(network = %network, "resolved network in topic names");
+        }
+        if !config.unknown_fields.is_empty() {
+            warn!(fields = ?config.unknown_fields, "ignoring unknown config fields");
+        }
+        info!(
+            accounts = self.account_data_notifications,
+            transactions = self.transaction_notifications,
+            "requesting notifications"
+        );
 
-        let (version_n, version_s) = get_rdkafka_version();
-        info!("rd_kafka_version: {:#08x}, {}", version_n, version_s);
+        #[cfg(feature = "rdkafka-backend")]
+        {
+            let (version_n, version_s) = rdkafka::util::get_rdkafka_version();
+            info!(
+                version = %version_s,
+                version_n = %format!("{:#08x}", version_n),
+                "using librdkafka"
+            );
+        }
 
-        let producer = config.producer().map_err(|e| {
-            error!("Failed to create kafka producer: {:?}", e);
-            PluginError::Custom(Box::new(e))
-        })?;
-        info!("Created rdkafka::FutureProducer");
+        let producer: Option<Box<dyn ProducerLike>> = if config.dry_run {
+            info!("dry run, events are published but only counted");
+            Some(Box::new(DryRunProducer))
+        } else if config.null_sink {
+            info!("using null sink, events are filtered and encoded but not published");
+            None
+        } else if let Some(rest_proxy) = &config.rest_proxy {
+            info!(url = %rest_proxy.url, "publishing through the Kafka REST Proxy");
+            Some(Box::new(RestProxyProducer::new(
+                rest_proxy,
+                Duration::from_millis(config.shutdown_timeout_ms),
+            )))
+        } else if let Some(zeromq) = &config.zeromq {
+            Some(create_zeromq_producer(zeromq)?)
+        } else if let Some(mqtt) = &config.mqtt {
+            Some(create_mqtt_producer(mqtt)?)
+        } else {
+            let producer = config.create_producer().map_err(|error| {
+                error!(?error, "failed to create kafka producer");
+                PluginError::Custom(Box::new(error))
+            })?;
+            info!(backend = ?config.backend, "created kafka producer");
+            Some(producer)
+        };
 
-        let publisher = Publisher::new(producer, &config);
+        let transform = config
+            .transform
+            .as_ref()
+            .map(transform::load)
+            .transpose()
+            .map_err(|error| {
+                error!(%error, "failed to load transform");
+                PluginError::Custom(error)
+            })?;
+        self.watermark_store = config
+            .watermark
+            .as_ref()
+            .map(WatermarkStore::new)
+            .transpose()
+            .map_err(|error| {
+                error!(%error, "failed to restore watermarks");
+                PluginError::Custom(error)
+            })?;
+        let publisher = Arc::new(Publisher::new(producer, transform, &config));
+        #[cfg(feature = "rdkafka-backend")]
+        if config.oversize.is_some() {
+            self.limits_fetcher = crate::topic_limits::LimitsFetcher::start(&config, &publisher)
+                .map_err(|msg| {
+                    error!(%msg, "invalid topic limits config");
+                    PluginError::ConfigFileReadError { msg }
+                })?;
+        }
+        if let Some(schemas) = &config.schemas {
+            match publisher.publish_schemas(&schemas.topic) {
+                Ok(()) => info!(topic = %schemas.topic, "published event schemas"),
+                Err(error) => {
+                    warn!(topic = %schemas.topic, %error, "failed to publish event schemas")
+                }
+            }
+        }
+        self.workers = config
+            .workers
+            .as_ref()
+            .map(|workers| Arc::new(Workers::new(Arc::clone(&publisher), workers)));
+        self.throttle = config
+            .account_rate_limit
+            .as_ref()
+            .map(|limit| Throttle::new(Arc::clone(&publisher), self.workers.clone(), limit));
+        self.checkpointer = config
+            .checkpoints
+            .as_ref()
+            .map(|checkpoints| Checkpointer::new(Arc::clone(&publisher), checkpoints));
+        #[cfg(feature = "rdkafka-backend")]
+        {
+            self.handoff = config
+                .handoff
+                .as_ref()
+                .map(|handoff| crate::handoff::Handoff::start(&config, handoff))
+                .transpose()
+                .map_err(|error| {
+                    error!(%error, "failed to consume the handoff control topic");
+                    PluginError::Custom(Box::new(error))
+                })?;
+        }
+        // Previous owners only need the metadata, snapshots and bootstrap need the data.
+        let keep_data = config.snapshots.is_some() || !config.bootstrap_topic.is_empty();
+        self.account_states = (keep_data || config.previous_owner).then(|| {
+            Arc::new(AccountStates::new(
+                config.account_states.as_ref(),
+                keep_data,
+            ))
+        });
+        self.bootstrapper = self
+            .account_states
+            .as_ref()
+            .filter(|_| !config.bootstrap_topic.is_empty())
+            .map(|states| {
+                Bootstrapper::new(
+                    Arc::clone(states),
+                    Arc::clone(&publisher),
+                    config.account_data_limit,
+                )
+            });
         let prometheus = config
             .create_prometheus()
             .map_err(|error| PluginError::Custom(Box::new(error)))?;
         self.publisher = Some(publisher);
-        self.filter = Some(Filter::new(&config));
+        let filter = Arc::new(RwLock::new(Arc::new(Filter::new(&config))));
+        self.filter_poller = FilterPoller::new(&config, Arc::clone(&filter));
+        self.filter = Some(filter);
         self.prometheus = prometheus;
-        info!("Spawned producer");
+        if let Some(admin) = &config.admin {
+            PAUSE.configure(admin);
+        }
+        MEMORY.configure(config.max_buffered_bytes);
+        if let Some(circuit_breaker) = &config.circuit_breaker {
+            BREAKER.configure(circuit_breaker);
+        }
+        self.watchdog = config.watchdog.clone().map(Watchdog::new);
+        self.dumper =
+            (config.admin.is_some() || config.dump_signal.is_some()).then(|| Dumper::new(&config));
+        if config.uses_brokers() {
+            self.failover = config.broker_failover.clone().map(Failover::new);
+            self.recovery = Some(Recovery::new(config.producer_recovery.clone()));
+            self.cert_watcher = CertWatcher::new(
+                &config.kafka,
+                Duration::from_millis(config.cert_reload_interval_ms),
+            );
+        }
+        self.top_programs = config
+            .top_programs
+            .as_ref()
+            .map(|config| Mutex::new(TopPrograms::new(config)));
+        self.create_snapshotter(&config)?;
+        self.startup = StartupProgress::new(&config);
+        if self.unwrap_publisher().wants_ops() {
+            match announce::plugin_started(&config, config_file) {
+                Ok(event) => {
+                    if let Err(error) = self.unwrap_publisher().plugin_started(event) {
+                        warn!(%error, "failed to announce plugin startup");
+                    }
+                }
+                Err(error) => warn!(%error, "failed to hash config file"),
+            }
+        }
+        self.config = Some(config);
+        info!("spawned producer");
 
         Ok(())
     }
 
-    fn on_unload(&mut self) {
-        self.publisher = None;
-        self.filter = None;
-        if let Some(prometheus) = self.prometheus.take() {
-            prometheus.shutdown();
-        }
-    }
-
-    fn update_account(
-        &mut self,
+    fn try_update_account(
+        &self,
         account: ReplicaAccountInfoVersions,
         slot: u64,
         is_startup: bool,
     ) -> PluginResult<()> {
+        self.check_watchdog()?;
+        if is_startup {
+            self.startup.record_scanned();
+        }
+        let info = AccountInfo::from(account);
+        // The rent sysvar is notified among the startup accounts.
+        self.rent.record(info.pubkey, info.data);
+        // Startup accounts are the initial account states.
+        if is_startup && !self.publish_all_accounts && self.account_states.is_none() {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", "startup"])
+                .inc();
+            return Ok(());
+        }
+
+        let _span = trace_span!("update_account", slot).entered();
+        self.block_times.record_clock(info.pubkey, info.data);
+        if let Some(epoch) = block_time::clock_epoch(info.pubkey, info.data) {
+            self.observe_epoch(epoch);
+        }
+        // Disabled accounts still keep the account states current, for the snapshots.
+        let enabled = SWITCHES.is_enabled(Notification::Account);
+        if !enabled && self.account_states.is_none() {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", "admin_disabled"])
+                .inc();
+            return Ok(());
+        }
+        if let Some(shard) = &self.shard {
+            if !shard.owns(info.pubkey, slot) {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&["account", "shard"])
+                    .inc();
+                return Ok(());
+            }
+        }
+        let data = match trace_span!("filter").in_scope(|| {
+            let _timer = CALLBACK_OVERHEAD_SECONDS
+                .with_label_values(&["account", "filter"])
+                .start_timer();
+            let filter = self.unwrap_filter();
+            match filter.wants_filter(info.owner, info.pubkey, info.data) {
+                Some(group) => Some(group.account_data(info.data)),
+                None if filter.wants_program(info.owner) || filter.wants_account(info.pubkey) => {
+                    Some(Cow::Borrowed(info.data))
+                }
+                None => None,
+            }
+        }) {
+            Some(data) => data,
+            None => {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&["account", "filter"])
+                    .inc();
+                return Ok(());
+            }
+        };
+
+        let rent_exempt = self.rent.is_exempt(info.lamports, info.data.len());
+        // Closed accounts are still published, as their removal.
+        let rent_skipped = self.rent_exempt_only && !rent_exempt && info.lamports > 0;
+        let previous_owner = self
+            .account_states
+            .as_ref()
+            .filter(|_| !rent_skipped)
+            .and_then(|states| states.record(&info, &data, slot, rent_exempt))
+            .filter(|_| self.previous_owner);
+        if !enabled {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", "admin_disabled"])
+                .inc();
+            return Ok(());
+        }
         if is_startup && !self.publish_all_accounts {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", "startup"])
+                .inc();
             return Ok(());
         }
+        if is_startup {
+            self.startup.record_matched();
+        }
 
-        let info = Self::unwrap_update_account(account);
-        if !self.unwrap_filter().wants_program(info.owner)
-            && !self.unwrap_filter().wants_account(info.pubkey)
-        {
+        if rent_skipped {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", "rent"])
+                .inc();
+            return Ok(());
+        }
+
+        let publisher = self.unwrap_publisher();
+        let decoded = self.publish_decoded(publisher, &info, slot)?;
+        if (decoded && !self.decoded_accounts_raw) || !publisher.wants_update_account() {
             return Ok(());
         }
 
@@ -112,105 +641,361 @@ impl GeyserPlugin for KafkaPlugin {
             owner: info.owner.to_vec(),
             executable: info.executable,
             rent_epoch: info.rent_epoch,
-            data: info.data.to_vec(),
+            data: match self.account_data_limit {
+                Some(limit) => data[..data.len().min(limit)].to_vec(),
+                None => data.to_vec(),
+            },
             write_version: info.write_version,
+            txn_signature: info
+                .txn_signature
+                .map(|signature| signature.as_ref().to_vec())
+                .unwrap_or_default(),
+            block_time: self.block_times.get(slot),
+            rent_exempt,
+            previous_owner: previous_owner
+                .map(|owner| owner.to_vec())
+                .unwrap_or_default(),
         };
 
-        let publisher = self.unwrap_publisher();
-        publisher
-            .update_account(event)
-            .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
+        let data_len = event.data.len();
+        self.publish(Job::Account(event))
+            .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+
+        if let Some(top_programs) = self.top_programs.as_ref() {
+            top_programs.lock().unwrap().record(info.owner, data_len);
+        }
+        Ok(())
     }
 
-    fn update_slot_status(
-        &mut self,
+    /// Publishes token, stake and vote accounts as structured events.
+    /// Returns whether the account was decoded.
+    fn publish_decoded(
+        &self,
+        publisher: &Publisher,
+        info: &AccountInfo,
+        slot: u64,
+    ) -> PluginResult<bool> {
+        let to_error = |e: PublishError| PluginError::AccountsUpdateError { msg: e.to_string() };
+        if publisher.wants_token_account() {
+            if let Some(event) = token::decode(info, slot) {
+                match &self.token_delegate_filter {
+                    Some(filter) if !filter.matches(&event) => {
+                        EVENTS_SKIPPED_TOTAL
+                            .with_label_values(&["token_account", "delegate"])
+                            .inc();
+                    }
+                    _ => self.publish(Job::TokenAccount(event)).map_err(to_error)?,
+                }
+                return Ok(true);
+            }
+        }
+        if publisher.wants_stake_account() {
+            if let Some(event) = staking::decode_stake(info, slot) {
+                self.publish(Job::StakeAccount(event)).map_err(to_error)?;
+                return Ok(true);
+            }
+        }
+        if publisher.wants_vote_account() {
+            if let Some(event) = staking::decode_vote(info, slot) {
+                self.publish(Job::VoteAccount(event)).map_err(to_error)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn try_update_slot_status(
+        &self,
         slot: u64,
         parent: Option<u64>,
-        status: PluginSlotStatus,
+        status: &PluginSlotStatus,
     ) -> PluginResult<()> {
+        self.check_watchdog()?;
         let publisher = self.unwrap_publisher();
         if !publisher.wants_slot_status() {
             return Ok(());
         }
+        if !SWITCHES.is_enabled(Notification::Slot) {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["slot", "admin_disabled"])
+                .inc();
+            return Ok(());
+        }
 
+        let event_status = match SlotStatus::from_plugin(status) {
+            Some(status) if self.slot_statuses.contains(&status) => status,
+            _ => {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&["slot", "status"])
+                    .inc();
+                return Ok(());
+            }
+        };
+        if let Some(shard) = &self.shard {
+            if !shard.owns_slot(slot) {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&["slot", "shard"])
+                    .inc();
+                return Ok(());
+            }
+        }
+
+        let _span = trace_span!("update_slot_status", slot).entered();
         let event = SlotStatusEvent {
             slot,
             parent: parent.unwrap_or(0),
-            status: SlotStatus::from(status).into(),
+            status: event_status.into(),
+            dead_error: SlotStatus::dead_error(status),
+            blockhash: self.blockhashes.get(slot).unwrap_or_default(),
         };
 
-        publisher
-            .update_slot_status(event)
+        self.publish(Job::Slot(event))
             .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
     }
 
-    fn notify_transaction(
-        &mut self,
+    fn try_notify_transaction(
+        &self,
         transaction: ReplicaTransactionInfoVersions,
         slot: u64,
     ) -> PluginResult<()> {
+        self.check_watchdog()?;
         let publisher = self.unwrap_publisher();
-        if !publisher.wants_transaction() {
+        if !publisher.wants_transaction()
+            && !publisher.wants_signature()
+            && !publisher.wants_instruction()
+            && !publisher.wants_program_upgrade()
+        {
+            return Ok(());
+        }
+        if !SWITCHES.is_enabled(Notification::Transaction) {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["transaction", "admin_disabled"])
+                .inc();
             return Ok(());
         }
 
+        let _span = trace_span!("notify_transaction", slot).entered();
         let filter = self.unwrap_filter();
-        let transaction = Self::unwrap_notify_transaction(transaction);
-        if !transaction
-            .transaction
-            .message()
-            .account_keys()
-            .iter()
-            .any(|pubkey| {
-                filter.wants_program(pubkey.as_ref()) || filter.wants_account(pubkey.as_ref())
-            })
+        let transaction = TransactionInfo::from(transaction);
+        if let Some(shard) = &self.shard {
+            if !shard.owns(transaction.signature.as_ref(), slot) {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&["transaction", "shard"])
+                    .inc();
+                return Ok(());
+            }
+        }
+        let to_error = |e: PublishError| PluginError::TransactionUpdateError { msg: e.to_string() };
+        // Upgrades of any program are published, regardless of the filters.
+        if publisher.wants_program_upgrade() {
+            for event in upgrade::build_program_upgraded_events(slot, &transaction) {
+                self.publish(Job::ProgramUpgrade(event)).map_err(to_error)?;
+            }
+        }
+        if !publisher.wants_transaction()
+            && !publisher.wants_signature()
+            && !publisher.wants_instruction()
         {
             return Ok(());
         }
+        if !trace_span!("filter").in_scope(|| {
+            let _timer = CALLBACK_OVERHEAD_SECONDS
+                .with_label_values(&["transaction", "filter"])
+                .start_timer();
+            transaction
+                .transaction
+                .message()
+                .account_keys()
+                .iter()
+                .any(|pubkey| {
+                    filter.wants_transaction_program(pubkey.as_ref())
+                        || filter.wants_account(pubkey.as_ref())
+                })
+        }) {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["transaction", "filter"])
+                .inc();
+            return Ok(());
+        }
 
-        let event = Self::build_transaction_event(slot, transaction);
+        if publisher.wants_signature() {
+            let status = &transaction.transaction_status_meta.status;
+            let event = SignatureEvent {
+                signature: transaction.signature.as_ref().into(),
+                slot,
+                is_vote: transaction.is_vote,
+                is_status_err: status.is_err(),
+                error_info: match status {
+                    Err(e) => e.to_string(),
+                    Ok(_) => "".to_owned(),
+                },
+            };
+            self.publish(Job::Signature(event)).map_err(to_error)?;
+        }
+        let block_time = self.block_times.get(slot);
+        if publisher.wants_instruction() {
+            for event in
+                instruction::build_instruction_events(slot, &transaction, &filter, block_time)
+            {
+                self.publish(Job::Instruction(event)).map_err(to_error)?;
+            }
+        }
+        if !publisher.wants_transaction() {
+            return Ok(());
+        }
+
+        let event = TransactionEvent {
+            block_time,
+            ..Self::build_transaction_event(slot, &transaction)
+        };
 
-        publisher
-            .update_transaction(event)
-            .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })
+        self.publish(Job::Transaction(Box::new(event)))
+            .map_err(to_error)
     }
 
-    fn account_data_notifications_enabled(&self) -> bool {
-        self.unwrap_publisher().wants_update_account()
+    fn try_notify_block_metadata(&self, block: ReplicaBlockInfoVersions) -> PluginResult<()> {
+        let block = BlockInfo::from(block);
+        if let Some(block_time) = block.block_time {
+            self.block_times.record(block.slot, block_time);
+        }
+        self.blockhashes.record(block.slot, block.blockhash);
+        if !self.unwrap_publisher().wants_block_rewards() {
+            return Ok(());
+        }
+        self.check_watchdog()?;
+        if let Some(shard) = &self.shard {
+            if !shard.owns_slot(block.slot) {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&["block_rewards", "shard"])
+                    .inc();
+                return Ok(());
+            }
+        }
+
+        let _span = trace_span!("notify_block_metadata", slot = block.slot).entered();
+        let event = BlockRewardsEvent {
+            slot: block.slot,
+            blockhash: block.blockhash.to_owned(),
+            block_time: block.block_time,
+            rewards: block.rewards.iter().map(Self::build_reward).collect(),
+        };
+
+        self.publish(Job::BlockRewards(event))
+            .map_err(|e| PluginError::Custom(Box::new(e)))
     }
 
-    fn transaction_notifications_enabled(&self) -> bool {
-        self.unwrap_publisher().wants_transaction()
+    /// Holds or drops events of paused types, and first publishes those held once resumed.
+    /// Before the snapshot backfill completes, events are held for the handoff instead.
+    fn publish(&self, job: Job) -> Result<(), PublishError> {
+        #[cfg(feature = "rdkafka-backend")]
+        let job = match &self.handoff {
+            Some(handoff) => {
+                let released = handoff.admit(job, |held| {
+                    if let Some(held) = PAUSE.admit(held, |held| self.dispatch_held(held)) {
+                        if let Err(error) = self.dispatch(held) {
+                            warn!(%error, "failed to publish event held for the backfill");
+                        }
+                    }
+                });
+                match released {
+                    Some(job) => job,
+                    None => return Ok(()),
+                }
+            }
+            None => job,
+        };
+        match PAUSE.admit(job, |held| self.dispatch_held(held)) {
+            Some(job) => self.dispatch(job),
+            None => Ok(()),
+        }
     }
-}
 
-impl KafkaPlugin {
-    pub fn new() -> Self {
-        Default::default()
+    fn dispatch_held(&self, held: Job) {
+        if let Err(error) = self.dispatch(held) {
+            warn!(%error, "failed to publish event held while paused");
+        }
     }
 
-    fn unwrap_publisher(&self) -> &Publisher {
-        self.publisher.as_ref().expect("publisher is unavailable")
+    /// Applies the rate limit, then hands the event to the workers, or publishes it on the
+    /// calling thread without them.
+    fn dispatch(&self, job: Job) -> Result<(), PublishError> {
+        let job = match &self.throttle {
+            Some(throttle) => match throttle.admit(job) {
+                Some(job) => job,
+                None => return Ok(()),
+            },
+            None => job,
+        };
+        match &self.workers {
+            Some(workers) => {
+                workers.submit(job);
+                Ok(())
+            }
+            None => job.publish(self.unwrap_publisher()),
+        }
     }
 
-    fn unwrap_filter(&self) -> &Filter {
-        self.filter.as_ref().expect("filter is unavailable")
+    #[cfg(feature = "parquet-snapshots")]
+    fn create_snapshotter(&mut self, config: &Config) -> PluginResult<()> {
+        self.snapshotter = config
+            .snapshots
+            .as_ref()
+            .zip(self.account_states.as_ref())
+            .map(|(snapshots, states)| {
+                crate::snapshot::Snapshotter::new(snapshots, Arc::clone(states))
+            });
+        Ok(())
     }
 
-    fn unwrap_update_account(account: ReplicaAccountInfoVersions) -> &ReplicaAccountInfo {
-        match account {
-            ReplicaAccountInfoVersions::V0_0_1(info) => info,
+    #[cfg(feature = "parquet-snapshots")]
+    fn observe_epoch(&self, epoch: u64) {
+        if let Some(snapshotter) = &self.snapshotter {
+            snapshotter.observe_epoch(epoch);
         }
     }
 
-    fn unwrap_notify_transaction(
-        transaction: ReplicaTransactionInfoVersions,
-    ) -> &ReplicaTransactionInfo {
-        match transaction {
-            ReplicaTransactionInfoVersions::V0_0_1(info) => info,
+    #[cfg(not(feature = "parquet-snapshots"))]
+    fn observe_epoch(&self, _epoch: u64) {}
+
+    #[cfg(not(feature = "parquet-snapshots"))]
+    fn create_snapshotter(&mut self, config: &Config) -> PluginResult<()> {
+        match config.snapshots {
+            Some(_) => Err(PluginError::Custom(Box::new(simple_error!(
+                "snapshots are configured but the plugin was built without the parquet-snapshots feature"
+            )))),
+            None => Ok(()),
         }
     }
 
+    /// Recreates the producer if the watchdog asked for it.
+    fn check_watchdog(&self) -> PluginResult<()> {
+        let restart = self.recovery.as_ref().is_some_and(Recovery::take_restart);
+        if !watchdog::take_recreate_request() && !restart {
+            return Ok(());
+        }
+
+        let config = self.config.as_ref().expect("config is unavailable");
+        if !config.uses_brokers() {
+            return Ok(());
+        }
+        let producer = config.create_producer().map_err(|error| {
+            error!(?error, "failed to recreate kafka producer");
+            PluginError::Custom(Box::new(error))
+        })?;
+        self.unwrap_publisher().replace_producer(producer);
+        info!("recreated kafka producer");
+        Ok(())
+    }
+
+    fn unwrap_publisher(&self) -> &Publisher {
+        self.publisher.as_deref().expect("publisher is unavailable")
+    }
+
+    fn unwrap_filter(&self) -> Arc<Filter> {
+        let filter = self.filter.as_ref().expect("filter is unavailable");
+        Arc::clone(&filter.read().unwrap())
+    }
+
     fn build_compiled_instruction(
         ix: &solana_program::instruction::CompiledInstruction,
     ) -> CompiledInstruction {
@@ -247,17 +1032,38 @@ impl KafkaPlugin {
         }
     }
 
-    fn build_transaction_event(
-        slot: u64,
-        transaction: &ReplicaTransactionInfo,
-    ) -> TransactionEvent {
+    fn build_reward(reward: &solana_transaction_status::Reward) -> Reward {
+        Reward {
+            pubkey: reward.pubkey.clone(),
+            lamports: reward.lamports,
+            post_balance: reward.post_balance,
+            reward_type: match reward.reward_type {
+                Some(r) => r as i32,
+                None => 0,
+            },
+            commission: match reward.commission {
+                Some(v) => v as u32,
+                None => 0,
+            },
+        }
+    }
+
+    fn build_transaction_event(slot: u64, transaction: &TransactionInfo) -> TransactionEvent {
         let transaction_status_meta = transaction.transaction_status_meta;
         let signature = transaction.signature;
         let is_vote = transaction.is_vote;
+        let index = transaction.index;
         let transaction = transaction.transaction;
+        let compute_budget = ComputeBudget::parse(
+            transaction
+                .message()
+                .program_instructions_iter()
+                .map(|(program, ix)| (program, ix.data.as_slice())),
+        );
         TransactionEvent {
             is_vote,
             slot,
+            index: index.map(|index| index as u64),
             signature: signature.as_ref().into(),
             transaction_status_meta: Some(TransactionStatusMeta {
                 is_status_err: transaction_status_meta.status.is_err(),
@@ -267,22 +1073,9 @@ impl KafkaPlugin {
                 },
                 rewards: transaction_status_meta
                     .rewards
-                    .clone()
-                    .unwrap()
-                    .into_iter()
-                    .map(|x| Reward {
-                        pubkey: x.pubkey,
-                        lamports: x.lamports,
-                        post_balance: x.post_balance,
-                        reward_type: match x.reward_type {
-                            Some(r) => r as i32,
-                            None => 0,
-                        },
-                        commission: match x.commission {
-                            Some(v) => v as u32,
-                            None => 0,
-                        },
-                    })
+                    .iter()
+                    .flatten()
+                    .map(Self::build_reward)
                     .collect(),
                 fee: transaction_status_meta.fee,
                 log_messages: match &transaction_status_meta.log_messages {
@@ -299,7 +1092,7 @@ impl KafkaPlugin {
                             instructions: inner
                                 .instructions
                                 .iter()
-                                .map(Self::build_compiled_instruction)
+                                .map(|ix| Self::build_compiled_instruction(&ix.instruction))
                                 .collect(),
                         })
                         .collect(),
@@ -327,8 +1120,16 @@ impl KafkaPlugin {
                 message_hash: transaction.message_hash().to_bytes().into(),
                 is_simple_vote_transaction: transaction.is_simple_vote_transaction(),
                 message: Some(SanitizedMessage {
+                    version: match transaction.message() {
+                        solana_program::message::SanitizedMessage::Legacy(_) => {
+                            MessageVersion::Legacy
+                        }
+                        solana_program::message::SanitizedMessage::V0(_) => MessageVersion::V0,
+                    }
+                    .into(),
                     message_payload: Some(match transaction.message() {
                         solana_program::message::SanitizedMessage::Legacy(lv) => {
+                            let lv = &lv.message;
                             sanitized_message::MessagePayload::Legacy(LegacyMessage {
                                 header: Some(Self::build_message_header(&lv.header)),
                                 account_keys: lv
@@ -410,6 +1211,296 @@ impl KafkaPlugin {
                     .map(|x| x.as_ref().into())
                     .collect(),
             }),
+            block_time: None,
+            compute_unit_limit: compute_budget.compute_unit_limit,
+            compute_unit_price: compute_budget.compute_unit_price,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{config::AccountFilterConfig, producer::MemoryProducer},
+        prost::Message,
+        solana_geyser_plugin_interface::geyser_plugin_interface::{
+            ReplicaAccountInfoV2, ReplicaTransactionInfoV2,
+        },
+        solana_sdk::{
+            hash::Hash,
+            instruction::CompiledInstruction as SdkCompiledInstruction,
+            message::{
+                v0::{self, LoadedAddresses as SdkLoadedAddresses},
+                Message as SdkMessage, MessageHeader as SdkMessageHeader, SimpleAddressLoader,
+                VersionedMessage,
+            },
+            pubkey::Pubkey,
+            signature::Signature,
+            transaction::{
+                MessageHash, SanitizedTransaction as SdkTransaction,
+                Transaction as SdkLegacyTransaction, TransactionError as SdkTransactionError,
+                VersionedTransaction,
+            },
+        },
+        solana_transaction_status::TransactionStatusMeta as SdkTransactionStatusMeta,
+    };
+
+    /// Plugin publishing to memory, with the fields set up from `config` that tests rely on.
+    fn plugin(config: &Config) -> (KafkaPlugin, MemoryProducer) {
+        let producer = MemoryProducer::default();
+        let publisher = Publisher::new(Some(Box::new(producer.clone())), None, config);
+        let plugin = KafkaPlugin {
+            publisher: Some(Arc::new(publisher)),
+            filter: Some(Arc::new(RwLock::new(Arc::new(Filter::new(config))))),
+            account_data_limit: config.account_data_limit,
+            ..KafkaPlugin::default()
+        };
+        (plugin, producer)
+    }
+
+    fn update_account(plugin: &KafkaPlugin, pubkey: &[u8], data: &[u8]) -> PluginResult<()> {
+        let info = ReplicaAccountInfoV2 {
+            pubkey,
+            lamports: 1,
+            owner: &[2; 32],
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 1,
+            txn_signature: None,
+        };
+        plugin.update_account(ReplicaAccountInfoVersions::V0_0_2(&info), 1, false)
+    }
+
+    fn published_accounts(producer: &MemoryProducer) -> Vec<UpdateAccountEvent> {
+        producer
+            .records()
+            .iter()
+            .map(|record| UpdateAccountEvent::decode(&record.payload[..]).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_panics_are_contained() {
+        let panics = || PLUGIN_PANICS_TOTAL.with_label_values(&["test"]).get();
+        let before = panics();
+
+        assert_eq!(contain_panic("test", || 1), Ok(1));
+        assert_eq!(
+            contain_panic("test", || -> u8 { panic!("boom") }),
+            Err("boom".to_owned())
+        );
+        match catch_panic("test", || -> PluginResult<()> { panic!("boom {}", 2) }) {
+            Err(PluginError::Custom(error)) => {
+                assert_eq!(error.to_string(), "test panicked: boom 2")
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(panics(), before + 2);
+    }
+
+    #[test]
+    fn test_account_data_limit() {
+        for (limit, data) in [
+            (None, vec![1, 2, 3]),
+            (Some(2), vec![1, 2]),
+            (Some(0), vec![]),
+        ] {
+            let (plugin, producer) = plugin(&Config {
+                update_account_topic: "accounts".to_owned(),
+                account_data_limit: limit,
+                ..Config::default()
+            });
+            update_account(&plugin, &[1; 32], &[1, 2, 3]).unwrap();
+            let accounts = published_accounts(&producer);
+            assert_eq!(accounts.len(), 1);
+            assert_eq!(accounts[0].data, data);
         }
     }
+
+    #[test]
+    fn test_v0_transaction_event() {
+        let table = Pubkey::new_unique();
+        let loaded_addresses = SdkLoadedAddresses {
+            writable: vec![Pubkey::new_unique()],
+            readonly: vec![Pubkey::new_unique()],
+        };
+        let message = v0::Message {
+            header: SdkMessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 1,
+            },
+            account_keys: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![SdkCompiledInstruction {
+                program_id_index: 1,
+                accounts: vec![0, 2, 3],
+                data: vec![1, 2, 3],
+            }],
+            address_table_lookups: vec![v0::MessageAddressTableLookup {
+                account_key: table,
+                writable_indexes: vec![5],
+                readonly_indexes: vec![9],
+            }],
+        };
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::new_unique()],
+            message: VersionedMessage::V0(message),
+        };
+        let address_loader = SimpleAddressLoader::Enabled(loaded_addresses.clone());
+        #[cfg(not(feature = "agave"))]
+        let transaction = SdkTransaction::try_create(
+            transaction,
+            MessageHash::Compute,
+            Some(false),
+            address_loader,
+        )
+        .unwrap();
+        #[cfg(feature = "agave")]
+        let transaction = SdkTransaction::try_create(
+            transaction,
+            MessageHash::Compute,
+            Some(false),
+            address_loader,
+            &Default::default(),
+        )
+        .unwrap();
+        let transaction_status_meta = SdkTransactionStatusMeta::default();
+
+        let event = KafkaPlugin::build_transaction_event(
+            7,
+            &TransactionInfo {
+                signature: transaction.signature(),
+                is_vote: false,
+                transaction: &transaction,
+                transaction_status_meta: &transaction_status_meta,
+                index: Some(0),
+            },
+        );
+        let message = event.transaction.unwrap().message.unwrap();
+        assert_eq!(message.version, MessageVersion::V0 as i32);
+        let loaded = match message.message_payload {
+            Some(sanitized_message::MessagePayload::V0(loaded)) => loaded,
+            _ => panic!("not a v0 message"),
+        };
+        assert_eq!(
+            loaded.message.unwrap().address_table_lookup,
+            [MessageAddressTableLookup {
+                account_key: table.to_bytes().to_vec(),
+                writable_indexes: vec![5],
+                readonly_indexes: vec![9],
+            }]
+        );
+        assert_eq!(
+            loaded.loaded_adresses,
+            Some(LoadedAddresses {
+                writable: vec![loaded_addresses.writable[0].to_bytes().to_vec()],
+                readonly: vec![loaded_addresses.readonly[0].to_bytes().to_vec()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_transaction_with_filter_groups() {
+        let (plugin, producer) = plugin(&Config {
+            signature_topic: "signatures".to_owned(),
+            filters: vec![AccountFilterConfig {
+                owners: vec![Pubkey::new_unique().to_string()],
+                ..AccountFilterConfig::default()
+            }],
+            ..Config::default()
+        });
+        let message = SdkMessage::new_with_compiled_instructions(
+            1,
+            0,
+            1,
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            Hash::new_unique(),
+            vec![SdkCompiledInstruction {
+                program_id_index: 1,
+                accounts: vec![0],
+                data: vec![],
+            }],
+        );
+        let transaction =
+            SdkTransaction::from_transaction_for_tests(SdkLegacyTransaction::new_unsigned(message));
+        let meta = SdkTransactionStatusMeta::default();
+        let info = ReplicaTransactionInfoV2 {
+            signature: transaction.signature(),
+            is_vote: false,
+            transaction: &transaction,
+            transaction_status_meta: &meta,
+            index: 0,
+        };
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 5)
+            .unwrap();
+
+        // Groups select accounts, transactions of any program are still published.
+        let records = producer.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, "signatures");
+    }
+
+    #[test]
+    fn test_signature_event() {
+        let program = Pubkey::new_unique();
+        let (plugin, producer) = plugin(&Config {
+            signature_topic: "signatures".to_owned(),
+            program_filters: vec![program.to_string()],
+            ..Config::default()
+        });
+        let notify = |program_id: Pubkey, status| {
+            let message = SdkMessage::new_with_compiled_instructions(
+                1,
+                0,
+                1,
+                vec![Pubkey::new_unique(), program_id],
+                Hash::new_unique(),
+                vec![SdkCompiledInstruction {
+                    program_id_index: 1,
+                    accounts: vec![0],
+                    data: vec![],
+                }],
+            );
+            let transaction = SdkTransaction::from_transaction_for_tests(
+                SdkLegacyTransaction::new_unsigned(message),
+            );
+            let meta = SdkTransactionStatusMeta {
+                status,
+                ..SdkTransactionStatusMeta::default()
+            };
+            let info = ReplicaTransactionInfoV2 {
+                signature: transaction.signature(),
+                is_vote: true,
+                transaction: &transaction,
+                transaction_status_meta: &meta,
+                index: 0,
+            };
+            plugin
+                .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 5)
+                .unwrap();
+            *transaction.signature()
+        };
+
+        let signature = notify(program, Err(SdkTransactionError::AccountNotFound));
+        notify(Pubkey::new_unique(), Ok(()));
+
+        let records = producer.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, "signatures");
+        let event = SignatureEvent::decode(&records[0].payload[..]).unwrap();
+        assert_eq!(
+            event,
+            SignatureEvent {
+                signature: signature.as_ref().to_vec(),
+                slot: 5,
+                is_vote: true,
+                is_status_err: true,
+                error_info: SdkTransactionError::AccountNotFound.to_string(),
+            }
+        );
+    }
 }
@@ -0,0 +1,395 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The producer interface `Publisher` sends through, implemented by the rdkafka producer
+//! and by an in-memory producer for tests.
+
+use {
+    crate::{
+        health::HEALTH,
+        prom::{DRY_RUN_BYTES_TOTAL, DRY_RUN_MESSAGES_TOTAL},
+        watermark::Position,
+    },
+    std::{
+        error::Error,
+        fmt::{self, Display, Formatter},
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+};
+#[cfg(feature = "rdkafka-backend")]
+use {
+    crate::{
+        prom::{DeliveryMeta, StatsThreadedProducerContext},
+        watermark::WATERMARKS,
+    },
+    rdkafka::{
+        error::{KafkaError, RDKafkaErrorCode},
+        message::{Header, OwnedHeaders},
+        producer::{BaseRecord, Producer, ThreadedProducer},
+    },
+    tracing::{field, trace_span},
+};
+
+/// Error of a producer, whichever client or sink it sends to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProducerError {
+    /// The producer could not be created from the config.
+    Creation(String),
+    /// The queue of messages waiting to be sent is full.
+    QueueFull,
+    /// The message is larger than the sink accepts.
+    MessageTooLarge,
+    /// A request timed out.
+    Timeout,
+    /// The producer is unusable and has to be recreated.
+    Fatal(String),
+    /// The producer is shutting down.
+    ShutDown,
+    /// Any other error of the client or sink.
+    Other(String),
+}
+
+impl Display for ProducerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Creation(error) => write!(f, "failed to create producer: {}", error),
+            Self::QueueFull => write!(f, "producer queue is full"),
+            Self::MessageTooLarge => write!(f, "message is too large"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Fatal(error) => write!(f, "fatal producer error: {}", error),
+            Self::ShutDown => write!(f, "producer is shutting down"),
+            Self::Other(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for ProducerError {}
+
+#[cfg(feature = "rdkafka-backend")]
+impl From<KafkaError> for ProducerError {
+    fn from(error: KafkaError) -> Self {
+        if let KafkaError::ClientCreation(error) = error {
+            return Self::Creation(error);
+        }
+        match error.rdkafka_error_code() {
+            Some(RDKafkaErrorCode::QueueFull) => Self::QueueFull,
+            Some(RDKafkaErrorCode::MessageSizeTooLarge) => Self::MessageTooLarge,
+            Some(
+                RDKafkaErrorCode::MessageTimedOut
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::OperationTimedOut,
+            ) => Self::Timeout,
+            Some(RDKafkaErrorCode::Fatal) => Self::Fatal(error.to_string()),
+            Some(RDKafkaErrorCode::BrokerDestroy) => Self::ShutDown,
+            _ => Self::Other(error.to_string()),
+        }
+    }
+}
+
+pub trait ProducerLike: Send + Sync {
+    /// Enqueues a message for delivery. Delivery of a message with a `position` advances
+    /// the watermark of its topic.
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError>;
+
+    /// Enqueues a message for delivery to `partition`. Sinks without partitions ignore it.
+    fn send_to_partition(
+        &self,
+        topic: &str,
+        _partition: i32,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        self.send(topic, key, headers, payload, position)
+    }
+
+    /// Number of partitions of `topic`, `None` if unknown or the sink has no partitions.
+    fn partition_count(&self, _topic: &str) -> Option<i32> {
+        None
+    }
+
+    /// Number of enqueued messages not delivered yet.
+    fn in_flight_count(&self) -> i32;
+
+    /// Waits up to `timeout` for enqueued messages to be delivered.
+    fn flush(&self, timeout: Duration);
+}
+
+/// How long to wait for topic metadata.
+#[cfg(feature = "rdkafka-backend")]
+const METADATA_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "rdkafka-backend")]
+impl ProducerLike for ThreadedProducer<StatsThreadedProducerContext> {
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        send_record(self, topic, None, key, headers, payload, position)
+    }
+
+    fn send_to_partition(
+        &self,
+        topic: &str,
+        partition: i32,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        send_record(
+            self,
+            topic,
+            Some(partition),
+            key,
+            headers,
+            payload,
+            position,
+        )
+    }
+
+    fn partition_count(&self, topic: &str) -> Option<i32> {
+        let metadata = self
+            .client()
+            .fetch_metadata(Some(topic), METADATA_TIMEOUT)
+            .ok()?;
+        let topic = metadata.topics().iter().find(|t| t.name() == topic)?;
+        match topic.partitions().len() {
+            0 => None,
+            count => i32::try_from(count).ok(),
+        }
+    }
+
+    fn in_flight_count(&self) -> i32 {
+        Producer::in_flight_count(self)
+    }
+
+    fn flush(&self, timeout: Duration) {
+        // Messages still undelivered after the timeout are reported by the caller.
+        let _ = Producer::flush(self, timeout);
+    }
+}
+
+#[cfg(feature = "rdkafka-backend")]
+fn send_record(
+    producer: &ThreadedProducer<StatsThreadedProducerContext>,
+    topic: &str,
+    partition: Option<i32>,
+    key: Option<&[u8]>,
+    headers: &[(&str, &str)],
+    payload: &[u8],
+    position: Option<Position>,
+) -> Result<(), ProducerError> {
+    let meta = Box::new(DeliveryMeta {
+        span: trace_span!("delivery", topic, error = field::Empty),
+        position,
+    });
+    let headers = headers.iter().fold(
+        OwnedHeaders::new_with_capacity(headers.len()),
+        |acc, &(name, value)| {
+            acc.insert(Header {
+                key: name,
+                value: Some(value),
+            })
+        },
+    );
+    let mut record = BaseRecord::<[u8], [u8], _>::with_opaque_to(topic, meta)
+        .payload(payload)
+        .headers(headers);
+    if let Some(key) = key {
+        record = record.key(key);
+    }
+    if let Some(partition) = partition {
+        record = record.partition(partition);
+    }
+    // Recorded first, the delivery report may arrive before `send` returns.
+    if let Some(position) = position {
+        WATERMARKS.record_enqueued(topic, position);
+    }
+    ThreadedProducer::send(producer, record)
+        .map(|_| ())
+        .map_err(|(e, _)| {
+            if let Some(position) = position {
+                WATERMARKS.record_resolved(topic, position, false);
+            }
+            e.into()
+        })
+}
+
+/// Producer of dry-run mode, counting the messages and bytes it would send per topic.
+/// Messages are reported as delivered right away, but never advance watermarks.
+#[derive(Debug, Default)]
+pub struct DryRunProducer;
+
+impl ProducerLike for DryRunProducer {
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        _headers: &[(&str, &str)],
+        payload: &[u8],
+        _position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        let size = key.map_or(0, <[u8]>::len) + payload.len();
+        DRY_RUN_MESSAGES_TOTAL.with_label_values(&[topic]).inc();
+        DRY_RUN_BYTES_TOTAL
+            .with_label_values(&[topic])
+            .inc_by(size as u64);
+        HEALTH.record_delivery(topic);
+        Ok(())
+    }
+
+    fn in_flight_count(&self) -> i32 {
+        0
+    }
+
+    fn flush(&self, _timeout: Duration) {}
+}
+
+/// A message sent to a `MemoryProducer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryRecord {
+    pub topic: String,
+    /// `None` if left to the partitioner.
+    pub partition: Option<i32>,
+    pub key: Option<Vec<u8>>,
+    pub headers: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+/// Producer keeping every message in memory, delivered immediately.
+/// Clones share the recorded messages.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryProducer {
+    records: Arc<Mutex<Vec<MemoryRecord>>>,
+    failure: Arc<Mutex<Option<ProducerError>>>,
+    partition_count: Arc<Mutex<Option<i32>>>,
+}
+
+impl MemoryProducer {
+    pub fn records(&self) -> Vec<MemoryRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Fails the following sends with `error`, or records them again if `None`.
+    pub fn fail_sends(&self, error: Option<ProducerError>) {
+        *self.failure.lock().unwrap() = error;
+    }
+
+    /// Reports `count` partitions for every topic, or none if `None`.
+    pub fn set_partition_count(&self, count: Option<i32>) {
+        *self.partition_count.lock().unwrap() = count;
+    }
+
+    fn record(
+        &self,
+        topic: &str,
+        partition: Option<i32>,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+    ) -> Result<(), ProducerError> {
+        if let Some(error) = self.failure.lock().unwrap().clone() {
+            return Err(error);
+        }
+        self.records.lock().unwrap().push(MemoryRecord {
+            topic: topic.to_owned(),
+            partition,
+            key: key.map(<[u8]>::to_vec),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            payload: payload.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+impl ProducerLike for MemoryProducer {
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        _position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        self.record(topic, None, key, headers, payload)
+    }
+
+    fn send_to_partition(
+        &self,
+        topic: &str,
+        partition: i32,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        _position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        self.record(topic, Some(partition), key, headers, payload)
+    }
+
+    fn partition_count(&self, _topic: &str) -> Option<i32> {
+        *self.partition_count.lock().unwrap()
+    }
+
+    fn in_flight_count(&self) -> i32 {
+        0
+    }
+
+    fn flush(&self, _timeout: Duration) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::watermark::WATERMARKS};
+
+    #[test]
+    fn test_dry_run() {
+        let topic = "test_dry_run";
+        let messages = || DRY_RUN_MESSAGES_TOTAL.with_label_values(&[topic]).get();
+        let bytes = || DRY_RUN_BYTES_TOTAL.with_label_values(&[topic]).get();
+        let (messages_before, bytes_before) = (messages(), bytes());
+
+        let position = Position {
+            slot: 3,
+            write_version: 1,
+        };
+        DryRunProducer
+            .send(topic, Some(b"key"), &[], b"payload", Some(position))
+            .unwrap();
+        DryRunProducer
+            .send(topic, None, &[("content-type", "x")], b"data", None)
+            .unwrap();
+
+        assert_eq!(messages() - messages_before, 2);
+        assert_eq!(bytes() - bytes_before, 14);
+        assert_eq!(DryRunProducer.in_flight_count(), 0);
+        assert!(!WATERMARKS.snapshot().contains_key(topic));
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-topic selection of the event fields to publish.
+//!
+//! Omitted fields are reset to their default, which protobuf does not encode at all.
+
+use {
+    crate::{config::TopicConfig, *},
+    tracing::warn,
+};
+
+/// Event whose top-level fields can be cleared by name.
+pub trait Project {
+    const FIELDS: &'static [&'static str];
+
+    fn clear(&mut self, field: &str);
+}
+
+macro_rules! project {
+    ($event:ty { $($field:ident),* $(,)? }) => {
+        impl Project for $event {
+            const FIELDS: &'static [&'static str] = &[$(stringify!($field)),*];
+
+            fn clear(&mut self, field: &str) {
+                match field {
+                    $(stringify!($field) => self.$field = Default::default(),)*
+                    _ => {}
+                }
+            }
+        }
+    };
+}
+
+project!(UpdateAccountEvent {
+    slot,
+    pubkey,
+    lamports,
+    owner,
+    executable,
+    rent_epoch,
+    data,
+    write_version,
+    txn_signature,
+    block_time,
+    rent_exempt,
+    previous_owner,
+});
+project!(SlotStatusEvent {
+    slot,
+    parent,
+    status,
+    dead_error,
+    blockhash,
+});
+project!(TransactionEvent {
+    signature,
+    is_vote,
+    transaction,
+    transaction_status_meta,
+    slot,
+    index,
+    block_time,
+    compute_unit_limit,
+    compute_unit_price,
+});
+project!(TokenAccountEvent {
+    slot,
+    pubkey,
+    program,
+    mint,
+    owner,
+    amount,
+    delegate,
+    delegated_amount,
+    state,
+    is_native,
+    close_authority,
+    lamports,
+    write_version,
+});
+project!(StakeAccountEvent {
+    slot,
+    pubkey,
+    state,
+    rent_exempt_reserve,
+    staker,
+    withdrawer,
+    lockup_unix_timestamp,
+    lockup_epoch,
+    lockup_custodian,
+    voter,
+    stake,
+    activation_epoch,
+    deactivation_epoch,
+    credits_observed,
+    lamports,
+    write_version,
+});
+project!(VoteAccountEvent {
+    slot,
+    pubkey,
+    node_pubkey,
+    authorized_voter,
+    authorized_withdrawer,
+    commission,
+    root_slot,
+    last_voted_slot,
+    credits,
+    epoch,
+    epoch_credits,
+    last_timestamp_slot,
+    last_timestamp,
+    lamports,
+    write_version,
+});
+project!(ProgramUpgradedEvent {
+    slot,
+    signature,
+    program_id,
+    program_data,
+    buffer,
+    authority,
+    deployed,
+});
+project!(SignatureEvent {
+    signature,
+    slot,
+    is_vote,
+    is_status_err,
+    error_info,
+});
+project!(InstructionEvent {
+    signature,
+    slot,
+    transaction_index,
+    instruction_index,
+    inner_index,
+    stack_height,
+    program_id,
+    accounts,
+    data,
+    is_status_err,
+    block_time,
+});
+project!(BlockRewardsEvent {
+    slot,
+    blockhash,
+    block_time,
+    rewards,
+});
+project!(CheckpointEvent {
+    topic,
+    slot,
+    timestamp,
+});
+project!(BootstrapMarkerEvent {
+    started_at,
+    end,
+    slot,
+    accounts,
+});
+project!(PluginStartedEvent {
+    plugin_version,
+    git_version,
+    interface_version,
+    config_hash,
+    filters,
+    validator_identity,
+    timestamp,
+});
+
+/// Fields of `M` to clear before publishing to `topic`. With an include list only those
+/// fields are kept, excluded fields are always dropped.
+pub fn cleared_fields<M: Project>(topic: &str, config: &TopicConfig) -> Vec<&'static str> {
+    for field in config.include.iter().chain(&config.exclude) {
+        if !M::FIELDS.contains(&field.as_str()) {
+            warn!(topic, field = %field, "unknown field in topic projection");
+        }
+    }
+    M::FIELDS
+        .iter()
+        .copied()
+        .filter(|field| {
+            (!config.include.is_empty() && !config.include.iter().any(|f| f == field))
+                || config.exclude.iter().any(|f| f == field)
+        })
+        .collect()
+}
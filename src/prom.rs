@@ -1,24 +1,66 @@
 use {
-    crate::version::VERSION as VERSION_INFO,
+    crate::{
+        bootstrap,
+        config::{PrometheusAuth, PrometheusTls},
+        dump,
+        health::HEALTH,
+        pause::PAUSE,
+        switches::SWITCHES,
+        telemetry,
+        version::VERSION as VERSION_INFO,
+    },
     hyper::{
-        server::conn::AddrStream,
-        service::{make_service_fn, service_fn},
-        Body, Request, Response, Server, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+        server::conn::Http,
+        service::service_fn,
+        Body, Method, Request, Response, StatusCode,
+    },
+    prometheus::{
+        exponential_buckets, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+        IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+    },
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io::{BufReader, Error as IoError, ErrorKind, Result as IoResult},
+        net::SocketAddr,
+        os::unix::fs::FileTypeExt,
+        path::{Path, PathBuf},
+        sync::{Arc, Once},
+        time::Duration,
+    },
+    tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::{TcpListener, UnixListener},
+        runtime::Runtime,
+    },
+    tokio_rustls::{
+        rustls::{Certificate, PrivateKey, ServerConfig},
+        TlsAcceptor,
+    },
+    tracing::{debug, error, info},
+};
+#[cfg(feature = "rdkafka-backend")]
+use {
+    crate::{
+        config::OAuthBearerConfig,
+        oauth, recovery,
+        watermark::{Position, WATERMARKS},
     },
-    log::*,
-    prometheus::{GaugeVec, IntCounterVec, Opts, Registry, TextEncoder},
+    prometheus::GaugeVec,
     rdkafka::{
-        client::ClientContext,
+        client::{ClientContext, OAuthToken},
+        consumer::ConsumerContext,
+        error::KafkaError,
+        message::Message,
         producer::{DeliveryResult, ProducerContext},
         statistics::Statistics,
     },
-    std::{io::Result as IoResult, net::SocketAddr, sync::Once, time::Duration},
-    tokio::runtime::Runtime,
+    std::error::Error,
+    tracing::{field, Span},
 };
 
 lazy_static::lazy_static! {
-    pub static ref REGISTRY: Registry = Registry::new();
-
     static ref VERSION: IntCounterVec = IntCounterVec::new(
         Opts::new("version", "Plugin version info"),
         &["key", "value"]
@@ -39,37 +81,215 @@ lazy_static::lazy_static! {
         &["status"]
     ).unwrap();
 
+    pub static ref UPLOAD_TOKEN_ACCOUNTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_token_accounts_total", "Status of uploaded decoded token accounts"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_STAKE_ACCOUNTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_stake_accounts_total", "Status of uploaded decoded stake accounts"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_VOTE_ACCOUNTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_vote_accounts_total", "Status of uploaded decoded vote accounts"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_SIGNATURES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_signatures_total", "Status of uploaded transaction signatures"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_INSTRUCTIONS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_instructions_total", "Status of uploaded instructions"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_PROGRAM_UPGRADES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_program_upgrades_total", "Status of uploaded program upgrades"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_BLOCK_REWARDS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_block_rewards_total", "Status of uploaded block rewards"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_PLUGIN_STARTED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_plugin_started_total", "Status of uploaded plugin startup announcements"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_CHECKPOINTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_checkpoints_total", "Status of uploaded checkpoints"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref EVENTS_SKIPPED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("events_skipped_total", "Events not published, by event type and reason"),
+        &["event", "reason"]
+    ).unwrap();
+
+    pub static ref CALLBACK_OVERHEAD_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("callback_overhead_seconds", "Time spent filtering and encoding events in validator callbacks")
+            .buckets(exponential_buckets(0.000_001, 2.0, 20).unwrap()),
+        &["event", "stage"]
+    ).unwrap();
+
+    pub static ref PLUGIN_PANICS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("plugin_panics_total", "Panics caught in validator callbacks"),
+        &["callback"]
+    ).unwrap();
+
+    pub static ref CIRCUIT_BREAKER_STATE: IntGauge = IntGauge::new(
+        "circuit_breaker_state", "Circuit breaker state: 0 closed, 1 open, 2 half-open"
+    ).unwrap();
+
+    pub static ref CIRCUIT_BREAKER_TRANSITIONS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("circuit_breaker_transitions_total", "Circuit breaker state changes by new state"),
+        &["state"]
+    ).unwrap();
+
+    pub static ref PRODUCER_RESTARTS_TOTAL: IntCounter = IntCounter::new(
+        "producer_restarts_total", "Producers rebuilt after fatal errors"
+    ).unwrap();
+
+    pub static ref WATCHDOG_ACTIONS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("watchdog_actions_total", "Actions taken by the watchdog"),
+        &["action"]
+    ).unwrap();
+
+    pub static ref TOP_PROGRAMS_ACCOUNTS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("top_programs_accounts", "Published account updates of the busiest owner programs over the sliding window"),
+        &["program"]
+    ).unwrap();
+
+    pub static ref TOP_PROGRAMS_BYTES: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("top_programs_bytes", "Published account data bytes of the busiest owner programs over the sliding window"),
+        &["program"]
+    ).unwrap();
+
+    pub static ref STARTUP_ACCOUNTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("startup_accounts_total", "Accounts replayed from the snapshot at startup"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref STARTUP_PROGRESS: Gauge = Gauge::new(
+        "startup_progress", "Estimated completion ratio of the startup replay"
+    ).unwrap();
+
+    pub static ref STARTUP_ETA_SECONDS: Gauge = Gauge::new(
+        "startup_eta_seconds", "Estimated seconds until the startup replay completes"
+    ).unwrap();
+
+    pub static ref WORKER_QUEUE_DEPTH: IntGauge = IntGauge::new(
+        "worker_queue_depth", "Number of events waiting for a publishing worker"
+    ).unwrap();
+
+    pub static ref ACCOUNT_STATES: IntGauge = IntGauge::new(
+        "account_states", "Accounts held in the latest-state cache"
+    ).unwrap();
+
+    pub static ref ACCOUNT_STATE_LOOKUPS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("account_state_lookups_total", "Account updates found in the latest-state cache or not"),
+        &["result"]
+    ).unwrap();
+
+    pub static ref ACCOUNT_STATE_EVICTIONS_TOTAL: IntCounter = IntCounter::new(
+        "account_state_evictions_total", "Accounts evicted from the full latest-state cache, or not held with the reject policy"
+    ).unwrap();
+
+    pub static ref BUFFERED_BYTES: IntGauge = IntGauge::new(
+        "buffered_bytes", "Bytes of events held in the worker queues and the pause buffer"
+    ).unwrap();
+
+    pub static ref BOOTSTRAP_ACCOUNTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("bootstrap_accounts_total", "Status of accounts republished to the bootstrap topic"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref ACCOUNT_SNAPSHOTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("account_snapshots_total", "Status of written account snapshots"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref REMOTE_FILTER_POLLS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("remote_filter_polls_total", "Polls of the remote filter section by result: updated, unchanged or failed"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref DRY_RUN_MESSAGES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dry_run_messages_total", "Messages that would have been published in dry-run mode"),
+        &["topic"]
+    ).unwrap();
+
+    pub static ref DRY_RUN_BYTES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dry_run_bytes_total", "Key and payload bytes that would have been published in dry-run mode"),
+        &["topic"]
+    ).unwrap();
+
+    pub static ref DATA_COMPRESSION_FAILURES_TOTAL: IntCounter = IntCounter::new(
+        "data_compression_failures_total", "JSON account and instruction data sent as plain base64 after Zstandard compression failed"
+    ).unwrap();
+
+    pub static ref OVERSIZE_EVENTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("oversize_events_total", "Events larger than the max.message.bytes of their topic"),
+        &["topic"]
+    ).unwrap();
+
+    pub static ref TOPIC_LIMITS_KNOWN: IntGauge = IntGauge::new(
+        "topic_limits_known", "1 once the topic max.message.bytes are fetched and event sizes checked against them"
+    ).unwrap();
+
+    pub static ref BROKER_GROUP_ACTIVE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("broker_group_active", "1 for the broker group the producer is connected to, 0 for the others"),
+        &["group"]
+    ).unwrap();
+}
+
+#[cfg(feature = "rdkafka-backend")]
+lazy_static::lazy_static! {
     static ref KAFKA_STATS: GaugeVec = GaugeVec::new(
         Opts::new("kafka_stats", "librdkafka metrics"),
         &["broker", "metric"]
     ).unwrap();
 }
 
+/// Where the Prometheus endpoint listens.
+#[derive(Debug, Clone)]
+pub enum PrometheusAddress {
+    Tcp(SocketAddr),
+    /// Unix domain socket path, for hosts where no extra TCP listener may be opened.
+    Unix(PathBuf),
+}
+
+/// Pause after a failed accept. Errors such as running out of file descriptors persist,
+/// and retrying right away would spin.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
 #[derive(Debug)]
 pub struct PrometheusService {
     runtime: Runtime,
+    /// Removed on shutdown.
+    socket_path: Option<PathBuf>,
 }
 
 impl PrometheusService {
-    pub fn new(address: SocketAddr) -> IoResult<Self> {
-        static REGISTER: Once = Once::new();
-        REGISTER.call_once(|| {
-            macro_rules! register {
-                ($collector:ident) => {
-                    REGISTRY
-                        .register(Box::new($collector.clone()))
-                        .expect("collector can't be registered");
-                };
-            }
-            register!(VERSION);
-            register!(UPLOAD_ACCOUNTS_TOTAL);
-            register!(UPLOAD_SLOTS_TOTAL);
-            register!(UPLOAD_TRANSACTIONS_TOTAL);
-            register!(KAFKA_STATS);
-
+    pub fn new(
+        address: PrometheusAddress,
+        tls: Option<&PrometheusTls>,
+        auth: Option<&PrometheusAuth>,
+        admin: bool,
+        namespace: Option<&str>,
+        labels: &HashMap<String, String>,
+    ) -> IoResult<Self> {
+        static VERSION_INFO_SET: Once = Once::new();
+        VERSION_INFO_SET.call_once(|| {
             for (key, value) in &[
                 ("version", VERSION_INFO.version),
                 ("solana", VERSION_INFO.solana),
+                ("interface", VERSION_INFO.interface),
                 ("git", VERSION_INFO.git),
                 ("rustc", VERSION_INFO.rustc),
                 ("buildts", VERSION_INFO.buildts),
@@ -77,40 +297,476 @@ impl PrometheusService {
                 VERSION.with_label_values(&[key, value]).inc()
             }
         });
+        let registry = Arc::new(
+            registry(namespace, labels)
+                .map_err(|error| IoError::new(ErrorKind::InvalidInput, error))?,
+        );
+
+        let connections = Connections {
+            acceptor: tls.map(load_tls_acceptor).transpose()?,
+            authorization: auth.map(|auth| Arc::<str>::from(auth.header_value())),
+            registry,
+            admin,
+        };
 
         let runtime = Runtime::new()?;
-        runtime.spawn(async move {
-            let make_service = make_service_fn(move |_: &AddrStream| async move {
-                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| async move {
-                    let response = match req.uri().path() {
-                        "/metrics" => metrics_handler(),
-                        _ => not_found_handler(),
+        let socket_path = match address {
+            PrometheusAddress::Tcp(address) => {
+                let listener = std::net::TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                let listener = {
+                    let _guard = runtime.enter();
+                    TcpListener::from_std(listener)?
+                };
+                runtime.spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, _)) => connections.spawn(stream),
+                            Err(error) => {
+                                error!(%error, "prometheus service failed to accept");
+                                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                            }
+                        }
+                    }
+                });
+                None
+            }
+            PrometheusAddress::Unix(path) => {
+                remove_stale_socket(&path)?;
+                let listener = std::os::unix::net::UnixListener::bind(&path)?;
+                listener.set_nonblocking(true)?;
+                let listener = {
+                    let _guard = runtime.enter();
+                    UnixListener::from_std(listener)?
+                };
+                runtime.spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, _)) => connections.spawn(stream),
+                            Err(error) => {
+                                error!(%error, "prometheus service failed to accept");
+                                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                            }
+                        }
+                    }
+                });
+                Some(path)
+            }
+        };
+        Ok(PrometheusService {
+            runtime,
+            socket_path,
+        })
+    }
+
+    pub fn shutdown(self) {
+        self.runtime.shutdown_timeout(Duration::from_secs(10));
+        if let Some(path) = &self.socket_path {
+            if let Err(error) = fs::remove_file(path) {
+                debug!(path = %path.display(), %error, "failed to remove prometheus socket");
+            }
+        }
+    }
+}
+
+/// Removes a socket left behind by a previous run, refusing to remove any other file or a
+/// socket another process still listens on.
+fn remove_stale_socket(path: &Path) -> IoResult<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(IoError::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is in use by another process", path.display()),
+                ));
+            }
+            fs::remove_file(path)
+        }
+        Ok(_) => Err(IoError::new(
+            ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// State shared by the connections of the Prometheus endpoint.
+#[derive(Clone)]
+struct Connections {
+    acceptor: Option<TlsAcceptor>,
+    authorization: Option<Arc<str>>,
+    registry: Arc<Registry>,
+    admin: bool,
+}
+
+impl Connections {
+    fn spawn<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let Connections {
+            acceptor,
+            authorization,
+            registry,
+            admin,
+        } = self.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req: Request<Body>| {
+                let authorization = authorization.clone();
+                let registry = Arc::clone(&registry);
+                async move {
+                    let response = if !is_authorized(&req, authorization.as_deref()) {
+                        unauthorized_handler()
+                    } else {
+                        match req.uri().path() {
+                            "/metrics" => metrics_handler(&registry),
+                            "/health" => health_handler(),
+                            "/admin/pause" | "/admin/resume" if admin => pause_handler(&req),
+                            "/admin/disable" | "/admin/enable" if admin => switch_handler(&req),
+                            "/admin/dump" if admin => dump_handler(&req),
+                            "/admin/bootstrap" if admin => bootstrap_handler(&req),
+                            "/admin/log-level" if admin => log_level_handler(&req),
+                            _ => not_found_handler(),
+                        }
                     };
                     Ok::<_, hyper::Error>(response)
-                }))
+                }
             });
-            if let Err(error) = Server::bind(&address).serve(make_service).await {
-                error!("prometheus service failed: {}", error);
+
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => Http::new().serve_connection(stream, service).await,
+                    Err(error) => {
+                        debug!(%error, "prometheus service TLS handshake failed");
+                        return;
+                    }
+                },
+                None => Http::new().serve_connection(stream, service).await,
+            };
+            if let Err(error) = result {
+                debug!(%error, "prometheus service connection failed");
             }
         });
-        Ok(PrometheusService { runtime })
     }
+}
 
-    pub fn shutdown(self) {
-        self.runtime.shutdown_timeout(Duration::from_secs(10));
+/// Registry of all metrics, with the namespace prefixed to their names and the labels added.
+pub fn registry(
+    namespace: Option<&str>,
+    labels: &HashMap<String, String>,
+) -> prometheus::Result<Registry> {
+    let namespace = namespace
+        .map(|namespace| namespace.trim_end_matches('_'))
+        .filter(|namespace| !namespace.is_empty());
+    let registry = Registry::new_custom(
+        namespace.map(str::to_owned),
+        (!labels.is_empty()).then(|| labels.clone()),
+    )?;
+    macro_rules! register {
+        ($collector:ident) => {
+            registry.register(Box::new($collector.clone()))?
+        };
+    }
+    register!(VERSION);
+    register!(UPLOAD_ACCOUNTS_TOTAL);
+    register!(UPLOAD_SLOTS_TOTAL);
+    register!(UPLOAD_TRANSACTIONS_TOTAL);
+    register!(UPLOAD_TOKEN_ACCOUNTS_TOTAL);
+    register!(UPLOAD_STAKE_ACCOUNTS_TOTAL);
+    register!(UPLOAD_VOTE_ACCOUNTS_TOTAL);
+    register!(UPLOAD_SIGNATURES_TOTAL);
+    register!(UPLOAD_INSTRUCTIONS_TOTAL);
+    register!(UPLOAD_PROGRAM_UPGRADES_TOTAL);
+    register!(UPLOAD_BLOCK_REWARDS_TOTAL);
+    register!(UPLOAD_CHECKPOINTS_TOTAL);
+    register!(UPLOAD_PLUGIN_STARTED_TOTAL);
+    register!(EVENTS_SKIPPED_TOTAL);
+    register!(CALLBACK_OVERHEAD_SECONDS);
+    register!(PLUGIN_PANICS_TOTAL);
+    register!(WATCHDOG_ACTIONS_TOTAL);
+    register!(PRODUCER_RESTARTS_TOTAL);
+    register!(CIRCUIT_BREAKER_STATE);
+    register!(CIRCUIT_BREAKER_TRANSITIONS_TOTAL);
+    register!(TOP_PROGRAMS_ACCOUNTS);
+    register!(TOP_PROGRAMS_BYTES);
+    register!(STARTUP_ACCOUNTS_TOTAL);
+    register!(STARTUP_PROGRESS);
+    register!(STARTUP_ETA_SECONDS);
+    register!(WORKER_QUEUE_DEPTH);
+    register!(BROKER_GROUP_ACTIVE);
+    register!(REMOTE_FILTER_POLLS_TOTAL);
+    register!(ACCOUNT_SNAPSHOTS_TOTAL);
+    register!(BOOTSTRAP_ACCOUNTS_TOTAL);
+    register!(BUFFERED_BYTES);
+    register!(ACCOUNT_STATES);
+    register!(ACCOUNT_STATE_LOOKUPS_TOTAL);
+    register!(ACCOUNT_STATE_EVICTIONS_TOTAL);
+    register!(DRY_RUN_MESSAGES_TOTAL);
+    register!(DRY_RUN_BYTES_TOTAL);
+    register!(DATA_COMPRESSION_FAILURES_TOTAL);
+    register!(OVERSIZE_EVENTS_TOTAL);
+    register!(TOPIC_LIMITS_KNOWN);
+    #[cfg(feature = "rdkafka-backend")]
+    register!(KAFKA_STATS);
+    Ok(registry)
+}
+
+fn load_tls_acceptor(tls: &PrometheusTls) -> IoResult<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&tls.cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(&tls.key_path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "no private key found"))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|error| IoError::new(ErrorKind::InvalidInput, error))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn is_authorized(req: &Request<Body>, authorization: Option<&str>) -> bool {
+    match authorization {
+        Some(expected) => req
+            .headers()
+            .get(AUTHORIZATION)
+            .map(|value| constant_time_eq(value.as_bytes(), expected.as_bytes()))
+            .unwrap_or(false),
+        None => true,
     }
 }
 
-fn metrics_handler() -> Response<Body> {
+/// Compares every byte, so that the response time does not tell how much of a guess matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn unauthorized_handler() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(WWW_AUTHENTICATE, "Basic, Bearer")
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn metrics_handler(registry: &Registry) -> Response<Body> {
     let metrics = TextEncoder::new()
-        .encode_to_string(&REGISTRY.gather())
+        .encode_to_string(&registry.gather())
         .unwrap_or_else(|error| {
-            error!("could not encode custom metrics: {}", error);
+            error!(%error, "could not encode custom metrics");
             String::new()
         });
     Response::builder().body(Body::from(metrics)).unwrap()
 }
 
+fn health_handler() -> Response<Body> {
+    let report = HEALTH.report();
+    let status = if report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::to_string(&report).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// `GET /admin/pause` reports the paused event types, `POST /admin/pause` and
+/// `POST /admin/resume` change them. `?events=account,slot` selects event types, all by default.
+fn pause_handler(req: &Request<Body>) -> Response<Body> {
+    let events = query_events(req);
+    let result = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/admin/pause") => Ok(PAUSE.report()),
+        (&Method::POST, "/admin/pause") => PAUSE.pause(&events),
+        (&Method::POST, "/admin/resume") => PAUSE.resume(&events),
+        _ => {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::empty())
+                .unwrap()
+        }
+    };
+    match result {
+        Ok(report) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_string(&report).unwrap_or_default(),
+            ))
+            .unwrap(),
+        Err(error) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(error))
+            .unwrap(),
+    }
+}
+
+/// `GET /admin/disable` reports the disabled notifications, `POST /admin/disable` and
+/// `POST /admin/enable` change them. `?events=account,slot` selects notifications, all by default.
+fn switch_handler(req: &Request<Body>) -> Response<Body> {
+    let events = query_events(req);
+    let result = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/admin/disable") => Ok(SWITCHES.report()),
+        (&Method::POST, "/admin/disable") => SWITCHES.disable(&events),
+        (&Method::POST, "/admin/enable") => SWITCHES.enable(&events),
+        _ => {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::empty())
+                .unwrap()
+        }
+    };
+    match result {
+        Ok(report) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_string(&report).unwrap_or_default(),
+            ))
+            .unwrap(),
+        Err(error) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(error))
+            .unwrap(),
+    }
+}
+
+/// Comma separated `events` of the query string.
+fn query_events(req: &Request<Body>) -> Vec<&str> {
+    req.uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("events="))
+        .flat_map(|events| events.split(','))
+        .filter(|event| !event.is_empty())
+        .collect()
+}
+
+/// `POST /admin/dump` logs a dump of the plugin's state.
+fn dump_handler(req: &Request<Body>) -> Response<Body> {
+    if req.method() != Method::POST {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    dump::request();
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// `POST /admin/bootstrap` republishes the latest state of all filtered accounts to `bootstrap_topic`.
+fn bootstrap_handler(req: &Request<Body>) -> Response<Body> {
+    if req.method() != Method::POST {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let status = match bootstrap::request() {
+        true => StatusCode::ACCEPTED,
+        false => StatusCode::CONFLICT,
+    };
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// `GET /admin/log-level` reports the log filter directives, `PUT /admin/log-level` changes
+/// them and `DELETE /admin/log-level` restores the configured ones. `?level=debug` sets the
+/// default level, `?publisher=trace` the level of a module.
+fn log_level_handler(req: &Request<Body>) -> Response<Body> {
+    if !matches!(*req.method(), Method::GET | Method::PUT | Method::DELETE) {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    // Another subscriber was installed before the plugin's.
+    let directives = match telemetry::log_directives() {
+        Some(directives) => directives,
+        None => {
+            return Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body(Body::empty())
+                .unwrap()
+        }
+    };
+    let result = match *req.method() {
+        Method::PUT => log_levels_query(req.uri().query().unwrap_or_default())
+            .and_then(|(level, modules)| telemetry::set_log_levels(level.as_deref(), modules)),
+        Method::DELETE => telemetry::reset_log_levels(),
+        _ => Ok(directives),
+    };
+    match result {
+        Ok(directives) => {
+            if *req.method() != Method::GET {
+                info!(%directives, "log levels changed");
+            }
+            Response::builder()
+                .header(CONTENT_TYPE, "text/plain")
+                .body(Body::from(directives))
+                .unwrap()
+        }
+        Err(error) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(error))
+            .unwrap(),
+    }
+}
+
+/// The default level and the module levels of a `log-level` query, percent-decoded.
+fn log_levels_query(query: &str) -> Result<(Option<String>, HashMap<String, String>), String> {
+    let mut level = None;
+    let mut modules = HashMap::new();
+    for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        let (name, value) = (percent_decode(name)?, percent_decode(value)?);
+        match name.as_str() {
+            "level" => level = Some(value),
+            _ => {
+                modules.insert(name, value);
+            }
+        }
+    }
+    Ok((level, modules))
+}
+
+/// Decodes `%XX` escapes and `+` as a space, as in `application/x-www-form-urlencoded`.
+fn percent_decode(value: &str) -> Result<String, String> {
+    let invalid = || format!("invalid percent-encoding: {}", value);
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'%' => {
+                let hex = rest.get(..2).ok_or_else(invalid)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                rest = &rest[2..];
+            }
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
 fn not_found_handler() -> Response<Body> {
     Response::builder()
         .status(StatusCode::NOT_FOUND)
@@ -118,12 +774,48 @@ fn not_found_handler() -> Response<Body> {
         .unwrap()
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-pub struct StatsThreadedProducerContext;
+#[cfg(feature = "rdkafka-backend")]
+#[derive(Debug, Default, Clone)]
+pub struct StatsThreadedProducerContext {
+    oauthbearer: Option<OAuthBearerConfig>,
+}
 
+#[cfg(feature = "rdkafka-backend")]
+impl StatsThreadedProducerContext {
+    pub fn new(oauthbearer: Option<OAuthBearerConfig>) -> Self {
+        Self { oauthbearer }
+    }
+}
+
+#[cfg(feature = "rdkafka-backend")]
 impl ClientContext for StatsThreadedProducerContext {
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn generate_oauth_token(
+        &self,
+        _oauthbearer_config: Option<&str>,
+    ) -> Result<OAuthToken, Box<dyn Error>> {
+        let config = self
+            .oauthbearer
+            .as_ref()
+            .ok_or("sasl.mechanism is OAUTHBEARER but no oauthbearer token source is configured")?;
+        oauth::token(config).map_err(|error| {
+            error!(%error, "failed to refresh OAUTHBEARER token");
+            error
+        })
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        error!("librdkafka: {}: {}", error, reason);
+        if recovery::is_fatal(&error) {
+            recovery::record_fatal();
+        }
+    }
+
     fn stats(&self, statistics: Statistics) {
         for (name, broker) in statistics.brokers {
+            HEALTH.set_broker_state(&name, &broker.state);
+
             macro_rules! set_value {
                 ($name:expr, $value:expr) => {
                     KAFKA_STATS
@@ -178,7 +870,186 @@ impl ClientContext for StatsThreadedProducerContext {
     }
 }
 
+/// State carried from enqueueing a message to its delivery report.
+#[cfg(feature = "rdkafka-backend")]
+#[derive(Debug)]
+pub struct DeliveryMeta {
+    /// Span covering the time between enqueue and delivery.
+    pub span: Span,
+    /// Position of the account update, tracked for the delivered watermark.
+    pub position: Option<Position>,
+}
+
+#[cfg(feature = "rdkafka-backend")]
 impl ProducerContext for StatsThreadedProducerContext {
-    type DeliveryOpaque = ();
-    fn delivery(&self, _: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {}
+    type DeliveryOpaque = Box<DeliveryMeta>;
+    fn delivery(&self, result: &DeliveryResult<'_>, meta: Self::DeliveryOpaque) {
+        match result {
+            Ok(message) => {
+                HEALTH.record_delivery(message.topic());
+                if let Some(position) = meta.position {
+                    WATERMARKS.record_resolved(message.topic(), position, true);
+                }
+            }
+            Err((error, message)) => {
+                HEALTH.record_delivery_failure(error);
+                if let Some(position) = meta.position {
+                    WATERMARKS.record_resolved(message.topic(), position, false);
+                }
+                meta.span.record("error", field::display(error));
+            }
+        }
+    }
+}
+
+/// For the handoff control topic consumer, which authenticates like the producer.
+#[cfg(feature = "rdkafka-backend")]
+impl ConsumerContext for StatsThreadedProducerContext {}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::switches::Notification};
+
+    #[test]
+    fn test_is_authorized() {
+        let request = |authorization: Option<&str>| {
+            let mut builder = Request::builder();
+            if let Some(authorization) = authorization {
+                builder = builder.header(AUTHORIZATION, authorization);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+        let expected = Some("Bearer secret");
+
+        assert!(is_authorized(&request(Some("Bearer secret")), expected));
+        assert!(!is_authorized(&request(Some("Bearer secreT")), expected));
+        assert!(!is_authorized(&request(Some("Bearer secret2")), expected));
+        assert!(!is_authorized(&request(None), expected));
+        assert!(is_authorized(&request(None), None));
+    }
+
+    #[test]
+    fn test_log_levels_query() {
+        let (level, modules) =
+            log_levels_query("level=debug&solana_runtime%3A%3Abank=trace&kafka=warn").unwrap();
+        assert_eq!(level.as_deref(), Some("debug"));
+        assert_eq!(
+            modules,
+            HashMap::from([
+                ("solana_runtime::bank".to_owned(), "trace".to_owned()),
+                ("kafka".to_owned(), "warn".to_owned()),
+            ])
+        );
+        assert_eq!(log_levels_query("").unwrap(), (None, HashMap::new()));
+        assert_eq!(
+            log_levels_query("level=de%2").unwrap_err(),
+            "invalid percent-encoding: de%2"
+        );
+        assert!(log_levels_query("level=%zz").is_err());
+        assert!(log_levels_query("level=%ff").is_err());
+        assert_eq!(percent_decode("a+b%20c").unwrap(), "a b c");
+    }
+
+    #[test]
+    fn test_switch_handler() {
+        let request = |method, uri| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = switch_handler(&req);
+            let status = response.status();
+            let body = tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(hyper::body::to_bytes(response.into_body()))
+                .unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        };
+
+        // Only slots, the other notifications are exercised by concurrent tests.
+        assert_eq!(
+            request(Method::POST, "/admin/disable?events=slot"),
+            (StatusCode::OK, r#"{"disabled":["slot"]}"#.to_owned())
+        );
+        assert!(!SWITCHES.is_enabled(Notification::Slot));
+        assert_eq!(
+            request(Method::GET, "/admin/disable"),
+            (StatusCode::OK, r#"{"disabled":["slot"]}"#.to_owned())
+        );
+        assert_eq!(
+            request(Method::POST, "/admin/enable?events=slot"),
+            (StatusCode::OK, r#"{"disabled":[]}"#.to_owned())
+        );
+        assert!(SWITCHES.is_enabled(Notification::Slot));
+        assert_eq!(
+            request(Method::POST, "/admin/disable?events=slots"),
+            (
+                StatusCode::BAD_REQUEST,
+                r#"unknown notification "slots""#.to_owned()
+            )
+        );
+        assert_eq!(
+            request(Method::PUT, "/admin/enable").0,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[test]
+    fn test_remove_stale_socket() {
+        let path =
+            std::env::temp_dir().join(format!("kafka-plugin-prom-{}.sock", std::process::id()));
+        assert!(remove_stale_socket(&path).is_ok());
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let error = remove_stale_socket(&path).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AddrInUse);
+        assert!(path.exists());
+
+        drop(listener);
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+
+        fs::write(&path, b"metrics").unwrap();
+        let error = remove_stale_socket(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_registry() {
+        DRY_RUN_MESSAGES_TOTAL
+            .with_label_values(&["test_registry"])
+            .inc();
+        let labels = HashMap::from([("cluster".to_owned(), "mainnet".to_owned())]);
+
+        let families = registry(Some("geyser_kafka_"), &labels).unwrap().gather();
+        let family = families
+            .iter()
+            .find(|family| family.get_name() == "geyser_kafka_dry_run_messages_total")
+            .unwrap();
+        let metric = family
+            .get_metric()
+            .iter()
+            .find(|metric| {
+                metric.get_label().iter().any(|label| {
+                    label.get_name() == "topic" && label.get_value() == "test_registry"
+                })
+            })
+            .unwrap();
+        assert!(metric
+            .get_label()
+            .iter()
+            .any(|label| label.get_name() == "cluster" && label.get_value() == "mainnet"));
+
+        let families = registry(Some("_"), &HashMap::new()).unwrap().gather();
+        let family = families
+            .iter()
+            .find(|family| family.get_name() == "dry_run_messages_total")
+            .unwrap();
+        assert!(family.get_metric().iter().all(|metric| metric
+            .get_label()
+            .iter()
+            .all(|label| label.get_name() != "cluster")));
+    }
 }
@@ -14,70 +14,1033 @@
 
 use {
     crate::{
+        block_time,
+        breaker::BREAKER,
+        buffer,
+        config::{
+            AccountKey, DataEncoding, Format, JsonCompression, OversizeConfig, OversizePolicy,
+            SizeTopic, SlotPartitioning, TopicConfig,
+        },
+        error::PublishError,
+        flat::{self, FlatEncode},
+        headers::HeaderTemplates,
+        health::HEALTH,
+        json,
+        producer::{ProducerError, ProducerLike},
+        projection::{self, Project},
         prom::{
-            StatsThreadedProducerContext, UPLOAD_ACCOUNTS_TOTAL, UPLOAD_SLOTS_TOTAL,
-            UPLOAD_TRANSACTIONS_TOTAL,
+            CALLBACK_OVERHEAD_SECONDS, EVENTS_SKIPPED_TOTAL, OVERSIZE_EVENTS_TOTAL,
+            UPLOAD_ACCOUNTS_TOTAL, UPLOAD_BLOCK_REWARDS_TOTAL, UPLOAD_CHECKPOINTS_TOTAL,
+            UPLOAD_INSTRUCTIONS_TOTAL, UPLOAD_PLUGIN_STARTED_TOTAL, UPLOAD_PROGRAM_UPGRADES_TOTAL,
+            UPLOAD_SIGNATURES_TOTAL, UPLOAD_SLOTS_TOTAL, UPLOAD_STAKE_ACCOUNTS_TOTAL,
+            UPLOAD_TOKEN_ACCOUNTS_TOTAL, UPLOAD_TRANSACTIONS_TOTAL, UPLOAD_VOTE_ACCOUNTS_TOTAL,
         },
+        sample::{Sampler, Summarize},
+        topic_limits::TopicLimits,
+        transform::Transform,
+        version::VERSION,
+        watchdog,
+        watermark::{Position, WATERMARKS},
         *,
     },
+    borsh::BorshSerialize,
+    prometheus::IntCounterVec,
     prost::Message,
-    rdkafka::{
-        error::KafkaError,
-        producer::{BaseRecord, Producer, ThreadedProducer},
+    serde::Serialize,
+    sha2::{Digest, Sha256},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        borrow::Cow,
+        collections::HashMap,
+        str::FromStr,
+        sync::{Mutex, RwLock},
+        thread,
+        time::{Duration, Instant},
     },
-    std::time::Duration,
+    tracing::{info, trace_span, warn},
 };
 
+/// How often to log progress while flushing on shutdown.
+const DRAIN_LOG_INTERVAL: Duration = Duration::from_secs(1);
+/// How often to refresh the partition count of the slot status topic.
+const PARTITION_COUNT_REFRESH: Duration = Duration::from_secs(60);
+/// Bytes of the `chunk-id`, `chunk-index` and `chunk-count` headers of a chunk at most, names
+/// included, for the 32 hex digits of the id and indexes of up to 10 digits.
+const CHUNK_HEADERS_SIZE: usize = 8 + 32 + 11 + 10 + 11 + 10;
+
 pub struct Publisher {
-    producer: ThreadedProducer<StatsThreadedProducerContext>,
+    /// `None` with the null sink.
+    producer: RwLock<Option<Box<dyn ProducerLike>>>,
     shutdown_timeout: Duration,
+    format: Format,
+    default_data_encoding: DataEncoding,
+    json_compression: JsonCompression,
+    topics: HashMap<String, TopicConfig>,
+    transform: Option<Box<dyn Transform>>,
+    sampler: Option<Sampler>,
+    /// Fields omitted from events, by topic.
+    cleared_fields: HashMap<String, Vec<&'static str>>,
+    /// Base58 validator identity sent as a header.
+    identity: Option<String>,
+    /// Send the current chain time as a header.
+    chain_time_header: bool,
+    /// Routing hint headers sent with every event.
+    header_templates: HeaderTemplates,
+    /// Track the positions of delivered events, for restart dedup and checkpoints.
+    track_positions: bool,
+    account_key: AccountKey,
+    oversize: Option<OversizeConfig>,
+    /// Set on startup when `oversize` is configured.
+    topic_limits: RwLock<Option<TopicLimits>>,
 
     update_account_topic: String,
+    /// Ordered by `max_data_len`.
+    account_size_topics: Vec<SizeTopic>,
     slot_status_topic: String,
+    slot_partitioning: SlotPartitioning,
+    slot_partition_count: Mutex<PartitionCount>,
     transaction_topic: String,
+    transaction_failed_topic: String,
+    /// Transaction topics by program, in `program_filters` order.
+    program_transaction_topics: Vec<([u8; 32], String)>,
+    token_account_topic: String,
+    stake_account_topic: String,
+    vote_account_topic: String,
+    signature_topic: String,
+    instruction_topic: String,
+    program_upgrade_topic: String,
+    ops_topic: String,
+    rewards_topic: String,
+    checkpoint_topic: String,
+    bootstrap_topic: String,
+}
+
+/// Partition count of the slot status topic, the last known one if a refresh failed.
+#[derive(Debug, Default)]
+struct PartitionCount {
+    /// When the count was last fetched or tried.
+    checked: Option<Instant>,
+    count: Option<i32>,
+}
+
+impl PartitionCount {
+    fn partition(&self, slot: u64) -> Option<i32> {
+        self.count.map(|count| (slot % count as u64) as i32)
+    }
+}
+
+/// How an event is sent under the oversize policy.
+enum Oversize<'a> {
+    /// Within the limit of its topic.
+    Fits,
+    Skip,
+    DeadLetter(&'a str),
+    /// Split into chunks, the limit of its topic.
+    Chunk(usize),
+}
+
+/// Sends `payload` in chunks of up to `chunk_size` bytes, in order, each with the `headers` and
+/// `chunk-id`, `chunk-index` and `chunk-count` headers to reassemble it. Only the last chunk
+/// carries the position, which the event reaches once complete.
+#[allow(clippy::too_many_arguments)]
+fn send_chunks(
+    producer: &dyn ProducerLike,
+    topic: &str,
+    partition: Option<i32>,
+    key: Option<&[u8]>,
+    headers: &[(&str, &str)],
+    payload: &[u8],
+    mut position: Option<Position>,
+    chunk_size: usize,
+) -> Result<(), ProducerError> {
+    // The same for identical payloads, which reassemble the same.
+    let id = hex::encode(&Sha256::digest(payload)[..16]);
+    let count = payload.len().div_ceil(chunk_size);
+    let count_value = count.to_string();
+    for (index, chunk) in payload.chunks(chunk_size).enumerate() {
+        let index_value = index.to_string();
+        let mut chunk_headers = headers.to_vec();
+        chunk_headers.extend([
+            ("chunk-id", id.as_str()),
+            ("chunk-index", index_value.as_str()),
+            ("chunk-count", count_value.as_str()),
+        ]);
+        let position = if index + 1 == count {
+            position.take()
+        } else {
+            None
+        };
+        match partition {
+            Some(partition) => {
+                producer.send_to_partition(topic, partition, key, &chunk_headers, chunk, position)
+            }
+            None => producer.send(topic, key, &chunk_headers, chunk, position),
+        }?;
+    }
+    Ok(())
 }
 
 impl Publisher {
-    pub fn new(producer: ThreadedProducer<StatsThreadedProducerContext>, config: &Config) -> Self {
+    pub fn new(
+        producer: Option<Box<dyn ProducerLike>>,
+        transform: Option<Box<dyn Transform>>,
+        config: &Config,
+    ) -> Self {
         Self {
-            producer,
+            producer: RwLock::new(producer),
             shutdown_timeout: Duration::from_millis(config.shutdown_timeout_ms),
+            format: config.format,
+            default_data_encoding: config.data_encoding,
+            json_compression: config.json_compression,
+            topics: config.topics.clone(),
+            transform,
+            sampler: Self::sampler(config),
+            cleared_fields: Self::cleared_fields(config),
+            identity: config.identity.clone(),
+            chain_time_header: config.chain_time_header,
+            // Validated when the config was read.
+            header_templates: HeaderTemplates::parse(&config.headers).unwrap_or_default(),
+            track_positions: config.watermark.is_some() || config.checkpoints.is_some(),
+            account_key: config.account_key,
+            oversize: config.oversize.clone(),
+            topic_limits: RwLock::new(None),
             update_account_topic: config.update_account_topic.clone(),
+            account_size_topics: Self::account_size_topics(config),
             slot_status_topic: config.slot_status_topic.clone(),
+            slot_partitioning: config.slot_partitioning,
+            slot_partition_count: Mutex::default(),
             transaction_topic: config.transaction_topic.clone(),
+            transaction_failed_topic: config.transaction_failed_topic.clone(),
+            program_transaction_topics: Self::program_transaction_topics(config),
+            token_account_topic: config.token_account_topic.clone(),
+            stake_account_topic: config.stake_account_topic.clone(),
+            vote_account_topic: config.vote_account_topic.clone(),
+            signature_topic: config.signature_topic.clone(),
+            instruction_topic: config.instruction_topic.clone(),
+            program_upgrade_topic: config.program_upgrade_topic.clone(),
+            ops_topic: config.ops_topic.clone(),
+            rewards_topic: config.rewards_topic.clone(),
+            checkpoint_topic: config
+                .checkpoints
+                .as_ref()
+                .map(|checkpoints| checkpoints.topic.clone())
+                .unwrap_or_default(),
+            bootstrap_topic: config.bootstrap_topic.clone(),
         }
     }
 
-    pub fn update_account(&self, ev: UpdateAccountEvent) -> Result<(), KafkaError> {
-        let buf = ev.encode_to_vec();
-        let record = BaseRecord::<Vec<u8>, _>::to(&self.update_account_topic)
-            .key(&ev.pubkey)
-            .payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
-        UPLOAD_ACCOUNTS_TOTAL
-            .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
-            .inc();
+    fn cleared_fields(config: &Config) -> HashMap<String, Vec<&'static str>> {
+        let mut cleared = HashMap::new();
+        let mut add = |topic: &str, resolve: fn(&str, &TopicConfig) -> Vec<&'static str>| {
+            if let Some(topic_config) = config.topics.get(topic) {
+                let fields = resolve(topic, topic_config);
+                if !fields.is_empty() {
+                    cleared.insert(topic.to_owned(), fields);
+                }
+            }
+        };
+        add(
+            &config.update_account_topic,
+            projection::cleared_fields::<UpdateAccountEvent>,
+        );
+        for size_topic in &config.account_size_topics {
+            add(
+                &size_topic.topic,
+                projection::cleared_fields::<UpdateAccountEvent>,
+            );
+        }
+        add(
+            &config.slot_status_topic,
+            projection::cleared_fields::<SlotStatusEvent>,
+        );
+        add(
+            &config.transaction_topic,
+            projection::cleared_fields::<TransactionEvent>,
+        );
+        add(
+            &config.transaction_failed_topic,
+            projection::cleared_fields::<TransactionEvent>,
+        );
+        for (_, topic) in Self::program_transaction_topics(config) {
+            add(&topic, projection::cleared_fields::<TransactionEvent>);
+        }
+        add(
+            &config.token_account_topic,
+            projection::cleared_fields::<TokenAccountEvent>,
+        );
+        add(
+            &config.stake_account_topic,
+            projection::cleared_fields::<StakeAccountEvent>,
+        );
+        add(
+            &config.vote_account_topic,
+            projection::cleared_fields::<VoteAccountEvent>,
+        );
+        add(
+            &config.signature_topic,
+            projection::cleared_fields::<SignatureEvent>,
+        );
+        add(
+            &config.instruction_topic,
+            projection::cleared_fields::<InstructionEvent>,
+        );
+        add(
+            &config.program_upgrade_topic,
+            projection::cleared_fields::<ProgramUpgradedEvent>,
+        );
+        add(
+            &config.rewards_topic,
+            projection::cleared_fields::<BlockRewardsEvent>,
+        );
+        cleared
+    }
+
+    fn sampler(config: &Config) -> Option<Sampler> {
+        Sampler::new(config.log_sample_every, Self::topic_names(config).iter())
+    }
+
+    /// Every topic events can be published to, including the per-program transaction topics.
+    pub fn topic_names(config: &Config) -> Vec<String> {
+        let program_topics = Self::program_transaction_topics(config);
+        config
+            .topic_names()
+            .cloned()
+            .chain(program_topics.into_iter().map(|(_, topic)| topic))
+            .collect()
+    }
+
+    fn account_size_topics(config: &Config) -> Vec<SizeTopic> {
+        let mut topics = config.account_size_topics.clone();
+        topics.sort_by_key(|size_topic| size_topic.max_data_len);
+        topics
+    }
+
+    fn program_transaction_topics(config: &Config) -> Vec<([u8; 32], String)> {
+        let mut programs = config.program_filters.clone();
+        programs.extend(
+            config
+                .transaction_program_topics
+                .keys()
+                .filter(|program| !config.program_filters.contains(program))
+                .cloned(),
+        );
+        programs
+            .into_iter()
+            .filter_map(|program| {
+                let topic = match config.transaction_program_topics.get(&program) {
+                    Some(topic) => topic.clone(),
+                    None if config.transaction_topic_per_program => {
+                        format!("{}-{}", config.transaction_topic, program)
+                    }
+                    None => return None,
+                };
+                let program = Pubkey::from_str(&program).ok()?;
+                Some((program.to_bytes(), topic))
+            })
+            .collect()
+    }
+
+    /// Topics of a transaction: the failed transaction topic if set and the transaction failed,
+    /// else one per routed program among its account keys, else the transaction topic.
+    fn transaction_topics(&self, ev: &TransactionEvent) -> Vec<&str> {
+        let failed = ev
+            .transaction_status_meta
+            .as_ref()
+            .is_some_and(|meta| meta.is_status_err);
+        if failed && !self.transaction_failed_topic.is_empty() {
+            return vec![&self.transaction_failed_topic];
+        }
+        let account_keys = match ev
+            .transaction
+            .as_ref()
+            .and_then(|transaction| transaction.message.as_ref())
+            .and_then(|message| message.message_payload.as_ref())
+        {
+            Some(sanitized_message::MessagePayload::Legacy(message)) => &message.account_keys,
+            Some(sanitized_message::MessagePayload::V0(message)) => match &message.message {
+                Some(message) => &message.account_keys,
+                None => return vec![&self.transaction_topic],
+            },
+            None => return vec![&self.transaction_topic],
+        };
+        let topics = self
+            .program_transaction_topics
+            .iter()
+            .filter(|(program, _)| account_keys.iter().any(|key| key[..] == program[..]))
+            .map(|(_, topic)| topic.as_str())
+            .collect::<Vec<_>>();
+        if topics.is_empty() {
+            vec![&self.transaction_topic]
+        } else {
+            topics
+        }
+    }
+
+    /// Topic of an account update, by data length.
+    fn account_topic(&self, data_len: usize) -> &str {
+        self.account_size_topics
+            .iter()
+            .find(|size_topic| data_len <= size_topic.max_data_len)
+            .map_or(&self.update_account_topic, |size_topic| &size_topic.topic)
+    }
+
+    pub fn update_account(&self, ev: UpdateAccountEvent) -> Result<(), PublishError> {
+        let topic = self.account_topic(ev.data.len());
+        let position = self.position(ev.slot, ev.write_version);
+        if self.was_delivered("account", topic, position) {
+            return Ok(());
+        }
+        let key = self.account_key(&ev.pubkey, ev.write_version);
+        let result = self.publish("account", topic, Some(&key), position, ev);
+        self.record_result(&UPLOAD_ACCOUNTS_TOTAL, "account", &result);
         result
     }
 
-    pub fn update_slot_status(&self, ev: SlotStatusEvent) -> Result<(), KafkaError> {
-        let buf = ev.encode_to_vec();
-        let record = BaseRecord::<(), _>::to(&self.slot_status_topic).payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
-        UPLOAD_SLOTS_TOTAL
-            .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
-            .inc();
+    pub fn update_slot_status(&self, ev: SlotStatusEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, 0);
+        let result = self.publish_to_partition(
+            "slot",
+            &self.slot_status_topic,
+            self.slot_partition(ev.slot),
+            None,
+            position,
+            ev,
+        );
+        self.record_result(&UPLOAD_SLOTS_TOTAL, "slot", &result);
+        result
+    }
+
+    /// Transactions touching several routed programs are published to each of their topics.
+    pub fn update_transaction(&self, ev: TransactionEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, 0);
+        let mut topics = self.transaction_topics(&ev);
+        let last = topics.pop().expect("at least one transaction topic");
+        let mut result = Ok(());
+        for topic in topics {
+            let sent = self.publish("transaction", topic, None, position, ev.clone());
+            self.record_result(&UPLOAD_TRANSACTIONS_TOTAL, "transaction", &sent);
+            result = result.and(sent);
+        }
+        let sent = self.publish("transaction", last, None, position, ev);
+        self.record_result(&UPLOAD_TRANSACTIONS_TOTAL, "transaction", &sent);
+        result.and(sent)
+    }
+
+    pub fn update_token_account(&self, ev: TokenAccountEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, ev.write_version);
+        if self.was_delivered("token_account", &self.token_account_topic, position) {
+            return Ok(());
+        }
+        let key = self.account_key(&ev.pubkey, ev.write_version);
+        let result = self.publish(
+            "token_account",
+            &self.token_account_topic,
+            Some(&key),
+            position,
+            ev,
+        );
+        self.record_result(&UPLOAD_TOKEN_ACCOUNTS_TOTAL, "token_account", &result);
+        result
+    }
+
+    pub fn update_stake_account(&self, ev: StakeAccountEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, ev.write_version);
+        if self.was_delivered("stake_account", &self.stake_account_topic, position) {
+            return Ok(());
+        }
+        let key = self.account_key(&ev.pubkey, ev.write_version);
+        let result = self.publish(
+            "stake_account",
+            &self.stake_account_topic,
+            Some(&key),
+            position,
+            ev,
+        );
+        self.record_result(&UPLOAD_STAKE_ACCOUNTS_TOTAL, "stake_account", &result);
         result
     }
 
-    pub fn update_transaction(&self, ev: TransactionEvent) -> Result<(), KafkaError> {
-        let buf = ev.encode_to_vec();
-        let record = BaseRecord::<(), _>::to(&self.transaction_topic).payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
-        UPLOAD_TRANSACTIONS_TOTAL
+    pub fn update_vote_account(&self, ev: VoteAccountEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, ev.write_version);
+        if self.was_delivered("vote_account", &self.vote_account_topic, position) {
+            return Ok(());
+        }
+        let key = self.account_key(&ev.pubkey, ev.write_version);
+        let result = self.publish(
+            "vote_account",
+            &self.vote_account_topic,
+            Some(&key),
+            position,
+            ev,
+        );
+        self.record_result(&UPLOAD_VOTE_ACCOUNTS_TOTAL, "vote_account", &result);
+        result
+    }
+
+    pub fn update_signature(&self, ev: SignatureEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, 0);
+        let result = self.publish("signature", &self.signature_topic, None, position, ev);
+        self.record_result(&UPLOAD_SIGNATURES_TOTAL, "signature", &result);
+        result
+    }
+
+    /// Instructions are keyed by the transaction signature, keeping those of a transaction in order.
+    pub fn update_instruction(&self, ev: InstructionEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, 0);
+        let key = ev.signature.clone();
+        let result = self.publish(
+            "instruction",
+            &self.instruction_topic,
+            Some(&key),
+            position,
+            ev,
+        );
+        self.record_result(&UPLOAD_INSTRUCTIONS_TOTAL, "instruction", &result);
+        result
+    }
+
+    /// Program upgrades are keyed by the program ID, keeping those of a program in order.
+    pub fn update_program_upgrade(&self, ev: ProgramUpgradedEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, 0);
+        let key = ev.program_id.clone();
+        let result = self.publish(
+            "program_upgrade",
+            &self.program_upgrade_topic,
+            Some(&key),
+            position,
+            ev,
+        );
+        self.record_result(&UPLOAD_PROGRAM_UPGRADES_TOTAL, "program_upgrade", &result);
+        result
+    }
+
+    pub fn update_block_rewards(&self, ev: BlockRewardsEvent) -> Result<(), PublishError> {
+        let position = self.position(ev.slot, 0);
+        let result = self.publish("block_rewards", &self.rewards_topic, None, position, ev);
+        self.record_result(&UPLOAD_BLOCK_REWARDS_TOTAL, "block_rewards", &result);
+        result
+    }
+
+    /// Checkpoints are keyed by the topic they describe and not tracked themselves.
+    pub fn update_checkpoint(&self, ev: CheckpointEvent) -> Result<(), PublishError> {
+        let key = ev.topic.clone().into_bytes();
+        let result = self.publish("checkpoint", &self.checkpoint_topic, Some(&key), None, ev);
+        self.record_result(&UPLOAD_CHECKPOINTS_TOTAL, "checkpoint", &result);
+        result
+    }
+
+    /// Keyed by validator identity, so that the announcements of a validator stay in order.
+    pub fn plugin_started(&self, ev: PluginStartedEvent) -> Result<(), PublishError> {
+        let key = ev.validator_identity.clone().into_bytes();
+        let key = (!key.is_empty()).then_some(key);
+        let result = self.publish("plugin_started", &self.ops_topic, key.as_deref(), None, ev);
+        self.record_result(&UPLOAD_PLUGIN_STARTED_TOTAL, "plugin_started", &result);
+        result
+    }
+
+    /// Republished state is not tracked, consumers of the bootstrap topic read it from the start.
+    pub fn bootstrap_account(&self, ev: UpdateAccountEvent) -> Result<(), PublishError> {
+        let key = self.account_key(&ev.pubkey, ev.write_version);
+        self.publish("account", &self.bootstrap_topic, Some(&key), None, ev)
+    }
+
+    /// Sent to every partition, so that consumers of any partition see where the bootstrap
+    /// starts and ends. Once only if the partition count is unknown.
+    pub fn bootstrap_marker(&self, ev: BootstrapMarkerEvent) -> Result<(), PublishError> {
+        let key: &[u8] = match ev.end {
+            false => b"bootstrap-begin",
+            true => b"bootstrap-end",
+        };
+        let partitions = self
+            .producer
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|producer| producer.partition_count(&self.bootstrap_topic));
+        match partitions {
+            Some(count) => (0..count).try_for_each(|partition| {
+                self.publish_to_partition(
+                    "bootstrap_marker",
+                    &self.bootstrap_topic,
+                    Some(partition),
+                    Some(key),
+                    None,
+                    ev.clone(),
+                )
+            }),
+            None => self.publish(
+                "bootstrap_marker",
+                &self.bootstrap_topic,
+                Some(key),
+                None,
+                ev,
+            ),
+        }
+    }
+
+    /// Publishes the compiled event schemas as is, keyed by plugin version, so that consumers
+    /// can decode events without the `.proto` files.
+    pub fn publish_schemas(&self, topic: &str) -> Result<(), PublishError> {
+        let producer = self.producer.read().unwrap();
+        let producer = match producer.as_ref() {
+            Some(producer) => producer,
+            None => return Ok(()),
+        };
+        let key = format!("{}/{}", env!("CARGO_PKG_NAME"), VERSION.version);
+        let headers = [
+            ("content-type", "application/x-protobuf"),
+            ("schema-version", SCHEMA_VERSION),
+            ("plugin-version", VERSION.version),
+        ];
+        let result = producer
+            .send(
+                topic,
+                Some(key.as_bytes()),
+                &headers,
+                FILE_DESCRIPTOR_SET,
+                None,
+            )
+            .map_err(PublishError::from);
+        match &result {
+            Ok(()) => HEALTH.record_enqueued(),
+            Err(error) => HEALTH.record_failure(error),
+        }
+        result
+    }
+
+    /// Announces a completed backfill to the handoff control topic, always as protobuf since
+    /// the plugin decodes it.
+    pub fn backfill_completed(
+        &self,
+        control_topic: &str,
+        ev: BackfillCompletedEvent,
+    ) -> Result<(), PublishError> {
+        let producer = self.producer.read().unwrap();
+        let producer = match producer.as_ref() {
+            Some(producer) => producer,
+            None => return Ok(()),
+        };
+        let headers = [
+            ("content-type", "application/x-protobuf"),
+            ("schema-version", SCHEMA_VERSION),
+        ];
+        let result = producer
+            .send(
+                control_topic,
+                Some(ev.topic.as_bytes()),
+                &headers,
+                &ev.encode_to_vec(),
+                None,
+            )
+            .map_err(PublishError::from);
+        match &result {
+            Ok(()) => HEALTH.record_enqueued(),
+            Err(error) => HEALTH.record_failure(error),
+        }
+        result
+    }
+
+    /// Waits up to `shutdown_timeout_ms` for enqueued messages to be delivered, returning the
+    /// number left undelivered.
+    pub fn flush(&self) -> usize {
+        match self.producer.read().unwrap().as_ref() {
+            Some(producer) => drain(producer.as_ref(), self.shutdown_timeout),
+            None => 0,
+        }
+    }
+
+    /// Record key of an account update.
+    fn account_key(&self, pubkey: &[u8], write_version: u64) -> Vec<u8> {
+        match self.account_key {
+            AccountKey::Pubkey => pubkey.to_vec(),
+            AccountKey::PubkeyWriteVersion => [pubkey, &write_version.to_be_bytes()].concat(),
+        }
+    }
+
+    /// Partition of a slot status update, `None` to leave it to the partitioner.
+    fn slot_partition(&self, slot: u64) -> Option<i32> {
+        if self.slot_partitioning != SlotPartitioning::Slot {
+            return None;
+        }
+        {
+            let mut cached = self.slot_partition_count.lock().unwrap();
+            let fresh = cached
+                .checked
+                .is_some_and(|checked| checked.elapsed() < PARTITION_COUNT_REFRESH);
+            if fresh {
+                return cached.partition(slot);
+            }
+            // Taken before fetching, so that other threads keep using the last known count
+            // meanwhile, and a failed fetch is only tried again at the next refresh.
+            cached.checked = Some(Instant::now());
+        }
+        let count = self
+            .producer
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|producer| producer.partition_count(&self.slot_status_topic));
+        let mut cached = self.slot_partition_count.lock().unwrap();
+        match count {
+            Some(count) => cached.count = Some(count),
+            None => warn!(topic = %self.slot_status_topic, "failed to fetch partition count"),
+        }
+        cached.partition(slot)
+    }
+
+    /// Position of an event, `None` unless positions are tracked.
+    fn position(&self, slot: u64, write_version: u64) -> Option<Position> {
+        self.track_positions.then_some(Position {
+            slot,
+            write_version,
+        })
+    }
+
+    /// Whether an account update was delivered before a restart and is skipped.
+    fn was_delivered(&self, event: &str, topic: &str, position: Option<Position>) -> bool {
+        match position {
+            Some(position) if WATERMARKS.is_delivered(topic, position) => {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&[event, "watermark"])
+                    .inc();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Encodes and sends an event using a pooled buffer.
+    fn publish<M: Message + Serialize + BorshSerialize + FlatEncode + Project + Summarize>(
+        &self,
+        event: &str,
+        topic: &str,
+        key: Option<&[u8]>,
+        position: Option<Position>,
+        message: M,
+    ) -> Result<(), PublishError> {
+        self.publish_to_partition(event, topic, None, key, position, message)
+    }
+
+    /// Publishes to `partition`, or to the one the partitioner picks if `None`.
+    fn publish_to_partition<
+        M: Message + Serialize + BorshSerialize + FlatEncode + Project + Summarize,
+    >(
+        &self,
+        event: &str,
+        topic: &str,
+        partition: Option<i32>,
+        key: Option<&[u8]>,
+        position: Option<Position>,
+        message: M,
+    ) -> Result<(), PublishError> {
+        if !BREAKER.allow() {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[event, "circuit_open"])
+                .inc();
+            return Ok(());
+        }
+        let sampled = self
+            .sampler
+            .as_ref()
+            .is_some_and(|sampler| sampler.sample(topic));
+        let summary = (sampled || self.header_templates.uses_summary()).then(|| message.summary());
+        let headers = self.header_templates.render(event, topic, summary.as_ref());
+        buffer::with_buffer(|buf| {
+            self.encode(event, topic, message, buf);
+            if let Some(summary) = summary.as_ref().filter(|_| sampled) {
+                info!(event, topic, size = buf.len(), "sampled event: {}", summary);
+            }
+            self.send(event, topic, partition, key, position, &headers, buf)
+        })
+    }
+
+    fn encode<M: Message + Serialize + BorshSerialize + FlatEncode + Project>(
+        &self,
+        event: &str,
+        topic: &str,
+        mut message: M,
+        buf: &mut Vec<u8>,
+    ) {
+        let _span = trace_span!("encode").entered();
+        let _timer = CALLBACK_OVERHEAD_SECONDS
+            .with_label_values(&[event, "encode"])
+            .start_timer();
+        if let Some(fields) = self.cleared_fields.get(topic) {
+            for field in fields {
+                Project::clear(&mut message, field);
+            }
+        }
+        match self.format(topic) {
+            Format::Protobuf => {
+                buf.reserve(message.encoded_len());
+                message
+                    .encode(buf)
+                    .expect("buffer has capacity for the message")
+            }
+            Format::Json => json::to_writer(buf, &message, self.data_encoding(topic)),
+            Format::Flatbuffers => flat::to_writer(buf, &message),
+            Format::Borsh => {
+                BorshSerialize::serialize(&message, buf).expect("events always serialize to Borsh")
+            }
+        }
+    }
+
+    /// Message encoding of a topic, falling back to the global setting.
+    fn format(&self, topic: &str) -> Format {
+        self.topics
+            .get(topic)
+            .and_then(|topic| topic.format)
+            .unwrap_or(self.format)
+    }
+
+    /// Data encoding of a topic, falling back to the global setting.
+    fn data_encoding(&self, topic: &str) -> DataEncoding {
+        self.topics
+            .get(topic)
+            .and_then(|topic| topic.data_encoding)
+            .unwrap_or(self.default_data_encoding)
+    }
+
+    /// Compression of JSON messages of a topic, falling back to the global setting.
+    fn json_compression(&self, topic: &str) -> JsonCompression {
+        if self.format(topic) != Format::Json {
+            return JsonCompression::None;
+        }
+        self.topics
+            .get(topic)
+            .and_then(|topic| topic.json_compression)
+            .unwrap_or(self.json_compression)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send(
+        &self,
+        event: &str,
+        topic: &str,
+        partition: Option<i32>,
+        key: Option<&[u8]>,
+        position: Option<Position>,
+        extra_headers: &[(&str, String)],
+        payload: &[u8],
+    ) -> Result<(), PublishError> {
+        if watchdog::is_paused() {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[event, "paused"])
+                .inc();
+            return Ok(());
+        }
+        let payload = match self.transform(event, payload) {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        let compression = self.json_compression(topic);
+        let (payload, content_encoding) = match json::compress(&payload, compression) {
+            Some(compressed) => (Cow::Owned(compressed), compression.content_encoding()),
+            None => (payload, None),
+        };
+        let producer = self.producer.read().unwrap();
+        let producer = match producer.as_ref() {
+            Some(producer) => producer,
+            None => {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&[event, "null_sink"])
+                    .inc();
+                return Ok(());
+            }
+        };
+
+        let _span = trace_span!("enqueue", topic).entered();
+        let chain_time = self
+            .chain_time_header
+            .then(block_time::chain_time)
+            .flatten()
+            .map(|time| time.to_string());
+        let mut headers = [
+            ("content-type", self.format(topic).content_type()),
+            ("schema-version", SCHEMA_VERSION),
+            ("", ""),
+            ("", ""),
+            ("", ""),
+            ("", ""),
+        ];
+        let mut len = 2;
+        if let Some(content_encoding) = content_encoding {
+            headers[len] = ("content-encoding", content_encoding);
+            len += 1;
+        }
+        if let Some(identity) = &self.identity {
+            headers[len] = ("validator-identity", identity);
+            len += 1;
+        }
+        if let Some(chain_time) = &chain_time {
+            headers[len] = ("chain-timestamp", chain_time);
+            len += 1;
+        }
+        let size = key.map_or(0, <[u8]>::len)
+            + payload.len()
+            + headers[..len]
+                .iter()
+                .map(|(name, value)| name.len() + value.len())
+                .sum::<usize>()
+            + extra_headers
+                .iter()
+                .map(|(name, value)| name.len() + value.len())
+                .sum::<usize>();
+        let mut chunk_size = None;
+        let (topic, partition, position) = match self.oversize(event, topic, size)? {
+            Oversize::Fits => (topic, partition, position),
+            Oversize::Skip => return Ok(()),
+            Oversize::DeadLetter(dead_letter_topic) => {
+                headers[len] = ("original-topic", topic);
+                len += 1;
+                // Not tracked, the position stays pending on the original topic otherwise.
+                (dead_letter_topic, None, None)
+            }
+            Oversize::Chunk(limit) => {
+                // What the key and headers leave of the limit to each chunk.
+                match limit.checked_sub(size - payload.len() + CHUNK_HEADERS_SIZE) {
+                    Some(room) if room > 0 => chunk_size = Some(room),
+                    _ => return Err(PublishError::MessageTooLarge),
+                }
+                (topic, partition, position)
+            }
+        };
+        let headers = &headers[..len];
+        let combined;
+        let headers = if extra_headers.is_empty() {
+            headers
+        } else {
+            combined = headers
+                .iter()
+                .copied()
+                .chain(
+                    extra_headers
+                        .iter()
+                        .map(|(name, value)| (*name, value.as_str())),
+                )
+                .collect::<Vec<_>>();
+            &combined[..]
+        };
+        let result = match chunk_size {
+            Some(chunk_size) => send_chunks(
+                producer.as_ref(),
+                topic,
+                partition,
+                key,
+                headers,
+                &payload,
+                position,
+                chunk_size,
+            ),
+            None => match partition {
+                Some(partition) => {
+                    producer.send_to_partition(topic, partition, key, headers, &payload, position)
+                }
+                None => producer.send(topic, key, headers, &payload, position),
+            },
+        }
+        .map_err(PublishError::from);
+        match &result {
+            Ok(()) => HEALTH.record_enqueued(),
+            Err(error) => HEALTH.record_failure(error),
+        }
+        result
+    }
+
+    /// How an event of `size` bytes is sent under the oversize policy.
+    fn oversize<'a>(
+        &'a self,
+        event: &str,
+        topic: &str,
+        size: usize,
+    ) -> Result<Oversize<'a>, PublishError> {
+        let oversize = match &self.oversize {
+            Some(oversize) => oversize,
+            None => return Ok(Oversize::Fits),
+        };
+        let limit = match self.topic_limits.read().unwrap().as_ref() {
+            Some(limits) => limits.get(topic),
+            None => return Ok(Oversize::Fits),
+        };
+        if size <= limit {
+            return Ok(Oversize::Fits);
+        }
+        OVERSIZE_EVENTS_TOTAL.with_label_values(&[topic]).inc();
+        match oversize.policy {
+            OversizePolicy::Reject => Err(PublishError::MessageTooLarge),
+            OversizePolicy::Skip => {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&[event, "oversize"])
+                    .inc();
+                Ok(Oversize::Skip)
+            }
+            OversizePolicy::DeadLetter => Ok(Oversize::DeadLetter(&oversize.dead_letter_topic)),
+            OversizePolicy::Chunk => Ok(Oversize::Chunk(limit)),
+        }
+    }
+
+    /// Sets the largest messages the topics accept, checked with the oversize policy.
+    pub fn set_topic_limits(&self, limits: TopicLimits) {
+        *self.topic_limits.write().unwrap() = Some(limits);
+    }
+
+    /// Runs the configured transform, `None` if the event is dropped.
+    fn transform<'a>(&self, event: &str, payload: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        let transform = match &self.transform {
+            Some(transform) => transform,
+            None => return Some(Cow::Borrowed(payload)),
+        };
+        let _span = trace_span!("transform").entered();
+        let _timer = CALLBACK_OVERHEAD_SECONDS
+            .with_label_values(&[event, "transform"])
+            .start_timer();
+        match transform.transform(event, payload) {
+            Ok(Some(payload)) => Some(Cow::Owned(payload)),
+            Ok(None) => {
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&[event, "transform"])
+                    .inc();
+                None
+            }
+            Err(error) => {
+                warn!(event, %error, "transform failed, dropping event");
+                EVENTS_SKIPPED_TOTAL
+                    .with_label_values(&[event, "transform_error"])
+                    .inc();
+                None
+            }
+        }
+    }
+
+    fn record_result(
+        &self,
+        counter: &IntCounterVec,
+        event: &str,
+        result: &Result<(), PublishError>,
+    ) {
+        // The null sink counts its events as skipped in `send`, nothing is uploaded.
+        if result.is_ok() && self.producer.read().unwrap().is_none() {
+            return;
+        }
+        counter
             .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
             .inc();
-        result
+
+        if let Some(reason) = result.as_ref().err().and_then(PublishError::reason) {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[event, reason])
+                .inc();
+        }
+    }
+
+    /// Swaps in a new producer. The old one is flushed in the background.
+    pub fn replace_producer(&self, producer: Box<dyn ProducerLike>) {
+        let old = self.producer.write().unwrap().replace(producer);
+        let timeout = self.shutdown_timeout;
+        if let Some(old) = old {
+            // Flushing the old producer may take up to `shutdown_timeout_ms`.
+            thread::spawn(move || shutdown(old.as_ref(), timeout));
+        }
     }
 
     pub fn wants_update_account(&self) -> bool {
@@ -91,10 +1054,804 @@ impl Publisher {
     pub fn wants_transaction(&self) -> bool {
         !self.transaction_topic.is_empty()
     }
+
+    pub fn wants_token_account(&self) -> bool {
+        !self.token_account_topic.is_empty()
+    }
+
+    pub fn wants_stake_account(&self) -> bool {
+        !self.stake_account_topic.is_empty()
+    }
+
+    pub fn wants_vote_account(&self) -> bool {
+        !self.vote_account_topic.is_empty()
+    }
+
+    pub fn wants_signature(&self) -> bool {
+        !self.signature_topic.is_empty()
+    }
+
+    pub fn wants_instruction(&self) -> bool {
+        !self.instruction_topic.is_empty()
+    }
+
+    pub fn wants_program_upgrade(&self) -> bool {
+        !self.program_upgrade_topic.is_empty()
+    }
+
+    pub fn wants_block_rewards(&self) -> bool {
+        !self.rewards_topic.is_empty()
+    }
+
+    pub fn wants_ops(&self) -> bool {
+        !self.ops_topic.is_empty()
+    }
 }
 
 impl Drop for Publisher {
     fn drop(&mut self) {
-        self.producer.flush(self.shutdown_timeout);
+        if let Ok(Some(producer)) = self.producer.get_mut() {
+            shutdown(producer.as_ref(), self.shutdown_timeout);
+        }
+    }
+}
+
+/// Flushes pending messages until `timeout` expires, reporting those left undelivered.
+fn shutdown(producer: &dyn ProducerLike, timeout: Duration) {
+    match drain(producer, timeout) {
+        0 => info!("flushed kafka producer"),
+        dropped => {
+            warn!(
+                dropped,
+                timeout_ms = timeout.as_millis() as u64,
+                "shutdown timeout expired, dropping undelivered messages"
+            );
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["any", "shutdown"])
+                .inc_by(dropped as u64);
+        }
+    }
+}
+
+/// Flushes pending messages until `timeout` expires, logging progress.
+/// Returns the number of messages left undelivered.
+fn drain(producer: &dyn ProducerLike, timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let pending = producer.in_flight_count();
+        if pending <= 0 {
+            return 0;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return pending as usize;
+        }
+        info!(
+            pending,
+            remaining_ms = remaining.as_millis() as u64,
+            "flushing kafka producer"
+        );
+        producer.flush(remaining.min(DRAIN_LOG_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            producer::{MemoryProducer, MemoryRecord, ProducerError},
+            transform::TransformError,
+        },
+    };
+
+    fn publisher(config: &Config) -> (Publisher, MemoryProducer) {
+        let producer = MemoryProducer::default();
+        let publisher = Publisher::new(Some(Box::new(producer.clone())), None, config);
+        (publisher, producer)
+    }
+
+    fn account() -> UpdateAccountEvent {
+        UpdateAccountEvent {
+            slot: 1,
+            pubkey: vec![1; 32],
+            lamports: 2,
+            owner: vec![3; 32],
+            executable: true,
+            rent_epoch: 4,
+            data: vec![5, 6],
+            write_version: 7,
+            txn_signature: Vec::new(),
+            block_time: None,
+            rent_exempt: false,
+            previous_owner: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_topics_and_keys() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            slot_status_topic: "slots".to_owned(),
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+
+        publisher.update_account(account()).unwrap();
+        let slot = SlotStatusEvent {
+            slot: 2,
+            parent: 1,
+            status: SlotStatus::Rooted.into(),
+            dead_error: String::new(),
+            blockhash: String::new(),
+        };
+        publisher.update_slot_status(slot.clone()).unwrap();
+
+        let headers = vec![
+            (
+                "content-type".to_owned(),
+                "application/x-protobuf".to_owned(),
+            ),
+            ("schema-version".to_owned(), SCHEMA_VERSION.to_owned()),
+        ];
+        assert_eq!(
+            producer.records(),
+            vec![
+                MemoryRecord {
+                    topic: "accounts".to_owned(),
+                    partition: None,
+                    key: Some(vec![1; 32]),
+                    headers: headers.clone(),
+                    payload: account().encode_to_vec(),
+                },
+                MemoryRecord {
+                    topic: "slots".to_owned(),
+                    partition: None,
+                    key: None,
+                    headers,
+                    payload: slot.encode_to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skipped_reasons() {
+        let (publisher, producer) = publisher(&Config::default());
+        let skipped = |reason| {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", reason])
+                .get()
+        };
+        let (queue_full, oversize) = (skipped("queue_full"), skipped("oversize"));
+
+        producer.fail_sends(Some(ProducerError::QueueFull));
+        assert_eq!(
+            publisher.update_account(account()),
+            Err(PublishError::QueueFull)
+        );
+        producer.fail_sends(Some(ProducerError::MessageTooLarge));
+        assert_eq!(
+            publisher.update_account(account()),
+            Err(PublishError::MessageTooLarge)
+        );
+        producer.fail_sends(None);
+        publisher.update_account(account()).unwrap();
+
+        assert_eq!(skipped("queue_full"), queue_full + 1);
+        assert_eq!(skipped("oversize"), oversize + 1);
+        assert_eq!(producer.records().len(), 1);
+    }
+
+    /// Returns the queued results in order.
+    struct Scripted(Mutex<Vec<Result<Option<Vec<u8>>, TransformError>>>);
+
+    impl Transform for Scripted {
+        fn transform(&self, _: &str, _: &[u8]) -> Result<Option<Vec<u8>>, TransformError> {
+            self.0.lock().unwrap().remove(0)
+        }
+    }
+
+    #[test]
+    fn test_transform() {
+        let producer = MemoryProducer::default();
+        let transform = Scripted(Mutex::new(vec![
+            Ok(Some(b"rewritten".to_vec())),
+            Ok(None),
+            Err("failed".into()),
+        ]));
+        let publisher = Publisher::new(
+            Some(Box::new(producer.clone())),
+            Some(Box::new(transform)),
+            &Config::default(),
+        );
+        let skipped = |reason| {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", reason])
+                .get()
+        };
+        let (dropped, failed) = (skipped("transform"), skipped("transform_error"));
+
+        for _ in 0..3 {
+            publisher.update_account(account()).unwrap();
+        }
+
+        let records = producer.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].payload, b"rewritten");
+        assert_eq!(skipped("transform"), dropped + 1);
+        assert_eq!(skipped("transform_error"), failed + 1);
+    }
+
+    #[test]
+    fn test_null_sink_skips() {
+        let config = Config {
+            rewards_topic: "rewards".to_owned(),
+            ..Config::default()
+        };
+        let publisher = Publisher::new(None, None, &config);
+        let skipped = || {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["block_rewards", "null_sink"])
+                .get()
+        };
+        let uploaded = || {
+            UPLOAD_BLOCK_REWARDS_TOTAL
+                .with_label_values(&["success"])
+                .get()
+        };
+        let (before_skipped, before_uploaded) = (skipped(), uploaded());
+
+        publisher
+            .update_block_rewards(BlockRewardsEvent::default())
+            .unwrap();
+
+        assert_eq!(skipped(), before_skipped + 1);
+        assert_eq!(uploaded(), before_uploaded);
+    }
+
+    #[test]
+    fn test_pubkey_write_version_key() {
+        let config = Config {
+            account_key: AccountKey::PubkeyWriteVersion,
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+        publisher.update_account(account()).unwrap();
+
+        let mut key = vec![1; 32];
+        key.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 7]);
+        assert_eq!(producer.records()[0].key, Some(key));
+    }
+
+    #[test]
+    fn test_json_projection() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            format: Format::Json,
+            topics: HashMap::from([(
+                "accounts".to_owned(),
+                TopicConfig {
+                    data_encoding: Some(DataEncoding::Hex),
+                    include: vec!["pubkey".to_owned(), "data".to_owned()],
+                    ..TopicConfig::default()
+                },
+            )]),
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+        publisher.update_account(account()).unwrap();
+
+        let records = producer.records();
+        let json: serde_json::Value = serde_json::from_slice(&records[0].payload).unwrap();
+        assert_eq!(json["pubkey"], bs58::encode([1; 32]).into_string());
+        assert_eq!(json["data"], "0506");
+        assert_eq!(json["lamports"], 0);
+        // The key is taken before projection.
+        assert_eq!(records[0].key, Some(vec![1; 32]));
+    }
+
+    #[test]
+    fn test_json_compression() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            slot_status_topic: "slots".to_owned(),
+            format: Format::Json,
+            json_compression: JsonCompression::Gzip,
+            topics: HashMap::from([(
+                "slots".to_owned(),
+                TopicConfig {
+                    json_compression: Some(JsonCompression::None),
+                    ..TopicConfig::default()
+                },
+            )]),
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+        publisher.update_account(account()).unwrap();
+        publisher
+            .update_slot_status(SlotStatusEvent::default())
+            .unwrap();
+
+        let records = producer.records();
+        assert!(records[0]
+            .headers
+            .contains(&("content-encoding".to_owned(), "gzip".to_owned())));
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(
+            &mut flate2::read::GzDecoder::new(&records[0].payload[..]),
+            &mut decoded,
+        )
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&decoded).unwrap();
+        assert_eq!(json["lamports"], 2);
+
+        assert!(records[1]
+            .headers
+            .iter()
+            .all(|(name, _)| name != "content-encoding"));
+        assert!(serde_json::from_slice::<serde_json::Value>(&records[1].payload).is_ok());
+    }
+
+    #[test]
+    fn test_oversize_policies() {
+        let publisher = |topic: &str, policy| {
+            let config = Config {
+                update_account_topic: "accounts".to_owned(),
+                slot_status_topic: topic.to_owned(),
+                oversize: Some(OversizeConfig {
+                    policy,
+                    dead_letter_topic: "dead-letters".to_owned(),
+                }),
+                ..Config::default()
+            };
+            let (publisher, producer) = publisher(&config);
+            publisher.set_topic_limits(TopicLimits::new(
+                HashMap::from([(topic.to_owned(), 4)]),
+                1_000_000,
+            ));
+            (publisher, producer)
+        };
+        let oversize = |topic| OVERSIZE_EVENTS_TOTAL.with_label_values(&[topic]).get();
+        let skipped = || {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["slot", "oversize"])
+                .get()
+        };
+        let skipped_before = skipped();
+
+        let (reject, producer) = publisher("oversize-reject", OversizePolicy::Reject);
+        assert_eq!(
+            reject.update_slot_status(SlotStatusEvent::default()),
+            Err(PublishError::MessageTooLarge)
+        );
+        // Topics without a limit of their own get the producer's.
+        reject.update_account(account()).unwrap();
+        let records = producer.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, "accounts");
+        assert_eq!(oversize("oversize-reject"), 1);
+
+        let (skip, producer) = publisher("oversize-skip", OversizePolicy::Skip);
+        skip.update_slot_status(SlotStatusEvent::default()).unwrap();
+        assert!(producer.records().is_empty());
+        assert_eq!(oversize("oversize-skip"), 1);
+        assert_eq!(skipped(), skipped_before + 2);
+
+        let (dead_letter, producer) = publisher("oversize-dead-letter", OversizePolicy::DeadLetter);
+        let slot = SlotStatusEvent {
+            slot: 9,
+            ..SlotStatusEvent::default()
+        };
+        dead_letter.update_slot_status(slot.clone()).unwrap();
+        let records = producer.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, "dead-letters");
+        assert!(records[0].headers.contains(&(
+            "original-topic".to_owned(),
+            "oversize-dead-letter".to_owned()
+        )));
+        assert_eq!(records[0].payload, slot.encode_to_vec());
+        assert_eq!(oversize("oversize-dead-letter"), 1);
+    }
+
+    #[test]
+    fn test_oversize_chunk() {
+        let config = Config {
+            update_account_topic: "oversize-chunk".to_owned(),
+            oversize: Some(OversizeConfig {
+                policy: OversizePolicy::Chunk,
+                dead_letter_topic: "dead-letters".to_owned(),
+            }),
+            ..Config::default()
+        };
+        let (chunking, producer) = publisher(&config);
+        let limit = 250;
+        chunking.set_topic_limits(TopicLimits::new(
+            HashMap::from([("oversize-chunk".to_owned(), limit)]),
+            1_000_000,
+        ));
+        let event = UpdateAccountEvent {
+            data: (0..=255).cycle().take(600).collect(),
+            ..account()
+        };
+        chunking.update_account(event.clone()).unwrap();
+
+        let records = producer.records();
+        assert!(records.len() > 1);
+        let header = |record: &MemoryRecord, name: &str| {
+            record
+                .headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        let mut payload = Vec::new();
+        for (index, record) in records.iter().enumerate() {
+            assert_eq!(record.topic, "oversize-chunk");
+            assert_eq!(record.key, records[0].key);
+            assert_eq!(header(record, "chunk-id"), header(&records[0], "chunk-id"));
+            assert_eq!(header(record, "chunk-index"), index.to_string());
+            assert_eq!(header(record, "chunk-count"), records.len().to_string());
+            let size = record.key.as_ref().map_or(0, Vec::len)
+                + record.payload.len()
+                + record
+                    .headers
+                    .iter()
+                    .map(|(name, value)| name.len() + value.len())
+                    .sum::<usize>();
+            assert!(size <= limit, "chunk of {} bytes", size);
+            payload.extend_from_slice(&record.payload);
+        }
+        assert_eq!(UpdateAccountEvent::decode(&payload[..]).unwrap(), event);
+        assert_eq!(
+            OVERSIZE_EVENTS_TOTAL
+                .with_label_values(&["oversize-chunk"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_topic_names() {
+        let program = Pubkey::new_unique().to_string();
+        let routed = Pubkey::new_unique().to_string();
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            account_size_topics: vec![SizeTopic {
+                max_data_len: 128,
+                topic: "accounts.small".to_owned(),
+            }],
+            transaction_topic: "transactions".to_owned(),
+            transaction_topic_per_program: true,
+            program_filters: vec![program.clone()],
+            transaction_program_topics: HashMap::from([(routed, "routed".to_owned())]),
+            ..Config::default()
+        };
+        let topics = Publisher::topic_names(&config);
+        for topic in [
+            "accounts".to_owned(),
+            "accounts.small".to_owned(),
+            "transactions".to_owned(),
+            format!("transactions-{}", program),
+            "routed".to_owned(),
+        ] {
+            assert!(topics.contains(&topic), "{} missing", topic);
+        }
+    }
+
+    #[test]
+    fn test_slot_partition() {
+        let config = Config {
+            slot_status_topic: "slots".to_owned(),
+            slot_partitioning: SlotPartitioning::Slot,
+            ..Config::default()
+        };
+        let slot = |slot| SlotStatusEvent {
+            slot,
+            ..SlotStatusEvent::default()
+        };
+        let partitions = |producer: &MemoryProducer| {
+            producer
+                .records()
+                .iter()
+                .map(|record| record.partition)
+                .collect::<Vec<_>>()
+        };
+
+        let (cached, producer) = publisher(&config);
+        producer.set_partition_count(Some(4));
+        cached.update_slot_status(slot(5)).unwrap();
+        // The count is cached until the next refresh.
+        producer.set_partition_count(Some(8));
+        cached.update_slot_status(slot(6)).unwrap();
+        assert_eq!(partitions(&producer), [Some(1), Some(2)]);
+
+        // So is a failed fetch, the partitioner then picks the partition.
+        let (failed, producer) = publisher(&config);
+        failed.update_slot_status(slot(5)).unwrap();
+        producer.set_partition_count(Some(4));
+        failed.update_slot_status(slot(6)).unwrap();
+        assert_eq!(partitions(&producer), [None, None]);
+    }
+
+    #[test]
+    fn test_publish_schemas() {
+        let (publisher, producer) = publisher(&Config::default());
+        publisher.publish_schemas("__schemas").unwrap();
+
+        let records = producer.records();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.topic, "__schemas");
+        assert_eq!(
+            record.key.as_deref(),
+            Some(format!("solana-accountsdb-plugin-kafka/{}", VERSION.version).as_bytes())
+        );
+        assert_eq!(
+            record.headers,
+            [
+                (
+                    "content-type".to_owned(),
+                    "application/x-protobuf".to_owned()
+                ),
+                ("schema-version".to_owned(), SCHEMA_VERSION.to_owned()),
+                ("plugin-version".to_owned(), VERSION.version.to_owned()),
+            ]
+        );
+        assert_eq!(record.payload, FILE_DESCRIPTOR_SET);
+    }
+
+    #[test]
+    fn test_borsh() {
+        use borsh::BorshDeserialize;
+
+        /// The layouts documented in the README.
+        #[derive(BorshDeserialize, Debug, PartialEq)]
+        struct Account {
+            slot: u64,
+            pubkey: Vec<u8>,
+            lamports: u64,
+            owner: Vec<u8>,
+            executable: bool,
+            rent_epoch: u64,
+            data: Vec<u8>,
+            write_version: u64,
+            txn_signature: Vec<u8>,
+            block_time: Option<i64>,
+            rent_exempt: bool,
+            previous_owner: Vec<u8>,
+        }
+
+        #[derive(BorshDeserialize, Debug, PartialEq)]
+        struct Header {
+            num_required_signatures: u32,
+            num_readonly_signed_accounts: u32,
+            num_readonly_unsigned_accounts: u32,
+        }
+
+        #[derive(BorshDeserialize, Debug, PartialEq)]
+        struct Instruction {
+            program_id_index: u32,
+            accounts: Vec<u32>,
+            data: Vec<u8>,
+        }
+
+        #[derive(BorshDeserialize, Debug, PartialEq)]
+        struct Legacy {
+            header: Option<Header>,
+            account_keys: Vec<Vec<u8>>,
+            recent_block_hash: Vec<u8>,
+            instructions: Vec<Instruction>,
+        }
+
+        #[derive(BorshDeserialize, Debug, PartialEq)]
+        enum Payload {
+            Legacy(Legacy),
+        }
+
+        #[derive(BorshDeserialize, Debug, PartialEq)]
+        struct Message {
+            version: i32,
+            payload: Option<Payload>,
+        }
+
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            format: Format::Borsh,
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+        let event = UpdateAccountEvent {
+            block_time: Some(-3),
+            previous_owner: vec![8; 32],
+            ..account()
+        };
+        publisher.update_account(event.clone()).unwrap();
+        let record = &producer.records()[0];
+        assert!(record
+            .headers
+            .contains(&("content-type".to_owned(), "application/x-borsh".to_owned())));
+        assert_eq!(
+            Account::try_from_slice(&record.payload).unwrap(),
+            Account {
+                slot: event.slot,
+                pubkey: event.pubkey,
+                lamports: event.lamports,
+                owner: event.owner,
+                executable: event.executable,
+                rent_epoch: event.rent_epoch,
+                data: event.data,
+                write_version: event.write_version,
+                txn_signature: event.txn_signature,
+                block_time: Some(-3),
+                rent_exempt: event.rent_exempt,
+                previous_owner: vec![8; 32],
+            }
+        );
+
+        // The oneof follows the other fields.
+        let message = SanitizedMessage {
+            message_payload: Some(sanitized_message::MessagePayload::Legacy(LegacyMessage {
+                header: Some(MessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 2,
+                }),
+                account_keys: vec![vec![1; 32]],
+                recent_block_hash: vec![2; 32],
+                instructions: vec![CompiledInstruction {
+                    program_id_index: 0,
+                    accounts: vec![0],
+                    data: vec![3],
+                }],
+            })),
+            version: MessageVersion::Legacy as i32,
+        };
+        let encoded = borsh::BorshSerialize::try_to_vec(&message).unwrap();
+        assert_eq!(
+            Message::try_from_slice(&encoded).unwrap(),
+            Message {
+                version: 0,
+                payload: Some(Payload::Legacy(Legacy {
+                    header: Some(Header {
+                        num_required_signatures: 1,
+                        num_readonly_signed_accounts: 0,
+                        num_readonly_unsigned_accounts: 2,
+                    }),
+                    account_keys: vec![vec![1; 32]],
+                    recent_block_hash: vec![2; 32],
+                    instructions: vec![Instruction {
+                        program_id_index: 0,
+                        accounts: vec![0],
+                        data: vec![3],
+                    }],
+                })),
+            }
+        );
+    }
+
+    #[test]
+    fn test_account_size_topics() {
+        let config = Config {
+            update_account_topic: "accounts.large".to_owned(),
+            account_size_topics: vec![
+                SizeTopic {
+                    max_data_len: 100,
+                    topic: "accounts.medium".to_owned(),
+                },
+                SizeTopic {
+                    max_data_len: 2,
+                    topic: "accounts.small".to_owned(),
+                },
+            ],
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+        for len in [0, 2, 3, 100, 101] {
+            let ev = UpdateAccountEvent {
+                data: vec![0; len],
+                ..account()
+            };
+            publisher.update_account(ev).unwrap();
+        }
+
+        let topics = producer
+            .records()
+            .into_iter()
+            .map(|record| record.topic)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            topics,
+            [
+                "accounts.small",
+                "accounts.small",
+                "accounts.medium",
+                "accounts.medium",
+                "accounts.large"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_program_transaction_topics() {
+        let serum = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+        let token = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+        let config = Config {
+            transaction_topic: "transactions".to_owned(),
+            transaction_topic_per_program: true,
+            transaction_program_topics: [(token.to_owned(), "token".to_owned())]
+                .into_iter()
+                .collect(),
+            program_filters: vec![serum.to_owned()],
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+        let transaction = |programs: &[&str]| TransactionEvent {
+            transaction: Some(SanitizedTransaction {
+                message: Some(SanitizedMessage {
+                    version: MessageVersion::Legacy.into(),
+                    message_payload: Some(sanitized_message::MessagePayload::Legacy(
+                        LegacyMessage {
+                            account_keys: programs
+                                .iter()
+                                .map(|program| {
+                                    Pubkey::from_str(program).unwrap().to_bytes().to_vec()
+                                })
+                                .collect(),
+                            ..LegacyMessage::default()
+                        },
+                    )),
+                }),
+                ..SanitizedTransaction::default()
+            }),
+            ..TransactionEvent::default()
+        };
+        publisher.update_transaction(transaction(&[serum])).unwrap();
+        publisher
+            .update_transaction(transaction(&[token, serum]))
+            .unwrap();
+        publisher
+            .update_transaction(transaction(&["11111111111111111111111111111111"]))
+            .unwrap();
+
+        let topics = producer
+            .records()
+            .into_iter()
+            .map(|record| record.topic)
+            .collect::<Vec<_>>();
+        let serum_topic = format!("transactions-{}", serum);
+        assert_eq!(
+            topics,
+            [serum_topic.as_str(), &serum_topic, "token", "transactions"]
+        );
+    }
+
+    #[test]
+    fn test_transaction_failed_topic() {
+        let config = Config {
+            transaction_topic: "transactions".to_owned(),
+            transaction_failed_topic: "transactions.failed".to_owned(),
+            ..Config::default()
+        };
+        let (publisher, producer) = publisher(&config);
+        for is_status_err in [true, false] {
+            let ev = TransactionEvent {
+                transaction_status_meta: Some(TransactionStatusMeta {
+                    is_status_err,
+                    ..TransactionStatusMeta::default()
+                }),
+                ..TransactionEvent::default()
+            };
+            publisher.update_transaction(ev).unwrap();
+        }
+
+        let topics = producer
+            .records()
+            .into_iter()
+            .map(|record| record.topic)
+            .collect::<Vec<_>>();
+        assert_eq!(topics, ["transactions.failed", "transactions"]);
     }
 }
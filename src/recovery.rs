@@ -0,0 +1,188 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilding the producer after librdkafka reports an error it cannot recover from, such as
+//! a fenced idempotent producer. Authentication failures are retried by librdkafka itself.
+
+#[cfg(feature = "rdkafka-backend")]
+use rdkafka::{error::KafkaError, types::RDKafkaErrorCode};
+use {
+    crate::{config::ProducerRecoveryConfig, health::HEALTH, prom::PRODUCER_RESTARTS_TOTAL},
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    tracing::{error, warn},
+};
+
+/// Set by the producer context, consumed by the plugin on its next callback.
+static FATAL_ERROR: AtomicBool = AtomicBool::new(false);
+
+/// Whether an error reported by librdkafka leaves the producer unusable.
+#[cfg(feature = "rdkafka-backend")]
+pub fn is_fatal(error: &KafkaError) -> bool {
+    matches!(
+        error.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::Fatal
+                | RDKafkaErrorCode::ProducerFenced
+                | RDKafkaErrorCode::InvalidProducerEpoch
+        )
+    )
+}
+
+/// Asks for the producer to be rebuilt.
+#[cfg(feature = "rdkafka-backend")]
+pub fn record_fatal() {
+    FATAL_ERROR.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub struct Recovery {
+    config: ProducerRecoveryConfig,
+    state: Mutex<State>,
+    /// Set once `max_restarts` restarts did not help, until the plugin is reloaded.
+    gave_up: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Restarts since the last delivery.
+    restarts: u32,
+    last_restart: Option<SystemTime>,
+    next_attempt: Option<Instant>,
+}
+
+impl Recovery {
+    pub fn new(config: ProducerRecoveryConfig) -> Self {
+        FATAL_ERROR.store(false, Ordering::Relaxed);
+        Self {
+            config,
+            state: Mutex::default(),
+            gave_up: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the producer has to be rebuilt now. Counts the restart.
+    pub fn take_restart(&self) -> bool {
+        if self.config.max_restarts == 0
+            || self.gave_up.load(Ordering::Relaxed)
+            || !FATAL_ERROR.load(Ordering::Relaxed)
+        {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        if matches!(state.next_attempt, Some(next_attempt) if Instant::now() < next_attempt) {
+            return false;
+        }
+        if state.last_restart.is_some_and(delivered_since) {
+            state.restarts = 0;
+        }
+        if state.restarts >= self.config.max_restarts {
+            error!(
+                restarts = state.restarts,
+                "producer still failing after restarts, giving up"
+            );
+            self.gave_up.store(true, Ordering::Relaxed);
+            return false;
+        }
+        FATAL_ERROR.store(false, Ordering::Relaxed);
+        state.restarts += 1;
+        state.last_restart = Some(SystemTime::now());
+        state.next_attempt = Some(Instant::now() + self.config.backoff(state.restarts));
+        PRODUCER_RESTARTS_TOTAL.inc();
+        warn!(
+            restart = state.restarts,
+            "restarting producer after fatal error"
+        );
+        true
+    }
+}
+
+impl ProducerRecoveryConfig {
+    /// Delay after the nth consecutive restart before another, doubling up to `max_backoff_ms`.
+    fn backoff(&self, restarts: u32) -> Duration {
+        let backoff = self
+            .backoff_ms
+            .saturating_mul(1u64 << restarts.saturating_sub(1).min(32));
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// Whether a message was delivered after the given time.
+fn delivered_since(time: SystemTime) -> bool {
+    let time_ms = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    HEALTH
+        .report()
+        .last_delivery_ms
+        .values()
+        .any(|&delivered_ms| delivered_ms > time_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let config = ProducerRecoveryConfig {
+            max_restarts: 5,
+            backoff_ms: 1_000,
+            max_backoff_ms: 5_000,
+        };
+        assert_eq!(config.backoff(1), Duration::from_secs(1));
+        assert_eq!(config.backoff(3), Duration::from_secs(4));
+        assert_eq!(config.backoff(4), Duration::from_secs(5));
+        assert_eq!(config.backoff(100), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_give_up() {
+        let recovery = Recovery::new(ProducerRecoveryConfig {
+            max_restarts: 1,
+            backoff_ms: 0,
+            max_backoff_ms: 0,
+        });
+        // Deliveries of other tests may reset the count, so restarts until it gives up.
+        for _ in 0..100 {
+            if recovery.gave_up.load(Ordering::Relaxed) {
+                break;
+            }
+            FATAL_ERROR.store(true, Ordering::Relaxed);
+            recovery.take_restart();
+        }
+        assert!(recovery.gave_up.load(Ordering::Relaxed));
+        FATAL_ERROR.store(true, Ordering::Relaxed);
+        assert!(!recovery.take_restart());
+    }
+
+    #[cfg(feature = "rdkafka-backend")]
+    #[test]
+    fn test_is_fatal() {
+        let error = |code| KafkaError::MessageProduction(code);
+        assert!(is_fatal(&error(RDKafkaErrorCode::Fatal)));
+        assert!(is_fatal(&error(RDKafkaErrorCode::ProducerFenced)));
+        assert!(!is_fatal(&error(RDKafkaErrorCode::Authentication)));
+        assert!(!is_fatal(&error(
+            RDKafkaErrorCode::SaslAuthenticationFailed
+        )));
+        assert!(!is_fatal(&error(RDKafkaErrorCode::QueueFull)));
+    }
+}
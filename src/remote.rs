@@ -0,0 +1,327 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filter section fetched from a URL and polled for changes, so that the filters of a fleet of
+//! validators can be managed in one place. Polls send the ETag of the applied version and
+//! rebuild the filter only when the server reports a change.
+//!
+//! `s3://` URLs are fetched with signed requests.
+
+use {
+    crate::{
+        config::{self, AccountFilterConfig, Config, RemoteFiltersConfig},
+        prom::REMOTE_FILTER_POLLS_TOTAL,
+        s3, Filter,
+    },
+    serde::Deserialize,
+    std::{
+        error::Error,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc, Mutex, RwLock,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+    tracing::{error, info, warn},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Filter swapped by the poller while callbacks read it.
+pub type SharedFilter = RwLock<Arc<Filter>>;
+
+/// Lists present replace the ones of the local config.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterSection {
+    program_ignores: Option<Vec<String>>,
+    program_filters: Option<Vec<String>>,
+    account_filters: Option<Vec<String>>,
+    filters: Option<Vec<AccountFilterConfig>>,
+}
+
+impl FilterSection {
+    /// All lists of the config.
+    fn of(config: &Config) -> Self {
+        Self {
+            program_ignores: Some(config.program_ignores.clone()),
+            program_filters: Some(config.program_filters.clone()),
+            account_filters: Some(config.account_filters.clone()),
+            filters: Some(config.filters.clone()),
+        }
+    }
+
+    fn apply(self, config: &mut Config) {
+        if let Some(program_ignores) = self.program_ignores {
+            config.program_ignores = program_ignores;
+        }
+        if let Some(program_filters) = self.program_filters {
+            config.program_filters = program_filters;
+        }
+        if let Some(account_filters) = self.account_filters {
+            config.account_filters = account_filters;
+        }
+        if let Some(filters) = self.filters {
+            config.filters = filters;
+        }
+    }
+}
+
+/// State of the filter section applied at load.
+#[derive(Debug)]
+pub struct Loaded {
+    /// Lists of the local config, for the ones a later version no longer sets.
+    local: FilterSection,
+    etag: Option<String>,
+}
+
+/// Applies the remote filter section, if configured, to the config.
+pub fn load(config: &mut Config) -> Result<(), String> {
+    let remote = match &config.remote_filters {
+        Some(remote) => remote.clone(),
+        None => return Ok(()),
+    };
+    let (section, etag) = fetch(&remote, None)
+        .and_then(|fetched| fetched.ok_or_else(|| "not modified".into()))
+        .map_err(|error| format!("failed to fetch remote filters {}: {}", remote.url, error))?;
+    let local = FilterSection::of(config);
+    section.apply(config);
+    config.remote_filters_loaded = Some(Loaded { local, etag });
+    Ok(())
+}
+
+/// A fetched filter section and its ETag.
+type Fetched = (FilterSection, Option<String>);
+
+/// Fetches the filter section and its ETag, `None` if it still matches `etag`.
+fn fetch(
+    config: &RemoteFiltersConfig,
+    etag: Option<&str>,
+) -> Result<Option<Fetched>, Box<dyn Error>> {
+    let mut request = match config.url.strip_prefix("s3://") {
+        Some(location) => s3::request("GET", location, config.region.as_deref(), b"")?,
+        None => ureq::get(&config.url),
+    }
+    .timeout(TIMEOUT);
+    if let Some(etag) = etag {
+        request = request.set("If-None-Match", etag);
+    }
+    let response = request.call()?;
+    if response.status() == 304 {
+        return Ok(None);
+    }
+    let etag = response.header("ETag").map(str::to_owned);
+    let section = serde_json::from_reader(response.into_reader())?;
+    Ok(Some((section, etag)))
+}
+
+/// Background thread polling the filter section and swapping in the filter built from it.
+#[derive(Debug)]
+pub struct FilterPoller {
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FilterPoller {
+    /// Polls if the config has remote filters with a poll interval.
+    pub fn new(config: &Config, filter: Arc<SharedFilter>) -> Option<Self> {
+        let remote = config.remote_filters.clone()?;
+        let loaded = config.remote_filters_loaded.as_ref()?;
+        if remote.poll_interval_ms == 0 {
+            return None;
+        }
+        let local = loaded.local.clone();
+        let mut etag = loaded.etag.clone();
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaFilters".to_owned())
+            .spawn(move || {
+                let interval = Duration::from_millis(remote.poll_interval_ms);
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match fetch(&remote, etag.as_deref()) {
+                        Ok(Some((section, new_etag))) => {
+                            let mut next = Config::default();
+                            local.clone().apply(&mut next);
+                            section.apply(&mut next);
+                            // A broken section would silently match nothing, keep the filter.
+                            if let Err(error) = config::check_memcmp(&next.filters) {
+                                warn!(url = %remote.url, %error, "rejected remote filters");
+                                REMOTE_FILTER_POLLS_TOTAL
+                                    .with_label_values(&["failed"])
+                                    .inc();
+                                continue;
+                            }
+                            *filter.write().unwrap() = Arc::new(Filter::new(&next));
+                            info!(
+                                url = %remote.url,
+                                etag = new_etag.as_deref().unwrap_or_default(),
+                                "applied new remote filters"
+                            );
+                            etag = new_etag;
+                            REMOTE_FILTER_POLLS_TOTAL
+                                .with_label_values(&["updated"])
+                                .inc();
+                        }
+                        Ok(None) => REMOTE_FILTER_POLLS_TOTAL
+                            .with_label_values(&["unchanged"])
+                            .inc(),
+                        Err(error) => {
+                            warn!(url = %remote.url, %error, "failed to poll remote filters");
+                            REMOTE_FILTER_POLLS_TOTAL
+                                .with_label_values(&["failed"])
+                                .inc();
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn remote filters thread");
+        Some(Self {
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for FilterPoller {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("remote filters thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::pubkey::Pubkey,
+        std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+            time::Instant,
+        },
+    };
+
+    /// Serves `responses` in order, then 304s, returning the URL and the received requests.
+    fn serve(responses: Vec<(Option<&'static str>, String)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/filters.json", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+        thread::spawn(move || {
+            let mut responses = responses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut request).unwrap() > 2 {}
+                received.lock().unwrap().push(request.to_lowercase());
+                let response = match responses.next() {
+                    Some((etag, body)) => format!(
+                        "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        etag.map(|etag| format!("ETag: {}\r\n", etag))
+                            .unwrap_or_default(),
+                        body.len(),
+                        body
+                    ),
+                    None => "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_owned(),
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (url, requests)
+    }
+
+    fn section(program: &Pubkey) -> String {
+        format!(r#"{{"program_filters": ["{}"]}}"#, program)
+    }
+
+    #[test]
+    fn test_fetch() {
+        let program = Pubkey::new_unique();
+        let (url, requests) = serve(vec![(Some("\"v1\""), section(&program))]);
+        let config = RemoteFiltersConfig {
+            url,
+            poll_interval_ms: 0,
+            region: None,
+        };
+
+        let (section, etag) = fetch(&config, None).unwrap().unwrap();
+        assert_eq!(section.program_filters, Some(vec![program.to_string()]));
+        assert_eq!(etag.as_deref(), Some("\"v1\""));
+        assert!(fetch(&config, etag.as_deref()).unwrap().is_none());
+
+        let requests = requests.lock().unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\"\r\n"));
+    }
+
+    #[test]
+    fn test_filter_poller() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (url, requests) = serve(vec![
+            (Some("\"v1\""), section(&first)),
+            (Some("\"v2\""), section(&second)),
+        ]);
+        let mut config = Config {
+            remote_filters: Some(RemoteFiltersConfig {
+                url,
+                poll_interval_ms: 10,
+                region: None,
+            }),
+            ..Config::default()
+        };
+        load(&mut config).unwrap();
+        assert_eq!(config.program_filters, [first.to_string()]);
+
+        let filter = Arc::new(RwLock::new(Arc::new(Filter::new(&config))));
+        let poller = FilterPoller::new(&config, Arc::clone(&filter)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while requests.lock().unwrap().len() < 3 {
+            assert!(Instant::now() < deadline, "no poll after the update");
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(poller);
+
+        let filter = filter.read().unwrap();
+        assert!(filter.wants_program(second.as_ref()));
+        assert!(!filter.wants_program(first.as_ref()));
+        assert!(requests.lock().unwrap()[2].contains("if-none-match: \"v2\"\r\n"));
+    }
+
+    #[test]
+    fn test_apply_section() {
+        let mut config = Config {
+            program_ignores: vec!["Vote111111111111111111111111111111111111111".to_owned()],
+            program_filters: vec!["11111111111111111111111111111111".to_owned()],
+            ..Config::default()
+        };
+        let local = FilterSection::of(&config);
+        let section: FilterSection = serde_json::from_str(
+            r#"{"program_filters": ["9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"]}"#,
+        )
+        .unwrap();
+        section.apply(&mut config);
+        assert_eq!(config.program_ignores, local.program_ignores.unwrap());
+        assert_eq!(
+            config.program_filters,
+            ["9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"]
+        );
+        assert!(serde_json::from_str::<FilterSection>(r#"{"program_filter": []}"#).is_err());
+    }
+}
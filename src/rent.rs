@@ -0,0 +1,74 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rent exemption of accounts, against the rent sysvar.
+//!
+//! The rent sysvar is notified among the startup accounts and rarely written after. Until it
+//! is seen, the default rent, that of all public clusters, applies.
+
+use {
+    solana_program::{rent::Rent, sysvar},
+    std::sync::RwLock,
+};
+
+/// Bincode `Rent`: lamports_per_byte_year, exemption_threshold and burn_percent.
+const RENT_LEN: usize = 17;
+
+#[derive(Debug, Default)]
+pub struct RentTracker {
+    rent: RwLock<Rent>,
+}
+
+impl RentTracker {
+    /// Records a rent sysvar update, ignoring any other account.
+    pub fn record(&self, pubkey: &[u8], data: &[u8]) {
+        if pubkey != sysvar::rent::id().as_ref() || data.len() < RENT_LEN {
+            return;
+        }
+        let rent = Rent {
+            lamports_per_byte_year: u64::from_le_bytes(data[0..8].try_into().expect("eight bytes")),
+            exemption_threshold: f64::from_le_bytes(data[8..16].try_into().expect("eight bytes")),
+            burn_percent: data[16],
+        };
+        *self.rent.write().unwrap() = rent;
+    }
+
+    /// Whether an account holding `lamports` and `data_len` bytes of data is rent-exempt.
+    pub fn is_exempt(&self, lamports: u64, data_len: usize) -> bool {
+        self.rent.read().unwrap().is_exempt(lamports, data_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rent_sysvar() {
+        let tracker = RentTracker::default();
+        let minimum = Rent::default().minimum_balance(100);
+        assert!(tracker.is_exempt(minimum, 100));
+        assert!(!tracker.is_exempt(minimum - 1, 100));
+
+        let mut rent = Vec::new();
+        rent.extend_from_slice(&(Rent::default().lamports_per_byte_year * 2).to_le_bytes());
+        rent.extend_from_slice(&2.0f64.to_le_bytes());
+        rent.push(50);
+        tracker.record(&[0; 32], &rent);
+        assert!(tracker.is_exempt(minimum, 100));
+        tracker.record(sysvar::rent::id().as_ref(), &rent);
+        assert!(!tracker.is_exempt(minimum, 100));
+        assert!(tracker.is_exempt(minimum * 2, 100));
+    }
+}
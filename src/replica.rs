@@ -0,0 +1,204 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Version independent views of the notifications passed by the validator.
+//!
+//! The validator picks the interface version at runtime, so every version known
+//! to the interface crate is accepted and fields missing from older versions are `None`.
+
+use {
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        ReplicaAccountInfoVersions, ReplicaBlockInfoVersions, ReplicaTransactionInfoVersions,
+    },
+    solana_sdk::{signature::Signature, transaction::SanitizedTransaction},
+    solana_transaction_status::{Reward, TransactionStatusMeta},
+};
+
+pub struct AccountInfo<'a> {
+    pub pubkey: &'a [u8],
+    pub lamports: u64,
+    pub owner: &'a [u8],
+    pub executable: bool,
+    pub rent_epoch: u64,
+    pub data: &'a [u8],
+    pub write_version: u64,
+    /// Transaction that caused the update, since V0_0_2.
+    pub txn_signature: Option<&'a Signature>,
+}
+
+impl<'a> From<ReplicaAccountInfoVersions<'a>> for AccountInfo<'a> {
+    fn from(account: ReplicaAccountInfoVersions<'a>) -> Self {
+        match account {
+            ReplicaAccountInfoVersions::V0_0_1(info) => Self {
+                pubkey: info.pubkey,
+                lamports: info.lamports,
+                owner: info.owner,
+                executable: info.executable,
+                rent_epoch: info.rent_epoch,
+                data: info.data,
+                write_version: info.write_version,
+                txn_signature: None,
+            },
+            ReplicaAccountInfoVersions::V0_0_2(info) => Self {
+                pubkey: info.pubkey,
+                lamports: info.lamports,
+                owner: info.owner,
+                executable: info.executable,
+                rent_epoch: info.rent_epoch,
+                data: info.data,
+                write_version: info.write_version,
+                txn_signature: info.txn_signature,
+            },
+            ReplicaAccountInfoVersions::V0_0_3(info) => Self {
+                pubkey: info.pubkey,
+                lamports: info.lamports,
+                owner: info.owner,
+                executable: info.executable,
+                rent_epoch: info.rent_epoch,
+                data: info.data,
+                write_version: info.write_version,
+                txn_signature: info.txn.map(|txn| txn.signature()),
+            },
+        }
+    }
+}
+
+pub struct TransactionInfo<'a> {
+    pub signature: &'a Signature,
+    pub is_vote: bool,
+    pub transaction: &'a SanitizedTransaction,
+    pub transaction_status_meta: &'a TransactionStatusMeta,
+    /// Position of the transaction in the block, since V0_0_2.
+    pub index: Option<usize>,
+}
+
+impl<'a> From<ReplicaTransactionInfoVersions<'a>> for TransactionInfo<'a> {
+    fn from(transaction: ReplicaTransactionInfoVersions<'a>) -> Self {
+        match transaction {
+            ReplicaTransactionInfoVersions::V0_0_1(info) => Self {
+                signature: info.signature,
+                is_vote: info.is_vote,
+                transaction: info.transaction,
+                transaction_status_meta: info.transaction_status_meta,
+                index: None,
+            },
+            ReplicaTransactionInfoVersions::V0_0_2(info) => Self {
+                signature: info.signature,
+                is_vote: info.is_vote,
+                transaction: info.transaction,
+                transaction_status_meta: info.transaction_status_meta,
+                index: Some(info.index),
+            },
+        }
+    }
+}
+
+pub struct BlockInfo<'a> {
+    pub slot: u64,
+    pub blockhash: &'a str,
+    pub rewards: &'a [Reward],
+    pub block_time: Option<i64>,
+}
+
+impl<'a> From<ReplicaBlockInfoVersions<'a>> for BlockInfo<'a> {
+    fn from(block: ReplicaBlockInfoVersions<'a>) -> Self {
+        match block {
+            ReplicaBlockInfoVersions::V0_0_1(info) => Self {
+                slot: info.slot,
+                blockhash: info.blockhash,
+                rewards: info.rewards,
+                block_time: info.block_time,
+            },
+            ReplicaBlockInfoVersions::V0_0_2(info) => Self {
+                slot: info.slot,
+                blockhash: info.blockhash,
+                rewards: info.rewards,
+                block_time: info.block_time,
+            },
+            ReplicaBlockInfoVersions::V0_0_3(info) => Self {
+                slot: info.slot,
+                blockhash: info.blockhash,
+                rewards: info.rewards,
+                block_time: info.block_time,
+            },
+            #[cfg(feature = "agave")]
+            ReplicaBlockInfoVersions::V0_0_4(info) => Self {
+                slot: info.slot,
+                blockhash: info.blockhash,
+                rewards: &info.rewards.rewards,
+                block_time: info.block_time,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_geyser_plugin_interface::geyser_plugin_interface::{
+            ReplicaAccountInfo, ReplicaAccountInfoV2, ReplicaAccountInfoV3,
+        },
+    };
+
+    #[test]
+    fn test_account_versions() {
+        let signature = Signature::new_unique();
+        let v1 = ReplicaAccountInfo {
+            pubkey: &[1; 32],
+            lamports: 2,
+            owner: &[3; 32],
+            executable: true,
+            rent_epoch: 4,
+            data: &[5],
+            write_version: 6,
+        };
+        let v2 = ReplicaAccountInfoV2 {
+            pubkey: v1.pubkey,
+            lamports: v1.lamports,
+            owner: v1.owner,
+            executable: v1.executable,
+            rent_epoch: v1.rent_epoch,
+            data: v1.data,
+            write_version: v1.write_version,
+            txn_signature: Some(&signature),
+        };
+        let v3 = ReplicaAccountInfoV3 {
+            pubkey: v1.pubkey,
+            lamports: v1.lamports,
+            owner: v1.owner,
+            executable: v1.executable,
+            rent_epoch: v1.rent_epoch,
+            data: v1.data,
+            write_version: v1.write_version,
+            txn: None,
+        };
+
+        for (account, txn_signature) in [
+            (ReplicaAccountInfoVersions::V0_0_1(&v1), None),
+            (ReplicaAccountInfoVersions::V0_0_2(&v2), Some(&signature)),
+            (ReplicaAccountInfoVersions::V0_0_3(&v3), None),
+        ] {
+            let info = AccountInfo::from(account);
+            assert_eq!(info.pubkey, &[1; 32]);
+            assert_eq!(info.lamports, 2);
+            assert_eq!(info.owner, &[3; 32]);
+            assert!(info.executable);
+            assert_eq!(info.rent_epoch, 4);
+            assert_eq!(info.data, &[5]);
+            assert_eq!(info.write_version, 6);
+            assert_eq!(info.txn_signature, txn_signature);
+        }
+    }
+}
@@ -0,0 +1,392 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Producer publishing through a Confluent REST Proxy over HTTP(S), for networks where the
+//! Kafka protocol is blocked. Records are batched per topic and sent with the v2 produce API,
+//! which has no record headers: the content type, schema version and identity headers are not
+//! carried over.
+//!
+//! Failed requests and records the proxy reports errors for are retried `retries` times.
+//! Dropping the producer sends the queued records for up to the shutdown timeout, then fails
+//! the rest.
+
+use {
+    crate::{
+        config::RestProxyConfig,
+        health::HEALTH,
+        producer::{ProducerError, ProducerLike},
+        watermark::{Position, WATERMARKS},
+    },
+    crossbeam_channel::{Receiver, Sender, TrySendError},
+    serde::Deserialize,
+    serde_json::json,
+    std::{
+        sync::{
+            atomic::{AtomicBool, AtomicI32, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tracing::{error, warn},
+};
+
+const CONTENT_TYPE: &str = "application/vnd.kafka.binary.v2+json";
+const ACCEPT: &str = "application/vnd.kafka.v2+json";
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Record {
+    topic: String,
+    key: Option<Vec<u8>>,
+    payload: Vec<u8>,
+    position: Option<Position>,
+}
+
+#[derive(Deserialize)]
+struct ProduceResponse {
+    offsets: Vec<ProduceOffset>,
+}
+
+#[derive(Deserialize)]
+struct ProduceOffset {
+    #[serde(default)]
+    error: Option<String>,
+}
+
+pub struct RestProxyProducer {
+    /// `None` once dropping, which lets the thread send the queued records and exit.
+    sender: Option<Sender<Record>>,
+    in_flight: Arc<AtomicI32>,
+    /// Set when the drain on drop times out, making the thread fail the records left.
+    abandoned: Arc<AtomicBool>,
+    shutdown_timeout: Duration,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RestProxyProducer {
+    pub fn new(config: &RestProxyConfig, shutdown_timeout: Duration) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(config.queue_capacity.max(1));
+        let in_flight = Arc::new(AtomicI32::new(0));
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let config = config.clone();
+            let in_flight = Arc::clone(&in_flight);
+            let abandoned = Arc::clone(&abandoned);
+            thread::Builder::new()
+                .name("kafkaRestProxy".to_owned())
+                .spawn(move || run(&config, &receiver, &in_flight, &abandoned))
+                .expect("failed to spawn REST proxy thread")
+        };
+        Self {
+            sender: Some(sender),
+            in_flight,
+            abandoned,
+            shutdown_timeout,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Sends batches of up to `batch_size` records, waiting up to `linger_ms` to fill them.
+fn run(
+    config: &RestProxyConfig,
+    receiver: &Receiver<Record>,
+    in_flight: &AtomicI32,
+    abandoned: &AtomicBool,
+) {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_millis(config.request_timeout_ms))
+        .build();
+    let authorization = config.username.as_ref().map(|username| {
+        let credentials = format!(
+            "{}:{}",
+            username,
+            config.password.as_deref().unwrap_or_default()
+        );
+        format!("Basic {}", base64::encode(credentials))
+    });
+    let linger = Duration::from_millis(config.linger_ms);
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + linger;
+        let mut batches: Vec<(String, Vec<Record>)> = Vec::new();
+        let mut next = Some(first);
+        let mut count = 0;
+        while let Some(record) = next.take() {
+            match batches.iter_mut().find(|(topic, _)| *topic == record.topic) {
+                Some((_, records)) => records.push(record),
+                None => batches.push((record.topic.clone(), vec![record])),
+            }
+            count += 1;
+            if count < config.batch_size {
+                next = receiver.recv_deadline(deadline).ok();
+            }
+        }
+        for (topic, mut records) in batches {
+            let url = format!("{}/topics/{}", config.url.trim_end_matches('/'), topic);
+            let mut attempt = 0;
+            loop {
+                let errors = if abandoned.load(Ordering::Relaxed) {
+                    vec![Some("shut down before delivery".to_owned()); records.len()]
+                } else {
+                    send_batch(&agent, &url, authorization.as_deref(), &records)
+                };
+                let retry = attempt < config.retries && !abandoned.load(Ordering::Relaxed);
+                let mut failed = Vec::new();
+                for (record, error) in records.into_iter().zip(errors) {
+                    match error {
+                        Some(_) if retry => failed.push(record),
+                        error => {
+                            match &error {
+                                None => HEALTH.record_delivery(&topic),
+                                Some(error) => HEALTH.record_delivery_failure(error),
+                            }
+                            if let Some(position) = record.position {
+                                WATERMARKS.record_resolved(&topic, position, error.is_none());
+                            }
+                            in_flight.fetch_sub(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+                if failed.is_empty() {
+                    break;
+                }
+                attempt += 1;
+                warn!(%topic, records = failed.len(), attempt, "retrying REST proxy records");
+                thread::sleep(Duration::from_millis(config.retry_backoff_ms));
+                records = failed;
+            }
+        }
+    }
+}
+
+/// Sends one batch to the topic URL, returning the error of each record, if any.
+fn send_batch(
+    agent: &ureq::Agent,
+    url: &str,
+    authorization: Option<&str>,
+    records: &[Record],
+) -> Vec<Option<String>> {
+    let mut request = agent
+        .post(url)
+        .set("Content-Type", CONTENT_TYPE)
+        .set("Accept", ACCEPT);
+    if let Some(authorization) = authorization {
+        request = request.set("Authorization", authorization);
+    }
+    let body = json!({
+        "records": records
+            .iter()
+            .map(|record| json!({
+                "key": record.key.as_ref().map(base64::encode),
+                "value": base64::encode(&record.payload),
+            }))
+            .collect::<Vec<_>>(),
+    });
+    let result = request
+        .send_json(body)
+        .map_err(|error| error.to_string())
+        .and_then(|response| {
+            response
+                .into_json::<ProduceResponse>()
+                .map_err(|error| error.to_string())
+        });
+    (0..records.len())
+        .map(|index| match &result {
+            Ok(response) => response.offsets.get(index).map_or_else(
+                || Some("missing offset in response".to_owned()),
+                |offset| offset.error.clone(),
+            ),
+            Err(error) => Some(error.clone()),
+        })
+        .collect()
+}
+
+impl ProducerLike for RestProxyProducer {
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        _headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        let sender = self.sender.as_ref().expect("sender is only taken on drop");
+        if let Some(position) = position {
+            WATERMARKS.record_enqueued(topic, position);
+        }
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let record = Record {
+            topic: topic.to_owned(),
+            key: key.map(<[u8]>::to_vec),
+            payload: payload.to_vec(),
+            position,
+        };
+        sender.try_send(record).map_err(|error| {
+            self.in_flight.fetch_sub(1, Ordering::Relaxed);
+            if let Some(position) = position {
+                WATERMARKS.record_resolved(topic, position, false);
+            }
+            match error {
+                TrySendError::Full(_) => ProducerError::QueueFull,
+                TrySendError::Disconnected(_) => ProducerError::ShutDown,
+            }
+        })
+    }
+
+    fn in_flight_count(&self) -> i32 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn flush(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.in_flight_count() > 0 && Instant::now() < deadline {
+            thread::sleep(FLUSH_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for RestProxyProducer {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let deadline = Instant::now() + self.shutdown_timeout;
+            while !thread.is_finished() && Instant::now() < deadline {
+                thread::sleep(FLUSH_POLL_INTERVAL);
+            }
+            if !thread.is_finished() {
+                // The thread fails the records left once its current request returns.
+                self.abandoned.store(true, Ordering::Relaxed);
+                error!(
+                    records = self.in_flight_count(),
+                    "REST proxy records not sent within the shutdown timeout"
+                );
+            } else if thread.join().is_err() {
+                error!("REST proxy thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            io::{BufRead, BufReader, Read, Write},
+            net::{TcpListener, TcpStream},
+            sync::{mpsc, Mutex},
+        },
+    };
+
+    /// Answers requests with `responses` in order, returning the URL and the request bodies.
+    fn serve(responses: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&bodies);
+        thread::spawn(move || {
+            for ((status, body), stream) in responses.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut request = vec![0; length];
+                reader.read_exact(&mut request).unwrap();
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(request).unwrap());
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        (url, bodies)
+    }
+
+    fn config(url: &str) -> RestProxyConfig {
+        serde_json::from_value(json!({
+            "url": url,
+            "linger_ms": 100,
+            "retry_backoff_ms": 1,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_retries() {
+        let (url, bodies) = serve(vec![
+            (500, "{}"),
+            (
+                200,
+                r#"{"offsets": [{"offset": 1}, {"error": "leader not available"}]}"#,
+            ),
+            (200, r#"{"offsets": [{"offset": 2}]}"#),
+        ]);
+        let producer = RestProxyProducer::new(&config(&url), Duration::from_secs(10));
+        producer.send("rest", None, &[], b"first", None).unwrap();
+        producer
+            .send("rest", Some(b"key"), &[], b"second", None)
+            .unwrap();
+        producer.flush(Duration::from_secs(10));
+        assert_eq!(producer.in_flight_count(), 0);
+
+        let bodies = bodies.lock().unwrap();
+        let records = |body: &str| {
+            serde_json::from_str::<serde_json::Value>(body).unwrap()["records"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|record| record["value"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let (first, second) = (base64::encode("first"), base64::encode("second"));
+        assert_eq!(records(&bodies[0]), [first.clone(), second.clone()]);
+        assert_eq!(records(&bodies[1]), [first, second.clone()]);
+        assert_eq!(records(&bodies[2]), [second]);
+    }
+
+    #[test]
+    fn test_drop_timeout() {
+        // Accepts the request but never answers it.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (connected, accepted) = mpsc::channel::<TcpStream>();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = connected.send(stream.unwrap());
+            }
+        });
+        let producer = RestProxyProducer::new(&config(&url), Duration::from_millis(100));
+        producer.send("rest", None, &[], b"stuck", None).unwrap();
+        let _stream = accepted.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let start = Instant::now();
+        drop(producer);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
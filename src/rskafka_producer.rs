@@ -0,0 +1,502 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Producer on the pure-Rust `rskafka` client, for builds that cannot ship librdkafka. It
+//! covers plaintext brokers, murmur2 partitioning and uncompressed batches. Of the `kafka`
+//! settings it only reads `bootstrap.servers`, `security.protocol` and `message.timeout.ms`,
+//! after which retries of a batch give up.
+//!
+//! Dropping the producer sends the queued records for up to `shutdown_timeout_ms`, then fails
+//! the rest.
+
+use {
+    crate::{
+        config::{Config, Partitioner},
+        failover,
+        health::HEALTH,
+        producer::{ProducerError, ProducerLike},
+        watermark::{Position, WATERMARKS},
+    },
+    crossbeam_channel::{Receiver, Sender, TrySendError},
+    rskafka::{
+        client::{
+            partition::{Compression, PartitionClient, UnknownTopicHandling},
+            Client, ClientBuilder,
+        },
+        record::Record as KafkaRecord,
+        BackoffConfig,
+    },
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::{
+            atomic::{AtomicBool, AtomicI32, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tokio::runtime::Runtime,
+    tracing::{error, info},
+};
+
+const QUEUE_CAPACITY: usize = 100_000;
+const BATCH_SIZE: usize = 1_000;
+const LINGER: Duration = Duration::from_millis(5);
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// librdkafka default of `message.timeout.ms`.
+const DEFAULT_MESSAGE_TIMEOUT_MS: u64 = 300_000;
+
+struct Record {
+    topic: String,
+    key: Option<Vec<u8>>,
+    headers: BTreeMap<String, Vec<u8>>,
+    payload: Vec<u8>,
+    position: Option<Position>,
+}
+
+pub struct RskafkaProducer {
+    /// `None` once dropping, which lets the thread send the queued records and exit.
+    sender: Option<Sender<Record>>,
+    in_flight: Arc<AtomicI32>,
+    /// Set when the drain on drop times out, making the thread fail the records left.
+    abandoned: Arc<AtomicBool>,
+    shutdown_timeout: Duration,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RskafkaProducer {
+    pub fn new(config: &Config) -> Result<Self, ProducerError> {
+        match config.kafka.get("security.protocol").map(String::as_str) {
+            None | Some("plaintext") | Some("PLAINTEXT") => {}
+            Some(protocol) => {
+                return Err(ProducerError::Creation(format!(
+                    "security.protocol {} is not supported by the rskafka backend",
+                    protocol
+                )))
+            }
+        }
+        if config.partitioner != Partitioner::Murmur2 {
+            return Err(ProducerError::Creation(
+                "the rskafka backend only partitions with murmur2".to_owned(),
+            ));
+        }
+        let bootstrap_servers = match &config.broker_failover {
+            Some(failover) => Some(&failover.groups[failover::active_group()].bootstrap_servers),
+            None => config.kafka.get("bootstrap.servers"),
+        };
+        let brokers = bootstrap_servers
+            .ok_or_else(|| {
+                ProducerError::Creation("bootstrap.servers is not configured".to_owned())
+            })?
+            .split(',')
+            .map(|broker| broker.trim().to_owned())
+            .collect::<Vec<_>>();
+        let message_timeout = match config.kafka.get("message.timeout.ms") {
+            Some(value) => value.parse().map_err(|_| {
+                ProducerError::Creation(format!("invalid kafka.message.timeout.ms {}", value))
+            })?,
+            None => DEFAULT_MESSAGE_TIMEOUT_MS,
+        };
+        let backoff = BackoffConfig {
+            deadline: Some(Duration::from_millis(message_timeout)),
+            ..BackoffConfig::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|error| ProducerError::Creation(error.to_string()))?;
+
+        let (sender, receiver) = crossbeam_channel::bounded(QUEUE_CAPACITY);
+        let in_flight = Arc::new(AtomicI32::new(0));
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let in_flight = Arc::clone(&in_flight);
+            let abandoned = Arc::clone(&abandoned);
+            thread::Builder::new()
+                .name("kafkaRskafka".to_owned())
+                .spawn(move || {
+                    Sink::new(brokers, backoff, runtime).run(&receiver, &in_flight, &abandoned)
+                })
+                .expect("failed to spawn rskafka thread")
+        };
+        info!("created rskafka producer");
+        Ok(Self {
+            sender: Some(sender),
+            in_flight,
+            abandoned,
+            shutdown_timeout: Duration::from_millis(config.shutdown_timeout_ms),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Connection state, kept by the sending thread.
+struct Sink {
+    brokers: Vec<String>,
+    /// Gives up retrying a request after `message.timeout.ms`.
+    backoff: BackoffConfig,
+    runtime: Runtime,
+    /// `None` until connected, and again after an error, to reconnect with the next batch.
+    client: Option<Client>,
+    partition_counts: HashMap<String, i32>,
+    partition_clients: HashMap<(String, i32), PartitionClient>,
+    /// Spreads records without a key over the partitions.
+    round_robin: usize,
+}
+
+impl Sink {
+    fn new(brokers: Vec<String>, backoff: BackoffConfig, runtime: Runtime) -> Self {
+        Self {
+            brokers,
+            backoff,
+            runtime,
+            client: None,
+            partition_counts: HashMap::new(),
+            partition_clients: HashMap::new(),
+            round_robin: 0,
+        }
+    }
+
+    /// Sends batches of up to `BATCH_SIZE` records, waiting up to `LINGER` to fill them.
+    fn run(mut self, receiver: &Receiver<Record>, in_flight: &AtomicI32, abandoned: &AtomicBool) {
+        while let Ok(first) = receiver.recv() {
+            let deadline = Instant::now() + LINGER;
+            let mut records = vec![first];
+            while records.len() < BATCH_SIZE {
+                match receiver.recv_deadline(deadline) {
+                    Ok(record) => records.push(record),
+                    Err(_) => break,
+                }
+            }
+            if abandoned.load(Ordering::Relaxed) {
+                let error = "shut down before delivery".to_owned();
+                for record in &records {
+                    resolve(record, Some(&error), in_flight);
+                }
+                continue;
+            }
+            let mut batches: Vec<((String, i32), Vec<Record>)> = Vec::new();
+            for record in records {
+                let partition = match self.partition(&record) {
+                    Ok(partition) => partition,
+                    Err(error) => {
+                        resolve(&record, Some(&error), in_flight);
+                        continue;
+                    }
+                };
+                let target = (record.topic.clone(), partition);
+                match batches.iter_mut().find(|(batch, _)| *batch == target) {
+                    Some((_, records)) => records.push(record),
+                    None => batches.push((target, vec![record])),
+                }
+            }
+            for (target, records) in batches {
+                let result = self.produce(&target, &records);
+                for record in &records {
+                    resolve(record, result.as_ref().err(), in_flight);
+                }
+            }
+        }
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        if self.client.is_none() {
+            let client = self
+                .runtime
+                .block_on(
+                    ClientBuilder::new(self.brokers.clone())
+                        .backoff_config(self.backoff.clone())
+                        .build(),
+                )
+                .map_err(|error| format!("failed to connect: {}", error))?;
+            self.client = Some(client);
+        }
+        Ok(())
+    }
+
+    /// Murmur2 hash of the key like the Java client, or the next partition without a key.
+    fn partition(&mut self, record: &Record) -> Result<i32, String> {
+        let count = match self.partition_counts.get(&record.topic) {
+            Some(count) => *count,
+            None => {
+                self.connect()?;
+                let client = self.client.as_ref().expect("connected");
+                let topics = self.runtime.block_on(client.list_topics());
+                let topics = topics.map_err(|error| {
+                    self.client = None;
+                    format!("failed to fetch metadata: {}", error)
+                })?;
+                let count = topics
+                    .iter()
+                    .find(|topic| topic.name == record.topic)
+                    .map(|topic| topic.partitions.len() as i32)
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| format!("unknown topic {}", record.topic))?;
+                self.partition_counts.insert(record.topic.clone(), count);
+                count
+            }
+        };
+        Ok(match &record.key {
+            Some(key) => ((murmur2(key) & 0x7fff_ffff) % count as u32) as i32,
+            None => {
+                self.round_robin = self.round_robin.wrapping_add(1);
+                (self.round_robin % count as usize) as i32
+            }
+        })
+    }
+
+    fn produce(&mut self, target: &(String, i32), records: &[Record]) -> Result<(), String> {
+        if !self.partition_clients.contains_key(target) {
+            self.connect()?;
+            let client = self.client.as_ref().expect("connected");
+            let partition_client = self
+                .runtime
+                .block_on(client.partition_client(
+                    target.0.clone(),
+                    target.1,
+                    UnknownTopicHandling::Error,
+                ))
+                .map_err(|error| format!("failed to connect to partition: {}", error))?;
+            self.partition_clients
+                .insert(target.clone(), partition_client);
+        }
+        let partition_client = &self.partition_clients[target];
+        let records = records
+            .iter()
+            .map(|record| KafkaRecord {
+                key: record.key.clone(),
+                value: Some(record.payload.clone()),
+                headers: record.headers.clone(),
+                timestamp: chrono::Utc::now(),
+            })
+            .collect();
+        let result = self
+            .runtime
+            .block_on(partition_client.produce(records, Compression::NoCompression))
+            .map(|_| ())
+            .map_err(|error| error.to_string());
+        if result.is_err() {
+            // Leadership may have moved, look the partition up again next time.
+            self.partition_clients.remove(target);
+            self.partition_counts.remove(&target.0);
+        }
+        result
+    }
+}
+
+fn resolve(record: &Record, error: Option<&String>, in_flight: &AtomicI32) {
+    match error {
+        None => HEALTH.record_delivery(&record.topic),
+        Some(error) => HEALTH.record_delivery_failure(error),
+    }
+    if let Some(position) = record.position {
+        WATERMARKS.record_resolved(&record.topic, position, error.is_none());
+    }
+    in_flight.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Kafka's murmur2 variant, as in the Java client's `Utils.murmur2`.
+fn murmur2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = 0x9747_b28c ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().expect("four bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let tail = chunks.remainder();
+    if tail.len() == 3 {
+        h ^= u32::from(tail[2]) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= u32::from(tail[1]) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= u32::from(tail[0]);
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+impl ProducerLike for RskafkaProducer {
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        let sender = self.sender.as_ref().expect("sender is only taken on drop");
+        if let Some(position) = position {
+            WATERMARKS.record_enqueued(topic, position);
+        }
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let record = Record {
+            topic: topic.to_owned(),
+            key: key.map(<[u8]>::to_vec),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            payload: payload.to_vec(),
+            position,
+        };
+        sender.try_send(record).map_err(|error| {
+            self.in_flight.fetch_sub(1, Ordering::Relaxed);
+            if let Some(position) = position {
+                WATERMARKS.record_resolved(topic, position, false);
+            }
+            match error {
+                TrySendError::Full(_) => ProducerError::QueueFull,
+                TrySendError::Disconnected(_) => ProducerError::ShutDown,
+            }
+        })
+    }
+
+    fn in_flight_count(&self) -> i32 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn flush(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.in_flight_count() > 0 && Instant::now() < deadline {
+            thread::sleep(FLUSH_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for RskafkaProducer {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let deadline = Instant::now() + self.shutdown_timeout;
+            while !thread.is_finished() && Instant::now() < deadline {
+                thread::sleep(FLUSH_POLL_INTERVAL);
+            }
+            if !thread.is_finished() {
+                // The thread fails the records left once its current request returns.
+                self.abandoned.store(true, Ordering::Relaxed);
+                error!(
+                    records = self.in_flight_count(),
+                    "rskafka records not sent within the shutdown timeout"
+                );
+            } else if thread.join().is_err() {
+                error!("rskafka thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            net::{TcpListener, TcpStream},
+            sync::mpsc,
+        },
+    };
+
+    fn config(kafka: &[(&str, &str)]) -> Config {
+        Config {
+            kafka: kafka
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+            shutdown_timeout_ms: 100,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_unsupported_config() {
+        let error = |config: &Config| RskafkaProducer::new(config).err().unwrap();
+        assert_eq!(
+            error(&config(&[])),
+            ProducerError::Creation("bootstrap.servers is not configured".to_owned())
+        );
+        assert_eq!(
+            error(&config(&[
+                ("bootstrap.servers", "localhost:9092"),
+                ("security.protocol", "ssl"),
+            ])),
+            ProducerError::Creation(
+                "security.protocol ssl is not supported by the rskafka backend".to_owned()
+            )
+        );
+        assert!(RskafkaProducer::new(&Config {
+            partitioner: Partitioner::Crc32,
+            ..config(&[("bootstrap.servers", "localhost:9092")])
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_unreachable_broker() {
+        // Bound and closed again, so that connecting is refused.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let producer = RskafkaProducer::new(&config(&[
+            ("bootstrap.servers", &address.to_string()),
+            ("message.timeout.ms", "100"),
+        ]))
+        .unwrap();
+        producer
+            .send("rskafka", Some(b"key"), &[], b"payload", None)
+            .unwrap();
+        producer.flush(Duration::from_secs(10));
+        assert_eq!(producer.in_flight_count(), 0);
+    }
+
+    #[test]
+    fn test_drop_timeout() {
+        // Accepts the connection but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (connected, accepted) = mpsc::channel::<TcpStream>();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = connected.send(stream.unwrap());
+            }
+        });
+        let producer = RskafkaProducer::new(&config(&[("bootstrap.servers", &address)])).unwrap();
+        producer.send("rskafka", None, &[], b"stuck", None).unwrap();
+        let _stream = accepted.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let start = Instant::now();
+        drop(producer);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_murmur2() {
+        // From the Java client's `UtilsTest`.
+        for (data, hash) in [
+            (&b"21"[..], -973_932_308),
+            (b"foobar", -790_332_482),
+            (b"abc", 479_470_107),
+            (b"a-little-bit-long-string", -985_981_536),
+        ] {
+            assert_eq!(murmur2(data) as i32, hash);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SigV4 signed S3 requests.
+
+use {
+    crate::sigv4,
+    sha2::{Digest, Sha256},
+    std::{error::Error, time::SystemTime},
+};
+
+/// Signed request of `bucket/key`, to be sent with `payload`.
+pub fn request(
+    method: &str,
+    location: &str,
+    region: Option<&str>,
+    payload: &[u8],
+) -> Result<ureq::Request, Box<dyn Error>> {
+    let (bucket, key) = location.split_once('/').ok_or("S3 URL has no object key")?;
+    let region = sigv4::region(region)?;
+    let credentials = sigv4::Credentials::load()?;
+    let host = format!("{}.s3.{}.amazonaws.com", bucket, region);
+    let path = key
+        .split('/')
+        .map(sigv4::uri_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let (date, time) = sigv4::utc_date_time(SystemTime::now());
+    let amz_date = format!("{}T{}Z", date, time);
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let payload_hash = hex::encode(Sha256::digest(payload));
+    let mut headers = vec![
+        ("host", host.clone()),
+        ("x-amz-content-sha256", payload_hash.clone()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(session_token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", session_token.clone()));
+    }
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect::<String>();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n/{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        sigv4::ALGORITHM,
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signature = sigv4::sign(&credentials, &date, &region, "s3", &string_to_sign);
+
+    let mut request = ureq::request(method, &format!("https://{}/{}", host, path)).set(
+        "Authorization",
+        &format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            sigv4::ALGORITHM,
+            credentials.access_key_id,
+            scope,
+            signed_headers,
+            signature
+        ),
+    );
+    for (name, value) in &headers[1..] {
+        request = request.set(name, value);
+    }
+    Ok(request)
+}
@@ -0,0 +1,159 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoded summaries of one in every N published events per topic, logged so that the content
+//! of topics can be sanity-checked without attaching a consumer.
+
+use {
+    crate::*,
+    std::{
+        collections::HashMap,
+        fmt,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Fields identifying an event in the log.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub slot: u64,
+    /// Base58 account pubkey, or transaction signature.
+    pub pubkey: Option<String>,
+    /// Base58 owner or program.
+    pub owner: Option<String>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "slot={}", self.slot)?;
+        if let Some(pubkey) = &self.pubkey {
+            write!(f, " pubkey={}", pubkey)?;
+        }
+        if let Some(owner) = &self.owner {
+            write!(f, " owner={}", owner)?;
+        }
+        Ok(())
+    }
+}
+
+/// Event that can be summarized for sampling.
+pub trait Summarize {
+    fn summary(&self) -> Summary;
+}
+
+fn base58(bytes: &[u8]) -> Option<String> {
+    (!bytes.is_empty()).then(|| bs58::encode(bytes).into_string())
+}
+
+macro_rules! summarize {
+    ($event:ty, $event_ident:ident => $pubkey:expr, $owner:expr) => {
+        impl Summarize for $event {
+            fn summary(&self) -> Summary {
+                let $event_ident = self;
+                Summary {
+                    slot: $event_ident.slot,
+                    pubkey: $pubkey,
+                    owner: $owner,
+                }
+            }
+        }
+    };
+}
+
+summarize!(UpdateAccountEvent, event => base58(&event.pubkey), base58(&event.owner));
+summarize!(SlotStatusEvent, event => None, None);
+summarize!(TransactionEvent, event => base58(&event.signature), None);
+summarize!(TokenAccountEvent, event => base58(&event.pubkey), base58(&event.owner));
+summarize!(StakeAccountEvent, event => base58(&event.pubkey), base58(&event.staker));
+summarize!(VoteAccountEvent, event => base58(&event.pubkey), base58(&event.node_pubkey));
+summarize!(SignatureEvent, event => base58(&event.signature), None);
+summarize!(InstructionEvent, event => base58(&event.signature), base58(&event.program_id));
+summarize!(ProgramUpgradedEvent, event => base58(&event.program_id), base58(&event.authority));
+summarize!(BlockRewardsEvent, event => Some(event.blockhash.clone()), None);
+summarize!(CheckpointEvent, event => Some(event.topic.clone()), None);
+summarize!(BootstrapMarkerEvent, event => None, None);
+
+/// Startups are not tied to a slot.
+impl Summarize for PluginStartedEvent {
+    fn summary(&self) -> Summary {
+        Summary {
+            pubkey: Some(self.plugin_version.clone()),
+            ..Summary::default()
+        }
+    }
+}
+
+/// Picks the events to log, counting separately per topic.
+#[derive(Debug)]
+pub struct Sampler {
+    every: u64,
+    counters: HashMap<String, AtomicU64>,
+}
+
+impl Sampler {
+    /// Samples one in every `every` events of the given topics. Returns `None` if `every` is zero.
+    pub fn new<'a>(every: u64, topics: impl Iterator<Item = &'a String>) -> Option<Self> {
+        (every > 0).then(|| Self {
+            every,
+            counters: topics
+                .filter(|topic| !topic.is_empty())
+                .map(|topic| (topic.clone(), AtomicU64::new(0)))
+                .collect(),
+        })
+    }
+
+    /// Whether to log the next event of the topic, the first one included.
+    pub fn sample(&self, topic: &str) -> bool {
+        self.counters
+            .get(topic)
+            .map(|counter| counter.fetch_add(1, Ordering::Relaxed) % self.every == 0)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_per_topic() {
+        let topics = ["accounts".to_owned(), "slots".to_owned()];
+        assert!(Sampler::new(0, topics.iter()).is_none());
+        let sampler = Sampler::new(3, topics.iter()).unwrap();
+        let sampled = (0..6)
+            .map(|_| sampler.sample("accounts"))
+            .collect::<Vec<_>>();
+        assert_eq!(sampled, [true, false, false, true, false, false]);
+        assert!(sampler.sample("slots"));
+        assert!(!sampler.sample("unknown"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let event = UpdateAccountEvent {
+            slot: 9,
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            ..Default::default()
+        };
+        assert_eq!(
+            event.summary().to_string(),
+            format!(
+                "slot=9 pubkey={} owner={}",
+                bs58::encode([1; 32]).into_string(),
+                bs58::encode([2; 32]).into_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::{ShardBy, ShardConfig};
+
+/// Subset of the events published by this instance, so that several validators running the
+/// plugin publish disjoint parts of the stream.
+#[derive(Debug, Clone)]
+pub struct Shard {
+    index: u64,
+    total: u64,
+    by: ShardBy,
+}
+
+impl Shard {
+    pub fn new(config: &ShardConfig) -> Self {
+        Self {
+            index: config.index,
+            total: config.total,
+            by: config.by,
+        }
+    }
+
+    /// Whether this instance publishes the event of an account or transaction.
+    pub fn owns(&self, key: &[u8], slot: u64) -> bool {
+        match self.by {
+            ShardBy::Key => self.owns_value(key_hash(key)),
+            ShardBy::Slot => self.owns_slot(slot),
+        }
+    }
+
+    /// Slot statuses are always assigned by slot.
+    pub fn owns_slot(&self, slot: u64) -> bool {
+        self.owns_value(slot)
+    }
+
+    fn owns_value(&self, value: u64) -> bool {
+        value % self.total == self.index
+    }
+}
+
+/// Pubkeys and signatures are uniformly distributed already. Bytes 8 to 16 are used because
+/// the publishing workers assign events by the leading bytes, which would otherwise leave
+/// workers idle when the worker count and the shard total share a factor.
+fn key_hash(key: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    if let Some(key) = key.get(8..) {
+        let len = key.len().min(8);
+        bytes[..len].copy_from_slice(&key[..len]);
+    }
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disjoint() {
+        let shards = (0..3)
+            .map(|index| {
+                Shard::new(&ShardConfig {
+                    index,
+                    total: 3,
+                    by: ShardBy::Key,
+                })
+            })
+            .collect::<Vec<_>>();
+        for i in 0..32u8 {
+            let key = [i.wrapping_mul(37); 32];
+            let owners = shards.iter().filter(|shard| shard.owns(&key, 7)).count();
+            assert_eq!(owners, 1);
+        }
+        for slot in 0..10 {
+            let owners = shards.iter().filter(|shard| shard.owns_slot(slot)).count();
+            assert_eq!(owners, 1);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! AWS Signature Version 4 helpers shared by MSK IAM authentication and S3 requests.
+//!
+//! Credentials come from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+//! `AWS_SESSION_TOKEN` environment variables or else from the EC2 instance profile.
+
+use {
+    hmac::{Hmac, Mac},
+    serde::Deserialize,
+    sha2::Sha256,
+    std::{
+        env,
+        error::Error,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+pub const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+const IMDS: &str = "http://169.254.169.254/latest";
+const IMDS_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InstanceCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+}
+
+impl Credentials {
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key_id), Ok(secret_access_key)) => Ok(Self {
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            }),
+            _ => Self::from_instance_profile(),
+        }
+    }
+
+    /// Role credentials from the EC2 instance metadata service (IMDSv2).
+    fn from_instance_profile() -> Result<Self, Box<dyn Error>> {
+        let agent = ureq::AgentBuilder::new().timeout(IMDS_TIMEOUT).build();
+        let token = agent
+            .put(&format!("{}/api/token", IMDS))
+            .set("X-aws-ec2-metadata-token-ttl-seconds", "60")
+            .call()?
+            .into_string()?;
+        let url = format!("{}/meta-data/iam/security-credentials/", IMDS);
+        let role = agent
+            .get(&url)
+            .set("X-aws-ec2-metadata-token", &token)
+            .call()?
+            .into_string()?;
+        let role = role.lines().next().ok_or("no instance profile role")?;
+        let credentials: InstanceCredentials = serde_json::from_str(
+            &agent
+                .get(&format!("{}{}", url, role))
+                .set("X-aws-ec2-metadata-token", &token)
+                .call()?
+                .into_string()?,
+        )?;
+        Ok(Self {
+            access_key_id: credentials.access_key_id,
+            secret_access_key: credentials.secret_access_key,
+            session_token: Some(credentials.token),
+        })
+    }
+}
+
+/// Region from the config, falling back to `AWS_REGION`.
+pub fn region(configured: Option<&str>) -> Result<String, Box<dyn Error>> {
+    match configured {
+        Some(region) => Ok(region.to_owned()),
+        None => env::var("AWS_REGION").map_err(|_| "no AWS region configured".into()),
+    }
+}
+
+/// Hex signature of `string_to_sign`, with the key derived for the date, region and service.
+pub fn sign(
+    credentials: &Credentials,
+    date: &str,
+    region: &str,
+    service: &str,
+    string_to_sign: &str,
+) -> String {
+    let mut key = hmac(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [region, service, "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    hex::encode(hmac(&key, string_to_sign.as_bytes()))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters, as SigV4 requires.
+pub fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// `YYYYMMDD` and `HHMMSS` in UTC.
+pub fn utc_date_time(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since the epoch, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        format!("{:04}{:02}{:02}", year, month, day),
+        format!(
+            "{:02}{:02}{:02}",
+            secs / 3_600,
+            secs % 3_600 / 60,
+            secs % 60
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_date_time() {
+        let time = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(
+            utc_date_time(time(0)),
+            ("19700101".to_owned(), "000000".to_owned())
+        );
+        assert_eq!(
+            utc_date_time(time(951_827_696)),
+            ("20000229".to_owned(), "123456".to_owned())
+        );
+        assert_eq!(
+            utc_date_time(time(1_672_531_199)),
+            ("20221231".to_owned(), "235959".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("a-Z_0.~"), "a-Z_0.~");
+        assert_eq!(uri_encode("a/b c+="), "a%2Fb%20c%2B%3D");
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parquet snapshots of the latest state of all filtered accounts, written to S3 at every epoch
+//! boundary, for analysts wanting point-in-time state next to the change stream.
+//!
+//! An epoch boundary is the first clock sysvar update of a new epoch. The snapshot taken then
+//! holds the newest update of every account up to that point, as notified, which includes
+//! updates of slots that were not rooted.
+
+use {
+    crate::{
+        account_state::{AccountState, AccountStates},
+        config::SnapshotConfig,
+        prom::ACCOUNT_SNAPSHOTS_TOTAL,
+        s3,
+    },
+    arrow_array::{ArrayRef, BinaryArray, BooleanArray, RecordBatch, StringArray, UInt64Array},
+    parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties},
+    std::{
+        error::Error,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            mpsc::{self, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+    },
+    tracing::{error, info, warn},
+};
+
+/// An epoch and the accounts at its end.
+type Request = (u64, Vec<Arc<AccountState>>);
+
+/// Background thread writing the snapshots.
+#[derive(Debug)]
+pub struct Snapshotter {
+    /// Newest epoch seen, 0 until the first clock update.
+    epoch: AtomicU64,
+    /// Accounts taken at the boundary of each finished epoch, `None` once dropping.
+    requests: Mutex<Option<Sender<Request>>>,
+    states: Arc<AccountStates>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Snapshotter {
+    pub fn new(config: &SnapshotConfig, states: Arc<AccountStates>) -> Self {
+        let (requests, received) = mpsc::channel::<Request>();
+        let config = config.clone();
+        let thread = thread::Builder::new()
+            .name("kafkaSnapshot".to_owned())
+            .spawn(move || {
+                for (epoch, mut accounts) in received {
+                    accounts.sort_unstable_by_key(|account| account.pubkey);
+                    match write(&config, epoch, &accounts) {
+                        Ok(location) => {
+                            info!(epoch, accounts = accounts.len(), %location, "wrote account snapshot");
+                            ACCOUNT_SNAPSHOTS_TOTAL
+                                .with_label_values(&["success"])
+                                .inc();
+                        }
+                        Err(error) => {
+                            warn!(epoch, %error, "failed to write account snapshot");
+                            ACCOUNT_SNAPSHOTS_TOTAL
+                                .with_label_values(&["failed"])
+                                .inc();
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn snapshot thread");
+        Self {
+            epoch: AtomicU64::new(0),
+            requests: Mutex::new(Some(requests)),
+            states,
+            thread: Some(thread),
+        }
+    }
+
+    /// Notes the epoch of a clock update, requesting a snapshot of the state at the end of the
+    /// previous epoch when it is new. The accounts are taken right away, before any update of the
+    /// new epoch is recorded, and only cost clones of their `Arc`s.
+    pub fn observe_epoch(&self, epoch: u64) {
+        let previous = self.epoch.fetch_max(epoch, Ordering::Relaxed);
+        if previous == 0 || epoch <= previous {
+            return;
+        }
+        let accounts = self.states.snapshot();
+        if let Some(requests) = self.requests.lock().unwrap().as_ref() {
+            let _ = requests.send((previous, accounts));
+        }
+    }
+}
+
+/// Encodes and uploads the snapshot of the state at the end of `epoch`, returning its location.
+fn write(
+    config: &SnapshotConfig,
+    epoch: u64,
+    accounts: &[Arc<AccountState>],
+) -> Result<String, Box<dyn Error>> {
+    let parquet = encode(accounts)?;
+    let prefix = config
+        .url
+        .strip_prefix("s3://")
+        .ok_or("snapshot url is not s3://bucket/prefix")?
+        .trim_end_matches('/');
+    let location = format!("{}/epoch={}/accounts.parquet", prefix, epoch);
+    s3::request("PUT", &location, config.region.as_deref(), &parquet)?
+        .set("Content-Type", "application/vnd.apache.parquet")
+        .send_bytes(&parquet)?;
+    Ok(format!("s3://{}", location))
+}
+
+fn encode(accounts: &[Arc<AccountState>]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let base58 = |bytes: &[u8; 32]| bs58::encode(bytes).into_string();
+    let batch = RecordBatch::try_from_iter([
+        (
+            "pubkey",
+            Arc::new(StringArray::from_iter_values(
+                accounts.iter().map(|account| base58(&account.pubkey)),
+            )) as ArrayRef,
+        ),
+        (
+            "owner",
+            Arc::new(StringArray::from_iter_values(
+                accounts.iter().map(|account| base58(&account.owner)),
+            )),
+        ),
+        (
+            "lamports",
+            Arc::new(UInt64Array::from_iter_values(
+                accounts.iter().map(|account| account.lamports),
+            )),
+        ),
+        (
+            "executable",
+            Arc::new(BooleanArray::from(
+                accounts
+                    .iter()
+                    .map(|account| account.executable)
+                    .collect::<Vec<_>>(),
+            )),
+        ),
+        (
+            "rent_epoch",
+            Arc::new(UInt64Array::from_iter_values(
+                accounts.iter().map(|account| account.rent_epoch),
+            )),
+        ),
+        (
+            "data",
+            Arc::new(BinaryArray::from_iter_values(
+                accounts.iter().map(|account| &account.data),
+            )),
+        ),
+        (
+            "slot",
+            Arc::new(UInt64Array::from_iter_values(
+                accounts.iter().map(|account| account.slot),
+            )),
+        ),
+        (
+            "write_version",
+            Arc::new(UInt64Array::from_iter_values(
+                accounts.iter().map(|account| account.write_version),
+            )),
+        ),
+    ])?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut parquet = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut parquet, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(parquet)
+}
+
+impl Drop for Snapshotter {
+    /// Finishes the snapshots requested so far.
+    fn drop(&mut self) {
+        self.requests.lock().unwrap().take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("snapshot thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        arrow_array::Array,
+        parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+        std::{env, fs},
+    };
+
+    fn state(pubkey: u8, lamports: u64, data: &[u8]) -> Arc<AccountState> {
+        Arc::new(AccountState {
+            pubkey: [pubkey; 32],
+            slot: 5,
+            lamports,
+            owner: [9; 32],
+            executable: false,
+            rent_epoch: 1,
+            data: data.to_vec(),
+            write_version: u64::from(pubkey),
+            rent_exempt: true,
+        })
+    }
+
+    #[test]
+    fn test_encode() {
+        let parquet = encode(&[state(1, 10, &[1, 2]), state(2, 20, &[])]).unwrap();
+        let path = env::temp_dir().join(format!(
+            "kafka-plugin-snapshot-{}.parquet",
+            std::process::id()
+        ));
+        fs::write(&path, parquet).unwrap();
+        let batches = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        let column = |name: &str| Arc::clone(batch.column_by_name(name).unwrap());
+        let pubkeys = column("pubkey");
+        let pubkeys = pubkeys.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(pubkeys.value(0), bs58::encode([1; 32]).into_string());
+        assert_eq!(pubkeys.value(1), bs58::encode([2; 32]).into_string());
+        let lamports = column("lamports");
+        let lamports = lamports.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(lamports.values(), &[10, 20]);
+        let data = column("data");
+        let data = data.as_any().downcast_ref::<BinaryArray>().unwrap();
+        assert_eq!(data.value(0), [1, 2]);
+        assert!(data.value(1).is_empty());
+        let write_versions = column("write_version");
+        let write_versions = write_versions
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(write_versions.values(), &[1, 2]);
+    }
+}
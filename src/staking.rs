@@ -0,0 +1,243 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of native stake and vote accounts.
+
+use {
+    crate::{replica::AccountInfo, StakeAccountEvent, StakeAccountState, VoteAccountEvent},
+    solana_program::{pubkey::Pubkey, vote::state::VoteState},
+};
+
+const STAKE_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("Stake11111111111111111111111111111111111111");
+const VOTE_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("Vote111111111111111111111111111111111111111");
+
+/// Length of a bincode encoded `StakeState::Stake(Meta, Stake)`.
+const STAKE_STATE_LEN: usize = 196;
+
+/// Decodes a stake account. The bincode layout of `StakeState` is read by hand,
+/// it has not changed since genesis.
+pub fn decode_stake(info: &AccountInfo, slot: u64) -> Option<StakeAccountEvent> {
+    if info.owner != STAKE_PROGRAM_ID.as_ref() || info.data.len() < 4 {
+        return None;
+    }
+    let data = info.data;
+    let state = match read_u32(&data[0..4]) {
+        0 => StakeAccountState::StakeUninitialized,
+        1 => StakeAccountState::StakeInitialized,
+        2 => StakeAccountState::StakeDelegated,
+        3 => StakeAccountState::StakeRewardsPool,
+        _ => return None,
+    };
+    let mut event = StakeAccountEvent {
+        slot,
+        pubkey: info.pubkey.to_vec(),
+        state: state.into(),
+        lamports: info.lamports,
+        write_version: info.write_version,
+        ..StakeAccountEvent::default()
+    };
+
+    if matches!(
+        state,
+        StakeAccountState::StakeInitialized | StakeAccountState::StakeDelegated
+    ) {
+        if data.len() < 124 {
+            return None;
+        }
+        event.rent_exempt_reserve = read_u64(&data[4..12]);
+        event.staker = data[12..44].to_vec();
+        event.withdrawer = data[44..76].to_vec();
+        event.lockup_unix_timestamp = read_u64(&data[76..84]) as i64;
+        event.lockup_epoch = read_u64(&data[84..92]);
+        event.lockup_custodian = data[92..124].to_vec();
+    }
+    if state == StakeAccountState::StakeDelegated {
+        if data.len() < STAKE_STATE_LEN {
+            return None;
+        }
+        event.voter = data[124..156].to_vec();
+        event.stake = read_u64(&data[156..164]);
+        event.activation_epoch = read_u64(&data[164..172]);
+        event.deactivation_epoch = read_u64(&data[172..180]);
+        event.credits_observed = read_u64(&data[188..196]);
+    }
+    Some(event)
+}
+
+pub fn decode_vote(info: &AccountInfo, slot: u64) -> Option<VoteAccountEvent> {
+    if info.owner != VOTE_PROGRAM_ID.as_ref() {
+        return None;
+    }
+    let vote_state = VoteState::deserialize(info.data).ok()?;
+    let (epoch, credits, _) = vote_state.epoch_credits.last().copied().unwrap_or_default();
+    Some(VoteAccountEvent {
+        slot,
+        pubkey: info.pubkey.to_vec(),
+        node_pubkey: vote_state.node_pubkey.to_bytes().to_vec(),
+        authorized_voter: vote_state
+            .authorized_voters()
+            .last()
+            .map(|(_, voter)| voter.to_bytes().to_vec())
+            .unwrap_or_default(),
+        authorized_withdrawer: vote_state.authorized_withdrawer.to_bytes().to_vec(),
+        commission: vote_state.commission as u32,
+        root_slot: vote_state.root_slot,
+        last_voted_slot: vote_state.last_voted_slot(),
+        credits: vote_state.credits(),
+        epoch,
+        epoch_credits: credits,
+        last_timestamp_slot: vote_state.last_timestamp.slot,
+        last_timestamp: vote_state.last_timestamp.timestamp,
+        lamports: info.lamports,
+        write_version: info.write_version,
+    })
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[..4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_program::{
+            clock::Clock,
+            stake::{
+                stake_flags::StakeFlags,
+                state::{Authorized, Delegation, Lockup, Meta, Stake, StakeStateV2},
+            },
+            vote::state::{VoteInit, VoteStateVersions},
+        },
+        solana_sdk::account::Account,
+    };
+
+    fn info<'a>(pubkey: &'a Pubkey, account: &'a Account) -> AccountInfo<'a> {
+        AccountInfo {
+            pubkey: pubkey.as_ref(),
+            lamports: account.lamports,
+            owner: account.owner.as_ref(),
+            executable: false,
+            rent_epoch: 0,
+            data: &account.data,
+            write_version: 7,
+            txn_signature: None,
+        }
+    }
+
+    #[test]
+    fn test_decode_stake() {
+        let pubkey = Pubkey::new_unique();
+        let (staker, withdrawer, custodian, voter) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let meta = Meta {
+            rent_exempt_reserve: 2_282_880,
+            authorized: Authorized { staker, withdrawer },
+            lockup: Lockup {
+                unix_timestamp: -5,
+                epoch: 6,
+                custodian,
+            },
+        };
+        let mut delegation = Delegation::new(&voter, 1_000_000, 10);
+        delegation.deactivation_epoch = 20;
+        let stake = Stake {
+            delegation,
+            credits_observed: 30,
+        };
+        let state = StakeStateV2::Stake(meta, stake, StakeFlags::empty());
+        let account = Account::new_data(5_000_000, &state, &STAKE_PROGRAM_ID).unwrap();
+
+        let event = decode_stake(&info(&pubkey, &account), 42).unwrap();
+        assert_eq!(event.state(), StakeAccountState::StakeDelegated);
+        assert_eq!(event.slot, 42);
+        assert_eq!(event.pubkey, pubkey.to_bytes());
+        assert_eq!(event.lamports, 5_000_000);
+        assert_eq!(event.write_version, 7);
+        assert_eq!(event.rent_exempt_reserve, 2_282_880);
+        assert_eq!(event.staker, staker.to_bytes());
+        assert_eq!(event.withdrawer, withdrawer.to_bytes());
+        assert_eq!(event.lockup_unix_timestamp, -5);
+        assert_eq!(event.lockup_epoch, 6);
+        assert_eq!(event.lockup_custodian, custodian.to_bytes());
+        assert_eq!(event.voter, voter.to_bytes());
+        assert_eq!(event.stake, 1_000_000);
+        assert_eq!(event.activation_epoch, 10);
+        assert_eq!(event.deactivation_epoch, 20);
+        assert_eq!(event.credits_observed, 30);
+
+        let state = StakeStateV2::Initialized(meta);
+        let account = Account::new_data(5_000_000, &state, &STAKE_PROGRAM_ID).unwrap();
+        let event = decode_stake(&info(&pubkey, &account), 42).unwrap();
+        assert_eq!(event.state(), StakeAccountState::StakeInitialized);
+        assert_eq!(event.staker, staker.to_bytes());
+        assert!(event.voter.is_empty());
+
+        let account = Account::new_data(5_000_000, &state, &VOTE_PROGRAM_ID).unwrap();
+        assert!(decode_stake(&info(&pubkey, &account), 42).is_none());
+    }
+
+    #[test]
+    fn test_decode_vote() {
+        let pubkey = Pubkey::new_unique();
+        let init = VoteInit {
+            node_pubkey: Pubkey::new_unique(),
+            authorized_voter: Pubkey::new_unique(),
+            authorized_withdrawer: Pubkey::new_unique(),
+            commission: 5,
+        };
+        let mut vote_state = VoteState::new(&init, &Clock::default());
+        vote_state.root_slot = Some(90);
+        vote_state.epoch_credits.push((3, 400, 100));
+        vote_state.last_timestamp.slot = 95;
+        vote_state.last_timestamp.timestamp = 1_700_000_000;
+        let state = VoteStateVersions::new_current(vote_state);
+        let account = Account::new_data(27_074_400, &state, &VOTE_PROGRAM_ID).unwrap();
+
+        let event = decode_vote(&info(&pubkey, &account), 100).unwrap();
+        assert_eq!(event.slot, 100);
+        assert_eq!(event.pubkey, pubkey.to_bytes());
+        assert_eq!(event.node_pubkey, init.node_pubkey.to_bytes());
+        assert_eq!(event.authorized_voter, init.authorized_voter.to_bytes());
+        assert_eq!(
+            event.authorized_withdrawer,
+            init.authorized_withdrawer.to_bytes()
+        );
+        assert_eq!(event.commission, 5);
+        assert_eq!(event.root_slot, Some(90));
+        assert_eq!(event.credits, 400);
+        assert_eq!(event.epoch, 3);
+        assert_eq!(event.epoch_credits, 400);
+        assert_eq!(event.last_timestamp_slot, 95);
+        assert_eq!(event.last_timestamp, 1_700_000_000);
+        assert_eq!(event.lamports, 27_074_400);
+
+        let account = Account::new_data(27_074_400, &state, &STAKE_PROGRAM_ID).unwrap();
+        assert!(decode_vote(&info(&pubkey, &account), 100).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        prom::{STARTUP_ACCOUNTS_TOTAL, STARTUP_ETA_SECONDS, STARTUP_PROGRESS},
+        Config,
+    },
+    std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Instant,
+    },
+    tracing::info,
+};
+
+/// Progress of the account replay from the snapshot at validator startup.
+#[derive(Debug)]
+pub struct StartupProgress {
+    started: Instant,
+    scanned: AtomicU64,
+    matched: AtomicU64,
+    expected: Option<u64>,
+    log_interval: u64,
+}
+
+impl Default for StartupProgress {
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+impl StartupProgress {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            started: Instant::now(),
+            scanned: AtomicU64::new(0),
+            matched: AtomicU64::new(0),
+            expected: config.startup_expected_accounts,
+            log_interval: config.startup_log_interval,
+        }
+    }
+
+    /// An account was notified during startup.
+    pub fn record_scanned(&self) {
+        let scanned = self.scanned.fetch_add(1, Ordering::Relaxed) + 1;
+        STARTUP_ACCOUNTS_TOTAL.with_label_values(&["scanned"]).inc();
+
+        if self.log_interval == 0 || scanned % self.log_interval != 0 {
+            return;
+        }
+
+        let matched = self.matched.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = scanned as f64 / elapsed.max(f64::EPSILON);
+        match self.expected {
+            Some(expected) if expected > 0 => {
+                let progress = (scanned as f64 / expected as f64).min(1.0);
+                let eta = expected.saturating_sub(scanned) as f64 / rate;
+                STARTUP_PROGRESS.set(progress);
+                STARTUP_ETA_SECONDS.set(eta);
+                info!(
+                    scanned,
+                    matched,
+                    rate = rate as u64,
+                    progress = %format!("{:.1}%", progress * 100.0),
+                    eta_secs = eta as u64,
+                    "startup replay progress"
+                );
+            }
+            _ => info!(
+                scanned,
+                matched,
+                rate = rate as u64,
+                "startup replay progress"
+            ),
+        }
+    }
+
+    /// An account notified during startup passed the filters.
+    pub fn record_matched(&self) {
+        self.matched.fetch_add(1, Ordering::Relaxed);
+        STARTUP_ACCOUNTS_TOTAL.with_label_values(&["matched"]).inc();
+    }
+
+    pub fn finish(&self) {
+        STARTUP_PROGRESS.set(1.0);
+        STARTUP_ETA_SECONDS.set(0.0);
+        info!(
+            scanned = self.scanned.load(Ordering::Relaxed),
+            matched = self.matched.load(Ordering::Relaxed),
+            elapsed_secs = self.started.elapsed().as_secs(),
+            "startup replay finished"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let scanned = || STARTUP_ACCOUNTS_TOTAL.with_label_values(&["scanned"]).get();
+        let matched = || STARTUP_ACCOUNTS_TOTAL.with_label_values(&["matched"]).get();
+        let (scanned_before, matched_before) = (scanned(), matched());
+        let progress = StartupProgress::new(&Config {
+            startup_expected_accounts: Some(4),
+            startup_log_interval: 2,
+            ..Config::default()
+        });
+
+        progress.record_scanned();
+        progress.record_matched();
+        progress.record_scanned();
+        assert_eq!(STARTUP_PROGRESS.get(), 0.5);
+        assert!(STARTUP_ETA_SECONDS.get() >= 0.0);
+        assert_eq!(scanned(), scanned_before + 2);
+        assert_eq!(matched(), matched_before + 1);
+
+        progress.finish();
+        assert_eq!(STARTUP_PROGRESS.get(), 1.0);
+        assert_eq!(STARTUP_ETA_SECONDS.get(), 0.0);
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Notifications switched off by operators through the admin API, to shed load selectively
+//! during incidents. Unlike pausing, a disabled notification is dropped as soon as the
+//! validator delivers it, before any filtering or encoding, and the interest flags report it
+//! to validators that check them on every notification.
+
+use {
+    serde::Serialize,
+    std::sync::atomic::{AtomicBool, Ordering},
+    tracing::info,
+};
+
+lazy_static::lazy_static! {
+    pub static ref SWITCHES: Switches = Switches::default();
+}
+
+/// Notifications that can be disabled, as labelled in metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notification {
+    Account,
+    Transaction,
+    Slot,
+}
+
+impl Notification {
+    const ALL: [Self; 3] = [Self::Account, Self::Transaction, Self::Slot];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Account => "account",
+            Self::Transaction => "transaction",
+            Self::Slot => "slot",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Switches {
+    /// Indexed by `Notification`.
+    disabled: [AtomicBool; 3],
+}
+
+#[derive(Debug, Serialize)]
+pub struct SwitchReport {
+    pub disabled: Vec<&'static str>,
+}
+
+impl Switches {
+    pub fn is_enabled(&self, notification: Notification) -> bool {
+        !self.disabled[notification as usize].load(Ordering::Relaxed)
+    }
+
+    /// Disables the given notifications, all if empty. Fails on unknown notifications.
+    pub fn disable(&self, names: &[&str]) -> Result<SwitchReport, String> {
+        self.set(names, true)
+    }
+
+    /// Enables the given notifications, all if empty. Fails on unknown notifications.
+    pub fn enable(&self, names: &[&str]) -> Result<SwitchReport, String> {
+        self.set(names, false)
+    }
+
+    fn set(&self, names: &[&str], disabled: bool) -> Result<SwitchReport, String> {
+        for notification in resolve(names)? {
+            self.disabled[notification as usize].store(disabled, Ordering::Relaxed);
+        }
+        let report = self.report();
+        info!(disabled = ?report.disabled, "switched notifications");
+        Ok(report)
+    }
+
+    pub fn report(&self) -> SwitchReport {
+        SwitchReport {
+            disabled: Notification::ALL
+                .into_iter()
+                .filter(|notification| !self.is_enabled(*notification))
+                .map(Notification::name)
+                .collect(),
+        }
+    }
+
+    /// Enables all notifications, on unload.
+    pub fn clear(&self) {
+        for disabled in &self.disabled {
+            disabled.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+fn resolve(names: &[&str]) -> Result<Vec<Notification>, String> {
+    if names.is_empty() {
+        return Ok(Notification::ALL.to_vec());
+    }
+    names
+        .iter()
+        .map(|name| {
+            Notification::ALL
+                .into_iter()
+                .find(|notification| notification.name() == *name)
+                .ok_or_else(|| format!("unknown notification {:?}", name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disable_and_enable() {
+        let switches = Switches::default();
+        assert_eq!(
+            switches.disable(&["transaction", "slot"]).unwrap().disabled,
+            ["transaction", "slot"]
+        );
+        assert!(switches.disable(&["transactions"]).is_err());
+        assert!(switches.is_enabled(Notification::Account));
+        assert!(!switches.is_enabled(Notification::Slot));
+
+        assert_eq!(
+            switches.enable(&["slot"]).unwrap().disabled,
+            ["transaction"]
+        );
+        assert_eq!(switches.disable(&[]).unwrap().disabled.len(), 3);
+        switches.clear();
+        assert!(switches.report().disabled.is_empty());
+    }
+}
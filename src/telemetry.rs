@@ -0,0 +1,231 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::config::{Config, LogConfig, OtlpConfig},
+    opentelemetry::{
+        sdk::{
+            trace::{self, Sampler, Tracer},
+            Resource,
+        },
+        KeyValue,
+    },
+    opentelemetry_otlp::WithExportConfig,
+    std::{collections::HashMap, error::Error, str::FromStr, sync::Mutex, time::Duration},
+    tokio::runtime::Runtime,
+    tracing::{info, level_filters::LevelFilter, warn, Level},
+    tracing_subscriber::{
+        filter::{EnvFilter, Targets},
+        fmt,
+        layer::SubscriberExt,
+        reload,
+        util::SubscriberInitExt,
+        Layer, Registry,
+    },
+};
+
+lazy_static::lazy_static! {
+    /// Log levels changed at runtime through the admin API, `None` if the plugin's
+    /// subscriber is not the one installed.
+    static ref LOG_LEVELS: Mutex<Option<LogLevels>> = Mutex::new(None);
+}
+
+struct LogLevels {
+    handle: reload::Handle<EnvFilter, Registry>,
+    configured: LogConfig,
+    current: LogConfig,
+}
+
+impl LogLevels {
+    fn apply(&mut self, levels: LogConfig) -> Result<String, String> {
+        let directives = levels.directives();
+        let filter = EnvFilter::try_new(&directives).map_err(|error| error.to_string())?;
+        self.handle
+            .reload(filter)
+            .map_err(|error| error.to_string())?;
+        self.current = levels;
+        Ok(directives)
+    }
+}
+
+/// Current log filter directives, `None` if they cannot be changed.
+pub fn log_directives() -> Option<String> {
+    let levels = LOG_LEVELS.lock().unwrap();
+    levels.as_ref().map(|levels| levels.current.directives())
+}
+
+/// Changes the default log level, if given, and the levels of the given modules, keeping
+/// those of other modules. Returns the new filter directives.
+pub fn set_log_levels(
+    level: Option<&str>,
+    modules: HashMap<String, String>,
+) -> Result<String, String> {
+    if let Some(invalid) = level
+        .into_iter()
+        .chain(modules.values().map(String::as_str))
+        .find(|level| LevelFilter::from_str(level).is_err())
+    {
+        return Err(format!("invalid log level: {}", invalid));
+    }
+    let mut levels = LOG_LEVELS.lock().unwrap();
+    let levels = levels.as_mut().ok_or("log levels cannot be changed")?;
+    let mut new = levels.current.clone();
+    if let Some(level) = level {
+        new.level = level.to_owned();
+    }
+    new.modules.extend(modules);
+    levels.apply(new)
+}
+
+/// Restores the configured log levels. Returns the filter directives.
+pub fn reset_log_levels() -> Result<String, String> {
+    let mut levels = LOG_LEVELS.lock().unwrap();
+    let levels = levels.as_mut().ok_or("log levels cannot be changed")?;
+    levels.apply(levels.configured.clone())
+}
+
+/// Installs the plugin's log output and, optionally, OTLP trace export.
+#[derive(Debug)]
+pub struct Telemetry {
+    runtime: Option<Runtime>,
+}
+
+impl Telemetry {
+    pub fn new(config: &Config) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (log_filter, handle) = reload::Layer::new(EnvFilter::try_new(config.log.directives())?);
+        let log_layer: Box<dyn Layer<Registry> + Send + Sync> = if config.log.json {
+            Box::new(fmt::layer().json().with_filter(log_filter))
+        } else {
+            Box::new(fmt::layer().with_filter(log_filter))
+        };
+
+        let (runtime, trace_layer) = match &config.otlp {
+            Some(otlp) => {
+                let runtime = Runtime::new()?;
+                let tracer = {
+                    let _guard = runtime.enter();
+                    Self::install_tracer(otlp)?
+                };
+                let layer = tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(
+                        Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::TRACE),
+                    );
+                (Some(runtime), Some(layer))
+            }
+            None => (None, None),
+        };
+
+        match Registry::default()
+            .with(log_layer)
+            .with(trace_layer)
+            .try_init()
+        {
+            Ok(()) => {
+                *LOG_LEVELS.lock().unwrap() = Some(LogLevels {
+                    handle,
+                    configured: config.log.clone(),
+                    current: config.log.clone(),
+                })
+            }
+            Err(error) => warn!(%error, "tracing subscriber already installed, keeping it"),
+        }
+        if let Some(otlp) = &config.otlp {
+            info!(
+                endpoint = %otlp.endpoint,
+                sample_ratio = otlp.sample_ratio,
+                "exporting traces"
+            );
+        }
+
+        Ok(Self { runtime })
+    }
+
+    fn install_tracer(config: &OtlpConfig) -> Result<Tracer, Box<dyn Error + Send + Sync>> {
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(&config.endpoint),
+            )
+            .with_trace_config(
+                trace::config()
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                        config.sample_ratio,
+                    ))))
+                    .with_resource(Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        config.service_name.clone(),
+                    )])),
+            )
+            .install_batch(opentelemetry::runtime::Tokio)?;
+        Ok(tracer)
+    }
+
+    pub fn shutdown(self) {
+        if let Some(runtime) = self.runtime {
+            opentelemetry::global::shutdown_tracer_provider();
+            runtime.shutdown_timeout(Duration::from_secs(10));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_levels() {
+        let configured = LogConfig {
+            level: "info".to_owned(),
+            json: false,
+            modules: HashMap::new(),
+        };
+        let (filter, handle) = reload::Layer::new(EnvFilter::try_new("info").unwrap());
+        // Kept for the handle to reload, without being installed.
+        let _subscriber = Registry::default().with(filter);
+        *LOG_LEVELS.lock().unwrap() = Some(LogLevels {
+            handle,
+            configured: configured.clone(),
+            current: configured,
+        });
+
+        let publisher = "solana_accountsdb_plugin_kafka::publisher=trace";
+        assert_eq!(
+            set_log_levels(
+                Some("debug"),
+                HashMap::from([("publisher".to_owned(), "trace".to_owned())])
+            ),
+            Ok(format!("debug,{}", publisher))
+        );
+        // Levels not given are kept.
+        assert_eq!(
+            set_log_levels(None, HashMap::new()),
+            Ok(format!("debug,{}", publisher))
+        );
+        assert_eq!(
+            set_log_levels(Some("loud"), HashMap::new()),
+            Err("invalid log level: loud".to_owned())
+        );
+        assert_eq!(log_directives(), Some(format!("debug,{}", publisher)));
+
+        assert_eq!(reset_log_levels(), Ok("info".to_owned()));
+        assert_eq!(log_directives().as_deref(), Some("info"));
+
+        *LOG_LEVELS.lock().unwrap() = None;
+        assert!(set_log_levels(None, HashMap::new()).is_err());
+        assert!(reset_log_levels().is_err());
+    }
+}
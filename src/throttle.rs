@@ -0,0 +1,303 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-account rate limit. Updates over the limit are held back, and only the latest held
+//! update of an account is published once its next window starts, so that consumers still
+//! see the final state of accounts rewritten many times per second.
+
+use {
+    crate::{
+        config::AccountRateLimitConfig,
+        prom::EVENTS_SKIPPED_TOTAL,
+        workers::{Job, Workers},
+        *,
+    },
+    std::{
+        collections::HashMap,
+        mem,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tracing::{debug, error, info},
+};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// How often held updates are checked for release.
+const RELEASE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    count: u32,
+    /// Latest update over the limit.
+    held: Option<Job>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Accounts updated within the last window, by event and pubkey.
+    windows: HashMap<(&'static str, Vec<u8>), Window>,
+}
+
+impl State {
+    /// Returns the job if it may be published now, else holds it.
+    fn admit(
+        &mut self,
+        job: Job,
+        key: (&'static str, Vec<u8>),
+        max: u32,
+        now: Instant,
+    ) -> Option<Job> {
+        let window = self.windows.entry(key).or_insert(Window {
+            start: now,
+            count: 0,
+            held: None,
+        });
+        if now.duration_since(window.start) >= WINDOW {
+            window.start = now;
+            window.count = 0;
+            if let Some(held) = window.held.take() {
+                skipped(&held);
+            }
+        }
+        if window.count < max {
+            window.count += 1;
+            return Some(job);
+        }
+        if let Some(superseded) = window.held.replace(job) {
+            skipped(&superseded);
+        }
+        None
+    }
+
+    /// Takes the held updates of accounts whose window is over, starting a new window for them.
+    /// Forgets accounts with an expired window and nothing held.
+    fn release(&mut self, now: Instant) -> Vec<Job> {
+        let mut released = Vec::new();
+        self.windows.retain(|_, window| {
+            if now.duration_since(window.start) < WINDOW {
+                return true;
+            }
+            match window.held.take() {
+                Some(job) => {
+                    released.push(job);
+                    window.start = now;
+                    window.count = 1;
+                    true
+                }
+                None => false,
+            }
+        });
+        released
+    }
+}
+
+fn skipped(job: &Job) {
+    EVENTS_SKIPPED_TOTAL
+        .with_label_values(&[job.event(), "throttled"])
+        .inc();
+}
+
+/// Limits the updates published per account and second.
+#[derive(Debug)]
+pub struct Throttle {
+    max_per_second: u32,
+    state: Arc<Mutex<State>>,
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Throttle {
+    /// Held updates are handed to `workers` when configured, like the admitted ones.
+    pub fn new(
+        publisher: Arc<Publisher>,
+        workers: Option<Arc<Workers>>,
+        config: &AccountRateLimitConfig,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (stop, stopped) = mpsc::channel();
+        let thread = {
+            let state = Arc::clone(&state);
+            thread::Builder::new()
+                .name("kafkaThrottle".to_owned())
+                .spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) =
+                        stopped.recv_timeout(RELEASE_INTERVAL)
+                    {
+                        // Handed over before the lock is released, so that newer updates admitted
+                        // meanwhile are queued behind.
+                        let mut state = state.lock().unwrap();
+                        let released = state.release(Instant::now());
+                        publish(&publisher, workers.as_deref(), released);
+                    }
+                    // Publish what is still held, it is the latest state of those accounts.
+                    let mut state = state.lock().unwrap();
+                    let windows = mem::take(&mut state.windows);
+                    publish(
+                        &publisher,
+                        workers.as_deref(),
+                        windows
+                            .into_values()
+                            .filter_map(|window| window.held)
+                            .collect(),
+                    );
+                })
+                .expect("failed to spawn throttle thread")
+        };
+        info!(
+            max_per_second = config.max_per_second,
+            "limiting updates per account"
+        );
+        Self {
+            max_per_second: config.max_per_second,
+            state,
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the job if it may be published now. Updates over the limit are held and
+    /// published later unless superseded. Jobs about no account are always returned.
+    pub fn admit(&self, job: Job) -> Option<Job> {
+        let key = match job.pubkey() {
+            Some(pubkey) => (job.event(), pubkey.to_vec()),
+            None => return Some(job),
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .admit(job, key, self.max_per_second, Instant::now())
+    }
+}
+
+/// Queues the held updates for the workers, or publishes them from the throttle thread
+/// without workers.
+fn publish(publisher: &Publisher, workers: Option<&Workers>, jobs: Vec<Job>) {
+    for job in jobs {
+        match workers {
+            Some(workers) => workers.submit(job),
+            None => {
+                let event = job.event();
+                if let Err(error) = job.publish(publisher) {
+                    debug!(event, %error, "failed to publish held update");
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Throttle {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("throttle thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{config::WorkersConfig, producer::MemoryProducer},
+        prost::Message,
+    };
+
+    fn account(write_version: u64) -> Job {
+        Job::Account(UpdateAccountEvent {
+            pubkey: vec![1; 32],
+            write_version,
+            ..UpdateAccountEvent::default()
+        })
+    }
+
+    fn write_version(job: &Job) -> u64 {
+        match job {
+            Job::Account(ev) => ev.write_version,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_keep_latest() {
+        let mut state = State::default();
+        let key = || ("account", vec![1; 32]);
+        let start = Instant::now();
+
+        assert!(state.admit(account(1), key(), 2, start).is_some());
+        assert!(state.admit(account(2), key(), 2, start).is_some());
+        assert!(state.admit(account(3), key(), 2, start).is_none());
+        assert!(state.admit(account(4), key(), 2, start).is_none());
+        assert!(state.release(start + WINDOW / 2).is_empty());
+
+        let released = state.release(start + WINDOW);
+        assert_eq!(released.iter().map(write_version).collect::<Vec<_>>(), [4]);
+        // The released update counts against the new window.
+        assert!(state.admit(account(5), key(), 2, start + WINDOW).is_some());
+        assert!(state.admit(account(6), key(), 2, start + WINDOW).is_none());
+
+        // A newer update in a later window supersedes the held one.
+        assert!(state
+            .admit(account(7), key(), 2, start + WINDOW * 2)
+            .is_some());
+        assert!(state.release(start + WINDOW * 2).is_empty());
+        assert!(state.release(start + WINDOW * 3).is_empty());
+        assert!(state.windows.is_empty());
+    }
+
+    #[test]
+    fn test_held_updates_follow_queued_ones() {
+        let producer = MemoryProducer::default();
+        let publisher = Arc::new(Publisher::new(
+            Some(Box::new(producer.clone())),
+            None,
+            &Config::default(),
+        ));
+        let workers = Arc::new(Workers::new(
+            Arc::clone(&publisher),
+            &WorkersConfig {
+                threads: 2,
+                queue_capacity: 100,
+            },
+        ));
+        let throttle = Throttle::new(
+            publisher,
+            Some(Arc::clone(&workers)),
+            &AccountRateLimitConfig { max_per_second: 2 },
+        );
+
+        for write_version in 1..=5 {
+            if let Some(job) = throttle.admit(account(write_version)) {
+                workers.submit(job);
+            }
+        }
+        drop(throttle);
+        drop(workers);
+
+        let write_versions = producer
+            .records()
+            .iter()
+            .map(|record| UpdateAccountEvent::decode(&record.payload[..]).unwrap())
+            .map(|event| event.write_version)
+            .collect::<Vec<_>>();
+        assert_eq!(write_versions, [1, 2, 5]);
+    }
+}
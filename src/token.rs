@@ -0,0 +1,201 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of SPL Token and Token-2022 accounts.
+//!
+//! The account layout is parsed by hand to avoid depending on the token program crates,
+//! which pin their own Solana versions.
+
+use {
+    crate::{
+        config::TokenDelegateFilterConfig, replica::AccountInfo, TokenAccountEvent,
+        TokenAccountState,
+    },
+    solana_program::pubkey::Pubkey,
+    std::{collections::HashSet, str::FromStr},
+};
+
+const TOKEN_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const TOKEN_2022_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+/// Length of the base token account layout.
+const ACCOUNT_LEN: usize = 165;
+/// Token-2022 account type byte following the base layout when extensions are present.
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+pub fn is_token_program(owner: &[u8]) -> bool {
+    owner == TOKEN_PROGRAM_ID.as_ref() || owner == TOKEN_2022_PROGRAM_ID.as_ref()
+}
+
+/// Decodes a token account, `None` for mints, multisigs, other programs' accounts
+/// and uninitialized accounts.
+pub fn decode(info: &AccountInfo, slot: u64) -> Option<TokenAccountEvent> {
+    if !is_token_program(info.owner) {
+        return None;
+    }
+    let data = info.data;
+    match data.len() {
+        ACCOUNT_LEN => {}
+        len if len > ACCOUNT_LEN
+            && info.owner == TOKEN_2022_PROGRAM_ID.as_ref()
+            && data[ACCOUNT_LEN] == ACCOUNT_TYPE_ACCOUNT => {}
+        _ => return None,
+    }
+
+    let state = match data[108] {
+        1 => TokenAccountState::Initialized,
+        2 => TokenAccountState::Frozen,
+        _ => return None,
+    };
+    let (delegate, delegated_amount) = match coption(&data[72..108]) {
+        Some(delegate) => (delegate.to_vec(), read_u64(&data[121..129])),
+        None => (Vec::new(), 0),
+    };
+    Some(TokenAccountEvent {
+        slot,
+        pubkey: info.pubkey.to_vec(),
+        program: info.owner.to_vec(),
+        mint: data[0..32].to_vec(),
+        owner: data[32..64].to_vec(),
+        amount: read_u64(&data[64..72]),
+        delegate,
+        delegated_amount,
+        state: state.into(),
+        is_native: coption(&data[109..121]).is_some(),
+        close_authority: coption(&data[129..165])
+            .map(<[u8]>::to_vec)
+            .unwrap_or_default(),
+        lamports: info.lamports,
+        write_version: info.write_version,
+    })
+}
+
+/// Matches token accounts with an active delegation.
+pub struct DelegateFilter {
+    /// Delegates to match, any delegate if empty.
+    delegates: HashSet<Vec<u8>>,
+}
+
+impl DelegateFilter {
+    pub fn new(config: &TokenDelegateFilterConfig) -> Self {
+        Self {
+            delegates: config
+                .delegates
+                .iter()
+                .filter_map(|delegate| Pubkey::from_str(delegate).ok())
+                .map(|delegate| delegate.to_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    /// Whether the account has a delegate, one of the configured ones if any, with a
+    /// non-zero delegated amount.
+    pub fn matches(&self, event: &TokenAccountEvent) -> bool {
+        event.delegated_amount > 0
+            && !event.delegate.is_empty()
+            && (self.delegates.is_empty() || self.delegates.contains(&event.delegate))
+    }
+}
+
+/// Reads a `COption`: a little-endian u32 tag followed by the value.
+fn coption(data: &[u8]) -> Option<&[u8]> {
+    match &data[..4] {
+        [1, 0, 0, 0] => Some(&data[4..]),
+        _ => None,
+    }
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_token_account() {
+        let pubkey = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        let mut data = vec![0; ACCOUNT_LEN];
+        data[0..32].copy_from_slice(mint.as_ref());
+        data[32..64].copy_from_slice(owner.as_ref());
+        data[64..72].copy_from_slice(&1_000u64.to_le_bytes());
+        data[72] = 1;
+        data[76..108].copy_from_slice(delegate.as_ref());
+        data[108] = 2;
+        data[121..129].copy_from_slice(&250u64.to_le_bytes());
+
+        let info = AccountInfo {
+            pubkey: pubkey.as_ref(),
+            lamports: 2_039_280,
+            owner: TOKEN_PROGRAM_ID.as_ref(),
+            executable: false,
+            rent_epoch: 0,
+            data: &data,
+            write_version: 7,
+            txn_signature: None,
+        };
+        let event = decode(&info, 42).unwrap();
+        assert_eq!(event.mint, mint.to_bytes());
+        assert_eq!(event.owner, owner.to_bytes());
+        assert_eq!(event.amount, 1_000);
+        assert_eq!(event.delegate, delegate.to_bytes());
+        assert_eq!(event.delegated_amount, 250);
+        assert_eq!(event.state(), TokenAccountState::Frozen);
+        assert!(!event.is_native);
+        assert!(event.close_authority.is_empty());
+
+        // Mints are not token accounts.
+        let info = AccountInfo {
+            data: &data[..82],
+            ..info
+        };
+        assert!(decode(&info, 42).is_none());
+    }
+
+    #[test]
+    fn test_delegate_filter() {
+        let delegate = Pubkey::new_unique();
+        let event = TokenAccountEvent {
+            delegate: delegate.to_bytes().to_vec(),
+            delegated_amount: 250,
+            ..TokenAccountEvent::default()
+        };
+        let any = DelegateFilter::new(&TokenDelegateFilterConfig { delegates: vec![] });
+        assert!(any.matches(&event));
+        // A fully spent approval is no longer active.
+        assert!(!any.matches(&TokenAccountEvent {
+            delegated_amount: 0,
+            ..event.clone()
+        }));
+        assert!(!any.matches(&TokenAccountEvent::default()));
+
+        let listed = DelegateFilter::new(&TokenDelegateFilterConfig {
+            delegates: vec![delegate.to_string()],
+        });
+        assert!(listed.matches(&event));
+        let other = DelegateFilter::new(&TokenDelegateFilterConfig {
+            delegates: vec![Pubkey::new_unique().to_string()],
+        });
+        assert!(!other.matches(&event));
+    }
+}
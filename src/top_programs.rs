@@ -0,0 +1,191 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        config::TopProgramsConfig,
+        prom::{TOP_PROGRAMS_ACCOUNTS, TOP_PROGRAMS_BYTES},
+    },
+    prometheus::IntGaugeVec,
+    solana_program::pubkey::Pubkey,
+    std::{
+        collections::{HashMap, HashSet, VecDeque},
+        time::{Duration, Instant},
+    },
+};
+
+/// Number of buckets the sliding window is split into.
+const BUCKETS: u32 = 12;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Volume {
+    accounts: u64,
+    bytes: u64,
+}
+
+/// Tracks published account volume per owner program over a sliding window
+/// and exports the top N programs as gauges.
+#[derive(Debug)]
+pub struct TopPrograms {
+    count: usize,
+    bucket_duration: Duration,
+    bucket_start: Instant,
+    current: HashMap<[u8; 32], Volume>,
+    buckets: VecDeque<HashMap<[u8; 32], Volume>>,
+    /// Programs with an exported gauge.
+    exported_accounts: HashSet<[u8; 32]>,
+    exported_bytes: HashSet<[u8; 32]>,
+}
+
+impl TopPrograms {
+    pub fn new(config: &TopProgramsConfig) -> Self {
+        Self {
+            count: config.count,
+            bucket_duration: Duration::from_secs(config.window_secs) / BUCKETS,
+            bucket_start: Instant::now(),
+            current: HashMap::new(),
+            buckets: VecDeque::with_capacity(BUCKETS as usize),
+            exported_accounts: HashSet::new(),
+            exported_bytes: HashSet::new(),
+        }
+    }
+
+    pub fn record(&mut self, owner: &[u8], bytes: usize) {
+        if self.bucket_start.elapsed() >= self.bucket_duration {
+            self.rotate();
+        }
+
+        let owner = match <[u8; 32]>::try_from(owner) {
+            Ok(owner) => owner,
+            Err(_) => return,
+        };
+        let volume = self.current.entry(owner).or_default();
+        volume.accounts += 1;
+        volume.bytes += bytes as u64;
+    }
+
+    fn rotate(&mut self) {
+        self.bucket_start = Instant::now();
+        if self.buckets.len() == BUCKETS as usize {
+            self.buckets.pop_front();
+        }
+        self.buckets.push_back(std::mem::take(&mut self.current));
+
+        let mut totals: HashMap<[u8; 32], Volume> = HashMap::new();
+        for bucket in self.buckets.iter() {
+            for (owner, volume) in bucket.iter() {
+                let total = totals.entry(*owner).or_default();
+                total.accounts += volume.accounts;
+                total.bytes += volume.bytes;
+            }
+        }
+        let mut totals = totals.into_iter().collect::<Vec<_>>();
+
+        totals.sort_unstable_by(|a, b| b.1.accounts.cmp(&a.1.accounts));
+        Self::export(
+            &TOP_PROGRAMS_ACCOUNTS,
+            &mut self.exported_accounts,
+            totals
+                .iter()
+                .take(self.count)
+                .map(|(owner, volume)| (*owner, volume.accounts)),
+        );
+
+        totals.sort_unstable_by(|a, b| b.1.bytes.cmp(&a.1.bytes));
+        Self::export(
+            &TOP_PROGRAMS_BYTES,
+            &mut self.exported_bytes,
+            totals
+                .iter()
+                .take(self.count)
+                .map(|(owner, volume)| (*owner, volume.bytes)),
+        );
+    }
+
+    /// Sets the gauges of the top programs and removes those of programs that left the top N.
+    fn export(
+        gauge: &IntGaugeVec,
+        exported: &mut HashSet<[u8; 32]>,
+        top: impl Iterator<Item = ([u8; 32], u64)>,
+    ) {
+        let mut current = HashSet::with_capacity(exported.len());
+        for (owner, value) in top {
+            gauge
+                .with_label_values(&[&Pubkey::new_from_array(owner).to_string()])
+                .set(value as i64);
+            current.insert(owner);
+        }
+        for owner in exported.difference(&current) {
+            let _ = gauge.remove_label_values(&[&Pubkey::new_from_array(*owner).to_string()]);
+        }
+        *exported = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exported(gauge: &IntGaugeVec) -> HashMap<String, i64> {
+        prometheus::core::Collector::collect(gauge)
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| {
+                (
+                    metric.get_label()[0].get_value().to_owned(),
+                    metric.get_gauge().get_value() as i64,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_programs_leaving_top_n() {
+        let mut top = TopPrograms::new(&TopProgramsConfig {
+            count: 1,
+            window_secs: 60,
+        });
+        let (a, b) = ([1; 32], [2; 32]);
+        let (a_name, b_name) = (
+            Pubkey::new_from_array(a).to_string(),
+            Pubkey::new_from_array(b).to_string(),
+        );
+
+        top.record(&a, 10);
+        top.record(&a, 10);
+        top.record(&b, 100);
+        top.rotate();
+        assert_eq!(
+            exported(&TOP_PROGRAMS_ACCOUNTS),
+            HashMap::from([(a_name.clone(), 2)])
+        );
+        assert_eq!(
+            exported(&TOP_PROGRAMS_BYTES),
+            HashMap::from([(b_name.clone(), 100)])
+        );
+
+        for _ in 0..3 {
+            top.record(&b, 1);
+        }
+        top.rotate();
+        assert_eq!(
+            exported(&TOP_PROGRAMS_ACCOUNTS),
+            HashMap::from([(b_name.clone(), 4)])
+        );
+        assert_eq!(
+            exported(&TOP_PROGRAMS_BYTES),
+            HashMap::from([(b_name, 103)])
+        );
+    }
+}
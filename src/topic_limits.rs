@@ -0,0 +1,231 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Largest messages the target topics accept, fetched on startup so that oversized events are
+//! handled before librdkafka rejects them. A failed fetch is retried in the background, sizes
+//! are not checked until it succeeds.
+
+use std::collections::HashMap;
+#[cfg(feature = "rdkafka-backend")]
+use {
+    crate::{
+        config::{Config, OAuthBearerConfig},
+        prom::{StatsThreadedProducerContext, TOPIC_LIMITS_KNOWN},
+        Publisher,
+    },
+    rdkafka::{
+        admin::{AdminClient, AdminOptions, ResourceSpecifier},
+        config::{ClientConfig, FromClientConfigAndContext},
+    },
+    std::{
+        error::Error,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+    tracing::{error, info, warn},
+};
+
+/// librdkafka default of `message.max.bytes`.
+#[cfg(feature = "rdkafka-backend")]
+const DEFAULT_MESSAGE_MAX_BYTES: usize = 1_000_000;
+#[cfg(feature = "rdkafka-backend")]
+const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(feature = "rdkafka-backend")]
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Largest message accepted by each topic.
+#[derive(Debug, Default)]
+pub struct TopicLimits {
+    topics: HashMap<String, usize>,
+    /// The producer's `message.max.bytes`, for topics without a fetched limit.
+    producer: usize,
+}
+
+impl TopicLimits {
+    #[cfg(test)]
+    pub fn new(topics: HashMap<String, usize>, producer: usize) -> Self {
+        Self { topics, producer }
+    }
+
+    /// Largest message `topic` accepts.
+    pub fn get(&self, topic: &str) -> usize {
+        self.topics.get(topic).copied().unwrap_or(self.producer)
+    }
+}
+
+/// What fetching the limits needs of the config, kept for retries.
+#[cfg(feature = "rdkafka-backend")]
+#[derive(Debug)]
+struct LimitsRequest {
+    client_config: ClientConfig,
+    oauthbearer: Option<OAuthBearerConfig>,
+    topics: Vec<String>,
+    /// The producer's `message.max.bytes`.
+    producer: usize,
+}
+
+#[cfg(feature = "rdkafka-backend")]
+impl LimitsRequest {
+    fn new(config: &Config) -> Result<Self, String> {
+        let producer = match config.kafka.get("message.max.bytes") {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("invalid kafka.message.max.bytes {}", value))?,
+            None => DEFAULT_MESSAGE_MAX_BYTES,
+        };
+        let mut client_config = config.client_config();
+        // Statistics of the admin client would be mixed into the producer's.
+        client_config.remove("statistics.interval.ms");
+        let mut topics = Publisher::topic_names(config);
+        topics.retain(|topic| !topic.is_empty());
+        topics.sort();
+        topics.dedup();
+        Ok(Self {
+            client_config,
+            oauthbearer: config.oauthbearer.clone(),
+            topics,
+            producer,
+        })
+    }
+
+    /// Fetches the `max.message.bytes` of the topics, capped by the producer's
+    /// `message.max.bytes`.
+    fn fetch(&self) -> Result<TopicLimits, Box<dyn Error + Send + Sync>> {
+        let admin = AdminClient::from_config_and_context(
+            &self.client_config,
+            StatsThreadedProducerContext::new(self.oauthbearer.clone()),
+        )?;
+        let specifiers = self
+            .topics
+            .iter()
+            .map(|topic| ResourceSpecifier::Topic(topic.as_str()))
+            .collect::<Vec<_>>();
+        let options = AdminOptions::new().request_timeout(Some(DESCRIBE_TIMEOUT));
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let results = runtime.block_on(admin.describe_configs(&specifiers, &options))?;
+
+        let mut limits = HashMap::new();
+        for (topic, result) in self.topics.iter().zip(results) {
+            let value = match result {
+                Ok(resource) => resource
+                    .get("max.message.bytes")
+                    .and_then(|entry| entry.value.clone()),
+                Err(code) => {
+                    // Not created yet, librdkafka still enforces the producer's limit.
+                    warn!(%topic, ?code, "failed to describe topic config");
+                    None
+                }
+            };
+            if let Some(limit) = value.and_then(|value| value.parse::<usize>().ok()) {
+                limits.insert(topic.clone(), limit.min(self.producer));
+            }
+        }
+        Ok(TopicLimits {
+            topics: limits,
+            producer: self.producer,
+        })
+    }
+}
+
+/// Background thread retrying a failed fetch until the limits are known.
+#[cfg(feature = "rdkafka-backend")]
+#[derive(Debug)]
+pub struct LimitsFetcher {
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "rdkafka-backend")]
+impl LimitsFetcher {
+    /// Fetches the limits into `publisher`, retrying in the background if that fails, in which
+    /// case the fetcher is returned.
+    pub fn start(config: &Config, publisher: &Arc<Publisher>) -> Result<Option<Self>, String> {
+        TOPIC_LIMITS_KNOWN.set(0);
+        let request = LimitsRequest::new(config)?;
+        match request.fetch() {
+            Ok(limits) => {
+                publisher.set_topic_limits(limits);
+                TOPIC_LIMITS_KNOWN.set(1);
+                return Ok(None);
+            }
+            Err(error) => warn!(
+                %error,
+                "failed to fetch topic max.message.bytes, not checking sizes until a retry succeeds"
+            ),
+        }
+        let publisher = Arc::clone(publisher);
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaTopicLimits".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(RETRY_INTERVAL) {
+                    match request.fetch() {
+                        Ok(limits) => {
+                            publisher.set_topic_limits(limits);
+                            TOPIC_LIMITS_KNOWN.set(1);
+                            info!("fetched topic max.message.bytes, checking sizes");
+                            return;
+                        }
+                        Err(error) => warn!(%error, "failed to fetch topic max.message.bytes"),
+                    }
+                }
+            })
+            .expect("failed to spawn topic limits thread");
+        Ok(Some(Self {
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        }))
+    }
+}
+
+#[cfg(feature = "rdkafka-backend")]
+impl Drop for LimitsFetcher {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("topic limits thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rdkafka-backend"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_request() {
+        let mut config = Config {
+            update_account_topic: "accounts".to_owned(),
+            slot_status_topic: "accounts".to_owned(),
+            ..Config::default()
+        };
+        let request = LimitsRequest::new(&config).unwrap();
+        assert_eq!(request.topics, ["accounts"]);
+        assert_eq!(request.producer, DEFAULT_MESSAGE_MAX_BYTES);
+
+        config
+            .kafka
+            .insert("message.max.bytes".to_owned(), "large".to_owned());
+        assert_eq!(
+            LimitsRequest::new(&config).unwrap_err(),
+            "invalid kafka.message.max.bytes large"
+        );
+    }
+}
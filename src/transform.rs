@@ -0,0 +1,328 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! User supplied transforms applied to encoded events before publishing.
+
+use {
+    crate::config::TransformConfig,
+    libloading::Library,
+    std::{error::Error, ptr, slice},
+    tracing::info,
+};
+#[cfg(feature = "wasm")]
+use {
+    std::sync::Mutex,
+    wasmtime::{Engine, Instance, Memory, Module, Store, TypedFunc},
+};
+
+pub type TransformError = Box<dyn Error + Send + Sync>;
+
+/// Rewrites an encoded event. `Ok(None)` drops the event.
+pub trait Transform: Send + Sync {
+    fn transform(&self, event: &str, payload: &[u8]) -> Result<Option<Vec<u8>>, TransformError>;
+}
+
+pub fn load(config: &TransformConfig) -> Result<Box<dyn Transform>, TransformError> {
+    match config {
+        TransformConfig::Wasm(path) => {
+            info!(path = %path, "loading wasm transform");
+            load_wasm(path)
+        }
+        TransformConfig::Library(path) => {
+            info!(path = %path, "loading native transform");
+            Ok(Box::new(NativeTransform::new(path)?))
+        }
+    }
+}
+
+type TransformFn = unsafe extern "C" fn(
+    event: *const u8,
+    event_len: usize,
+    payload: *const u8,
+    payload_len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32;
+type FreeFn = unsafe extern "C" fn(out: *mut u8, out_len: usize);
+
+/// A shared library exporting:
+///
+/// ```c
+/// int32_t transform(const uint8_t *event, size_t event_len,
+///                   const uint8_t *payload, size_t payload_len,
+///                   uint8_t **out, size_t *out_len);
+/// void transform_free(uint8_t *out, size_t out_len);
+/// ```
+///
+/// `transform` returns 0 after storing the buffer to publish in `out`, 1 to drop the event
+/// and a negative value on failure. Buffers are handed back through `transform_free` once copied.
+/// Both functions are called concurrently from validator threads.
+pub struct NativeTransform {
+    transform: TransformFn,
+    free: FreeFn,
+    // Keeps the function pointers above valid.
+    _library: Library,
+}
+
+impl NativeTransform {
+    pub fn new(path: &str) -> Result<Self, TransformError> {
+        // Safety: loading a library runs its initializers, the library is trusted by configuration.
+        unsafe {
+            let library = Library::new(path)?;
+            let transform = *library.get::<TransformFn>(b"transform\0")?;
+            let free = *library.get::<FreeFn>(b"transform_free\0")?;
+            Ok(Self {
+                transform,
+                free,
+                _library: library,
+            })
+        }
+    }
+}
+
+impl Transform for NativeTransform {
+    fn transform(&self, event: &str, payload: &[u8]) -> Result<Option<Vec<u8>>, TransformError> {
+        let mut out = ptr::null_mut();
+        let mut out_len = 0;
+        // Safety: the arguments outlive the call, `out` is only read when the library reports it set.
+        unsafe {
+            match (self.transform)(
+                event.as_ptr(),
+                event.len(),
+                payload.as_ptr(),
+                payload.len(),
+                &mut out,
+                &mut out_len,
+            ) {
+                0 if out.is_null() => Ok(Some(Vec::new())),
+                0 => {
+                    let output = slice::from_raw_parts(out, out_len).to_vec();
+                    (self.free)(out, out_len);
+                    Ok(Some(output))
+                }
+                1 => Ok(None),
+                code => Err(format!("native transform failed with {}", code).into()),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn load_wasm(path: &str) -> Result<Box<dyn Transform>, TransformError> {
+    Ok(Box::new(WasmTransform::new(path)?))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn load_wasm(_path: &str) -> Result<Box<dyn Transform>, TransformError> {
+    Err("wasm transforms require building with the `wasm` feature".into())
+}
+
+/// A WebAssembly module without imports, exporting:
+///
+/// - `memory`
+/// - `alloc(len: i32) -> i32`, returning a buffer of `len` bytes in `memory`
+/// - `transform(event_ptr: i32, event_len: i32, payload_ptr: i32, payload_len: i32) -> i64`,
+///   returning the output buffer as `ptr << 32 | len`, or a negative value to drop the event.
+///
+/// Both arguments are passed in buffers obtained from `alloc`. The event name is UTF-8.
+/// The host never frees buffers, the module may reuse its memory on the next call.
+#[cfg(feature = "wasm")]
+pub struct WasmTransform {
+    instance: Mutex<WasmInstance>,
+}
+
+#[cfg(feature = "wasm")]
+struct WasmInstance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    transform: TypedFunc<(i32, i32, i32, i32), i64>,
+}
+
+#[cfg(feature = "wasm")]
+impl WasmTransform {
+    pub fn new(path: &str) -> Result<Self, TransformError> {
+        let engine = Engine::default();
+        let module = Module::from_file(&engine, path)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("wasm transform does not export `memory`")?;
+        let alloc = instance.get_typed_func(&mut store, "alloc")?;
+        let transform = instance.get_typed_func(&mut store, "transform")?;
+        Ok(Self {
+            instance: Mutex::new(WasmInstance {
+                store,
+                memory,
+                alloc,
+                transform,
+            }),
+        })
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl WasmInstance {
+    fn write(&mut self, bytes: &[u8]) -> Result<i32, TransformError> {
+        let ptr = self.alloc.call(&mut self.store, bytes.len() as i32)?;
+        self.memory.write(&mut self.store, ptr as usize, bytes)?;
+        Ok(ptr)
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl Transform for WasmTransform {
+    fn transform(&self, event: &str, payload: &[u8]) -> Result<Option<Vec<u8>>, TransformError> {
+        let mut guard = self.instance.lock().unwrap();
+        let instance = &mut *guard;
+        let event_ptr = instance.write(event.as_bytes())?;
+        let payload_ptr = instance.write(payload)?;
+        let result = instance.transform.call(
+            &mut instance.store,
+            (
+                event_ptr,
+                event.len() as i32,
+                payload_ptr,
+                payload.len() as i32,
+            ),
+        )?;
+        if result < 0 {
+            return Ok(None);
+        }
+        let (ptr, len) = ((result >> 32) as usize, (result & 0xffff_ffff) as usize);
+        let mut output = vec![0; len];
+        instance.memory.read(&instance.store, ptr, &mut output)?;
+        Ok(Some(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{fs, path::PathBuf, process::Command},
+    };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("kafka-plugin-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Publishes the event name, drops empty payloads and fails on payloads starting with `x`.
+    const NATIVE_SOURCE: &str = r#"
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int32_t transform(const uint8_t *event, size_t event_len,
+                  const uint8_t *payload, size_t payload_len,
+                  uint8_t **out, size_t *out_len) {
+    if (payload_len == 0) return 1;
+    if (payload[0] == 'x') return -2;
+    *out = malloc(event_len);
+    memcpy(*out, event, event_len);
+    *out_len = event_len;
+    return 0;
+}
+
+void transform_free(uint8_t *out, size_t out_len) {
+    (void)out_len;
+    free(out);
+}
+"#;
+
+    #[test]
+    fn test_native_transform() {
+        let dir = temp_dir("native-transform");
+        let (source, library) = (dir.join("transform.c"), dir.join("libtransform.so"));
+        fs::write(&source, NATIVE_SOURCE).unwrap();
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&library)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let transform = load(&TransformConfig::Library(
+            library.to_str().unwrap().to_owned(),
+        ))
+        .unwrap();
+        assert_eq!(
+            transform.transform("account", b"payload").unwrap(),
+            Some(b"account".to_vec())
+        );
+        assert_eq!(transform.transform("account", b"").unwrap(), None);
+        assert_eq!(
+            transform
+                .transform("account", b"x")
+                .unwrap_err()
+                .to_string(),
+            "native transform failed with -2"
+        );
+
+        assert!(NativeTransform::new(dir.join("missing.so").to_str().unwrap()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Publishes the event name and drops empty payloads. Buffers start at 16 so that pointers
+    /// are not zero.
+    #[cfg(feature = "wasm")]
+    const WASM_SOURCE: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $next (mut i32) (i32.const 16))
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+  (func (export "transform")
+    (param $event i32) (param $event_len i32) (param $payload i32) (param $payload_len i32)
+    (result i64)
+    (if (i32.eqz (local.get $payload_len)) (then (return (i64.const -1))))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $event)) (i64.const 32))
+      (i64.extend_i32_u (local.get $event_len)))))
+"#;
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn test_wasm_transform() {
+        let dir = temp_dir("wasm-transform");
+        let module = dir.join("transform.wat");
+        fs::write(&module, WASM_SOURCE).unwrap();
+
+        let transform = load(&TransformConfig::Wasm(module.to_str().unwrap().to_owned())).unwrap();
+        assert_eq!(
+            transform.transform("account", b"payload").unwrap(),
+            Some(b"account".to_vec())
+        );
+        assert_eq!(
+            transform.transform("slot", b"payload").unwrap(),
+            Some(b"slot".to_vec())
+        );
+        assert_eq!(transform.transform("account", b"").unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    #[test]
+    fn test_wasm_requires_feature() {
+        assert!(load(&TransformConfig::Wasm("transform.wasm".to_owned())).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deployments and upgrades of BPF Loader Upgradeable programs, detected from the loader
+//! instructions writing their program-data accounts.
+
+use {
+    crate::{replica::TransactionInfo, ProgramUpgradedEvent},
+    solana_program::{
+        bpf_loader_upgradeable, instruction::CompiledInstruction as SdkCompiledInstruction,
+        pubkey::Pubkey,
+    },
+};
+
+/// `UpgradeableLoaderInstruction::DeployWithMaxDataLen`.
+const DEPLOY_WITH_MAX_DATA_LEN: u32 = 2;
+/// `UpgradeableLoaderInstruction::Upgrade`.
+const UPGRADE: u32 = 3;
+
+/// Events of the outer and inner deploy and upgrade instructions of a successful transaction,
+/// in execution order.
+pub fn build_program_upgraded_events(
+    slot: u64,
+    transaction: &TransactionInfo,
+) -> Vec<ProgramUpgradedEvent> {
+    if transaction.transaction_status_meta.status.is_err() {
+        return Vec::new();
+    }
+    let message = transaction.transaction.message();
+    let account_keys = message.account_keys();
+    let build = |ix: &SdkCompiledInstruction| {
+        let program_id = account_keys.get(ix.program_id_index as usize)?;
+        if *program_id != bpf_loader_upgradeable::id() || ix.data.len() < 4 {
+            return None;
+        }
+        let account = |position: usize| -> Option<&Pubkey> {
+            account_keys.get(*ix.accounts.get(position)? as usize)
+        };
+        // Positions of the program-data, program, buffer and authority accounts.
+        let (deployed, [program_data, program, buffer, authority]) =
+            match u32::from_le_bytes(ix.data[..4].try_into().unwrap()) {
+                DEPLOY_WITH_MAX_DATA_LEN => (true, [1, 2, 3, 7]),
+                UPGRADE => (false, [0, 1, 2, 6]),
+                _ => return None,
+            };
+        Some(ProgramUpgradedEvent {
+            slot,
+            signature: transaction.signature.as_ref().to_vec(),
+            program_id: account(program)?.to_bytes().to_vec(),
+            program_data: account(program_data)?.to_bytes().to_vec(),
+            buffer: account(buffer)?.to_bytes().to_vec(),
+            authority: account(authority)?.to_bytes().to_vec(),
+            deployed,
+        })
+    };
+
+    let inner_instructions = transaction
+        .transaction_status_meta
+        .inner_instructions
+        .as_deref()
+        .unwrap_or_default();
+    let mut events = Vec::new();
+    for (index, ix) in message.instructions().iter().enumerate() {
+        events.extend(build(ix));
+        for inner in inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == index)
+        {
+            for ix in &inner.instructions {
+                events.extend(build(&ix.instruction));
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::{
+            hash::Hash,
+            message::Message,
+            transaction::{SanitizedTransaction, Transaction, TransactionError},
+        },
+        solana_transaction_status::{
+            InnerInstruction, InnerInstructions, TransactionStatusMeta as SdkTransactionStatusMeta,
+        },
+    };
+
+    fn instruction(
+        program_id_index: u8,
+        accounts: Vec<u8>,
+        instruction: u32,
+    ) -> SdkCompiledInstruction {
+        let mut data = instruction.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 8]);
+        SdkCompiledInstruction {
+            program_id_index,
+            accounts,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_program_upgraded_events() {
+        let keys = (0..10).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let mut account_keys = keys.clone();
+        account_keys[8] = bpf_loader_upgradeable::id();
+        let (payer, program_data, program, buffer, authority, other_buffer) = (0, 1, 2, 3, 7, 9);
+        let message = Message::new_with_compiled_instructions(
+            1,
+            0,
+            1,
+            account_keys,
+            Hash::new_unique(),
+            vec![
+                instruction(
+                    8,
+                    vec![payer, program_data, program, buffer, 4, 5, 6, authority],
+                    DEPLOY_WITH_MAX_DATA_LEN,
+                ),
+                instruction(6, vec![], 0),
+            ],
+        );
+        let transaction =
+            SanitizedTransaction::from_transaction_for_tests(Transaction::new_unsigned(message));
+        let meta = SdkTransactionStatusMeta {
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 1,
+                instructions: vec![InnerInstruction {
+                    instruction: instruction(
+                        8,
+                        vec![program_data, program, other_buffer, payer, 4, 5, authority],
+                        UPGRADE,
+                    ),
+                    stack_height: Some(2),
+                }],
+            }]),
+            ..SdkTransactionStatusMeta::default()
+        };
+        let failed = SdkTransactionStatusMeta {
+            status: Err(TransactionError::AccountNotFound),
+            ..meta.clone()
+        };
+        let info = |meta| TransactionInfo {
+            signature: transaction.signature(),
+            is_vote: false,
+            transaction: &transaction,
+            transaction_status_meta: meta,
+            index: None,
+        };
+        let key = |index: u8| keys[index as usize].to_bytes().to_vec();
+
+        let events = build_program_upgraded_events(5, &info(&meta));
+        let signature = transaction.signature().as_ref().to_vec();
+        assert_eq!(
+            events,
+            [
+                ProgramUpgradedEvent {
+                    slot: 5,
+                    signature: signature.clone(),
+                    program_id: key(program),
+                    program_data: key(program_data),
+                    buffer: key(buffer),
+                    authority: key(authority),
+                    deployed: true,
+                },
+                ProgramUpgradedEvent {
+                    slot: 5,
+                    signature,
+                    program_id: key(program),
+                    program_data: key(program_data),
+                    buffer: key(other_buffer),
+                    authority: key(authority),
+                    deployed: false,
+                },
+            ]
+        );
+
+        assert!(build_program_upgraded_events(5, &info(&failed)).is_empty());
+    }
+}
@@ -4,6 +4,7 @@ use {serde::Serialize, std::env};
 pub struct Version {
     pub version: &'static str,
     pub solana: &'static str,
+    pub interface: &'static str,
     pub git: &'static str,
     pub rustc: &'static str,
     pub buildts: &'static str,
@@ -12,6 +13,7 @@ pub struct Version {
 pub const VERSION: Version = Version {
     version: env!("VERGEN_BUILD_SEMVER"),
     solana: env!("SOLANA_SDK_VERSION"),
+    interface: env!("GEYSER_INTERFACE_VERSION"),
     git: env!("GIT_VERSION"),
     rustc: env!("VERGEN_RUSTC_SEMVER"),
     buildts: env!("VERGEN_BUILD_TIMESTAMP"),
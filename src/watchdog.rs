@@ -0,0 +1,190 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        config::{WatchdogAction, WatchdogConfig},
+        health::HEALTH,
+        prom::WATCHDOG_ACTIONS_TOTAL,
+    },
+    std::{
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            mpsc::{self, RecvTimeoutError, Sender},
+            Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    tracing::{error, info, warn},
+};
+
+/// Set by the watchdog, consumed by the plugin on its next callback.
+static RECREATE_PRODUCER: AtomicBool = AtomicBool::new(false);
+/// Unix timestamp in milliseconds until which publishing is paused.
+static PAUSED_UNTIL_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the watchdog asked for the producer to be recreated. Clears the request.
+pub fn take_recreate_request() -> bool {
+    RECREATE_PRODUCER.swap(false, Ordering::Relaxed)
+}
+
+/// Asks the plugin to recreate the producer on its next callback.
+pub fn request_recreate() {
+    RECREATE_PRODUCER.store(true, Ordering::Relaxed);
+}
+
+pub fn is_paused() -> bool {
+    PAUSED_UNTIL_MS.load(Ordering::Relaxed) > now_ms()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Tracks whether the watchdog already acted on the current trouble, so that it acts once
+/// instead of on every check, e.g. sending SIGTERM again while the validator shuts down.
+#[derive(Debug, Default)]
+struct Trouble {
+    acted: bool,
+}
+
+impl Trouble {
+    /// The reason to act, if checks failed for the first time since they last passed.
+    fn first(&mut self, reason: Option<String>) -> Option<String> {
+        match reason {
+            Some(reason) if !self.acted => {
+                self.acted = true;
+                Some(reason)
+            }
+            Some(_) => None,
+            None => {
+                self.acted = false;
+                None
+            }
+        }
+    }
+}
+
+/// Background thread acting on sustained publish failure or queue growth.
+#[derive(Debug)]
+pub struct Watchdog {
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("kafkaWatchdog".to_owned())
+            .spawn(move || {
+                let interval = Duration::from_millis(config.check_interval_ms);
+                let mut trouble = Trouble::default();
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if is_paused() {
+                        continue;
+                    }
+                    if let Some(reason) = trouble.first(Self::check(&config)) {
+                        Self::act(&config, &reason);
+                    }
+                }
+            })
+            .expect("failed to spawn watchdog thread");
+        Self {
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        }
+    }
+
+    fn check(config: &WatchdogConfig) -> Option<String> {
+        let report = HEALTH.report();
+        if let Some(failing_for_ms) = report.failing_for_ms {
+            if failing_for_ms > config.failure_timeout_ms {
+                return Some(format!("publishing failing for {}ms", failing_for_ms));
+            }
+        }
+        if config.max_queue_depth > 0 && report.queue_depth > config.max_queue_depth {
+            return Some(format!("queue depth {}", report.queue_depth));
+        }
+        None
+    }
+
+    fn act(config: &WatchdogConfig, reason: &str) {
+        WATCHDOG_ACTIONS_TOTAL
+            .with_label_values(&[config.action.as_str()])
+            .inc();
+        match config.action {
+            WatchdogAction::RecreateProducer => {
+                warn!(reason, "watchdog: recreating producer");
+                HEALTH.reset_failure();
+                request_recreate();
+            }
+            WatchdogAction::Pause => {
+                warn!(
+                    reason,
+                    pause_ms = config.pause_ms,
+                    "watchdog: pausing publishing"
+                );
+                HEALTH.reset_failure();
+                PAUSED_UNTIL_MS.store(now_ms() + config.pause_ms, Ordering::Relaxed);
+            }
+            WatchdogAction::Exit => {
+                error!(reason, "watchdog: asking the validator to exit");
+                // SAFETY: sending a signal to our own process has no memory safety implications.
+                unsafe {
+                    libc::kill(libc::getpid(), libc::SIGTERM);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("watchdog thread panicked");
+            } else {
+                info!("watchdog stopped");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trouble_acts_once() {
+        let mut trouble = Trouble::default();
+        assert_eq!(trouble.first(None), None);
+        assert_eq!(
+            trouble.first(Some("queue".to_owned())),
+            Some("queue".to_owned())
+        );
+        assert_eq!(trouble.first(Some("queue".to_owned())), None);
+        assert_eq!(trouble.first(Some("failing".to_owned())), None);
+        assert_eq!(trouble.first(None), None);
+        assert_eq!(
+            trouble.first(Some("queue".to_owned())),
+            Some("queue".to_owned())
+        );
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-water marks of delivered events, persisted so that a restarted validator does not
+//! publish again the account updates delivered before the restart.
+//!
+//! The mark of a topic is the highest `(slot, write_version)` such that every event
+//! enqueued at or below it has had its delivery reported. Events without a write version
+//! are tracked at write version 0 of their slot.
+
+use {
+    crate::config::WatermarkConfig,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap},
+        error::Error,
+        fs, io,
+        path::{Path, PathBuf},
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Mutex, RwLock,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+    tracing::{error, info, warn},
+};
+
+lazy_static::lazy_static! {
+    pub static ref WATERMARKS: Watermarks = Watermarks::default();
+}
+
+/// Position of an account update in the stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Position {
+    pub slot: u64,
+    pub write_version: u64,
+}
+
+impl Position {
+    /// Highest slot whose enqueued updates were all delivered, with this position as the mark.
+    /// Updates of the mark's own slot may still follow, unless it is the slot's last position.
+    pub fn complete_slot(self) -> Option<u64> {
+        match self.write_version {
+            u64::MAX => Some(self.slot),
+            _ => self.slot.checked_sub(1),
+        }
+    }
+
+    /// The position immediately before this one.
+    fn previous(self) -> Option<Self> {
+        match (self.slot, self.write_version) {
+            (0, 0) => None,
+            (slot, 0) => Some(Self {
+                slot: slot - 1,
+                write_version: u64::MAX,
+            }),
+            (slot, write_version) => Some(Self {
+                slot,
+                write_version: write_version - 1,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TopicState {
+    /// Enqueued positions whose delivery was not reported yet, with their count.
+    pending: BTreeMap<Position, usize>,
+    /// Highest position delivered.
+    delivered: Option<Position>,
+}
+
+impl TopicState {
+    fn complete(&self) -> Option<Position> {
+        match self.pending.keys().next() {
+            Some(lowest) => self.delivered.min(lowest.previous()),
+            None => self.delivered,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Watermarks {
+    topics: Mutex<HashMap<String, TopicState>>,
+    /// Marks loaded from the state file on startup.
+    restored: RwLock<HashMap<String, Position>>,
+}
+
+impl Watermarks {
+    pub fn record_enqueued(&self, topic: &str, position: Position) {
+        let mut topics = self.topics.lock().unwrap();
+        let state = match topics.get_mut(topic) {
+            Some(state) => state,
+            None => topics.entry(topic.to_owned()).or_default(),
+        };
+        *state.pending.entry(position).or_default() += 1;
+    }
+
+    /// Failed deliveries also resolve their position, the mark never waits for retries.
+    pub fn record_resolved(&self, topic: &str, position: Position, delivered: bool) {
+        let mut topics = self.topics.lock().unwrap();
+        let state = match topics.get_mut(topic) {
+            Some(state) => state,
+            None => return,
+        };
+        if let Some(count) = state.pending.get_mut(&position) {
+            *count -= 1;
+            if *count == 0 {
+                state.pending.remove(&position);
+            }
+        }
+        if delivered {
+            state.delivered = state.delivered.max(Some(position));
+        }
+    }
+
+    /// Whether an update at `position` was delivered before the restart.
+    pub fn is_delivered(&self, topic: &str, position: Position) -> bool {
+        self.restored
+            .read()
+            .unwrap()
+            .get(topic)
+            .is_some_and(|mark| position <= *mark)
+    }
+
+    /// Marks of every topic, including those restored and not published to since.
+    pub fn snapshot(&self) -> BTreeMap<String, Position> {
+        let mut marks = self
+            .restored
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(topic, mark)| (topic.clone(), *mark))
+            .collect::<BTreeMap<_, _>>();
+        for (topic, state) in self.topics.lock().unwrap().iter() {
+            if let Some(complete) = state.complete() {
+                let mark = marks.entry(topic.clone()).or_insert(complete);
+                *mark = (*mark).max(complete);
+            }
+        }
+        marks
+    }
+
+    fn restore(&self, marks: HashMap<String, Position>) {
+        *self.restored.write().unwrap() = marks;
+    }
+}
+
+fn load(path: &Path) -> Result<HashMap<String, Position>, Box<dyn Error + Send + Sync>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Writes to a temporary file first so that a crash never leaves a truncated state file.
+fn save(path: &Path) -> io::Result<()> {
+    let contents = serde_json::to_vec(&WATERMARKS.snapshot())?;
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+/// Background thread persisting the marks to the state file.
+#[derive(Debug)]
+pub struct WatermarkStore {
+    path: PathBuf,
+    stop: Mutex<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatermarkStore {
+    /// Restores the marks saved by the previous run.
+    pub fn new(config: &WatermarkConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let path = PathBuf::from(&config.path);
+        let marks = load(&path)?;
+        info!(path = %path.display(), ?marks, "restored delivered watermarks");
+        WATERMARKS.restore(marks);
+
+        let interval = Duration::from_millis(config.save_interval_ms);
+        let (stop, stopped) = mpsc::channel();
+        let thread = {
+            let path = path.clone();
+            thread::Builder::new()
+                .name("kafkaWatermarks".to_owned())
+                .spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                        if let Err(error) = save(&path) {
+                            warn!(path = %path.display(), %error, "failed to save watermarks");
+                        }
+                    }
+                })?
+        };
+        Ok(Self {
+            path,
+            stop: Mutex::new(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for WatermarkStore {
+    /// Saves the marks a last time, expected after the producer was flushed.
+    fn drop(&mut self) {
+        let _ = self.stop.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("watermark thread panicked");
+            }
+        }
+        match save(&self.path) {
+            Ok(()) => info!(path = %self.path.display(), "saved delivered watermarks"),
+            Err(error) => error!(path = %self.path.display(), %error, "failed to save watermarks"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(slot: u64, write_version: u64) -> Position {
+        Position {
+            slot,
+            write_version,
+        }
+    }
+
+    #[test]
+    fn test_complete_waits_for_pending() {
+        let watermarks = Watermarks::default();
+        watermarks.record_enqueued("accounts", position(1, 10));
+        watermarks.record_enqueued("accounts", position(2, 20));
+        watermarks.record_enqueued("accounts", position(2, 21));
+
+        watermarks.record_resolved("accounts", position(2, 20), true);
+        assert_eq!(watermarks.snapshot()["accounts"], position(1, 9));
+
+        watermarks.record_resolved("accounts", position(1, 10), true);
+        assert_eq!(watermarks.snapshot()["accounts"], position(2, 20));
+
+        watermarks.record_resolved("accounts", position(2, 21), false);
+        assert_eq!(watermarks.snapshot()["accounts"], position(2, 20));
+    }
+
+    #[test]
+    fn test_restored() {
+        let watermarks = Watermarks::default();
+        watermarks.restore(HashMap::from([("accounts".to_owned(), position(5, 50))]));
+        assert!(watermarks.is_delivered("accounts", position(5, 50)));
+        assert!(watermarks.is_delivered("accounts", position(4, 99)));
+        assert!(!watermarks.is_delivered("accounts", position(5, 51)));
+        assert!(!watermarks.is_delivered("tokens", position(1, 1)));
+
+        watermarks.record_enqueued("accounts", position(3, 1));
+        watermarks.record_resolved("accounts", position(3, 1), true);
+        assert_eq!(watermarks.snapshot()["accounts"], position(5, 50));
+    }
+}
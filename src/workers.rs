@@ -0,0 +1,446 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker threads encoding and enqueueing events, so that the validator's notification
+//! threads only build events and hand them over.
+//!
+//! Every worker has its own queue and events are assigned by key, so updates of the same
+//! account are always enqueued by the same worker in the order they were submitted.
+
+use {
+    crate::{
+        config::WorkersConfig,
+        error::PublishError,
+        memory::{self, MEMORY},
+        pause::PAUSE,
+        prom::{EVENTS_SKIPPED_TOTAL, PLUGIN_PANICS_TOTAL, WORKER_QUEUE_DEPTH},
+        *,
+    },
+    prost::Message,
+    std::{
+        collections::VecDeque,
+        panic::{self, AssertUnwindSafe},
+        sync::{Arc, Condvar, Mutex},
+        thread::{self, JoinHandle},
+    },
+    tracing::{debug, error, info},
+};
+
+/// An event waiting to be published.
+#[derive(Debug)]
+pub enum Job {
+    Account(UpdateAccountEvent),
+    Slot(SlotStatusEvent),
+    Transaction(Box<TransactionEvent>),
+    TokenAccount(TokenAccountEvent),
+    StakeAccount(StakeAccountEvent),
+    VoteAccount(VoteAccountEvent),
+    Signature(SignatureEvent),
+    Instruction(InstructionEvent),
+    ProgramUpgrade(ProgramUpgradedEvent),
+    BlockRewards(BlockRewardsEvent),
+}
+
+impl Job {
+    /// Event label used in metrics.
+    pub fn event(&self) -> &'static str {
+        match self {
+            Self::Account(_) => "account",
+            Self::Slot(_) => "slot",
+            Self::Transaction(_) => "transaction",
+            Self::TokenAccount(_) => "token_account",
+            Self::StakeAccount(_) => "stake_account",
+            Self::VoteAccount(_) => "vote_account",
+            Self::Signature(_) => "signature",
+            Self::Instruction(_) => "instruction",
+            Self::ProgramUpgrade(_) => "program_upgrade",
+            Self::BlockRewards(_) => "block_rewards",
+        }
+    }
+
+    /// Slot the event belongs to.
+    #[cfg_attr(not(feature = "rdkafka-backend"), allow(dead_code))]
+    pub fn slot(&self) -> u64 {
+        match self {
+            Self::Account(ev) => ev.slot,
+            Self::Slot(ev) => ev.slot,
+            Self::Transaction(ev) => ev.slot,
+            Self::TokenAccount(ev) => ev.slot,
+            Self::StakeAccount(ev) => ev.slot,
+            Self::VoteAccount(ev) => ev.slot,
+            Self::Signature(ev) => ev.slot,
+            Self::Instruction(ev) => ev.slot,
+            Self::ProgramUpgrade(ev) => ev.slot,
+            Self::BlockRewards(ev) => ev.slot,
+        }
+    }
+
+    /// Account the event is about, `None` for slots, transactions, signatures, instructions,
+    /// program upgrades and blocks.
+    pub fn pubkey(&self) -> Option<&[u8]> {
+        match self {
+            Self::Account(ev) => Some(&ev.pubkey),
+            Self::TokenAccount(ev) => Some(&ev.pubkey),
+            Self::StakeAccount(ev) => Some(&ev.pubkey),
+            Self::VoteAccount(ev) => Some(&ev.pubkey),
+            Self::Slot(_)
+            | Self::Transaction(_)
+            | Self::Signature(_)
+            | Self::Instruction(_)
+            | Self::ProgramUpgrade(_)
+            | Self::BlockRewards(_) => None,
+        }
+    }
+
+    /// Events with the same shard key are published in order, so it follows the record key.
+    /// Keys are pubkeys and signatures, whose leading bytes are already uniformly distributed.
+    fn shard_key(&self) -> u64 {
+        let bytes = match self {
+            Self::Account(ev) => &ev.pubkey,
+            Self::Slot(ev) => return ev.slot,
+            Self::BlockRewards(ev) => return ev.slot,
+            Self::Transaction(ev) => &ev.signature,
+            Self::Signature(ev) => &ev.signature,
+            Self::Instruction(ev) => &ev.signature,
+            Self::ProgramUpgrade(ev) => &ev.program_id,
+            Self::TokenAccount(ev) => &ev.pubkey,
+            Self::StakeAccount(ev) => &ev.pubkey,
+            Self::VoteAccount(ev) => &ev.pubkey,
+        };
+        let mut key = [0; 8];
+        let len = bytes.len().min(8);
+        key[..len].copy_from_slice(&bytes[..len]);
+        u64::from_le_bytes(key)
+    }
+
+    /// Approximate bytes held by the event, its encoded length.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Account(ev) => ev.encoded_len(),
+            Self::Slot(ev) => ev.encoded_len(),
+            Self::Transaction(ev) => ev.encoded_len(),
+            Self::TokenAccount(ev) => ev.encoded_len(),
+            Self::StakeAccount(ev) => ev.encoded_len(),
+            Self::VoteAccount(ev) => ev.encoded_len(),
+            Self::Signature(ev) => ev.encoded_len(),
+            Self::Instruction(ev) => ev.encoded_len(),
+            Self::ProgramUpgrade(ev) => ev.encoded_len(),
+            Self::BlockRewards(ev) => ev.encoded_len(),
+        }
+    }
+
+    pub fn publish(self, publisher: &Publisher) -> Result<(), PublishError> {
+        match self {
+            Self::Account(ev) => publisher.update_account(ev),
+            Self::Slot(ev) => publisher.update_slot_status(ev),
+            Self::Transaction(ev) => publisher.update_transaction(*ev),
+            Self::TokenAccount(ev) => publisher.update_token_account(ev),
+            Self::StakeAccount(ev) => publisher.update_stake_account(ev),
+            Self::VoteAccount(ev) => publisher.update_vote_account(ev),
+            Self::Signature(ev) => publisher.update_signature(ev),
+            Self::Instruction(ev) => publisher.update_instruction(ev),
+            Self::ProgramUpgrade(ev) => publisher.update_program_upgrade(ev),
+            Self::BlockRewards(ev) => publisher.update_block_rewards(ev),
+        }
+    }
+}
+
+/// Queue of a worker.
+#[derive(Debug, Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    jobs: VecDeque<Job>,
+    /// Set when shutting down, which lets the thread finish the queue and exit.
+    closed: bool,
+}
+
+impl Queue {
+    /// Waits for the next job, `None` once closed and empty.
+    fn pop(&self) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                return Some(job);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().unwrap().jobs.len()
+    }
+}
+
+/// Pool of threads publishing jobs from bounded queues, one per thread.
+#[derive(Debug)]
+pub struct Workers {
+    queues: Vec<Arc<Queue>>,
+    capacity: usize,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Workers {
+    pub fn new(publisher: Arc<Publisher>, config: &WorkersConfig) -> Self {
+        let count = config.threads.max(1);
+        let capacity = (config.queue_capacity / count).max(1);
+        let mut queues = Vec::with_capacity(count);
+        let threads = (0..count)
+            .map(|i| {
+                let publisher = Arc::clone(&publisher);
+                let queue = Arc::new(Queue::default());
+                queues.push(Arc::clone(&queue));
+                thread::Builder::new()
+                    .name(format!("kafkaWorker{:02}", i))
+                    .spawn(move || {
+                        while let Some(job) = queue.pop() {
+                            let event = job.event();
+                            let size = job.size();
+                            match panic::catch_unwind(AssertUnwindSafe(|| job.publish(&publisher)))
+                            {
+                                // Failures are counted by the publisher.
+                                Ok(Ok(())) => {}
+                                Ok(Err(error)) => debug!(event, %error, "failed to publish"),
+                                Err(_) => {
+                                    PLUGIN_PANICS_TOTAL.with_label_values(&["worker"]).inc();
+                                    error!(event, "worker panicked while publishing");
+                                }
+                            }
+                            MEMORY.release(size);
+                        }
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        info!(
+            threads = count,
+            queue_capacity = capacity * count,
+            "started publishing workers"
+        );
+        Self {
+            queues,
+            capacity,
+            threads,
+        }
+    }
+
+    /// Queues a job without blocking, dropping it if the queue is full. Over the memory cap,
+    /// the oldest account updates are evicted, held paused events first.
+    pub fn submit(&self, job: Job) {
+        let shard = (job.shard_key() % self.queues.len() as u64) as usize;
+        let queue = &self.queues[shard];
+        if queue.len() >= self.capacity {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[job.event(), "worker_queue_full"])
+                .inc();
+            return;
+        }
+        let size = job.size();
+        let admitted = MEMORY.reserve(&job, size, |needed| {
+            let mut freed = PAUSE.evict_oldest_accounts(needed);
+            for queue in &self.queues {
+                if freed >= needed {
+                    break;
+                }
+                let mut state = queue.state.lock().unwrap();
+                freed += memory::evict_oldest_accounts(&mut state.jobs, needed - freed);
+            }
+            freed
+        });
+        if !admitted {
+            return;
+        }
+        let mut state = queue.state.lock().unwrap();
+        if state.closed {
+            drop(state);
+            MEMORY.release(size);
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&[job.event(), "worker_stopped"])
+                .inc();
+            return;
+        }
+        state.jobs.push_back(job);
+        drop(state);
+        queue.available.notify_one();
+        let depth: usize = self.queues.iter().map(|queue| queue.len()).sum();
+        WORKER_QUEUE_DEPTH.set(depth as i64);
+    }
+}
+
+impl Drop for Workers {
+    /// Publishes the queued jobs before returning.
+    fn drop(&mut self) {
+        for queue in &self.queues {
+            queue.state.lock().unwrap().closed = true;
+            queue.available.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("worker thread panicked");
+            }
+        }
+        WORKER_QUEUE_DEPTH.set(0);
+        info!("workers stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            producer::MemoryProducer,
+            transform::{Transform, TransformError},
+        },
+        std::{thread::sleep, time::Duration},
+    };
+
+    fn account(pubkey: u8, write_version: u64) -> Job {
+        Job::Account(UpdateAccountEvent {
+            pubkey: vec![pubkey; 32],
+            write_version,
+            ..UpdateAccountEvent::default()
+        })
+    }
+
+    fn workers(threads: usize, queue_capacity: usize) -> (Workers, MemoryProducer) {
+        workers_with(threads, queue_capacity, None)
+    }
+
+    fn workers_with(
+        threads: usize,
+        queue_capacity: usize,
+        transform: Option<Box<dyn Transform>>,
+    ) -> (Workers, MemoryProducer) {
+        let producer = MemoryProducer::default();
+        let publisher = Publisher::new(
+            Some(Box::new(producer.clone())),
+            transform,
+            &Config::default(),
+        );
+        let config = WorkersConfig {
+            threads,
+            queue_capacity,
+        };
+        (Workers::new(Arc::new(publisher), &config), producer)
+    }
+
+    /// Blocks publishing while the test holds the lock.
+    struct Gate(Arc<Mutex<()>>);
+
+    impl Transform for Gate {
+        fn transform(&self, _: &str, payload: &[u8]) -> Result<Option<Vec<u8>>, TransformError> {
+            let _open = self.0.lock().unwrap();
+            Ok(Some(payload.to_vec()))
+        }
+    }
+
+    #[test]
+    fn test_same_key_in_order() {
+        let (workers, producer) = workers(4, 10_000);
+        for write_version in 0..200 {
+            for pubkey in 0..8 {
+                workers.submit(account(pubkey, write_version));
+            }
+        }
+        drop(workers);
+
+        let records = producer.records();
+        assert_eq!(records.len(), 1600);
+        for pubkey in 0..8 {
+            let write_versions = records
+                .iter()
+                .map(|record| UpdateAccountEvent::decode(&record.payload[..]).unwrap())
+                .filter(|event| event.pubkey == [pubkey; 32])
+                .map(|event| event.write_version)
+                .collect::<Vec<_>>();
+            assert_eq!(write_versions, (0..200).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_queue_full() {
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+        let (workers, producer) = workers_with(1, 1, Some(Box::new(Gate(Arc::clone(&gate)))));
+        let skipped = || {
+            EVENTS_SKIPPED_TOTAL
+                .with_label_values(&["account", "worker_queue_full"])
+                .get()
+        };
+        let before = skipped();
+
+        // Held by the worker at the gate.
+        workers.submit(account(1, 1));
+        while workers.queues[0].len() > 0 {
+            sleep(Duration::from_millis(1));
+        }
+        workers.submit(account(1, 2));
+        workers.submit(account(1, 3));
+        assert_eq!(skipped(), before + 1);
+
+        drop(closed);
+        drop(workers);
+        assert_eq!(producer.records().len(), 2);
+    }
+
+    #[test]
+    fn test_shard_key() {
+        let pubkey = vec![7; 32];
+        let token_account = Job::TokenAccount(TokenAccountEvent {
+            pubkey: pubkey.clone(),
+            ..TokenAccountEvent::default()
+        });
+        assert_eq!(account(7, 1).shard_key(), account(7, 2).shard_key());
+        assert_eq!(account(7, 1).shard_key(), token_account.shard_key());
+        assert_ne!(account(7, 1).shard_key(), account(8, 1).shard_key());
+
+        let slot = |slot, status: SlotStatus| {
+            Job::Slot(SlotStatusEvent {
+                slot,
+                status: status.into(),
+                ..SlotStatusEvent::default()
+            })
+        };
+        assert_eq!(
+            slot(5, SlotStatus::Processed).shard_key(),
+            slot(5, SlotStatus::Rooted).shard_key()
+        );
+
+        let instruction = |signature: u8, index| {
+            Job::Instruction(InstructionEvent {
+                signature: vec![signature; 64],
+                instruction_index: index,
+                ..InstructionEvent::default()
+            })
+        };
+        assert_eq!(instruction(1, 0).shard_key(), instruction(1, 1).shard_key());
+
+        let upgrade = |signature: u8| {
+            Job::ProgramUpgrade(ProgramUpgradedEvent {
+                signature: vec![signature; 64],
+                program_id: pubkey.clone(),
+                ..ProgramUpgradedEvent::default()
+            })
+        };
+        assert_eq!(upgrade(1).shard_key(), upgrade(2).shard_key());
+    }
+}
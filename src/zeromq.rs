@@ -0,0 +1,159 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ZeroMQ PUB socket sink, for low-latency consumers next to the validator. Every event is a
+//! three-frame message: the topic, which subscribers filter on by prefix, the key, empty for
+//! unkeyed events, and the encoded event. There are no headers.
+
+use {
+    crate::{
+        config::ZeroMqConfig,
+        health::HEALTH,
+        producer::{ProducerError, ProducerLike},
+        watermark::{Position, WATERMARKS},
+    },
+    std::{sync::Mutex, time::Duration},
+    tracing::info,
+};
+
+pub struct ZeroMqProducer {
+    /// Sockets are not thread safe.
+    socket: Mutex<zmq::Socket>,
+}
+
+impl ZeroMqProducer {
+    pub fn new(config: &ZeroMqConfig) -> Result<Self, ProducerError> {
+        let creation_error =
+            |error: zmq::Error| ProducerError::Creation(format!("zeromq: {}", error));
+        let socket = zmq::Context::new()
+            .socket(zmq::PUB)
+            .map_err(creation_error)?;
+        socket.set_sndhwm(config.send_hwm).map_err(creation_error)?;
+        // Do not hold up shutdown for slow subscribers.
+        socket.set_linger(0).map_err(creation_error)?;
+        socket.bind(&config.endpoint).map_err(creation_error)?;
+        info!(endpoint = %config.endpoint, "bound zeromq PUB socket");
+        Ok(Self {
+            socket: Mutex::new(socket),
+        })
+    }
+}
+
+impl ProducerLike for ZeroMqProducer {
+    fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        _headers: &[(&str, &str)],
+        payload: &[u8],
+        position: Option<Position>,
+    ) -> Result<(), ProducerError> {
+        let frames: [&[u8]; 3] = [topic.as_bytes(), key.unwrap_or_default(), payload];
+        // PUB sockets drop messages for subscribers over the high-water mark instead of
+        // blocking, a full queue only surfaces as EAGAIN while no peer can take any.
+        let result = self
+            .socket
+            .lock()
+            .unwrap()
+            .send_multipart(frames, zmq::DONTWAIT);
+        match result {
+            Ok(()) => {
+                HEALTH.record_delivery(topic);
+                if let Some(position) = position {
+                    WATERMARKS.record_enqueued(topic, position);
+                    WATERMARKS.record_resolved(topic, position, true);
+                }
+                Ok(())
+            }
+            Err(zmq::Error::EAGAIN) => Err(ProducerError::QueueFull),
+            Err(zmq::Error::ETERM) => Err(ProducerError::ShutDown),
+            Err(error) => Err(ProducerError::Other(format!("zeromq: {}", error))),
+        }
+    }
+
+    fn in_flight_count(&self) -> i32 {
+        0
+    }
+
+    fn flush(&self, _timeout: Duration) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{env, thread, time::Instant},
+    };
+
+    #[test]
+    fn test_frames() {
+        let path = env::temp_dir().join(format!("kafka-plugin-zeromq-{}", std::process::id()));
+        let endpoint = format!("ipc://{}", path.display());
+        let producer = ZeroMqProducer::new(&ZeroMqConfig {
+            endpoint: endpoint.clone(),
+            send_hwm: 1_000,
+        })
+        .unwrap();
+        let subscriber = zmq::Context::new().socket(zmq::SUB).unwrap();
+        subscriber.connect(&endpoint).unwrap();
+        subscriber.set_subscribe(b"accounts").unwrap();
+        subscriber.set_rcvtimeo(10).unwrap();
+
+        // Subscriptions reach the PUB socket asynchronously, messages sent before are dropped.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let keyed = loop {
+            assert!(Instant::now() < deadline, "no message received");
+            producer
+                .send(
+                    "accounts",
+                    Some(b"key"),
+                    &[("content-type", "x")],
+                    b"event",
+                    None,
+                )
+                .unwrap();
+            producer.send("slots", None, &[], b"slot", None).unwrap();
+            if let Ok(frames) = subscriber.recv_multipart(0) {
+                break frames;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(keyed, [&b"accounts"[..], b"key", b"event"]);
+
+        producer
+            .send("accounts", None, &[], b"unkeyed", None)
+            .unwrap();
+        subscriber.set_rcvtimeo(10_000).unwrap();
+        let unkeyed = loop {
+            let frames = subscriber.recv_multipart(0).unwrap();
+            if frames[2] != b"event" {
+                break frames;
+            }
+        };
+        assert_eq!(unkeyed, [&b"accounts"[..], b"", b"unkeyed"]);
+        drop(producer);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_invalid_endpoint() {
+        let error = ZeroMqProducer::new(&ZeroMqConfig {
+            endpoint: "tcp://".to_owned(),
+            send_hwm: 1_000,
+        })
+        .err()
+        .unwrap();
+        assert!(matches!(error, ProducerError::Creation(_)));
+    }
+}
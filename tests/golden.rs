@@ -0,0 +1,250 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wire compatibility checks: every event type is published in every format and compared
+//! byte for byte with the fixtures in `tests/fixtures`, which must also decode back to the event.
+//!
+//! Intentional format changes are recorded by regenerating the fixtures:
+//!
+//! ```shell
+//! UPDATE_FIXTURES=1 cargo test --test golden
+//! ```
+
+use {
+    prost::Message,
+    solana_accountsdb_plugin_kafka::{
+        sanitized_message::MessagePayload, CompiledInstruction, Config, Format, LegacyMessage,
+        MemoryProducer, MessageHeader, MessageVersion, Publisher, SanitizedMessage,
+        SanitizedTransaction, SlotStatus, SlotStatusEvent, StakeAccountEvent, StakeAccountState,
+        TokenAccountEvent, TokenAccountState, TransactionEvent, TransactionStatusMeta,
+        UpdateAccountEvent, VoteAccountEvent,
+    },
+    std::{env, fmt::Debug, fs, path::PathBuf},
+};
+
+fn key(byte: u8) -> Vec<u8> {
+    vec![byte; 32]
+}
+
+fn signature(byte: u8) -> Vec<u8> {
+    vec![byte; 64]
+}
+
+fn account() -> UpdateAccountEvent {
+    UpdateAccountEvent {
+        slot: 100,
+        pubkey: key(1),
+        lamports: 1_000_000,
+        owner: key(2),
+        executable: false,
+        rent_epoch: 361,
+        data: vec![0xde, 0xad, 0xbe, 0xef],
+        write_version: 42,
+        txn_signature: signature(3),
+        block_time: Some(1_700_000_000),
+        rent_exempt: false,
+        previous_owner: Vec::new(),
+    }
+}
+
+fn slot() -> SlotStatusEvent {
+    SlotStatusEvent {
+        slot: 101,
+        parent: 100,
+        status: SlotStatus::Confirmed.into(),
+        dead_error: String::new(),
+        blockhash: String::new(),
+    }
+}
+
+fn transaction() -> TransactionEvent {
+    TransactionEvent {
+        signature: signature(4),
+        is_vote: false,
+        transaction: Some(SanitizedTransaction {
+            message: Some(SanitizedMessage {
+                version: MessageVersion::Legacy.into(),
+                message_payload: Some(MessagePayload::Legacy(LegacyMessage {
+                    header: Some(MessageHeader {
+                        num_required_signatures: 1,
+                        num_readonly_signed_accounts: 0,
+                        num_readonly_unsigned_accounts: 1,
+                    }),
+                    account_keys: vec![key(5), key(6)],
+                    recent_block_hash: key(7),
+                    instructions: vec![CompiledInstruction {
+                        program_id_index: 1,
+                        accounts: vec![0],
+                        data: vec![1, 2, 3],
+                    }],
+                })),
+            }),
+            message_hash: key(8),
+            is_simple_vote_transaction: false,
+            signatures: vec![signature(4)],
+        }),
+        transaction_status_meta: Some(TransactionStatusMeta {
+            fee: 5_000,
+            pre_balances: vec![10, 20],
+            post_balances: vec![5, 20],
+            log_messages: vec!["Program log: hello".to_owned()],
+            ..TransactionStatusMeta::default()
+        }),
+        slot: 102,
+        index: Some(3),
+        block_time: Some(1_700_000_000),
+        compute_unit_limit: Some(200_000),
+        compute_unit_price: Some(1_000),
+    }
+}
+
+fn token_account() -> TokenAccountEvent {
+    TokenAccountEvent {
+        slot: 103,
+        pubkey: key(9),
+        program: key(10),
+        mint: key(11),
+        owner: key(12),
+        amount: 500,
+        state: TokenAccountState::Initialized.into(),
+        lamports: 2_039_280,
+        write_version: 43,
+        ..TokenAccountEvent::default()
+    }
+}
+
+fn stake_account() -> StakeAccountEvent {
+    StakeAccountEvent {
+        slot: 104,
+        pubkey: key(13),
+        state: StakeAccountState::StakeDelegated.into(),
+        rent_exempt_reserve: 2_282_880,
+        staker: key(14),
+        withdrawer: key(15),
+        voter: key(16),
+        stake: 1_000_000_000,
+        activation_epoch: 300,
+        deactivation_epoch: u64::MAX,
+        credits_observed: 1234,
+        lamports: 1_002_282_880,
+        write_version: 44,
+        ..StakeAccountEvent::default()
+    }
+}
+
+fn vote_account() -> VoteAccountEvent {
+    VoteAccountEvent {
+        slot: 105,
+        pubkey: key(17),
+        node_pubkey: key(18),
+        authorized_voter: key(19),
+        authorized_withdrawer: key(20),
+        commission: 10,
+        root_slot: Some(90),
+        last_voted_slot: Some(104),
+        credits: 5_000,
+        epoch: 361,
+        epoch_credits: 200,
+        last_timestamp_slot: 104,
+        last_timestamp: 1_700_000_000,
+        lamports: 27_074_400,
+        write_version: 45,
+    }
+}
+
+/// Payloads of every event type, published in `format`.
+fn publish(format: Format) -> Vec<(&'static str, Vec<u8>)> {
+    let config = Config {
+        format,
+        update_account_topic: "account".to_owned(),
+        slot_status_topic: "slot".to_owned(),
+        transaction_topic: "transaction".to_owned(),
+        token_account_topic: "token_account".to_owned(),
+        stake_account_topic: "stake_account".to_owned(),
+        vote_account_topic: "vote_account".to_owned(),
+        ..Config::default()
+    };
+    let producer = MemoryProducer::default();
+    let publisher = Publisher::new(Some(Box::new(producer.clone())), None, &config);
+    publisher.update_account(account()).unwrap();
+    publisher.update_slot_status(slot()).unwrap();
+    publisher.update_transaction(transaction()).unwrap();
+    publisher.update_token_account(token_account()).unwrap();
+    publisher.update_stake_account(stake_account()).unwrap();
+    publisher.update_vote_account(vote_account()).unwrap();
+
+    let names = [
+        "account",
+        "slot",
+        "transaction",
+        "token_account",
+        "stake_account",
+        "vote_account",
+    ];
+    let records = producer.records();
+    assert_eq!(records.len(), names.len());
+    names
+        .into_iter()
+        .zip(records)
+        .map(|(name, record)| {
+            assert_eq!(record.topic, name);
+            (name, record.payload)
+        })
+        .collect()
+}
+
+fn fixture(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{}.{}", name, extension))
+}
+
+fn check_fixtures(format: Format, extension: &str) {
+    let update = env::var_os("UPDATE_FIXTURES").is_some();
+    for (name, payload) in publish(format) {
+        let path = fixture(name, extension);
+        if update {
+            fs::write(&path, &payload).unwrap();
+            continue;
+        }
+        let expected = fs::read(&path).unwrap();
+        assert!(
+            payload == expected,
+            "{} differs from {}, regenerate fixtures if the change is intended",
+            name,
+            path.display()
+        );
+    }
+}
+
+fn check_decode<M: Message + Default + PartialEq + Debug>(name: &str, event: M) {
+    let payload = fs::read(fixture(name, "bin")).unwrap();
+    assert_eq!(M::decode(&payload[..]).unwrap(), event, "{}", name);
+}
+
+#[test]
+fn test_protobuf_fixtures() {
+    check_fixtures(Format::Protobuf, "bin");
+    check_decode("account", account());
+    check_decode("slot", slot());
+    check_decode("transaction", transaction());
+    check_decode("token_account", token_account());
+    check_decode("stake_account", stake_account());
+    check_decode("vote_account", vote_account());
+}
+
+#[test]
+fn test_json_fixtures() {
+    check_fixtures(Format::Json, "json");
+}
@@ -0,0 +1,244 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! End-to-end tests against a Kafka broker in a container. Requires Docker:
+//!
+//! ```shell
+//! cargo test --features integration-tests --test kafka
+//! ```
+
+#![cfg(all(feature = "integration-tests", not(feature = "agave")))]
+
+use {
+    prost::Message,
+    rdkafka::{
+        config::ClientConfig,
+        consumer::{BaseConsumer, Consumer},
+        message::{Headers, Message as _, OwnedMessage},
+    },
+    serde_json::json,
+    solana_accountsdb_plugin_kafka::{
+        KafkaPlugin, SlotStatus, SlotStatusEvent, TokenAccountEvent, TokenAccountState,
+        UpdateAccountEvent,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPlugin, ReplicaAccountInfoV2, ReplicaAccountInfoVersions,
+        SlotStatus as PluginSlotStatus,
+    },
+    solana_program::pubkey::Pubkey,
+    solana_sdk::signature::Signature,
+    std::{
+        collections::HashMap,
+        env, fs,
+        time::{Duration, Instant},
+    },
+    testcontainers::{
+        clients::Cli,
+        images::kafka::{Kafka, KAFKA_PORT},
+    },
+};
+
+const ACCOUNT_TOPIC: &str = "test.account_updates";
+const SLOT_TOPIC: &str = "test.slot_status";
+const TOKEN_TOPIC: &str = "test.token_accounts";
+
+const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+/// Loads the plugin from a config file pointing at `bootstrap_servers`, as the validator does.
+fn load_plugin(bootstrap_servers: &str) -> KafkaPlugin {
+    let config = json!({
+        "kafka": {
+            "bootstrap.servers": bootstrap_servers,
+            "queue.buffering.max.ms": "10",
+        },
+        "shutdown_timeout_ms": 30000,
+        "update_account_topic": ACCOUNT_TOPIC,
+        "slot_status_topic": SLOT_TOPIC,
+        "token_account_topic": TOKEN_TOPIC,
+        "publish_all_accounts": true,
+    });
+    let path = env::temp_dir().join(format!("kafka-plugin-test-{}.json", std::process::id()));
+    fs::write(&path, config.to_string()).unwrap();
+
+    let mut plugin = KafkaPlugin::new();
+    plugin.on_load(path.to_str().unwrap()).unwrap();
+    fs::remove_file(path).unwrap();
+    plugin
+}
+
+/// Reads `count` messages from the start of `topics`, grouped by topic.
+fn consume(
+    bootstrap_servers: &str,
+    topics: &[&str],
+    count: usize,
+) -> HashMap<String, Vec<OwnedMessage>> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", bootstrap_servers)
+        .set("group.id", "integration-test")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .unwrap();
+    consumer.subscribe(topics).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(60);
+    let mut messages: HashMap<String, Vec<OwnedMessage>> = HashMap::new();
+    let mut received = 0;
+    while received < count {
+        assert!(
+            Instant::now() < deadline,
+            "timed out with {} of {} messages",
+            received,
+            count
+        );
+        if let Some(message) = consumer.poll(Duration::from_secs(1)) {
+            let message = message.unwrap().detach();
+            messages
+                .entry(message.topic().to_owned())
+                .or_default()
+                .push(message);
+            received += 1;
+        }
+    }
+    messages
+}
+
+fn update_account(plugin: &mut KafkaPlugin, info: ReplicaAccountInfoV2, slot: u64) {
+    plugin
+        .update_account(ReplicaAccountInfoVersions::V0_0_2(&info), slot, false)
+        .unwrap();
+}
+
+#[test]
+fn test_publish_and_consume() {
+    let docker = Cli::default();
+    let kafka = docker.run(Kafka::default());
+    let bootstrap_servers = format!("127.0.0.1:{}", kafka.get_host_port_ipv4(KAFKA_PORT));
+    let mut plugin = load_plugin(&bootstrap_servers);
+
+    // A plain account is published to the account topic, keyed by its pubkey.
+    let pubkey = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let signature = Signature::new(&[7; 64]);
+    update_account(
+        &mut plugin,
+        ReplicaAccountInfoV2 {
+            pubkey: pubkey.as_ref(),
+            lamports: 1_000,
+            owner: owner.as_ref(),
+            executable: false,
+            rent_epoch: 3,
+            data: &[1, 2, 3],
+            write_version: 9,
+            txn_signature: Some(&signature),
+        },
+        42,
+    );
+    let account = UpdateAccountEvent {
+        slot: 42,
+        pubkey: pubkey.to_bytes().to_vec(),
+        lamports: 1_000,
+        owner: owner.to_bytes().to_vec(),
+        executable: false,
+        rent_epoch: 3,
+        data: vec![1, 2, 3],
+        write_version: 9,
+        txn_signature: signature.as_ref().to_vec(),
+        block_time: None,
+        rent_exempt: false,
+        previous_owner: Vec::new(),
+    };
+
+    // A token account is decoded to the token topic and still published raw.
+    let token_pubkey = Pubkey::new_unique();
+    let mint = Pubkey::new_unique();
+    let wallet = Pubkey::new_unique();
+    let token_program = TOKEN_PROGRAM_ID.parse::<Pubkey>().unwrap();
+    let mut token_data = vec![0; 165];
+    token_data[0..32].copy_from_slice(mint.as_ref());
+    token_data[32..64].copy_from_slice(wallet.as_ref());
+    token_data[64..72].copy_from_slice(&5u64.to_le_bytes());
+    token_data[108] = 1;
+    update_account(
+        &mut plugin,
+        ReplicaAccountInfoV2 {
+            pubkey: token_pubkey.as_ref(),
+            lamports: 2_039_280,
+            owner: token_program.as_ref(),
+            executable: false,
+            rent_epoch: 0,
+            data: &token_data,
+            write_version: 10,
+            txn_signature: None,
+        },
+        42,
+    );
+    let token_account = TokenAccountEvent {
+        slot: 42,
+        pubkey: token_pubkey.to_bytes().to_vec(),
+        program: token_program.to_bytes().to_vec(),
+        mint: mint.to_bytes().to_vec(),
+        owner: wallet.to_bytes().to_vec(),
+        amount: 5,
+        state: TokenAccountState::Initialized.into(),
+        lamports: 2_039_280,
+        write_version: 10,
+        ..TokenAccountEvent::default()
+    };
+
+    // Slot updates carry no key.
+    plugin
+        .update_slot_status(43, Some(42), PluginSlotStatus::Confirmed)
+        .unwrap();
+    let slot = SlotStatusEvent {
+        slot: 43,
+        parent: 42,
+        status: SlotStatus::Confirmed.into(),
+        dead_error: String::new(),
+        blockhash: String::new(),
+    };
+
+    // Flushes the producer.
+    plugin.on_unload();
+
+    let mut messages = consume(
+        &bootstrap_servers,
+        &[ACCOUNT_TOPIC, SLOT_TOPIC, TOKEN_TOPIC],
+        4,
+    );
+
+    let accounts = messages.remove(ACCOUNT_TOPIC).unwrap();
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0].key(), Some(pubkey.as_ref()));
+    assert_eq!(accounts[0].payload(), Some(&account.encode_to_vec()[..]));
+    let headers = accounts[0].headers().unwrap();
+    assert_eq!(
+        headers.get(0),
+        Some(("content-type", &b"application/x-protobuf"[..]))
+    );
+    assert_eq!(headers.get(1).unwrap().0, "schema-version");
+    assert_eq!(accounts[1].key(), Some(token_pubkey.as_ref()));
+
+    let tokens = messages.remove(TOKEN_TOPIC).unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].key(), Some(token_pubkey.as_ref()));
+    assert_eq!(
+        tokens[0].payload(),
+        Some(&token_account.encode_to_vec()[..])
+    );
+
+    let slots = messages.remove(SLOT_TOPIC).unwrap();
+    assert_eq!(slots.len(), 1);
+    assert_eq!(slots[0].key(), None);
+    assert_eq!(slots[0].payload(), Some(&slot.encode_to_vec()[..]));
+}